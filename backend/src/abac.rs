@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::models::auth::TokenIntrospection;
+
+/// The tenants named by the admin's `ADMIN_TENANT_CLAIM`, a string or an
+/// array of strings. `None` when the token has no such claim, which leaves
+/// the admin unrestricted; a claim with no usable value restricts them to
+/// nothing.
+pub fn admin_tenants(introspection: &TokenIntrospection, claim: &str) -> Option<Vec<String>> {
+    let value = introspection.claims.get(claim)?;
+    let tenants = match value {
+        Value::String(tenant) => vec![tenant.clone()],
+        Value::Array(values) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_owned)
+            .collect(),
+        _ => Vec::new(),
+    };
+    Some(
+        tenants
+            .into_iter()
+            .map(|tenant| tenant.trim().to_owned())
+            .filter(|tenant| !tenant.is_empty())
+            .collect(),
+    )
+}
+
+/// Whether an admin restricted to `tenants` may act on a user with
+/// `attributes`: the user's `attribute` has to hold one of them. Users
+/// without the attribute belong to no tenant and are out of reach of every
+/// tenant admin.
+pub fn permits(
+    tenants: Option<&[String]>,
+    attributes: &HashMap<String, Vec<String>>,
+    attribute: &str,
+) -> bool {
+    let Some(tenants) = tenants else {
+        return true;
+    };
+    attributes.get(attribute).is_some_and(|values| {
+        values
+            .iter()
+            .any(|value| tenants.iter().any(|tenant| tenant == value.trim()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(tenant: &[&str]) -> HashMap<String, Vec<String>> {
+        HashMap::from([(
+            "tenant".to_owned(),
+            tenant.iter().map(|value| (*value).to_owned()).collect(),
+        )])
+    }
+
+    #[test]
+    fn unrestricted_admin_manages_everyone() {
+        assert!(permits(None, &attributes(&["acme"]), "tenant"));
+        assert!(permits(None, &HashMap::new(), "tenant"));
+    }
+
+    #[test]
+    fn tenant_admin_manages_only_their_tenant() {
+        let tenants = vec!["acme".to_owned()];
+        assert!(permits(Some(&tenants), &attributes(&["acme"]), "tenant"));
+        assert!(permits(
+            Some(&tenants),
+            &attributes(&["globex", "acme"]),
+            "tenant"
+        ));
+        assert!(!permits(Some(&tenants), &attributes(&["globex"]), "tenant"));
+        assert!(!permits(Some(&tenants), &HashMap::new(), "tenant"));
+        assert!(!permits(Some(&[]), &attributes(&["acme"]), "tenant"));
+    }
+
+    #[test]
+    fn reads_the_claim_as_string_or_array() {
+        let mut introspection = TokenIntrospection::default();
+        assert_eq!(admin_tenants(&introspection, "tenant"), None);
+        introspection
+            .claims
+            .insert("tenant".to_owned(), Value::from("acme"));
+        assert_eq!(
+            admin_tenants(&introspection, "tenant"),
+            Some(vec!["acme".to_owned()])
+        );
+        introspection
+            .claims
+            .insert("tenant".to_owned(), serde_json::json!(["acme", "", 7]));
+        assert_eq!(
+            admin_tenants(&introspection, "tenant"),
+            Some(vec!["acme".to_owned()])
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, HashMap};
+
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::keycloak::{KeycloakError, KeycloakService, RoleRepresentation};
+use crate::models::user::{SERVER_ATTRIBUTES, UserRepresentation};
+use crate::util::unix_now;
+
+/// Set on a merged duplicate: the id of the account it was merged into.
+pub const MERGED_INTO_ATTRIBUTE: &str = "mergedInto";
+/// Unix seconds of the merge, next to `mergedInto`.
+pub const MERGED_AT_ATTRIBUTE: &str = "mergedAt";
+
+/// How the duplicate's attributes reach the primary account.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttributePolicy {
+    /// Only attributes the primary does not have are copied.
+    #[default]
+    FillMissing,
+    /// The duplicate's values replace the primary's.
+    PreferDuplicate,
+    /// Values from both accounts are kept.
+    Union,
+    /// Nothing is copied.
+    Ignore,
+}
+
+/// What moved from the duplicate to the primary.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeReport {
+    /// Paths of groups the primary joined.
+    pub groups: Vec<String>,
+    pub realm_roles: Vec<String>,
+    /// Keyed by client id.
+    pub client_roles: BTreeMap<String, Vec<String>>,
+    pub identity_providers: Vec<String>,
+    /// Providers the primary is already linked with; the duplicate keeps
+    /// those links, disabled.
+    pub skipped_identity_providers: Vec<String>,
+    /// Names of primary attributes that were added or changed.
+    pub attributes: Vec<String>,
+}
+
+/// Folds `duplicate` into `primary`: the primary gains the duplicate's
+/// group memberships, directly assigned roles and identity provider links,
+/// and its attributes per `policy`, except the server attributes and
+/// `tenant_attribute`, which would move it into another tenant's admin
+/// scope. The duplicate loses its groups, roles
+/// and links, is marked `mergedInto`, disabled and signed out everywhere.
+/// Each step is its own Keycloak call, so a failure part way leaves the
+/// steps before it done; running the merge again finishes the rest.
+pub async fn merge(
+    keycloak: &KeycloakService,
+    primary: &UserRepresentation,
+    duplicate: &UserRepresentation,
+    policy: AttributePolicy,
+    tenant_attribute: &str,
+) -> Result<MergeReport, KeycloakError> {
+    let (primary_identities, identities, primary_groups, groups, primary_roles, roles) = tokio::try_join!(
+        keycloak.federated_identities(&primary.id),
+        keycloak.federated_identities(&duplicate.id),
+        keycloak.user_groups(&primary.id),
+        keycloak.user_groups(&duplicate.id),
+        keycloak.role_mappings(&primary.id),
+        keycloak.role_mappings(&duplicate.id),
+    )?;
+    let mut report = MergeReport::default();
+
+    // A provider account can only be linked to one user at a time.
+    for identity in identities {
+        let provider = identity.identity_provider.clone();
+        if primary_identities
+            .iter()
+            .any(|linked| linked.identity_provider == provider)
+        {
+            report.skipped_identity_providers.push(provider);
+            continue;
+        }
+        keycloak.unlink_identity(&duplicate.id, &provider).await?;
+        if let Err(err) = keycloak.link_identity(&primary.id, &identity).await {
+            warn!(
+                "[Merge] linking {} to {} failed, restoring it on {}",
+                provider, primary.id, duplicate.id
+            );
+            if let Err(restore) = keycloak.link_identity(&duplicate.id, &identity).await {
+                warn!("[Merge] restoring {} failed: {}", provider, restore);
+            }
+            return Err(err);
+        }
+        report.identity_providers.push(provider);
+    }
+
+    for group in &groups {
+        if !primary_groups.iter().any(|joined| joined.id == group.id) {
+            keycloak.join_group(&primary.id, &group.id).await?;
+            report.groups.push(group.path.clone());
+        }
+        keycloak.leave_group(&duplicate.id, &group.id).await?;
+    }
+
+    let missing = missing_roles(&roles.realm_mappings, &primary_roles.realm_mappings);
+    if !missing.is_empty() {
+        keycloak
+            .change_role_mappings(Method::POST, &primary.id, None, &missing)
+            .await?;
+        report.realm_roles = missing.into_iter().map(|role| role.name).collect();
+    }
+    if !roles.realm_mappings.is_empty() {
+        keycloak
+            .change_role_mappings(Method::DELETE, &duplicate.id, None, &roles.realm_mappings)
+            .await?;
+    }
+
+    for (client, mappings) in &roles.client_mappings {
+        let held = primary_roles
+            .client_mappings
+            .get(client)
+            .map(|held| held.mappings.as_slice())
+            .unwrap_or_default();
+        let missing = missing_roles(&mappings.mappings, held);
+        if !missing.is_empty() {
+            keycloak
+                .change_role_mappings(Method::POST, &primary.id, Some(&mappings.id), &missing)
+                .await?;
+            report.client_roles.insert(
+                client.clone(),
+                missing.into_iter().map(|role| role.name).collect(),
+            );
+        }
+        if !mappings.mappings.is_empty() {
+            keycloak
+                .change_role_mappings(
+                    Method::DELETE,
+                    &duplicate.id,
+                    Some(&mappings.id),
+                    &mappings.mappings,
+                )
+                .await?;
+        }
+    }
+
+    let mut merged = primary.clone();
+    report.attributes = merge_attributes(
+        &mut merged.attributes,
+        &duplicate.attributes,
+        policy,
+        tenant_attribute,
+    );
+    if !report.attributes.is_empty() {
+        keycloak.update_user(&merged, merged.enabled).await?;
+    }
+
+    let mut retired = duplicate.clone();
+    retired
+        .attributes
+        .insert(MERGED_INTO_ATTRIBUTE.to_owned(), vec![primary.id.clone()]);
+    retired
+        .attributes
+        .insert(MERGED_AT_ATTRIBUTE.to_owned(), vec![unix_now().to_string()]);
+    keycloak.update_user(&retired, false).await?;
+    if let Err(err) = keycloak.logout_user_sessions(&duplicate.id).await {
+        warn!(
+            "[Merge] ending sessions of {} failed: {}",
+            duplicate.id, err
+        );
+    }
+
+    info!(
+        "[Merge] {} into {}: {} group(s), {} realm role(s), {} identity link(s), {} attribute(s)",
+        duplicate.id,
+        primary.id,
+        report.groups.len(),
+        report.realm_roles.len(),
+        report.identity_providers.len(),
+        report.attributes.len()
+    );
+    Ok(report)
+}
+
+fn missing_roles(
+    roles: &[RoleRepresentation],
+    held: &[RoleRepresentation],
+) -> Vec<RoleRepresentation> {
+    roles
+        .iter()
+        .filter(|role| !held.iter().any(|existing| existing.id == role.id))
+        .cloned()
+        .collect()
+}
+
+/// Applies `policy` to `primary` and returns the names it changed, sorted.
+fn merge_attributes(
+    primary: &mut HashMap<String, Vec<String>>,
+    duplicate: &HashMap<String, Vec<String>>,
+    policy: AttributePolicy,
+    tenant_attribute: &str,
+) -> Vec<String> {
+    let mut changed = Vec::new();
+    if policy == AttributePolicy::Ignore {
+        return changed;
+    }
+    for (name, values) in duplicate {
+        if SERVER_ATTRIBUTES.contains(&name.as_str())
+            || name == tenant_attribute
+            || values.is_empty()
+        {
+            continue;
+        }
+        let current = primary.entry(name.clone()).or_default();
+        let before = current.clone();
+        match policy {
+            AttributePolicy::FillMissing if current.is_empty() => current.clone_from(values),
+            AttributePolicy::PreferDuplicate => current.clone_from(values),
+            AttributePolicy::Union => {
+                for value in values {
+                    if !current.contains(value) {
+                        current.push(value.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        if *current != before {
+            changed.push(name.clone());
+        }
+    }
+    primary.retain(|_, values| !values.is_empty());
+    changed.sort();
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(entries: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), vec![(*value).to_owned()]))
+            .collect()
+    }
+
+    #[test]
+    fn never_copies_the_tenant_attribute() {
+        let duplicate = attributes(&[("tenant", "other"), ("nickname", "dup")]);
+        for policy in [
+            AttributePolicy::FillMissing,
+            AttributePolicy::PreferDuplicate,
+            AttributePolicy::Union,
+        ] {
+            let mut primary = attributes(&[("tenant", "home")]);
+            let changed = merge_attributes(&mut primary, &duplicate, policy, "tenant");
+            assert_eq!(changed, vec!["nickname".to_owned()]);
+            assert_eq!(primary["tenant"], vec!["home".to_owned()]);
+
+            let mut untenanted = HashMap::new();
+            merge_attributes(&mut untenanted, &duplicate, policy, "tenant");
+            assert!(!untenanted.contains_key("tenant"));
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use axum::{
+    Json, async_trait,
+    extract::FromRequestParts,
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION, request::Parts},
+};
+use tracing::{error, warn};
+
+use crate::AppState;
+use crate::abac;
+use crate::deadline;
+use crate::dpop::{self, DpopError};
+use crate::error_codes::ErrorCode;
+use crate::extract::Rejection;
+use crate::keycloak::KeycloakError;
+use crate::models::auth::TokenIntrospection;
+use crate::models::user::ErrorResponse;
+use crate::scope::{ADMIN_SCOPE, AUDIT_SCOPE};
+
+/// Caller authenticated by introspecting its bearer token against Keycloak.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: String,
+    pub username: Option<String>,
+    /// Tenants the caller is confined to; see [`abac::admin_tenants`].
+    pub tenants: Option<Vec<String>>,
+}
+
+impl Principal {
+    pub fn display_name(&self) -> &str {
+        self.username.as_deref().unwrap_or(&self.subject)
+    }
+}
+
+/// Caller of an `/api/admin` route holding the configured admin realm role or
+/// the `argus:admin` scope.
+#[derive(Debug, Clone)]
+pub struct AdminPrincipal(pub Principal);
+
+/// Caller holding the configured auditor realm role or the `argus:audit`
+/// scope.
+#[derive(Debug, Clone)]
+pub struct AuditorPrincipal(pub Principal);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminPrincipal {
+    type Rejection = Rejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        authenticate(parts, state, ADMIN_SCOPE, &state.config.admin_role)
+            .await
+            .map(Self)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuditorPrincipal {
+    type Rejection = Rejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        authenticate(parts, state, AUDIT_SCOPE, &state.config.auditor_role)
+            .await
+            .map(Self)
+    }
+}
+
+async fn authenticate(
+    parts: &mut Parts,
+    state: &AppState,
+    scope: &str,
+    role: &str,
+) -> Result<Principal, Rejection> {
+    let introspection = introspect(parts, state).await?;
+    let subject = introspection.sub.clone().unwrap_or_default();
+    if !introspection.grants(scope, role) {
+        warn!(
+            "[Admin] sub={} denied: missing scope {} or role {}",
+            subject, scope, role
+        );
+        return Err(reject(StatusCode::FORBIDDEN, "Insufficient privileges"));
+    }
+
+    Ok(Principal {
+        tenants: abac::admin_tenants(&introspection, &state.config.admin_tenant_claim),
+        subject,
+        username: introspection.username,
+    })
+}
+
+/// Validates the bearer token with Keycloak (or the introspection cache)
+/// once per request; the result is kept in the request extensions so later
+/// guards and extractors reuse it.
+pub async fn introspect(
+    parts: &mut Parts,
+    state: &AppState,
+) -> Result<TokenIntrospection, Rejection> {
+    if let Some(introspection) = parts.extensions.get::<TokenIntrospection>() {
+        return Ok(introspection.clone());
+    }
+
+    let Some(token) = bearer_token(parts) else {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Missing bearer token"));
+    };
+    if state.revocations.is_revoked(&token).await {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"));
+    }
+
+    let introspection = match state.introspections.introspect(&token).await {
+        Ok(introspection) => introspection,
+        Err(KeycloakError::DeadlineExceeded) => return Err(deadline::exceeded()),
+        Err(err) => {
+            error!(?err, "[Admin] token introspection failed");
+            return Err(reject(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Unable to verify credentials",
+            ));
+        }
+    };
+
+    if !introspection.active {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"));
+    }
+
+    if let Some(bound) = introspection.dpop_thumbprint() {
+        verify_dpop(parts, state, &token, bound).await?;
+    }
+
+    parts.extensions.insert(introspection.clone());
+    Ok(introspection)
+}
+
+/// Sender-constrained tokens must arrive with the `DPoP` scheme and a proof
+/// for this request signed by the key the token is bound to.
+async fn verify_dpop(
+    parts: &Parts,
+    state: &AppState,
+    token: &str,
+    bound: &str,
+) -> Result<(), Rejection> {
+    if !authorization_scheme(parts).is_some_and(|scheme| scheme.eq_ignore_ascii_case("dpop")) {
+        return Err(reject(
+            StatusCode::UNAUTHORIZED,
+            "DPoP-bound token requires the DPoP scheme",
+        ));
+    }
+    let Some(proof) = dpop::proof_header(&parts.headers) else {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Missing DPoP proof"));
+    };
+
+    match state
+        .dpop
+        .verify(proof, &parts.method, parts.uri.path(), Some(token))
+        .await
+    {
+        Ok(thumbprint) if thumbprint == bound => Ok(()),
+        Ok(_) => {
+            warn!("[Admin] DPoP proof key does not match token binding");
+            Err(reject(
+                StatusCode::UNAUTHORIZED,
+                &DpopError::KeyMismatch.to_string(),
+            ))
+        }
+        Err(err) => {
+            warn!(?err, "[Admin] DPoP proof rejected");
+            Err(reject(StatusCode::UNAUTHORIZED, &err.to_string()))
+        }
+    }
+}
+
+fn authorization_scheme(parts: &Parts) -> Option<&str> {
+    let value = parts.headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+    value.split_once(' ').map(|(scheme, _)| scheme)
+}
+
+/// Access token from an `Authorization: Bearer` or `Authorization: DPoP`
+/// header.
+pub fn bearer_token(parts: &Parts) -> Option<String> {
+    bearer_token_from(&parts.headers)
+}
+
+pub fn bearer_token_from(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+    let (scheme, token) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") && !scheme.eq_ignore_ascii_case("dpop") {
+        return None;
+    }
+
+    let token = token.trim();
+    (!token.is_empty()).then(|| token.to_owned())
+}
+
+fn reject(status: StatusCode, message: &str) -> Rejection {
+    (
+        status,
+        Json(ErrorResponse::new(
+            ErrorCode::from_status(status),
+            message.to_owned(),
+        )),
+    )
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hmac::Mac;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::AppConfig;
+use crate::captcha::CaptchaError;
+use crate::session::SessionStore;
+use crate::util::{HmacSha256, hmac_sha256, unix_now};
+
+const ALGORITHM: &str = "SHA-256";
+const REDEEMED_KEY_PREFIX: &str = "altcha:";
+
+/// Self-hosted proof-of-work captcha following the ALTCHA challenge format.
+/// Solved challenges are remembered in the session store until they expire,
+/// so a payload can only be redeemed once across replicas and restarts.
+pub struct AltchaService {
+    hmac_key: Vec<u8>,
+    max_number: u64,
+    ttl: Duration,
+    sessions: Arc<dyn SessionStore>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AltchaChallenge {
+    pub algorithm: &'static str,
+    pub challenge: String,
+    pub maxnumber: u64,
+    pub salt: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AltchaSolution {
+    algorithm: String,
+    challenge: String,
+    number: u64,
+    salt: String,
+    signature: String,
+}
+
+impl AltchaService {
+    pub fn from_config(config: &AppConfig, sessions: Arc<dyn SessionStore>) -> Self {
+        let hmac_key = match config.altcha_hmac_key.as_deref().map(str::trim) {
+            Some(key) if !key.is_empty() => key.as_bytes().to_vec(),
+            _ => {
+                warn!(
+                    "[Captcha] ALTCHA_HMAC_KEY not set; using an ephemeral key (challenges will not survive restarts or work across replicas)"
+                );
+                let mut key = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                key
+            }
+        };
+
+        Self {
+            hmac_key,
+            max_number: config.altcha_max_number.max(1),
+            ttl: config.altcha_challenge_ttl,
+            sessions,
+        }
+    }
+
+    pub fn create_challenge(&self) -> AltchaChallenge {
+        let mut rng = rand::thread_rng();
+        let mut salt_bytes = [0u8; 12];
+        rng.fill_bytes(&mut salt_bytes);
+
+        let expires = unix_now() + self.ttl.as_secs();
+        let salt = format!("{}?expires={}", hex::encode(salt_bytes), expires);
+        let number = rng.gen_range(0..=self.max_number);
+        let challenge = sha256_hex(&format!("{salt}{number}"));
+        let signature = hex::encode(self.sign(&challenge));
+
+        AltchaChallenge {
+            algorithm: ALGORITHM,
+            challenge,
+            maxnumber: self.max_number,
+            salt,
+            signature,
+        }
+    }
+
+    pub async fn verify(&self, payload: &str) -> Result<(), CaptchaError> {
+        let decoded = STANDARD
+            .decode(payload.trim())
+            .map_err(|_| CaptchaError::Rejected)?;
+        let solution: AltchaSolution =
+            serde_json::from_slice(&decoded).map_err(|_| CaptchaError::Rejected)?;
+
+        if solution.algorithm != ALGORITHM || solution.number > self.max_number {
+            return Err(CaptchaError::Rejected);
+        }
+
+        let expires = salt_expiry(&solution.salt).ok_or(CaptchaError::Rejected)?;
+        let now = unix_now();
+        if expires <= now {
+            debug!("[Captcha] ALTCHA challenge expired");
+            return Err(CaptchaError::Rejected);
+        }
+
+        let expected = sha256_hex(&format!("{}{}", solution.salt, solution.number));
+        if expected != solution.challenge {
+            return Err(CaptchaError::Rejected);
+        }
+
+        let signature = hex::decode(&solution.signature).map_err(|_| CaptchaError::Rejected)?;
+        let mut mac = self.mac();
+        mac.update(solution.challenge.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| CaptchaError::Rejected)?;
+
+        let fresh = self
+            .sessions
+            .set_if_absent(
+                &format!("{REDEEMED_KEY_PREFIX}{}", solution.challenge),
+                "1",
+                Duration::from_secs(expires - now),
+            )
+            .await
+            .map_err(|err| {
+                warn!("[Captcha] ALTCHA replay check unavailable: {}", err);
+                CaptchaError::RequestFailed
+            })?;
+        if !fresh {
+            warn!("[Captcha] ALTCHA solution replayed");
+            return Err(CaptchaError::Rejected);
+        }
+
+        Ok(())
+    }
+
+    fn sign(&self, challenge: &str) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(challenge.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        hmac_sha256(&self.hmac_key)
+    }
+}
+
+fn salt_expiry(salt: &str) -> Option<u64> {
+    let (_, params) = salt.split_once('?')?;
+    params
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "expires")
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+fn sha256_hex(input: &str) -> String {
+    hex::encode(Sha256::digest(input.as_bytes()))
+}
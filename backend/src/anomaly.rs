@@ -0,0 +1,299 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{error, warn};
+
+use crate::AppConfig;
+use crate::metrics::Metrics;
+use crate::util::unix_now;
+
+/// Subjects counted at most; past it, those idle longest are dropped.
+const MAX_TRACKED_KEYS: usize = 50_000;
+/// Alerts kept at most; acknowledged ones go first, then the oldest.
+const MAX_RETAINED_ALERTS: usize = 1_000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    FailedLogin,
+    CaptchaRejection,
+    Registration,
+}
+
+impl AnomalyKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::FailedLogin => "failed_login",
+            Self::CaptchaRejection => "captcha_rejection",
+            Self::Registration => "registration",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub id: u64,
+    pub kind: AnomalyKind,
+    /// `ip` for a single address, `network` for its /24 (IPv4) or /48 (IPv6).
+    pub scope: &'static str,
+    pub subject: String,
+    pub count: usize,
+    pub window_secs: u64,
+    pub raised_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acknowledged_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acknowledged_at: Option<u64>,
+}
+
+impl Alert {
+    pub fn is_active(&self) -> bool {
+        self.acknowledged_at.is_none()
+    }
+}
+
+/// Counts abuse signals per client address and network over a rolling window
+/// and raises an alert once a per-kind threshold is reached. An alert stays
+/// active, suppressing duplicates for the same subject, until acknowledged.
+pub struct AnomalyDetector {
+    window: Duration,
+    failed_login_threshold: AtomicUsize,
+    captcha_rejection_threshold: AtomicUsize,
+    registration_threshold: AtomicUsize,
+    webhook_url: Option<String>,
+    inner: Mutex<DetectorState>,
+}
+
+#[derive(Default)]
+struct DetectorState {
+    next_id: u64,
+    windows: HashMap<(AnomalyKind, &'static str, String), VecDeque<Instant>>,
+    alerts: VecDeque<Alert>,
+}
+
+impl AnomalyDetector {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            window: config.anomaly_window,
+            failed_login_threshold: AtomicUsize::new(config.anomaly_failed_login_threshold),
+            captcha_rejection_threshold: AtomicUsize::new(
+                config.anomaly_captcha_rejection_threshold,
+            ),
+            registration_threshold: AtomicUsize::new(config.anomaly_registration_threshold),
+            webhook_url: config.alert_webhook_url.clone(),
+            inner: Mutex::new(DetectorState {
+                next_id: 1,
+                ..DetectorState::default()
+            }),
+        }
+    }
+
+    fn threshold(&self, kind: AnomalyKind) -> usize {
+        match kind {
+            AnomalyKind::FailedLogin => &self.failed_login_threshold,
+            AnomalyKind::CaptchaRejection => &self.captcha_rejection_threshold,
+            AnomalyKind::Registration => &self.registration_threshold,
+        }
+        .load(Ordering::Relaxed)
+    }
+
+    /// Takes reloaded thresholds; hits already counted stay.
+    pub fn set_thresholds(&self, config: &AppConfig) {
+        for (threshold, value) in [
+            (
+                &self.failed_login_threshold,
+                config.anomaly_failed_login_threshold,
+            ),
+            (
+                &self.captcha_rejection_threshold,
+                config.anomaly_captcha_rejection_threshold,
+            ),
+            (
+                &self.registration_threshold,
+                config.anomaly_registration_threshold,
+            ),
+        ] {
+            threshold.store(value, Ordering::Relaxed);
+        }
+    }
+
+    pub fn observe(&self, kind: AnomalyKind, ip: IpAddr, metrics: &Metrics, client: &Client) {
+        let threshold = self.threshold(kind);
+        if threshold == 0 || self.window.is_zero() {
+            return;
+        }
+
+        let subjects = [("ip", ip.to_string()), ("network", network_of(ip))];
+        let now = Instant::now();
+        let mut raised = Vec::new();
+
+        {
+            let mut state = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+            if state.windows.len() > MAX_TRACKED_KEYS {
+                let window = self.window;
+                state.windows.retain(|_, hits| {
+                    hits.back()
+                        .is_some_and(|last| now.duration_since(*last) < window)
+                });
+                evict_idlest(&mut state.windows);
+            }
+
+            for (scope, subject) in subjects {
+                let hits = state
+                    .windows
+                    .entry((kind, scope, subject.clone()))
+                    .or_default();
+                hits.push_back(now);
+                // Hits past the threshold add nothing but memory.
+                while hits.len() > threshold
+                    || hits
+                        .front()
+                        .is_some_and(|first| now.duration_since(*first) >= self.window)
+                {
+                    hits.pop_front();
+                }
+
+                let count = hits.len();
+                if count < threshold {
+                    continue;
+                }
+
+                let already_active = state.alerts.iter().any(|alert| {
+                    alert.is_active()
+                        && alert.kind == kind
+                        && alert.scope == scope
+                        && alert.subject == subject
+                });
+                if already_active {
+                    continue;
+                }
+
+                let alert = Alert {
+                    id: state.next_id,
+                    kind,
+                    scope,
+                    subject,
+                    count,
+                    window_secs: self.window.as_secs(),
+                    raised_at: unix_now(),
+                    acknowledged_by: None,
+                    acknowledged_at: None,
+                };
+                state.next_id += 1;
+                if state.alerts.len() >= MAX_RETAINED_ALERTS {
+                    let index = state
+                        .alerts
+                        .iter()
+                        .position(|alert| !alert.is_active())
+                        .unwrap_or(0);
+                    state.alerts.remove(index);
+                }
+                state.alerts.push_back(alert.clone());
+                raised.push(alert);
+            }
+        }
+
+        for alert in raised {
+            warn!(
+                "[Anomaly] {} {}={} count={} window={}s",
+                alert.kind.as_str(),
+                alert.scope,
+                alert.subject,
+                alert.count,
+                alert.window_secs
+            );
+            metrics
+                .anomaly_alerts
+                .with_label_values(&[alert.kind.as_str(), alert.scope])
+                .inc();
+            if let Some(url) = &self.webhook_url {
+                spawn_webhook(client.clone(), url.clone(), alert);
+            }
+        }
+    }
+
+    /// All retained alerts, newest first; `active_only` drops acknowledged ones.
+    pub fn alerts(&self, active_only: bool) -> Vec<Alert> {
+        let state = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        state
+            .alerts
+            .iter()
+            .rev()
+            .filter(|alert| !active_only || alert.is_active())
+            .cloned()
+            .collect()
+    }
+
+    pub fn acknowledge(&self, id: u64, by: &str) -> Option<Alert> {
+        let mut state = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        let alert = state.alerts.iter_mut().find(|alert| alert.id == id)?;
+        if alert.is_active() {
+            alert.acknowledged_by = Some(by.to_owned());
+            alert.acknowledged_at = Some(unix_now());
+        }
+        Some(alert.clone())
+    }
+}
+
+/// Drops the subjects idle longest until a tenth of `MAX_TRACKED_KEYS` is
+/// free again, for when more than that many are active in one window.
+fn evict_idlest(windows: &mut HashMap<(AnomalyKind, &'static str, String), VecDeque<Instant>>) {
+    let keep = MAX_TRACKED_KEYS - MAX_TRACKED_KEYS / 10;
+    if windows.len() <= keep {
+        return;
+    }
+    let mut last_hits: Vec<Instant> = windows
+        .values()
+        .filter_map(|hits| hits.back().copied())
+        .collect();
+    let cut = last_hits
+        .len()
+        .saturating_sub(keep)
+        .min(last_hits.len() - 1);
+    let (_, &mut cutoff, _) = last_hits.select_nth_unstable(cut);
+    windows.retain(|_, hits| hits.back().is_some_and(|last| *last >= cutoff));
+}
+
+/// Posts the alert as JSON. The `text` field makes the payload usable as a
+/// Slack incoming webhook as-is.
+fn spawn_webhook(client: Client, url: String, alert: Alert) {
+    tokio::spawn(async move {
+        let text = format!(
+            "Argus portal anomaly: {} from {} {} ({} in {}s)",
+            alert.kind.as_str(),
+            alert.scope,
+            alert.subject,
+            alert.count,
+            alert.window_secs
+        );
+        let body = json!({ "text": text, "alert": alert });
+        match client.post(&url).json(&body).send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => {
+                error!(
+                    "[Anomaly] alert webhook returned status={}",
+                    response.status()
+                );
+            }
+            Err(err) => error!(?err, "[Anomaly] alert webhook request failed"),
+        }
+    });
+}
+
+fn network_of(ip: IpAddr) -> String {
+    let prefix = match ip {
+        IpAddr::V4(_) => 24,
+        IpAddr::V6(_) => 48,
+    };
+    IpNet::new(ip, prefix)
+        .map(|net| net.trunc().to_string())
+        .unwrap_or_else(|_| ip.to_string())
+}
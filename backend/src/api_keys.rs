@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use axum::http::HeaderMap;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::AppConfig;
+use crate::session::SessionStore;
+use crate::util::unix_now;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+const REGISTRY_KEY: &str = "api-keys";
+/// Start of every key, so leaked ones are easy to search for.
+const KEY_TAG: &str = "argus";
+const PREFIX_BYTES: usize = 8;
+const SECRET_BYTES: usize = 32;
+/// How long a verified key is trusted without hashing it again.
+const VERIFIED_TTL: Duration = Duration::from_secs(60);
+const VERIFIED_MAX_ENTRIES: usize = 1_000;
+
+/// An issued key. Revoked ones stay listed until they expire so the audit
+/// trail can still be matched to a label.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    /// Non-secret start of the key that it is looked up by.
+    pub prefix: String,
+    pub label: String,
+    pub created_by: String,
+    pub created_at: u64,
+    pub expires_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+}
+
+/// A key as listed to admins.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListedKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    /// Hashed with other parameters than the current ones; it is hashed
+    /// again the next time it is used.
+    pub outdated: bool,
+}
+
+/// The registry entry: the key and the PHC string of its Argon2id hash,
+/// which carries the salt and the parameters it was made with.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    #[serde(flatten)]
+    key: ApiKey,
+    hash: String,
+}
+
+#[derive(Debug)]
+pub enum ApiKeyError {
+    NotFound,
+    Invalid(&'static str),
+    Unavailable,
+}
+
+/// Admin-issued keys for partner backends, sent as `X-Api-Key:
+/// argus_<prefix>_<secret>`. The key itself is shown once; the registry,
+/// in the session store so that every replica sees issues and revocations,
+/// holds only an Argon2id hash of it, found by the non-secret prefix and
+/// checked in constant time. The cost comes from
+/// `API_KEY_ARGON2_MEMORY_KIB`, `API_KEY_ARGON2_ITERATIONS` and
+/// `API_KEY_ARGON2_PARALLELISM`. After a change, a key hashed with the old
+/// parameters is hashed again the next time it is verified, and listed as
+/// outdated until then.
+pub struct ApiKeys {
+    params: Params,
+    max_ttl: Duration,
+    store: Arc<dyn SessionStore>,
+    write_lock: Mutex<()>,
+    /// SHA-256 of recently verified keys, so a busy client is not hashed
+    /// with Argon2 on every request. Revocation is still read from the
+    /// registry each time.
+    verified: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl ApiKeys {
+    pub fn from_config(config: &AppConfig, store: Arc<dyn SessionStore>) -> Self {
+        Self {
+            // `AppConfig::validate` has already refused parameters Argon2
+            // does not accept.
+            params: argon2_params(config).unwrap_or_default(),
+            max_ttl: config.api_key_max_ttl,
+            store,
+            write_lock: Mutex::new(()),
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    /// Returns the key record and the key itself, which is not stored and
+    /// cannot be shown again. `ttl` is capped at `API_KEY_MAX_TTL_SECS`.
+    pub async fn issue(
+        &self,
+        label: &str,
+        ttl: Option<Duration>,
+        created_by: &str,
+    ) -> Result<(ApiKey, String), ApiKeyError> {
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(ApiKeyError::Invalid("label is required"));
+        }
+        let ttl = ttl.unwrap_or(self.max_ttl).min(self.max_ttl);
+        if ttl.is_zero() {
+            return Err(ApiKeyError::Invalid("ttlSecs must be positive"));
+        }
+
+        let mut prefix = [0u8; PREFIX_BYTES];
+        let mut secret = [0u8; SECRET_BYTES];
+        rand::thread_rng().fill_bytes(&mut prefix);
+        rand::thread_rng().fill_bytes(&mut secret);
+        let prefix = hex::encode(prefix);
+        let raw = format!("{KEY_TAG}_{prefix}_{}", URL_SAFE_NO_PAD.encode(secret));
+        let hash = self.hash(raw.clone()).await?;
+
+        let now = unix_now();
+        let key = ApiKey {
+            prefix,
+            label: label.to_owned(),
+            created_by: created_by.to_owned(),
+            created_at: now,
+            expires_at: now + ttl.as_secs(),
+            revoked_at: None,
+        };
+        let _guard = self.write_lock.lock().await;
+        let mut registry = self.load().await?;
+        registry.push(Stored {
+            key: key.clone(),
+            hash,
+        });
+        self.save(&registry).await?;
+        Ok((key, raw))
+    }
+
+    pub async fn list(&self) -> Result<Vec<ListedKey>, ApiKeyError> {
+        Ok(self
+            .load()
+            .await?
+            .into_iter()
+            .map(|stored| ListedKey {
+                outdated: !self.is_current(&stored.hash),
+                key: stored.key,
+            })
+            .collect())
+    }
+
+    pub async fn revoke(&self, prefix: &str) -> Result<ApiKey, ApiKeyError> {
+        let _guard = self.write_lock.lock().await;
+        let mut registry = self.load().await?;
+        let stored = registry
+            .iter_mut()
+            .find(|stored| stored.key.prefix == prefix)
+            .ok_or(ApiKeyError::NotFound)?;
+        stored.key.revoked_at.get_or_insert_with(unix_now);
+        let key = stored.key.clone();
+        self.save(&registry).await?;
+        Ok(key)
+    }
+
+    /// The key `raw` stands for, when it was issued here, has not expired
+    /// and has not been revoked.
+    pub async fn verify(&self, raw: &str) -> Option<ApiKey> {
+        let raw = raw.trim();
+        let prefix = api_key_prefix(raw)?;
+        let stored = self.active(prefix).await?;
+
+        let digest: [u8; 32] = Sha256::digest(raw.as_bytes()).into();
+        if self.was_verified(&digest).await {
+            return Some(stored.key);
+        }
+
+        let hasher = self.hasher();
+        let (candidate, hash) = (raw.to_owned(), stored.hash.clone());
+        let valid = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&hash).is_ok_and(|parsed| {
+                hasher
+                    .verify_password(candidate.as_bytes(), &parsed)
+                    .is_ok()
+            })
+        })
+        .await
+        .unwrap_or(false);
+        if !valid {
+            return None;
+        }
+
+        let mut verified = self.verified.lock().await;
+        if verified.len() >= VERIFIED_MAX_ENTRIES {
+            verified.retain(|_, at| at.elapsed() < VERIFIED_TTL);
+            if verified.len() >= VERIFIED_MAX_ENTRIES {
+                verified.clear();
+            }
+        }
+        verified.insert(digest, Instant::now());
+        drop(verified);
+
+        if !self.is_current(&stored.hash) {
+            self.rehash(prefix, raw.to_owned()).await;
+        }
+        Some(stored.key)
+    }
+
+    /// Like [`Self::verify`], but only answers from the keys verified in
+    /// the last `VERIFIED_TTL`, so it never runs Argon2.
+    pub async fn verify_cached(&self, raw: &str) -> Option<ApiKey> {
+        let raw = raw.trim();
+        let digest: [u8; 32] = Sha256::digest(raw.as_bytes()).into();
+        if !self.was_verified(&digest).await {
+            return None;
+        }
+        self.active(api_key_prefix(raw)?)
+            .await
+            .map(|stored| stored.key)
+    }
+
+    async fn active(&self, prefix: &str) -> Option<Stored> {
+        self.load()
+            .await
+            .ok()?
+            .into_iter()
+            .find(|stored| stored.key.prefix == prefix && stored.key.revoked_at.is_none())
+    }
+
+    async fn was_verified(&self, digest: &[u8; 32]) -> bool {
+        self.verified
+            .lock()
+            .await
+            .get(digest)
+            .is_some_and(|at| at.elapsed() < VERIFIED_TTL)
+    }
+
+    /// Whether `hash` is Argon2id with the configured parameters.
+    fn is_current(&self, hash: &str) -> bool {
+        PasswordHash::new(hash).is_ok_and(|parsed| {
+            parsed.algorithm == Algorithm::Argon2id.ident()
+                && Params::try_from(&parsed).is_ok_and(|params| {
+                    (params.m_cost(), params.t_cost(), params.p_cost())
+                        == (
+                            self.params.m_cost(),
+                            self.params.t_cost(),
+                            self.params.p_cost(),
+                        )
+                })
+        })
+    }
+
+    /// Replaces a hash made with other parameters.
+    async fn rehash(&self, prefix: &str, raw: String) {
+        let Ok(hash) = self.hash(raw).await else {
+            return;
+        };
+        let _guard = self.write_lock.lock().await;
+        let Ok(mut registry) = self.load().await else {
+            return;
+        };
+        let Some(stored) = registry
+            .iter_mut()
+            .find(|stored| stored.key.prefix == prefix)
+        else {
+            return;
+        };
+        stored.hash = hash;
+        if self.save(&registry).await.is_ok() {
+            info!(
+                "[ApiKeys] rehashed key {} with the current parameters",
+                prefix
+            );
+        }
+    }
+
+    async fn hash(&self, raw: String) -> Result<String, ApiKeyError> {
+        let hasher = self.hasher();
+        tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            hasher
+                .hash_password(raw.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+        })
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .ok_or(ApiKeyError::Unavailable)
+    }
+
+    async fn load(&self) -> Result<Vec<Stored>, ApiKeyError> {
+        let stored = self.store.get(REGISTRY_KEY).await.map_err(|err| {
+            warn!("[ApiKeys] unable to load keys: {}", err);
+            ApiKeyError::Unavailable
+        })?;
+        let now = unix_now();
+        let mut registry: Vec<Stored> = stored
+            .and_then(|value| serde_json::from_str(&value).ok())
+            .unwrap_or_default();
+        registry.retain(|stored| stored.key.expires_at >= now);
+        Ok(registry)
+    }
+
+    async fn save(&self, registry: &[Stored]) -> Result<(), ApiKeyError> {
+        let encoded = serde_json::to_string(registry).unwrap_or_default();
+        self.store
+            .set(REGISTRY_KEY, &encoded, self.max_ttl)
+            .await
+            .map_err(|err| {
+                warn!("[ApiKeys] unable to store keys: {}", err);
+                ApiKeyError::Unavailable
+            })
+    }
+}
+
+pub fn argon2_params(config: &AppConfig) -> Result<Params, argon2::Error> {
+    Params::new(
+        config.api_key_argon2_memory_kib,
+        config.api_key_argon2_iterations,
+        config.api_key_argon2_parallelism,
+        None,
+    )
+}
+
+/// The non-secret prefix of a key in the `argus_<prefix>_<secret>` form.
+pub fn api_key_prefix(raw: &str) -> Option<&str> {
+    raw.trim()
+        .strip_prefix(KEY_TAG)?
+        .strip_prefix('_')?
+        .split_once('_')
+        .map(|(prefix, _)| prefix)
+}
+
+/// The `X-Api-Key` header, if the request carries one.
+pub fn api_key_header(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
@@ -0,0 +1,135 @@
+use axum::{
+    Json,
+    extract::Request,
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{ACCEPT, CONTENT_TYPE, VARY},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::info;
+
+use crate::error_codes::ErrorCode;
+use crate::models::user::ErrorResponse;
+
+const VENDOR_PREFIX: &str = "application/vnd.argus.v";
+const VENDOR_SUFFIX: &str = "+json";
+
+/// Response shape a client asked for with
+/// `Accept: application/vnd.argus.v<N>+json`. Plain `application/json`, a
+/// wildcard or no `Accept` at all get v1, so existing clients never see a
+/// new shape they did not ask for.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ApiVersion {
+    V1,
+    /// Token responses carry the access token's `claims`.
+    V2,
+}
+
+impl ApiVersion {
+    fn from_number(number: &str) -> Option<Self> {
+        match number {
+            "1" => Some(Self::V1),
+            "2" => Some(Self::V2),
+            _ => None,
+        }
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::V1 => "application/vnd.argus.v1+json",
+            Self::V2 => "application/vnd.argus.v2+json",
+        }
+    }
+
+    /// The version to answer `headers` with; v1 unless a newer one is asked
+    /// for.
+    pub fn of(headers: &HeaderMap) -> Self {
+        negotiate(headers).unwrap_or(Self::V1)
+    }
+}
+
+/// The highest supported vendor version in `Accept`, v1 when none is named,
+/// or `None` when the only acceptable ranges are vendor versions this server
+/// does not know.
+fn negotiate(headers: &HeaderMap) -> Option<ApiVersion> {
+    let mut requested = None;
+    let mut unknown = false;
+    let mut other = false;
+    for value in headers.get_all(ACCEPT) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for range in value.split(',') {
+            let mut params = range.split(';');
+            let media = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let refused = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .any(|q| q.trim().parse::<f32>().is_ok_and(|q| q <= 0.0));
+            if media.is_empty() || refused {
+                continue;
+            }
+            match media
+                .strip_prefix(VENDOR_PREFIX)
+                .and_then(|rest| rest.strip_suffix(VENDOR_SUFFIX))
+            {
+                Some(number) => match ApiVersion::from_number(number) {
+                    Some(version) => requested = requested.max(Some(version)),
+                    None => unknown = true,
+                },
+                None => other = true,
+            }
+        }
+    }
+    match requested {
+        Some(version) => Some(version),
+        None if unknown && !other => None,
+        None => Some(ApiVersion::V1),
+    }
+}
+
+/// Answers 406 when `Accept` names only `application/vnd.argus.v<N>+json`
+/// versions that do not exist, rather than silently sending v1 to a client
+/// that cannot read it.
+pub async fn enforce(request: Request, next: Next) -> Response {
+    if negotiate(request.headers()).is_some() {
+        return next.run(request).await;
+    }
+
+    let accept = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    info!(
+        "[ApiVersion] unsupported accept={} path={}",
+        accept,
+        request.uri().path()
+    );
+    (
+        StatusCode::NOT_ACCEPTABLE,
+        Json(ErrorResponse::new(
+            ErrorCode::NotAcceptable,
+            format!(
+                "Supported response versions are {} and {}",
+                ApiVersion::V1.media_type(),
+                ApiVersion::V2.media_type()
+            ),
+        )),
+    )
+        .into_response()
+}
+
+/// Labels a versioned answer: the vendor content type for v2 and up, and
+/// `Vary: Accept` on all of them so caches keep the shapes apart.
+pub fn apply(version: ApiVersion, headers: &mut HeaderMap) {
+    headers.insert(VARY, HeaderValue::from_static("accept"));
+    if version > ApiVersion::V1 {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(version.media_type()));
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+/// Keycloak attributes from the extra fields of a registration: strings
+/// trimmed, booleans and numbers as text, arrays of those flattened, and
+/// anything else, or anything left empty, dropped.
+pub fn extract(extra: &HashMap<String, Value>) -> HashMap<String, Vec<String>> {
+    extra
+        .iter()
+        .filter_map(|(key, value)| {
+            let values = match value {
+                Value::String(text) => vec![text.trim().to_owned()],
+                Value::Bool(flag) => vec![flag.to_string()],
+                Value::Number(num) => vec![num.to_string()],
+                Value::Array(items) => items
+                    .iter()
+                    .filter_map(|item| match item {
+                        Value::String(text) => Some(text.trim().to_owned()),
+                        Value::Bool(flag) => Some(flag.to_string()),
+                        Value::Number(num) => Some(num.to_string()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+                _ => Vec::new(),
+            };
+            let values = values
+                .into_iter()
+                .filter(|text| !text.is_empty())
+                .collect::<Vec<_>>();
+
+            if values.is_empty() {
+                None
+            } else {
+                Some((key.clone(), values))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Any JSON value, nested a few levels deep.
+    fn json_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<f64>().prop_map(Value::from),
+            ".*".prop_map(Value::from),
+        ];
+        leaf.prop_recursive(3, 32, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+                prop::collection::hash_map(".*", inner, 0..8)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    /// The extra fields of a `RegisterRequest`.
+    fn register_extras() -> impl Strategy<Value = HashMap<String, Value>> {
+        prop::collection::hash_map(".*", json_value(), 0..16)
+    }
+
+    proptest! {
+        #[test]
+        fn extracts_only_trimmed_non_empty_values(extra in register_extras()) {
+            let attributes = extract(&extra);
+            for (key, values) in &attributes {
+                prop_assert!(extra.contains_key(key));
+                prop_assert!(!values.is_empty());
+                for value in values {
+                    prop_assert!(!value.is_empty());
+                    prop_assert_eq!(value.trim(), value.as_str());
+                }
+            }
+        }
+    }
+}
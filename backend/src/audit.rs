@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::journal::Journal;
+use crate::util::unix_now;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub id: u64,
+    pub timestamp: u64,
+    pub actor: String,
+    pub action: String,
+    pub outcome: AuditOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AuditFilter<'a> {
+    pub actor: Option<&'a str>,
+    pub action: Option<&'a str>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+/// Append-only record of security-relevant actions. The most recent
+/// `AUDIT_LOG_CAPACITY` entries are kept in memory for querying; when
+/// `AUDIT_LOG_FILE` is set every entry is also appended there as a JSON line,
+/// by a background task so recording never waits on the disk, and the tail
+/// is reloaded on startup.
+pub struct AuditLog {
+    capacity: usize,
+    journal: Option<Journal>,
+    inner: Mutex<AuditInner>,
+}
+
+struct AuditInner {
+    next_id: u64,
+    entries: VecDeque<AuditEvent>,
+}
+
+impl AuditLog {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let capacity = config.audit_log_capacity.max(1);
+        let mut entries = VecDeque::with_capacity(capacity.min(4096));
+        let mut journal = None;
+
+        if let Some(path) = config.audit_log_file.as_deref() {
+            if let Ok(file) = File::open(path) {
+                for line in BufReader::new(file).lines().map_while(Result::ok) {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str::<AuditEvent>(&line) {
+                        Ok(event) => {
+                            if entries.len() == capacity {
+                                entries.pop_front();
+                            }
+                            entries.push_back(event);
+                        }
+                        Err(err) => warn!(%path, ?err, "Skipping unreadable audit log line"),
+                    }
+                }
+            }
+
+            match Journal::open(path, "Audit") {
+                Ok(opened) => journal = Some(opened),
+                Err(err) => {
+                    warn!(%path, %err, "Unable to open audit log file; keeping audit log in memory only")
+                }
+            }
+        }
+
+        let next_id = entries.back().map(|event| event.id + 1).unwrap_or(1);
+
+        Self {
+            capacity,
+            journal,
+            inner: Mutex::new(AuditInner { next_id, entries }),
+        }
+    }
+
+    pub fn record(
+        &self,
+        actor: &str,
+        action: &str,
+        outcome: AuditOutcome,
+        target: Option<&str>,
+        ip: Option<IpAddr>,
+    ) {
+        let timestamp = unix_now();
+
+        let mut inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        let event = AuditEvent {
+            id: inner.next_id,
+            timestamp,
+            actor: actor.to_owned(),
+            action: action.to_owned(),
+            outcome,
+            target: target.map(str::to_owned),
+            ip: ip.map(|ip| ip.to_string()),
+        };
+        inner.next_id += 1;
+
+        if let Some(journal) = &self.journal
+            && let Ok(line) = serde_json::to_string(&event)
+        {
+            journal.append(line);
+        }
+
+        if inner.entries.len() == self.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(event);
+    }
+
+    /// Matching entries, newest first.
+    pub fn query(&self, filter: &AuditFilter<'_>) -> Vec<AuditEvent> {
+        let inner = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        inner
+            .entries
+            .iter()
+            .rev()
+            .filter(|event| filter.matches(event))
+            .cloned()
+            .collect()
+    }
+
+    /// Waits until every entry so far is in `AUDIT_LOG_FILE`.
+    pub async fn flush(&self) {
+        if let Some(journal) = &self.journal {
+            journal.flush().await;
+        }
+    }
+}
+
+impl AuditFilter<'_> {
+    fn matches(&self, event: &AuditEvent) -> bool {
+        self.actor
+            .is_none_or(|actor| event.actor.eq_ignore_ascii_case(actor))
+            && self
+                .action
+                .is_none_or(|action| match action.strip_suffix('*') {
+                    Some(prefix) => event.action.starts_with(prefix),
+                    None => event.action == action,
+                })
+            && self.from.is_none_or(|from| event.timestamp >= from)
+            && self.to.is_none_or(|to| event.timestamp <= to)
+    }
+}
+
+pub fn to_csv(events: &[AuditEvent]) -> String {
+    let mut out = String::from("id,timestamp,actor,action,outcome,target,ip\n");
+    for event in events {
+        let row = [
+            event.id.to_string(),
+            event.timestamp.to_string(),
+            csv_field(&event.actor),
+            csv_field(&event.action),
+            event.outcome.as_str().to_owned(),
+            csv_field(event.target.as_deref().unwrap_or_default()),
+            csv_field(event.ip.as_deref().unwrap_or_default()),
+        ];
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    // Leading formula characters are neutralised so exports are safe to open
+    // in spreadsheet tools.
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_owned()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
@@ -0,0 +1,428 @@
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{ImageReader, Limits};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// The avatar's URL, kept on the Keycloak account so other applications in
+/// the realm can show it too.
+pub const AVATAR_ATTRIBUTE: &str = "avatarUrl";
+
+/// Bytes of the content hash that version an avatar, hex encoded.
+const VERSION_LEN: usize = 8;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl ImageFormat {
+    /// The format named by a `Content-Type`, parameters ignored.
+    pub fn from_content_type(value: &str) -> Option<Self> {
+        match value
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "image/jpeg" | "image/jpg" => Some(Self::Jpeg),
+            "image/png" => Some(Self::Png),
+            "image/webp" => Some(Self::Webp),
+            _ => None,
+        }
+    }
+
+    /// The format the bytes actually are, going by their signature.
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(PNG_SIGNATURE) {
+            Some(Self::Png)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(Self::Webp)
+        } else {
+            None
+        }
+    }
+
+    fn codec(self) -> image::ImageFormat {
+        match self {
+            Self::Jpeg => image::ImageFormat::Jpeg,
+            Self::Png => image::ImageFormat::Png,
+            Self::Webp => image::ImageFormat::WebP,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+/// Why an upload is not accepted as an image.
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error("the image is damaged or truncated")]
+    Malformed,
+    #[error("the file carries data after the image")]
+    TrailingData,
+    #[error("the image could not be re-encoded: {0}")]
+    Encoding(String),
+}
+
+/// An upload checked and rewritten by [`sanitize`].
+pub struct Image {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// 1 unless animated.
+    pub frames: u32,
+}
+
+impl Image {
+    /// What a decoder would have to allocate for, over all frames.
+    pub fn pixels(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height) * u64::from(self.frames)
+    }
+}
+
+/// Checks that the file is put together like an image of `format` all the
+/// way through (PNG chunk checksums included) and that nothing follows its
+/// end, which rules out HTML, scripts or archives glued onto an image
+/// header. It is then rewritten with only what is needed to display it:
+/// EXIF (with its GPS position and camera serial), XMP, comments and text
+/// chunks are dropped. Dimensions come from the headers; nothing is
+/// decoded, so the pixel limit can be checked before [`reencode`] runs.
+pub fn sanitize(format: ImageFormat, bytes: &[u8]) -> Result<Image, ImageError> {
+    let image = match format {
+        ImageFormat::Jpeg => sanitize_jpeg(bytes),
+        ImageFormat::Png => sanitize_png(bytes),
+        ImageFormat::Webp => sanitize_webp(bytes),
+    }?;
+    if image.width == 0 || image.height == 0 || image.frames == 0 {
+        return Err(ImageError::Malformed);
+    }
+    Ok(image)
+}
+
+/// Decodes the image and encodes it again in the same format, scaled down
+/// to fit `max_side` pixels each way if it is larger, so what is stored
+/// is pixels written by this server and nothing else from the upload.
+/// Animated images keep their first frame. The decoder may allocate no
+/// more than `max_pixels` RGBA pixels need. This is CPU-bound; run it off
+/// the async workers.
+pub fn reencode(
+    format: ImageFormat,
+    image: &Image,
+    max_side: u32,
+    max_pixels: u64,
+) -> Result<Vec<u8>, ImageError> {
+    let mut reader = ImageReader::with_format(Cursor::new(&image.bytes), format.codec());
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(max_pixels.saturating_mul(4));
+    reader.limits(limits);
+    let mut decoded = reader.decode().map_err(|_| ImageError::Malformed)?;
+    if decoded.width() > max_side || decoded.height() > max_side {
+        decoded = decoded.resize(max_side, max_side, FilterType::Lanczos3);
+    }
+
+    let mut out = Vec::new();
+    let written = match format {
+        ImageFormat::Jpeg => decoded
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)),
+        ImageFormat::Png => decoded.write_with_encoder(PngEncoder::new(&mut out)),
+        ImageFormat::Webp => decoded
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut out)),
+    };
+    written.map_err(|err| ImageError::Encoding(err.to_string()))?;
+    Ok(out)
+}
+
+/// Identifies one upload: changes whenever the image does, which lets the
+/// avatar URL be cached for good.
+pub fn version(bytes: &[u8]) -> String {
+    hex::encode(&Sha256::digest(bytes)[..VERSION_LEN])
+}
+
+pub fn is_version(value: &str) -> bool {
+    value.len() == VERSION_LEN * 2 && value.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+pub fn object_key(user_id: &str, version: &str) -> String {
+    format!("avatars/{user_id}/{version}")
+}
+
+/// `GET /api/users/{id}/avatar?v=<version>`, absolute when
+/// `AVATAR_PUBLIC_URL` is set.
+pub fn url(public_url: Option<&str>, user_id: &str, version: &str) -> String {
+    format!(
+        "{}/api/users/{user_id}/avatar?v={version}",
+        public_url.unwrap_or_default().trim_end_matches('/')
+    )
+}
+
+/// Keeps JFIF (APP0), ICC profiles (APP2) and Adobe colour transforms
+/// (APP14) besides the codec segments; every other APPn and COM goes.
+fn sanitize_jpeg(bytes: &[u8]) -> Result<Image, ImageError> {
+    let mut out = vec![0xFF, 0xD8];
+    let mut size = None;
+    let mut scanned = false;
+    let mut pos = 2;
+    loop {
+        if byte(bytes, pos)? != 0xFF {
+            return Err(ImageError::Malformed);
+        }
+        while byte(bytes, pos)? == 0xFF {
+            pos += 1;
+        }
+        let marker = bytes[pos];
+        pos += 1;
+        match marker {
+            0xD9 => {
+                let (width, height) = size.filter(|_| scanned).ok_or(ImageError::Malformed)?;
+                if pos != bytes.len() {
+                    return Err(ImageError::TrailingData);
+                }
+                out.extend_from_slice(&[0xFF, 0xD9]);
+                return Ok(Image {
+                    bytes: out,
+                    width,
+                    height,
+                    frames: 1,
+                });
+            }
+            0x01 | 0xD0..=0xD7 => out.extend_from_slice(&[0xFF, marker]),
+            _ => {
+                let length = usize::from(u16::from_be_bytes([
+                    byte(bytes, pos)?,
+                    byte(bytes, pos + 1)?,
+                ]));
+                let segment = bytes
+                    .get(pos..pos + length)
+                    .filter(|_| length >= 2)
+                    .ok_or(ImageError::Malformed)?;
+                let keep = match marker {
+                    0xE0 | 0xEE => true,
+                    0xE2 => segment[2..].starts_with(b"ICC_PROFILE\0"),
+                    0xE1 | 0xE3..=0xEF | 0xFE => false,
+                    _ => true,
+                };
+                if keep {
+                    out.extend_from_slice(&[0xFF, marker]);
+                    out.extend_from_slice(segment);
+                }
+                pos += length;
+
+                // Start of frame, less DHT (C4), JPG (C8) and DAC (CC).
+                if matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+                    if segment.len() < 7 || size.is_some() {
+                        return Err(ImageError::Malformed);
+                    }
+                    let height = u16::from_be_bytes([segment[3], segment[4]]);
+                    let width = u16::from_be_bytes([segment[5], segment[6]]);
+                    size = Some((u32::from(width), u32::from(height)));
+                }
+
+                // Scan data runs to the next marker; 0xFF is escaped as
+                // 0xFF00 inside it and restart markers belong to it.
+                if marker == 0xDA {
+                    if size.is_none() {
+                        return Err(ImageError::Malformed);
+                    }
+                    scanned = true;
+                    let start = pos;
+                    while byte(bytes, pos)? != 0xFF
+                        || matches!(byte(bytes, pos + 1)?, 0x00 | 0xD0..=0xD7)
+                    {
+                        pos += 1;
+                    }
+                    out.extend_from_slice(&bytes[start..pos]);
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the critical chunks, animation and the ancillary chunks that
+/// affect how pixels look; text, `eXIf`, `tIME` and unknown chunks go.
+fn sanitize_png(bytes: &[u8]) -> Result<Image, ImageError> {
+    const KEPT: &[&[u8; 4]] = &[
+        b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"pHYs", b"bKGD", b"acTL", b"fcTL",
+        b"fdAT",
+    ];
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    let mut size = None;
+    let mut frames = 1;
+    let mut has_data = false;
+    let mut pos = PNG_SIGNATURE.len();
+    loop {
+        let length = u32_at(bytes, pos, u32::from_be_bytes)? as usize;
+        let chunk = pos
+            .checked_add(length)
+            .and_then(|end| end.checked_add(12))
+            .and_then(|end| bytes.get(pos..end))
+            .ok_or(ImageError::Malformed)?;
+        let kind = &chunk[4..8];
+        let data = &chunk[8..8 + length];
+        if crc32(&chunk[4..8 + length]).to_be_bytes() != chunk[8 + length..] {
+            return Err(ImageError::Malformed);
+        }
+
+        match kind {
+            b"IHDR" if size.is_none() && length == 13 => {
+                size = Some((
+                    u32_at(data, 0, u32::from_be_bytes)?,
+                    u32_at(data, 4, u32::from_be_bytes)?,
+                ));
+            }
+            _ if size.is_none() => return Err(ImageError::Malformed),
+            b"IHDR" => return Err(ImageError::Malformed),
+            b"IDAT" => has_data = true,
+            b"acTL" => frames = u32_at(data, 0, u32::from_be_bytes)?,
+            _ => {}
+        }
+        let critical = kind[0].is_ascii_uppercase();
+        if critical || KEPT.iter().any(|kept| kept.as_slice() == kind) {
+            out.extend_from_slice(chunk);
+        }
+        pos += chunk.len();
+
+        if kind == b"IEND" {
+            let (width, height) = size.filter(|_| has_data).ok_or(ImageError::Malformed)?;
+            if pos != bytes.len() {
+                return Err(ImageError::TrailingData);
+            }
+            return Ok(Image {
+                bytes: out,
+                width,
+                height,
+                frames,
+            });
+        }
+    }
+}
+
+/// Drops the `EXIF` and `XMP ` chunks and clears their flags in `VP8X`.
+fn sanitize_webp(bytes: &[u8]) -> Result<Image, ImageError> {
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+
+    let declared = u32_at(bytes, 4, u32::from_le_bytes)? as usize;
+    let end = declared.checked_add(8).ok_or(ImageError::Malformed)?;
+    let body = bytes.get(12..end).ok_or(ImageError::Malformed)?;
+    if end != bytes.len() {
+        return Err(ImageError::TrailingData);
+    }
+
+    let mut chunks = Vec::new();
+    let mut size = None;
+    let mut extended = false;
+    let mut frames = 0;
+    let mut pos = 0;
+    while pos < body.len() {
+        let length = u32_at(body, pos + 4, u32::from_le_bytes)? as usize;
+        let chunk = length
+            .checked_add(length % 2)
+            .and_then(|padded| padded.checked_add(pos + 8))
+            .and_then(|end| body.get(pos..end))
+            .ok_or(ImageError::Malformed)?;
+        let kind = &chunk[..4];
+        let data = &chunk[8..8 + length];
+
+        // The first chunk says what kind of WebP this is and how big.
+        if pos == 0 {
+            size = Some(match kind {
+                b"VP8X" if length >= 10 => {
+                    extended = true;
+                    (u24_le(&data[4..7]) + 1, u24_le(&data[7..10]) + 1)
+                }
+                b"VP8 " if length >= 10 && data[3..6] == [0x9D, 0x01, 0x2A] => (
+                    u32::from(u16::from_le_bytes([data[6], data[7]]) & 0x3FFF),
+                    u32::from(u16::from_le_bytes([data[8], data[9]]) & 0x3FFF),
+                ),
+                b"VP8L" if length >= 5 && data[0] == 0x2F => {
+                    let bits = u32_at(data, 1, u32::from_le_bytes)?;
+                    ((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1)
+                }
+                _ => return Err(ImageError::Malformed),
+            });
+        }
+        match kind {
+            b"VP8 " | b"VP8L" | b"ANMF" => frames += 1,
+            _ => {}
+        }
+
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let mut chunk = chunk.to_vec();
+                chunk[8] &= !(EXIF_FLAG | XMP_FLAG);
+                chunks.extend_from_slice(&chunk);
+            }
+            _ => chunks.extend_from_slice(chunk),
+        }
+        pos += chunk.len();
+    }
+    // A simple file is its one bitstream; an extended one needs at least one.
+    if !extended && frames != 1 {
+        return Err(ImageError::Malformed);
+    }
+    let (width, height) = size.ok_or(ImageError::Malformed)?;
+
+    let mut out = b"RIFF".to_vec();
+    let riff_size = u32::try_from(chunks.len() + 4).map_err(|_| ImageError::Malformed)?;
+    out.extend_from_slice(&riff_size.to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&chunks);
+    Ok(Image {
+        bytes: out,
+        width,
+        height,
+        frames,
+    })
+}
+
+fn byte(bytes: &[u8], pos: usize) -> Result<u8, ImageError> {
+    bytes.get(pos).copied().ok_or(ImageError::Malformed)
+}
+
+fn u32_at(bytes: &[u8], pos: usize, read: fn([u8; 4]) -> u32) -> Result<u32, ImageError> {
+    bytes
+        .get(pos..pos + 4)
+        .and_then(|slice| slice.try_into().ok())
+        .map(read)
+        .ok_or(ImageError::Malformed)
+}
+
+fn u24_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
+
+/// The CRC-32 of PNG chunks (ISO 3309, reflected `0xEDB88320`).
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
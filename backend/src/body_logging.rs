@@ -0,0 +1,261 @@
+use std::sync::RwLock;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, header::CONTENT_TYPE},
+    middleware::Next,
+    response::Response,
+};
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+
+use crate::{AppConfig, AppState};
+
+/// Most of a body that is held in memory to be logged, matching axum's
+/// default limit for extracted bodies. Larger bodies stream through and are
+/// logged by size.
+const BUFFER_LIMIT: usize = 2 * 1024 * 1024;
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BodyLogSettings {
+    pub enabled: bool,
+    /// Fraction of matching requests that are logged, from 0 to 1.
+    pub sample_rate: f64,
+    /// Path prefixes to log; every route when empty.
+    pub routes: Vec<String>,
+    /// Longest body excerpt written per request or response.
+    pub max_bytes: usize,
+}
+
+/// Fields of a runtime update; absent fields keep their value.
+#[derive(Debug, Default)]
+pub struct BodyLogChanges {
+    pub enabled: Option<bool>,
+    pub sample_rate: Option<f64>,
+    pub routes: Option<Vec<String>>,
+    pub max_bytes: Option<usize>,
+}
+
+impl BodyLogSettings {
+    fn samples(&self, path: &str) -> bool {
+        self.enabled
+            && (self.routes.is_empty() || self.routes.iter().any(|route| path.starts_with(route)))
+            && rand::random::<f64>() < self.sample_rate
+    }
+}
+
+/// Debug logging of request and response bodies, off unless
+/// `BODY_LOG_ENABLED` is set or an admin turns it on through
+/// `/api/admin/logging`. Password, token, secret and similar fields of JSON
+/// and form bodies are redacted before anything is written; other bodies are
+/// only logged by size.
+pub struct BodyLogger {
+    settings: RwLock<BodyLogSettings>,
+}
+
+impl BodyLogger {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            settings: RwLock::new(BodyLogSettings {
+                enabled: config.body_log_enabled,
+                sample_rate: config.body_log_sample_rate.clamp(0.0, 1.0),
+                routes: config.body_log_routes.clone(),
+                max_bytes: config.body_log_max_bytes,
+            }),
+        }
+    }
+
+    pub fn settings(&self) -> BodyLogSettings {
+        self.settings
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    pub fn update(&self, changes: BodyLogChanges) -> Result<BodyLogSettings, &'static str> {
+        if changes
+            .sample_rate
+            .is_some_and(|rate| !(0.0..=1.0).contains(&rate))
+        {
+            return Err("sampleRate must be between 0 and 1");
+        }
+
+        let mut settings = self.settings.write().unwrap_or_else(|err| err.into_inner());
+        if let Some(enabled) = changes.enabled {
+            settings.enabled = enabled;
+        }
+        if let Some(rate) = changes.sample_rate {
+            settings.sample_rate = rate;
+        }
+        if let Some(routes) = changes.routes {
+            settings.routes = routes
+                .into_iter()
+                .map(|route| route.trim().to_owned())
+                .filter(|route| !route.is_empty())
+                .collect();
+        }
+        if let Some(max_bytes) = changes.max_bytes {
+            settings.max_bytes = max_bytes;
+        }
+        Ok(settings.clone())
+    }
+}
+
+pub async fn capture(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let settings = state.body_logging.settings();
+    if !settings.samples(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let (parts, body) = request.into_parts();
+    let (excerpt, body) = peek(body).await;
+    info!(
+        "[BodyLog] {} {} request body={}",
+        method,
+        path,
+        describe(&parts.headers, &excerpt, settings.max_bytes)
+    );
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let (parts, body) = response.into_parts();
+    let (excerpt, body) = peek(body).await;
+    info!(
+        "[BodyLog] {} {} response status={} body={}",
+        method,
+        path,
+        parts.status.as_u16(),
+        describe(&parts.headers, &excerpt, settings.max_bytes)
+    );
+    Response::from_parts(parts, body)
+}
+
+/// What [`peek`] saw of a body.
+enum Excerpt {
+    Whole(Bytes),
+    /// Larger than `BUFFER_LIMIT`, or failed part way; logged by size only.
+    Partial(usize),
+}
+
+/// Reads up to `BUFFER_LIMIT` bytes of `body` for logging and hands back a
+/// body that replays them followed by the rest, so large or failing bodies
+/// pass through exactly as they would without logging.
+async fn peek(body: Body) -> (Excerpt, Body) {
+    let mut stream = body.into_data_stream();
+    let mut chunks = Vec::new();
+    let mut size = 0;
+    while let Some(chunk) = stream.next().await {
+        let failed = chunk.is_err();
+        size += chunk.as_ref().map_or(0, Bytes::len);
+        chunks.push(chunk);
+        if failed || size > BUFFER_LIMIT {
+            let replay = futures_util::stream::iter(chunks).chain(stream);
+            return (Excerpt::Partial(size), Body::from_stream(replay));
+        }
+    }
+
+    let mut whole = Vec::with_capacity(size);
+    for chunk in chunks.into_iter().flatten() {
+        whole.extend_from_slice(&chunk);
+    }
+    let whole = Bytes::from(whole);
+    (Excerpt::Whole(whole.clone()), Body::from(whole))
+}
+
+fn describe(headers: &HeaderMap, excerpt: &Excerpt, max_bytes: usize) -> String {
+    match excerpt {
+        Excerpt::Whole(body) => render(headers, body, max_bytes),
+        Excerpt::Partial(size) => format!("<{size}+ bytes, not logged>"),
+    }
+}
+
+fn render(headers: &HeaderMap, body: &Bytes, max_bytes: usize) -> String {
+    if body.is_empty() {
+        return "<empty>".to_owned();
+    }
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+
+    let rendered = if content_type.contains("json") {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(mut value) => {
+                redact(&mut value);
+                value.to_string()
+            }
+            Err(_) => format!("<{} bytes of invalid JSON>", body.len()),
+        }
+    } else if content_type.starts_with("application/x-www-form-urlencoded") {
+        form_urlencoded::parse(body)
+            .map(|(key, value)| {
+                let value = if is_sensitive(&key) {
+                    REDACTED.into()
+                } else {
+                    value
+                };
+                format!("{key}={value}")
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    } else {
+        return format!("<{} bytes of {}>", body.len(), content_type);
+    };
+    truncate(rendered, max_bytes)
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                if is_sensitive(key) {
+                    *value = Value::String(REDACTED.to_owned());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Field names are compared without case or separators, so `refresh_token`,
+/// `refreshToken` and `Refresh-Token` are all caught.
+fn is_sensitive(key: &str) -> bool {
+    let key: String = key
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    key == "code"
+        || [
+            "password",
+            "token",
+            "secret",
+            "otp",
+            "assertion",
+            "credential",
+        ]
+        .iter()
+        .any(|name| key.contains(name))
+}
+
+fn truncate(mut value: String, max_bytes: usize) -> String {
+    if value.len() <= max_bytes {
+        return value;
+    }
+    let mut end = max_bytes;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value.truncate(end);
+    value.push_str("...(truncated)");
+    value
+}
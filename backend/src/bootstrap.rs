@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use serde_json::{Value, json};
+
+use crate::AppConfig;
+use crate::keycloak::KeycloakService;
+use crate::realm_admin::RealmAdmin;
+use crate::realm_spec::RealmSpec;
+
+/// Creates or updates everything the spec describes, in dependency order,
+/// printing one line per object. Running it again converges on the spec
+/// without deleting anything it does not mention.
+pub async fn run(
+    config: &AppConfig,
+    keycloak: Arc<KeycloakService>,
+    spec: &RealmSpec,
+) -> Result<(), String> {
+    let admin = RealmAdmin::sign_in(config, keycloak).await?;
+    let realm = spec.realm_name(config);
+
+    let representation = spec.realm_representation(config);
+    if admin.get(&realm).await?.is_some() {
+        admin.put(&realm, &representation).await?;
+        report("updated", "realm", &realm);
+    } else {
+        admin.post("", &representation).await?;
+        report("created", "realm", &realm);
+    }
+
+    for role in &spec.roles {
+        let path = format!("{realm}/roles/{}", role.name);
+        let body = json!({ "name": role.name, "description": role.description });
+        if admin.get(&path).await?.is_some() {
+            admin.put(&path, &body).await?;
+            report("updated", "role", &role.name);
+        } else {
+            admin.post(&format!("{realm}/roles"), &body).await?;
+            report("created", "role", &role.name);
+        }
+    }
+
+    if !spec.default_roles.is_empty() {
+        let roles = realm_roles(&admin, &realm, &spec.default_roles).await?;
+        admin
+            .post(
+                &format!("{realm}/roles/default-roles-{realm}/composites"),
+                &roles,
+            )
+            .await?;
+        report("updated", "default roles", &spec.default_roles.join(", "));
+    }
+
+    let admin_client_id = spec.admin_client_id(config);
+    let admin_client = upsert_client(
+        &admin,
+        &realm,
+        &admin_client_id,
+        &spec.admin_client_representation(config),
+    )
+    .await?;
+    grant_service_account_roles(
+        &admin,
+        &realm,
+        &admin_client,
+        &spec.admin_client.service_account_roles,
+    )
+    .await?;
+
+    let public_client_id = spec.public_client_id(config);
+    upsert_client(
+        &admin,
+        &realm,
+        &public_client_id,
+        &spec.public_client_representation(config),
+    )
+    .await?;
+
+    for group in &spec.groups {
+        let id = upsert_group(&admin, &realm, &group.name).await?;
+        if !group.realm_roles.is_empty() {
+            let roles = realm_roles(&admin, &realm, &group.realm_roles).await?;
+            admin
+                .post(&format!("{realm}/groups/{id}/role-mappings/realm"), &roles)
+                .await?;
+        }
+        if group.default {
+            admin
+                .put(&format!("{realm}/default-groups/{id}"), &json!({}))
+                .await?;
+        }
+    }
+
+    for action in &spec.required_actions {
+        let path = format!("{realm}/authentication/required-actions/{}", action.alias);
+        let Some(mut current) = admin.get(&path).await? else {
+            return Err(format!(
+                "required action {} is not registered",
+                action.alias
+            ));
+        };
+        current["enabled"] = json!(action.enabled);
+        current["defaultAction"] = json!(action.default_action);
+        admin.put(&path, &current).await?;
+        report("updated", "required action", &action.alias);
+    }
+
+    Ok(())
+}
+
+/// Creates or updates a client and returns its internal id.
+async fn upsert_client(
+    admin: &RealmAdmin,
+    realm: &str,
+    client_id: &str,
+    representation: &Value,
+) -> Result<String, String> {
+    if let Some(id) = admin.client_uuid(realm, client_id).await? {
+        admin
+            .put(&format!("{realm}/clients/{id}"), representation)
+            .await?;
+        report("updated", "client", client_id);
+        return Ok(id);
+    }
+
+    admin
+        .post(&format!("{realm}/clients"), representation)
+        .await?;
+    report("created", "client", client_id);
+    admin
+        .client_uuid(realm, client_id)
+        .await?
+        .ok_or_else(|| format!("client {client_id} missing after creation"))
+}
+
+async fn grant_service_account_roles(
+    admin: &RealmAdmin,
+    realm: &str,
+    client: &str,
+    roles: &[String],
+) -> Result<(), String> {
+    if roles.is_empty() {
+        return Ok(());
+    }
+
+    let account = admin
+        .get(&format!("{realm}/clients/{client}/service-account-user"))
+        .await?
+        .and_then(|user| user["id"].as_str().map(str::to_owned))
+        .ok_or("service account user not found")?;
+    let management = admin
+        .client_uuid(realm, "realm-management")
+        .await?
+        .ok_or("realm-management client not found")?;
+
+    let mut representations = Vec::new();
+    for role in roles {
+        let representation = admin
+            .get(&format!("{realm}/clients/{management}/roles/{role}"))
+            .await?
+            .ok_or_else(|| format!("realm-management role {role} not found"))?;
+        representations.push(representation);
+    }
+    admin
+        .post(
+            &format!("{realm}/users/{account}/role-mappings/clients/{management}"),
+            &Value::Array(representations),
+        )
+        .await?;
+    report("updated", "service account roles", &roles.join(", "));
+    Ok(())
+}
+
+async fn upsert_group(admin: &RealmAdmin, realm: &str, name: &str) -> Result<String, String> {
+    if let Some(id) = admin.group_id(realm, name).await? {
+        report("exists", "group", name);
+        return Ok(id);
+    }
+    admin
+        .post(&format!("{realm}/groups"), &json!({ "name": name }))
+        .await?;
+    report("created", "group", name);
+    admin
+        .group_id(realm, name)
+        .await?
+        .ok_or_else(|| format!("group {name} missing after creation"))
+}
+
+async fn realm_roles(admin: &RealmAdmin, realm: &str, names: &[String]) -> Result<Value, String> {
+    let mut roles = Vec::new();
+    for name in names {
+        let role = admin
+            .get(&format!("{realm}/roles/{name}"))
+            .await?
+            .ok_or_else(|| format!("realm role {name} not found"))?;
+        roles.push(role);
+    }
+    Ok(Value::Array(roles))
+}
+
+fn report(action: &str, kind: &str, name: &str) {
+    println!("[{action}] {kind} {name}");
+}
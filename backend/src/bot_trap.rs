@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use hmac::Mac;
+use rand::RngCore;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::util::unix_millis;
+use crate::util::{HmacSha256, hmac_sha256};
+
+#[derive(Debug, Clone, Copy)]
+pub enum BotTrapError {
+    Honeypot,
+    MissingFormToken,
+    InvalidFormToken,
+    TooFast,
+    Expired,
+}
+
+impl BotTrapError {
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::Honeypot => "honeypot",
+            Self::MissingFormToken => "missing_form_token",
+            Self::InvalidFormToken => "invalid_form_token",
+            Self::TooFast => "too_fast",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+/// Cheap checks that run before any captcha call: a hidden form field that
+/// humans leave empty, and a signed "form rendered at" timestamp issued by
+/// `/api/config` that must be old enough when the form is submitted.
+pub struct BotTrap {
+    secret: Vec<u8>,
+    honeypot_field: Option<String>,
+    min_fill_time: Duration,
+    max_form_age: Duration,
+}
+
+impl BotTrap {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let secret = match config.form_token_secret.as_deref().map(str::trim) {
+            Some(secret) if !secret.is_empty() => secret.as_bytes().to_vec(),
+            _ => {
+                let mut secret = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                secret
+            }
+        };
+
+        Self {
+            secret,
+            honeypot_field: config.register_honeypot_field.clone(),
+            min_fill_time: config.register_min_fill_time,
+            max_form_age: config.form_token_max_age,
+        }
+    }
+
+    pub fn time_trap_enabled(&self) -> bool {
+        !self.min_fill_time.is_zero()
+    }
+
+    pub fn honeypot_field(&self) -> Option<&str> {
+        self.honeypot_field.as_deref()
+    }
+
+    pub fn issue_form_token(&self) -> String {
+        let issued_at = unix_millis();
+        format!("{issued_at}.{}", hex::encode(self.sign(issued_at)))
+    }
+
+    pub fn check(
+        &self,
+        form_token: Option<&str>,
+        extra: &HashMap<String, Value>,
+    ) -> Result<(), BotTrapError> {
+        if let Some(field) = self.honeypot_field.as_deref()
+            && extra.get(field).is_some_and(is_filled)
+        {
+            return Err(BotTrapError::Honeypot);
+        }
+
+        if !self.time_trap_enabled() {
+            return Ok(());
+        }
+
+        let token = form_token
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .ok_or(BotTrapError::MissingFormToken)?;
+        let (issued_at, signature) = token
+            .split_once('.')
+            .ok_or(BotTrapError::InvalidFormToken)?;
+        let issued_at: u64 = issued_at
+            .parse()
+            .map_err(|_| BotTrapError::InvalidFormToken)?;
+        let signature = hex::decode(signature).map_err(|_| BotTrapError::InvalidFormToken)?;
+
+        let mut mac = self.mac();
+        mac.update(issued_at.to_string().as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| BotTrapError::InvalidFormToken)?;
+
+        let elapsed = Duration::from_millis(unix_millis().saturating_sub(issued_at));
+        if elapsed < self.min_fill_time {
+            return Err(BotTrapError::TooFast);
+        }
+        if elapsed > self.max_form_age {
+            return Err(BotTrapError::Expired);
+        }
+
+        Ok(())
+    }
+
+    fn sign(&self, issued_at: u64) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(issued_at.to_string().as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        hmac_sha256(&self.secret)
+    }
+}
+
+pub fn log_rejection(error: BotTrapError, email: &str) {
+    warn!(
+        "[Register] user={} rejected by bot trap reason={}",
+        email,
+        error.reason()
+    );
+}
+
+fn is_filled(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::String(text) => !text.trim().is_empty(),
+        Value::Array(items) => items.iter().any(is_filled),
+        _ => true,
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Small bounded cache that evicts the least recently used entry once full and
+/// treats entries older than `ttl` as missing.
+pub struct TtlLruCache<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    tick: u64,
+    entries: HashMap<K, CacheEntry<V>>,
+    recency: BTreeMap<u64, K>,
+}
+
+struct CacheEntry<V> {
+    value: V,
+    inserted_at: Instant,
+    last_used: u64,
+}
+
+impl<K, V> TtlLruCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0 && !self.ttl.is_zero()
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let expired = match self.entries.get(key) {
+            Some(entry) => entry.inserted_at.elapsed() >= self.ttl,
+            None => return None,
+        };
+
+        if expired {
+            self.remove(key);
+            return None;
+        }
+
+        let tick = self.next_tick();
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = tick;
+        self.recency.insert(tick, key.clone());
+
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                inserted_at: Instant::now(),
+                last_used: tick,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        Some(entry.value)
+    }
+
+    /// Drops every entry whose key fails `keep`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        let doomed: Vec<K> = self
+            .entries
+            .keys()
+            .filter(|key| !keep(key))
+            .cloned()
+            .collect();
+        for key in doomed {
+            self.remove(&key);
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick = self.tick.wrapping_add(1);
+        self.tick
+    }
+}
@@ -1,9 +1,40 @@
+use std::net::IpAddr;
+
 use axum::http::StatusCode;
 use reqwest::Client;
-use serde::Deserialize;
-use tracing::{error, warn};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::anomaly::AnomalyKind;
+use crate::audit::AuditOutcome;
+use crate::error_codes::ErrorCode;
+use crate::metrics::Metrics;
+use crate::tenant::TenantConfig;
+use crate::{AppConfig, AppState, DEV_MOCK_SITE_KEY, MOCK_SUCCESS_TOKEN};
 
-use crate::{AppState, DEV_MOCK_SITE_KEY, MOCK_SUCCESS_TOKEN};
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaProvider {
+    Turnstile,
+    Altcha,
+}
+
+impl CaptchaProvider {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "turnstile" => Some(Self::Turnstile),
+            "altcha" => Some(Self::Altcha),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Turnstile => "turnstile",
+            Self::Altcha => "altcha",
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum CaptchaError {
@@ -14,16 +45,189 @@ pub enum CaptchaError {
     Rejected,
 }
 
+impl CaptchaError {
+    fn outcome(&self) -> &'static str {
+        match self {
+            Self::MissingToken => "missing_token",
+            Self::Misconfigured => "misconfigured",
+            Self::RequestFailed => "request_failed",
+            Self::DecodeFailed => "decode_failed",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+/// The widget action a token must have been minted for; mirrors the
+/// `action` the SPA passes to `turnstile.execute`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CaptchaAction {
+    Login,
+    Register,
+}
+
+impl CaptchaAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "login" => Some(Self::Login),
+            "register" => Some(Self::Register),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::Register => "register",
+        }
+    }
+}
+
+/// Everything a captcha check needs to know about the calling request, so
+/// every endpoint goes through the same verification path.
+pub struct CaptchaContext<'a> {
+    pub action: CaptchaAction,
+    pub tenant: Option<&'a TenantConfig>,
+    pub remote_ip: Option<IpAddr>,
+    /// Value of the `X-Captcha-Exemption` header.
+    pub exemption: Option<&'a str>,
+}
+
 #[derive(Deserialize)]
 struct TurnstileResponse {
     success: bool,
     #[serde(default, rename = "error-codes")]
     error_codes: Vec<String>,
+    #[serde(default)]
+    hostname: Option<String>,
+    #[serde(default)]
+    action: Option<String>,
+}
+
+struct TurnstileExpectations<'a> {
+    action: Option<&'a str>,
+    hostnames: &'a [String],
+    remote_ip: Option<IpAddr>,
 }
 
-pub async fn ensure_valid(state: &AppState, token: Option<&str>) -> Result<(), CaptchaError> {
-    if should_skip_captcha(state, token) {
-        return Ok(());
+pub struct TurnstileKeys<'a> {
+    pub site_key: &'a str,
+    pub secret_key: Option<&'a str>,
+}
+
+/// A tenant that configures its own site key brings its own secret as well;
+/// the global secret is never paired with a tenant-specific site key.
+pub fn turnstile_keys<'a>(
+    config: &'a AppConfig,
+    tenant: Option<&'a TenantConfig>,
+) -> TurnstileKeys<'a> {
+    match tenant.and_then(|tenant| {
+        tenant
+            .turnstile_site_key
+            .as_deref()
+            .map(|key| (tenant, key))
+    }) {
+        Some((tenant, site_key)) => TurnstileKeys {
+            site_key,
+            secret_key: tenant.turnstile_secret_key.as_deref(),
+        },
+        None => TurnstileKeys {
+            site_key: config.turnstile_site_key.as_str(),
+            secret_key: config.turnstile_secret_key.as_deref(),
+        },
+    }
+}
+
+pub async fn ensure_valid(
+    state: &AppState,
+    context: &CaptchaContext<'_>,
+    token: Option<&str>,
+) -> Result<(), CaptchaError> {
+    let result = match context.exemption {
+        Some(exemption) => check_exemption(state, context, exemption)
+            .await
+            .map(|_| "exempted"),
+        None => verify_token(state, context, token)
+            .await
+            .map(|verified| if verified { "success" } else { "skipped" }),
+    };
+    let outcome = match &result {
+        Ok(outcome) => outcome,
+        Err(error) => error.outcome(),
+    };
+    if matches!(
+        result,
+        Err(CaptchaError::Rejected | CaptchaError::MissingToken)
+    ) && let Some(ip) = context.remote_ip
+    {
+        state.anomalies.observe(
+            AnomalyKind::CaptchaRejection,
+            ip,
+            &state.metrics,
+            &state.http_client,
+        );
+    }
+    state
+        .metrics
+        .captcha_verifications
+        .with_label_values(&[state.config.captcha_provider.as_str(), outcome])
+        .inc();
+
+    result.map(|_| ())
+}
+
+/// A request that presents an exemption is judged on it alone: an invalid,
+/// expired or revoked exemption is a rejection, not a fall back to the
+/// widget token. Every use is audited.
+async fn check_exemption(
+    state: &AppState,
+    context: &CaptchaContext<'_>,
+    token: &str,
+) -> Result<(), CaptchaError> {
+    let action = context.action.as_str();
+    let Some(exemption) = state.captcha_exemptions.verify(token, context.action).await else {
+        warn!("[Captcha] rejected invalid exemption for {}", action);
+        state.audit.record(
+            "captcha-exemption",
+            "captcha.exemption_used",
+            AuditOutcome::Failure,
+            Some(action),
+            context.remote_ip,
+        );
+        return Err(CaptchaError::Rejected);
+    };
+
+    info!(
+        "[Captcha] exemption {} ({}) used for {}",
+        exemption.id, exemption.label, action
+    );
+    state.audit.record(
+        &exemption.label,
+        "captcha.exemption_used",
+        AuditOutcome::Success,
+        Some(&exemption.id),
+        context.remote_ip,
+    );
+    Ok(())
+}
+
+/// Returns `Ok(false)` when verification was skipped (mock/dev mode).
+async fn verify_token(
+    state: &AppState,
+    context: &CaptchaContext<'_>,
+    token: Option<&str>,
+) -> Result<bool, CaptchaError> {
+    if state.config.captcha_provider == CaptchaProvider::Altcha {
+        let payload = token
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .ok_or(CaptchaError::MissingToken)?;
+        let altcha = state.altcha.as_ref().ok_or(CaptchaError::Misconfigured)?;
+        return altcha.verify(payload).await.map(|_| true);
+    }
+
+    let keys = turnstile_keys(&state.config, context.tenant);
+    if should_skip_captcha(&keys, token) {
+        return Ok(false);
     }
 
     let captcha_token = token
@@ -31,38 +235,57 @@ pub async fn ensure_valid(state: &AppState, token: Option<&str>) -> Result<(), C
         .filter(|value| !value.is_empty())
         .ok_or(CaptchaError::MissingToken)?;
 
-    let secret = state
-        .config
-        .turnstile_secret_key
-        .as_ref()
+    let secret = keys
+        .secret_key
         .map(|value| value.trim())
         .filter(|value| !value.is_empty())
         .ok_or(CaptchaError::Misconfigured)?;
 
+    let expectations = TurnstileExpectations {
+        action: state
+            .live
+            .current()
+            .turnstile_validate_action
+            .then_some(context.action.as_str()),
+        hostnames: &state.config.turnstile_expected_hostnames,
+        remote_ip: context.remote_ip.filter(|ip| !ip.is_unspecified()),
+    };
+
     verify_with_turnstile(
         &state.http_client,
+        &state.metrics,
         state.config.turnstile_verify_url.as_str(),
         secret,
         captcha_token,
+        &expectations,
     )
     .await
+    .map(|_| true)
 }
 
-fn should_skip_captcha(state: &AppState, token: Option<&str>) -> bool {
-    state.config.turnstile_site_key.is_empty()
-        || state.config.turnstile_site_key == DEV_MOCK_SITE_KEY
+fn should_skip_captcha(keys: &TurnstileKeys<'_>, token: Option<&str>) -> bool {
+    keys.site_key.is_empty()
+        || keys.site_key == DEV_MOCK_SITE_KEY
         || token == Some(MOCK_SUCCESS_TOKEN)
 }
 
 async fn verify_with_turnstile(
     client: &Client,
+    metrics: &Metrics,
     endpoint: &str,
     secret: &str,
     token: &str,
+    expectations: &TurnstileExpectations<'_>,
 ) -> Result<(), CaptchaError> {
+    let remote_ip = expectations.remote_ip.map(|ip| ip.to_string());
+    let mut form = vec![("secret", secret), ("response", token)];
+    if let Some(remote_ip) = remote_ip.as_deref() {
+        form.push(("remoteip", remote_ip));
+    }
+
     let response = client
         .post(endpoint)
-        .form(&[("secret", secret), ("response", token)])
+        .form(&form)
         .send()
         .await
         .map_err(|err| {
@@ -81,26 +304,97 @@ async fn verify_with_turnstile(
     })?;
 
     if !payload.success {
-        warn!(codes = ?payload.error_codes, "Turnstile verification did not succeed");
+        record_error_codes(metrics, &payload.error_codes);
         return Err(CaptchaError::Rejected);
     }
 
+    if !expectations.hostnames.is_empty() {
+        let hostname = payload.hostname.as_deref().unwrap_or_default();
+        if !expectations
+            .hostnames
+            .iter()
+            .any(|expected| expected.eq_ignore_ascii_case(hostname))
+        {
+            warn!(%hostname, "Turnstile token was issued for an unexpected hostname");
+            record_error_codes(metrics, &["hostname-mismatch".to_owned()]);
+            return Err(CaptchaError::Rejected);
+        }
+    }
+
+    if let Some(expected) = expectations.action {
+        let action = payload.action.as_deref().unwrap_or_default();
+        if action != expected {
+            warn!(%action, %expected, "Turnstile token was issued for a different action");
+            record_error_codes(metrics, &["action-mismatch".to_owned()]);
+            return Err(CaptchaError::Rejected);
+        }
+    }
+
     Ok(())
 }
 
-pub fn captcha_error_status(error: CaptchaError) -> (StatusCode, &'static str) {
+fn record_error_codes(metrics: &Metrics, codes: &[String]) {
+    if codes.is_empty() {
+        if let Some(occurrences) = metrics.captcha_warnings.sample("unknown") {
+            warn!(
+                occurrences,
+                "Turnstile verification did not succeed without error codes"
+            );
+        }
+        return;
+    }
+
+    for code in codes {
+        let class = error_code_class(code);
+        metrics
+            .captcha_error_codes
+            .with_label_values(&[code.as_str(), class])
+            .inc();
+
+        if let Some(occurrences) = metrics.captcha_warnings.sample(class) {
+            warn!(
+                %code,
+                class,
+                occurrences,
+                "Turnstile verification did not succeed"
+            );
+        }
+    }
+}
+
+/// Groups Turnstile error codes so operators can tell a broken deployment
+/// (`misconfiguration`) from hostile or stale clients.
+fn error_code_class(code: &str) -> &'static str {
+    match code {
+        "missing-input-secret" | "invalid-input-secret" => "misconfiguration",
+        "missing-input-response" | "invalid-input-response" | "bad-request" => "invalid_token",
+        "timeout-or-duplicate" => "expired_or_replayed",
+        "internal-error" => "provider_error",
+        "hostname-mismatch" | "action-mismatch" => "foreign_token",
+        _ => "other",
+    }
+}
+
+pub fn captcha_error_status(error: CaptchaError) -> (StatusCode, ErrorCode, &'static str) {
     match error {
-        CaptchaError::MissingToken => (StatusCode::BAD_REQUEST, "Missing captcha token"),
+        CaptchaError::MissingToken => (
+            StatusCode::BAD_REQUEST,
+            ErrorCode::CaptchaRequired,
+            "Missing captcha token",
+        ),
         CaptchaError::Misconfigured => (
             StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::InternalError,
             "CAPTCHA verification misconfigured",
         ),
-        CaptchaError::RequestFailed => {
-            (StatusCode::BAD_GATEWAY, "CAPTCHA verification unavailable")
-        }
-        CaptchaError::DecodeFailed => (StatusCode::BAD_GATEWAY, "CAPTCHA verification unavailable"),
+        CaptchaError::RequestFailed | CaptchaError::DecodeFailed => (
+            StatusCode::BAD_GATEWAY,
+            ErrorCode::CaptchaUnavailable,
+            "CAPTCHA verification unavailable",
+        ),
         CaptchaError::Rejected => (
             StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::CaptchaRejected,
             "CAPTCHA verification failed",
         ),
     }
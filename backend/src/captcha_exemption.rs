@@ -0,0 +1,248 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::Mac;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::captcha::CaptchaAction;
+use crate::session::{SessionStore, StoreError};
+use crate::util::{HmacSha256, hmac_sha256, unix_now};
+
+pub const EXEMPTION_HEADER: &str = "x-captcha-exemption";
+
+/// `<prefix><id>` holds each exemption until it expires.
+const EXEMPTION_PREFIX: &str = "captcha-exemption:";
+/// `<prefix><id>` marks a revoked exemption with the time it was revoked.
+const REVOKED_PREFIX: &str = "captcha-exemption-revoked:";
+
+/// An issued exemption. Revoked ones stay listed until they expire so the
+/// audit trail can still be matched to a label.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Exemption {
+    pub id: String,
+    pub label: String,
+    pub scopes: Vec<String>,
+    pub created_by: String,
+    pub created_at: u64,
+    pub expires_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ExemptionError {
+    Disabled,
+    NotFound,
+    Invalid(&'static str),
+    Unavailable,
+}
+
+/// Admin-issued tokens that let automated test traffic past the captcha
+/// without switching the whole deployment to mock mode. A token is
+/// `<id>.<scopes>.<expiry>.<b64url HMAC>` signed with
+/// `CAPTCHA_EXEMPTION_SECRET`; it is only honoured while its id is in the
+/// registry and not marked revoked. Both are entries of their own in the
+/// session store, written once and never rewritten, so replicas issuing or
+/// revoking at the same time cannot overwrite each other. Without a secret
+/// the feature is off.
+pub struct CaptchaExemptions {
+    secret: Option<Vec<u8>>,
+    max_ttl: Duration,
+    store: Arc<dyn SessionStore>,
+}
+
+impl CaptchaExemptions {
+    pub fn from_config(config: &AppConfig, store: Arc<dyn SessionStore>) -> Self {
+        let secret = config
+            .captcha_exemption_secret
+            .as_deref()
+            .map(str::trim)
+            .filter(|secret| !secret.is_empty())
+            .map(|secret| secret.as_bytes().to_vec());
+
+        Self {
+            secret,
+            max_ttl: config.captcha_exemption_max_ttl,
+            store,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Returns the exemption and its token; the token is not stored and
+    /// cannot be shown again. `ttl` is capped at
+    /// `CAPTCHA_EXEMPTION_MAX_TTL_SECS`.
+    pub async fn issue(
+        &self,
+        label: &str,
+        scopes: &[CaptchaAction],
+        ttl: Option<Duration>,
+        created_by: &str,
+    ) -> Result<(Exemption, String), ExemptionError> {
+        let secret = self.secret.as_deref().ok_or(ExemptionError::Disabled)?;
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(ExemptionError::Invalid("label is required"));
+        }
+        if scopes.is_empty() {
+            return Err(ExemptionError::Invalid("at least one scope is required"));
+        }
+        let ttl = ttl.unwrap_or(self.max_ttl).min(self.max_ttl);
+        if ttl.is_zero() {
+            return Err(ExemptionError::Invalid("ttlSecs must be positive"));
+        }
+
+        let mut id = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut id);
+        let now = unix_now();
+        let mut scopes: Vec<String> = scopes
+            .iter()
+            .map(|scope| scope.as_str().to_owned())
+            .collect();
+        scopes.sort();
+        scopes.dedup();
+        let exemption = Exemption {
+            id: hex::encode(id),
+            label: label.to_owned(),
+            scopes,
+            created_by: created_by.to_owned(),
+            created_at: now,
+            expires_at: now + ttl.as_secs(),
+            revoked_at: None,
+        };
+
+        let encoded = serde_json::to_string(&exemption).unwrap_or_default();
+        let stored = self
+            .store
+            .set_if_absent(
+                &format!("{EXEMPTION_PREFIX}{}", exemption.id),
+                &encoded,
+                ttl,
+            )
+            .await
+            .map_err(unavailable)?;
+        if !stored {
+            return Err(ExemptionError::Unavailable);
+        }
+
+        let scope_part = exemption.scopes.join("+");
+        let payload = format!("{}.{}.{}", exemption.id, scope_part, exemption.expires_at);
+        let mac = URL_SAFE_NO_PAD.encode(sign(secret, &payload));
+        Ok((exemption, format!("{payload}.{mac}")))
+    }
+
+    pub async fn list(&self) -> Result<Vec<Exemption>, ExemptionError> {
+        if !self.is_enabled() {
+            return Err(ExemptionError::Disabled);
+        }
+        let ids = self
+            .store
+            .keys(EXEMPTION_PREFIX)
+            .await
+            .map_err(unavailable)?;
+        let mut exemptions = Vec::new();
+        for key in ids {
+            if let Some(exemption) = self.load(&key[EXEMPTION_PREFIX.len()..]).await? {
+                exemptions.push(exemption);
+            }
+        }
+        exemptions.sort_by_key(|exemption| exemption.created_at);
+        Ok(exemptions)
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<Exemption, ExemptionError> {
+        if !self.is_enabled() {
+            return Err(ExemptionError::Disabled);
+        }
+        let mut exemption = self.load(id).await?.ok_or(ExemptionError::NotFound)?;
+        if exemption.revoked_at.is_none() {
+            let now = unix_now();
+            // The marker only has to outlive the exemption it revokes. A
+            // revocation that loses the race keeps the earlier time.
+            let ttl = Duration::from_secs(exemption.expires_at.saturating_sub(now).max(1));
+            self.store
+                .set_if_absent(&format!("{REVOKED_PREFIX}{id}"), &now.to_string(), ttl)
+                .await
+                .map_err(unavailable)?;
+            exemption = self.load(id).await?.ok_or(ExemptionError::NotFound)?;
+        }
+        Ok(exemption)
+    }
+
+    /// The exemption `token` stands for when it is correctly signed,
+    /// unexpired, covers `action` and has not been revoked.
+    pub async fn verify(&self, token: &str, action: CaptchaAction) -> Option<Exemption> {
+        let secret = self.secret.as_deref()?;
+        let (payload, mac_part) = token.trim().rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(mac_part).ok()?;
+        mac(secret, payload).verify_slice(&signature).ok()?;
+
+        let mut parts = payload.splitn(3, '.');
+        let id = parts.next()?;
+        let scopes = parts.next()?;
+        let expires_at: u64 = parts.next()?.parse().ok()?;
+        if expires_at < unix_now() || !scopes.split('+').any(|scope| scope == action.as_str()) {
+            return None;
+        }
+
+        self.load(id)
+            .await
+            .ok()
+            .flatten()
+            .filter(|exemption| exemption.revoked_at.is_none())
+    }
+
+    /// The exemption `id` with its revocation, if it is still stored.
+    async fn load(&self, id: &str) -> Result<Option<Exemption>, ExemptionError> {
+        let stored = self
+            .store
+            .get(&format!("{EXEMPTION_PREFIX}{id}"))
+            .await
+            .map_err(unavailable)?;
+        let Some(mut exemption) =
+            stored.and_then(|value| serde_json::from_str::<Exemption>(&value).ok())
+        else {
+            return Ok(None);
+        };
+        exemption.revoked_at = self
+            .store
+            .get(&format!("{REVOKED_PREFIX}{id}"))
+            .await
+            .map_err(unavailable)?
+            .and_then(|value| value.parse().ok());
+        Ok(Some(exemption))
+    }
+}
+
+fn unavailable(err: StoreError) -> ExemptionError {
+    warn!("[Captcha] exemption registry unavailable: {}", err);
+    ExemptionError::Unavailable
+}
+
+/// The `X-Captcha-Exemption` header, if the request carries one.
+pub fn exemption_header(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(EXEMPTION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn sign(secret: &[u8], payload: &str) -> Vec<u8> {
+    mac(secret, payload).finalize().into_bytes().to_vec()
+}
+
+fn mac(secret: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = hmac_sha256(secret);
+    mac.update(payload.as_bytes());
+    mac
+}
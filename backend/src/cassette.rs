@@ -0,0 +1,454 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::sync::Mutex;
+
+use axum::http;
+use reqwest::{Client, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::{error, info, warn};
+
+use crate::AppConfig;
+
+/// Form fields and JSON keys whose values never reach a cassette.
+const SECRET_FIELDS: &[&str] = &[
+    "client_secret",
+    "client_assertion",
+    "password",
+    "secret",
+    "secretData",
+    "totp",
+    "otp",
+];
+/// Tokens and codes, in requests and answers alike. They are random enough
+/// to be stored as a digest, which keeps calls about different tokens apart
+/// on replay.
+const DIGESTED_FIELDS: &[&str] = &[
+    "token",
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "subject_token",
+    "code",
+    "device_code",
+    "assertion",
+    "session_state",
+];
+const REDACTED: &str = "<redacted>";
+/// Response headers that describe the connection rather than the answer, or
+/// that carry credentials.
+const UNRECORDED_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "date",
+    "transfer-encoding",
+    "set-cookie",
+    "authorization",
+];
+
+/// `KEYCLOAK_CASSETTE_MODE`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+impl CassetteMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "record" => Some(Self::Record),
+            "replay" => Some(Self::Replay),
+            _ => None,
+        }
+    }
+}
+
+/// One request to Keycloak and its answer, as a line of a cassette.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Interaction {
+    method: String,
+    /// Path and query; the host is left out so a cassette recorded against
+    /// one Keycloak replays under any `KEYCLOAK_BASE_URL`.
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_body: Option<String>,
+    status: u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    body: String,
+}
+
+impl Interaction {
+    fn key(&self) -> String {
+        format!(
+            "{} {}\n{}",
+            self.method,
+            self.path,
+            self.request_body.as_deref().unwrap_or_default()
+        )
+    }
+
+    fn response(&self) -> Response {
+        let mut response = http::Response::builder()
+            .status(StatusCode::from_u16(self.status).unwrap_or(StatusCode::BAD_GATEWAY));
+        for (name, value) in &self.headers {
+            response = response.header(name, value);
+        }
+        response
+            .body(self.body.clone())
+            .unwrap_or_else(|_| http::Response::new(self.body.clone()))
+            .into()
+    }
+}
+
+/// Record/replay of the portal's calls to Keycloak, for reproducing realm
+/// behaviour reported from an environment without access to it. Only built
+/// with the `cassette` feature (and in tests). With
+/// `KEYCLOAK_CASSETTE_MODE=record` every call made through
+/// `KeycloakService` is forwarded as usual and appended to the
+/// `KEYCLOAK_CASSETTE` file as a JSON line by a background writer. Secrets
+/// are redacted and tokens digested in requests and answers alike. With
+/// `replay` nothing is sent: each call gets the next recorded answer for the
+/// same method, path and body, the last one repeating once they run out,
+/// and a call that was never recorded gets a 502. A cassette that cannot be
+/// opened or parsed fails the configuration at startup.
+pub struct Cassette {
+    mode: CassetteMode,
+    writer: Option<UnboundedSender<String>>,
+    recorded: Mutex<HashMap<String, VecDeque<Interaction>>>,
+}
+
+impl Cassette {
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        let path = config.keycloak_cassette.as_deref()?;
+        let mode = config.keycloak_cassette_mode?;
+
+        let mut writer = None;
+        let mut recorded = HashMap::new();
+        match mode {
+            CassetteMode::Record => match open_for_recording(path) {
+                Ok(file) => {
+                    warn!("[Cassette] recording Keycloak calls to {}", path);
+                    writer = Some(spawn_writer(path.to_owned(), file));
+                }
+                Err(err) => error!("[Cassette] {}", err),
+            },
+            CassetteMode::Replay => match load(path) {
+                Ok(interactions) => {
+                    info!(
+                        "[Cassette] replaying {} Keycloak calls from {}",
+                        interactions.values().map(VecDeque::len).sum::<usize>(),
+                        path
+                    );
+                    recorded = interactions;
+                }
+                Err(err) => error!("[Cassette] {}", err),
+            },
+        }
+
+        Some(Self {
+            mode,
+            writer,
+            recorded: Mutex::new(recorded),
+        })
+    }
+
+    /// Sends `request`, or answers it from the cassette.
+    pub async fn exchange(
+        &self,
+        client: &Client,
+        request: Request,
+    ) -> Result<Response, reqwest::Error> {
+        let (method, path, request_body) = describe(&request);
+        if self.mode == CassetteMode::Replay {
+            return Ok(self.replay(method, path, request_body));
+        }
+
+        let response = client.execute(request).await?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !UNRECORDED_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| {
+                Some((name.as_str().to_owned(), value.to_str().ok()?.to_owned()))
+            })
+            .collect();
+        let body = redact_body(&response.bytes().await?);
+        let interaction = Interaction {
+            method,
+            path,
+            request_body,
+            status,
+            headers,
+            body,
+        };
+        self.record(&interaction);
+        Ok(interaction.response())
+    }
+
+    fn record(&self, interaction: &Interaction) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let line = serde_json::to_string(interaction).unwrap_or_default();
+        if writer.send(line).is_err() {
+            error!(
+                "[Cassette] recording {} failed: writer stopped",
+                interaction.path
+            );
+        }
+    }
+
+    fn replay(&self, method: String, path: String, request_body: Option<String>) -> Response {
+        let wanted = Interaction {
+            method,
+            path,
+            request_body,
+            status: StatusCode::BAD_GATEWAY.as_u16(),
+            headers: Vec::new(),
+            body: String::new(),
+        };
+        let mut recorded = self.recorded.lock().expect("cassette lock poisoned");
+        let Some(queue) = recorded.get_mut(&wanted.key()) else {
+            warn!(
+                "[Cassette] no recorded answer for {} {}",
+                wanted.method, wanted.path
+            );
+            return Interaction {
+                body: format!("no recorded answer for {} {}", wanted.method, wanted.path),
+                ..wanted
+            }
+            .response();
+        };
+        let interaction = if queue.len() > 1 {
+            queue.pop_front()
+        } else {
+            queue.front().cloned()
+        };
+        interaction.map_or_else(|| wanted.response(), |interaction| interaction.response())
+    }
+}
+
+/// Checks that the configured cassette can be recorded to or replayed, for
+/// startup validation.
+pub fn check(config: &AppConfig) -> Result<(), String> {
+    let (Some(path), mode) = (
+        config.keycloak_cassette.as_deref(),
+        config.keycloak_cassette_mode,
+    ) else {
+        return Ok(());
+    };
+    match mode {
+        None => {
+            Err("KEYCLOAK_CASSETTE requires KEYCLOAK_CASSETTE_MODE=record or replay".to_owned())
+        }
+        Some(CassetteMode::Record) => open_for_recording(path).map(drop),
+        Some(CassetteMode::Replay) => load(path).map(drop),
+    }
+}
+
+fn open_for_recording(path: &str) -> Result<fs::File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("cannot record to {path}: {err}"))
+}
+
+/// Appends recorded lines off the request path.
+fn spawn_writer(path: String, file: fs::File) -> UnboundedSender<String> {
+    let (sender, mut lines) = mpsc::unbounded_channel::<String>();
+    let mut file = tokio::fs::File::from_std(file);
+    tokio::spawn(async move {
+        while let Some(mut line) = lines.recv().await {
+            line.push('\n');
+            if let Err(err) = file.write_all(line.as_bytes()).await {
+                error!("[Cassette] writing to {} failed: {}", path, err);
+            }
+        }
+    });
+    sender
+}
+
+/// Every interaction in the cassette at `path`, by request.
+fn load(path: &str) -> Result<HashMap<String, VecDeque<Interaction>>, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("cannot read {path}: {err}"))?;
+    let mut recorded: HashMap<String, VecDeque<Interaction>> = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let interaction = serde_json::from_str::<Interaction>(line)
+            .map_err(|err| format!("{path}:{} is not a recorded call: {err}", number + 1))?;
+        recorded
+            .entry(interaction.key())
+            .or_default()
+            .push_back(interaction);
+    }
+    Ok(recorded)
+}
+
+/// A response body with its tokens digested and secrets redacted.
+fn redact_body(body: &[u8]) -> String {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut json) => {
+            redact_json(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    }
+}
+
+/// Method, path with query, and the body with secrets redacted.
+fn describe(request: &Request) -> (String, String, Option<String>) {
+    let url = request.url();
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_owned(),
+    };
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .filter(|bytes| !bytes.is_empty())
+        .map(|bytes| redact(bytes, request.headers().get(http::header::CONTENT_TYPE)));
+    (request.method().to_string(), path, body)
+}
+
+fn redact(body: &[u8], content_type: Option<&http::HeaderValue>) -> String {
+    let form = content_type
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if form {
+        let pairs: Vec<(String, String)> = form_urlencoded::parse(body)
+            .map(|(name, value)| {
+                let value = conceal(&name, &value).unwrap_or_else(|| value.into_owned());
+                (name.into_owned(), value)
+            })
+            .collect();
+        return form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(pairs)
+            .finish();
+    }
+    redact_body(body)
+}
+
+/// Secret keys anywhere in the document, and the `value` of password
+/// credentials.
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            let password_credential =
+                object.get("type").and_then(Value::as_str) == Some("password");
+            for (key, value) in object.iter_mut() {
+                let concealed = match value.as_str() {
+                    Some(_) if password_credential && key == "value" => Some(REDACTED.to_owned()),
+                    Some(text) => conceal(key, text),
+                    None => None,
+                };
+                match concealed {
+                    Some(concealed) => *value = Value::String(concealed),
+                    None => redact_json(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// What a secret field is stored as, or `None` if `name` is not one.
+fn conceal(name: &str, value: &str) -> Option<String> {
+    if SECRET_FIELDS.contains(&name) {
+        Some(REDACTED.to_owned())
+    } else if DIGESTED_FIELDS.contains(&name) {
+        Some(format!(
+            "sha256:{}",
+            hex::encode(&Sha256::digest(value)[..8])
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    fn cassette_file(name: &str, lines: &[Value]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("argus-cassette-{}-{name}", std::process::id()));
+        let contents: Vec<String> = lines.iter().map(Value::to_string).collect();
+        fs::write(&path, contents.join("\n")).expect("cassette written");
+        path
+    }
+
+    fn replaying(path: &Path) -> Cassette {
+        Cassette {
+            mode: CassetteMode::Replay,
+            writer: None,
+            recorded: Mutex::new(load(path.to_str().unwrap()).expect("cassette loads")),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_answers_in_order_and_repeats_the_last() {
+        let path = cassette_file(
+            "replay",
+            &[
+                serde_json::json!({"method": "GET", "path": "/admin/realms/argus/users?email=a", "status": 200, "body": "[1]"}),
+                serde_json::json!({"method": "GET", "path": "/admin/realms/argus/users?email=a", "status": 200, "body": "[2]"}),
+            ],
+        );
+        let cassette = replaying(&path);
+        let client = Client::new();
+        let request = || {
+            client
+                .get("http://keycloak.invalid/admin/realms/argus/users?email=a")
+                .build()
+                .unwrap()
+        };
+
+        for expected in ["[1]", "[2]", "[2]"] {
+            let response = cassette.exchange(&client, request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.text().await.unwrap(), expected);
+        }
+        let unknown = client.get("http://keycloak.invalid/other").build().unwrap();
+        let response = cassette.exchange(&client, unknown).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn rejects_a_cassette_with_a_bad_line() {
+        let path = cassette_file("bad", &[serde_json::json!({"method": "GET"})]);
+        assert!(load(path.to_str().unwrap()).is_err());
+        assert!(load("/nonexistent/argus-cassette").is_err());
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn redacts_secrets_and_digests_tokens_in_answers() {
+        let body = serde_json::json!({
+            "access_token": "eyJ.access",
+            "refresh_token": "eyJ.refresh",
+            "id_token": "eyJ.id",
+            "client_secret": "s3cret",
+            "expires_in": 300,
+        });
+        let redacted = redact_body(body.to_string().as_bytes());
+        for secret in ["eyJ.access", "eyJ.refresh", "eyJ.id", "s3cret"] {
+            assert!(!redacted.contains(secret), "{secret} leaked: {redacted}");
+        }
+        assert!(redacted.contains("\"expires_in\":300"));
+    }
+}
@@ -0,0 +1,150 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::sync::Arc;
+
+use dotenvy::dotenv;
+
+use crate::keycloak::KeycloakService;
+use crate::loadtest::{self, LoadTest};
+use crate::metrics::Metrics;
+use crate::realm_admin::RealmAdmin;
+use crate::realm_spec::RealmSpec;
+use crate::{
+    AppConfig, bootstrap, cookies, doctor, keycloak_http_client, realm_backup, realm_diff,
+};
+
+/// Maintenance subcommands run instead of the server, e.g.
+/// `backend generate-cookie-key`, `backend doctor`,
+/// `backend bootstrap-realm [spec.toml]`, `backend realm-diff [spec.toml]`,
+/// `backend decrypt-backup <file>` or `backend loadtest [options]`.
+pub enum Command {
+    GenerateCookieKey,
+    Doctor,
+    /// Provisions the realm from a spec file, `realm.toml` by default.
+    BootstrapRealm(String),
+    /// Compares the realm with a spec file; exits with 1 on drift and 2 when
+    /// the check itself failed.
+    RealmDiff(String),
+    /// Writes the JSON inside a realm backup snapshot to stdout, using
+    /// `REALM_BACKUP_KEY`.
+    DecryptBackup(String),
+    /// Drives login, refresh and register traffic at a portal and reports
+    /// latency percentiles; exits with 2 on bad options.
+    LoadTest(Vec<String>),
+}
+
+impl Command {
+    pub fn from_args() -> Option<Self> {
+        match env::args().nth(1)?.as_str() {
+            "generate-cookie-key" => Some(Self::GenerateCookieKey),
+            "doctor" => Some(Self::Doctor),
+            "bootstrap-realm" => Some(Self::BootstrapRealm(spec_path())),
+            "realm-diff" => Some(Self::RealmDiff(spec_path())),
+            "decrypt-backup" => Some(Self::DecryptBackup(env::args().nth(2).unwrap_or_default())),
+            "loadtest" => Some(Self::LoadTest(env::args().skip(2).collect())),
+            _ => None,
+        }
+    }
+
+    pub async fn run(self) {
+        match self {
+            Self::GenerateCookieKey => println!("{}", cookies::generate_key()),
+            Self::Doctor => {
+                dotenv().ok();
+                let config = AppConfig::from_env();
+                if !doctor::run(&config, keycloak_http_client(&config)).await {
+                    process::exit(1);
+                }
+            }
+            Self::BootstrapRealm(path) => {
+                dotenv().ok();
+                let config = AppConfig::from_env();
+                let result = match RealmSpec::load(&path) {
+                    Ok(spec) => bootstrap::run(&config, keycloak_service(&config), &spec).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    eprintln!("bootstrap-realm failed: {err}");
+                    process::exit(1);
+                }
+            }
+            Self::RealmDiff(path) => {
+                dotenv().ok();
+                let config = AppConfig::from_env();
+                let result = match RealmSpec::load(&path) {
+                    Ok(spec) => {
+                        match RealmAdmin::sign_in(&config, keycloak_service(&config)).await {
+                            Ok(admin) => realm_diff::diff(&admin, &config, &spec).await,
+                            Err(err) => Err(err),
+                        }
+                    }
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(drift) => {
+                        realm_diff::print(&drift);
+                        if !drift.is_empty() {
+                            process::exit(1);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("realm-diff failed: {err}");
+                        process::exit(2);
+                    }
+                }
+            }
+            Self::DecryptBackup(path) => {
+                dotenv().ok();
+                let config = AppConfig::from_env();
+                let result = fs::read(&path)
+                    .map_err(|err| format!("{path}: {err}"))
+                    .and_then(|sealed| {
+                        let key = config
+                            .realm_backup_key
+                            .as_deref()
+                            .ok_or("REALM_BACKUP_KEY is not set")?;
+                        realm_backup::open(key, &sealed)
+                    })
+                    .and_then(|json| io::stdout().write_all(&json).map_err(|err| err.to_string()));
+                if let Err(err) = result {
+                    eprintln!("decrypt-backup failed: {err}");
+                    process::exit(1);
+                }
+            }
+            Self::LoadTest(args) => {
+                dotenv().ok();
+                let test = match LoadTest::from_args(args) {
+                    Ok(test) => test,
+                    Err(err) => {
+                        eprintln!("loadtest: {err}\n{}", loadtest::USAGE);
+                        process::exit(2);
+                    }
+                };
+                match test.run().await {
+                    Ok(report) => report.print(),
+                    Err(err) => {
+                        eprintln!("loadtest failed: {err}");
+                        process::exit(1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn spec_path() -> String {
+    env::args()
+        .nth(2)
+        .unwrap_or_else(|| "realm.toml".to_owned())
+}
+
+/// A Keycloak client for a one-off command; its metrics are not exported.
+fn keycloak_service(config: &AppConfig) -> Arc<KeycloakService> {
+    KeycloakService::new(
+        config,
+        keycloak_http_client(config),
+        Arc::new(Metrics::new()),
+    )
+}
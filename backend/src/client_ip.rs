@@ -0,0 +1,107 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{HeaderMap, request::Parts},
+};
+use ipnet::IpNet;
+
+use crate::AppState;
+
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// Address of the end user. When the TCP peer is a trusted proxy the
+/// `X-Forwarded-For` chain is walked from the right, skipping further trusted
+/// hops, so clients cannot spoof their address by prepending entries.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Marks a request that arrived over a Unix domain socket; the local peer is
+/// always the fronting proxy.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalSocketPeer;
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if parts.extensions.get::<LocalSocketPeer>().is_some() {
+            return Ok(Self(walk_forwarded(
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                &parts.headers,
+                &state.config.trusted_proxies,
+            )));
+        }
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+        Ok(Self(resolve(
+            peer,
+            &parts.headers,
+            &state.config.trusted_proxies,
+        )))
+    }
+}
+
+pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    if !is_trusted(peer, trusted) {
+        return peer;
+    }
+
+    walk_forwarded(peer, headers, trusted)
+}
+
+fn walk_forwarded(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    let hops = headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+
+    hops.into_iter()
+        .rev()
+        .find(|hop| !is_trusted(*hop, trusted))
+        .unwrap_or(peer)
+}
+
+fn is_trusted(ip: IpAddr, trusted: &[IpNet]) -> bool {
+    trusted.iter().any(|net| net.contains(&ip))
+}
+
+/// Parses a comma separated list of CIDRs or bare addresses. Any entry that
+/// does not parse fails the whole list, since dropping it could leave an
+/// allowlist empty and so open to everyone.
+pub fn parse_networks(value: &str) -> Result<Vec<IpNet>, String> {
+    let mut networks = Vec::new();
+    let mut invalid = Vec::new();
+    for item in value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        match item
+            .parse::<IpNet>()
+            .ok()
+            .or_else(|| item.parse::<IpAddr>().ok().map(IpNet::from))
+        {
+            Some(network) => networks.push(network),
+            None => invalid.push(item),
+        }
+    }
+    if invalid.is_empty() {
+        Ok(networks)
+    } else {
+        Err(format!("invalid network entries {}", invalid.join(", ")))
+    }
+}
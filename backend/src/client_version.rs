@@ -0,0 +1,88 @@
+use std::cmp::Ordering;
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tracing::info;
+
+use crate::AppState;
+use crate::error_codes::ErrorCode;
+use crate::terms::compare_versions;
+
+pub const CLIENT_VERSION_HEADER: &str = "x-client-version";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpgradeRequiredResponse {
+    error: String,
+    code: ErrorCode,
+    platform: String,
+    minimum_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upgrade_url: Option<String>,
+}
+
+/// Turns away auth requests from app builds older than the
+/// `MIN_CLIENT_VERSIONS` entry for their platform. Clients identify
+/// themselves as `X-Client-Version: <platform>/<version>`, e.g.
+/// `ios/2.3.1`; requests without the header, or from a platform with no
+/// minimum, are let through so browsers and integrations keep working.
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some((platform, version)) = client_version(request.headers()) else {
+        return next.run(request).await;
+    };
+    let Some(minimum) = lookup(&state.config.min_client_versions, &platform) else {
+        return next.run(request).await;
+    };
+    if compare_versions(&version, minimum) != Ordering::Less {
+        return next.run(request).await;
+    }
+
+    info!(
+        "[ClientVersion] rejected {} {} below minimum {} path={}",
+        platform,
+        version,
+        minimum,
+        request.uri().path()
+    );
+    state
+        .metrics
+        .client_upgrade_required
+        .with_label_values(&[&platform])
+        .inc();
+
+    (
+        StatusCode::UPGRADE_REQUIRED,
+        Json(UpgradeRequiredResponse {
+            error: format!("This version of the app is no longer supported, please update to {minimum} or later"),
+            code: ErrorCode::UpgradeRequired,
+            minimum_version: minimum.to_owned(),
+            upgrade_url: lookup(&state.config.client_upgrade_urls, &platform).map(str::to_owned),
+            platform,
+        }),
+    )
+        .into_response()
+}
+
+/// The lower-cased platform and the version from `X-Client-Version`.
+fn client_version(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(CLIENT_VERSION_HEADER)?.to_str().ok()?;
+    let (platform, version) = value.trim().split_once('/')?;
+    let (platform, version) = (platform.trim(), version.trim());
+    if platform.is_empty() || version.is_empty() {
+        return None;
+    }
+    Some((platform.to_ascii_lowercase(), version.to_owned()))
+}
+
+fn lookup<'a>(entries: &'a [(String, String)], platform: &str) -> Option<&'a str> {
+    entries
+        .iter()
+        .find(|(name, _)| name == platform)
+        .map(|(_, value)| value.as_str())
+}
@@ -0,0 +1,9 @@
+use std::time::Duration;
+
+/// Seconds of a token's `expires_in` worth relying on when the clocks of
+/// Keycloak, this host and the client may disagree by up to `leeway`
+/// (`CLOCK_SKEW_LEEWAY_SECS`). At most half the lifetime is given up, so a
+/// large leeway cannot make a short-lived token look expired on arrival.
+pub fn usable_lifetime(expires_in: u64, leeway: Duration) -> u64 {
+    expires_in - leeway.as_secs().min(expires_in / 2)
+}
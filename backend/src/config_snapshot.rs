@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+
+use axum::http::HeaderName;
+use hmac::Mac;
+use serde::Serializer;
+use serde_json::Value;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::audit::AuditOutcome;
+use crate::util::hmac_sha256;
+use crate::{AppConfig, AppState};
+
+const ACTION: &str = "config.snapshot";
+const ACTOR: &str = "portal";
+/// Recorded alongside the settings, so upgrades show up in the diff.
+const VERSION_KEY: &str = "version";
+/// Stands in for secrets without `CONFIG_SNAPSHOT_SECRET`.
+const REDACTED: &str = "redacted";
+
+/// The settings in effect, by `AppConfig` field. Secrets are kept as an
+/// HMAC under `CONFIG_SNAPSHOT_SECRET` that changes with them, or as
+/// `redacted` without that secret.
+type Snapshot = BTreeMap<String, String>;
+
+/// Records the effective configuration in the audit log as a
+/// `config.snapshot` event (the settings as JSON in `target`) at startup,
+/// after each reload and then every `CONFIG_SNAPSHOT_INTERVAL_SECS` (3600
+/// by default, 0 turns snapshots off). A snapshot equal to the last one
+/// recorded is not recorded again; otherwise the settings that were added,
+/// removed or changed since are logged. The last snapshot is kept in
+/// `CONFIG_SNAPSHOT_FILE` when set, so the comparison spans restarts and
+/// the audit log answers what changed before a deployment misbehaved.
+pub struct ConfigSnapshots {
+    key: Option<Vec<u8>>,
+    file: Option<String>,
+    last: Mutex<Option<Snapshot>>,
+}
+
+impl ConfigSnapshots {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let last = config.config_snapshot_file.as_deref().and_then(|path| {
+            let contents = fs::read_to_string(path)
+                .inspect_err(|err| {
+                    if err.kind() != std::io::ErrorKind::NotFound {
+                        warn!("[ConfigSnapshot] unable to read {}: {}", path, err);
+                    }
+                })
+                .ok()?;
+            serde_json::from_str(&contents)
+                .inspect_err(|err| warn!("[ConfigSnapshot] ignoring {}: {}", path, err))
+                .ok()
+        });
+
+        Self {
+            key: config
+                .config_snapshot_secret
+                .as_ref()
+                .map(|secret| secret.as_bytes().to_vec()),
+            file: config.config_snapshot_file.clone(),
+            last: Mutex::new(last),
+        }
+    }
+
+    fn capture(&self, config: &AppConfig) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        let Ok(Value::Object(settings)) = serde_json::to_value(config) else {
+            return snapshot;
+        };
+        for (name, value) in settings {
+            if value.is_null() {
+                continue;
+            }
+            let value = self.redact(&name, value);
+            let value = match value {
+                Value::String(value) => value,
+                other => other.to_string(),
+            };
+            snapshot.insert(name, value);
+        }
+        snapshot.insert(VERSION_KEY.to_owned(), env!("CARGO_PKG_VERSION").to_owned());
+        snapshot
+    }
+
+    /// Replaces the whole value of a secret setting, and of secret fields in
+    /// nested settings such as tenants, with its digest.
+    fn redact(&self, name: &str, value: Value) -> Value {
+        if is_secret(name, &value) {
+            return Value::String(self.digest(&value));
+        }
+        match value {
+            Value::Object(fields) => Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(field, value)| {
+                        let value = self.redact(&field, value);
+                        (field, value)
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(
+                items
+                    .into_iter()
+                    .map(|item| self.redact(name, item))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    fn digest(&self, value: &Value) -> String {
+        let Some(key) = &self.key else {
+            return REDACTED.to_owned();
+        };
+        let mut mac = hmac_sha256(key);
+        mac.update(value.to_string().as_bytes());
+        format!("hmac:{}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn lock_last(&self) -> std::sync::MutexGuard<'_, Option<Snapshot>> {
+        self.last.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+pub fn spawn(state: AppState) {
+    let Some(interval) = state.config.config_snapshot_interval else {
+        return;
+    };
+    tokio::spawn(async move {
+        loop {
+            record(&state);
+            sleep(interval).await;
+        }
+    });
+}
+
+/// Records a snapshot of the live configuration unless it equals the last
+/// one.
+pub fn record(state: &AppState) {
+    let snapshots = &state.config_snapshots;
+    let current = snapshots.capture(&state.live.current());
+    let previous = snapshots.lock_last().replace(current.clone());
+
+    match previous {
+        Some(previous) if previous == current => {
+            debug!("[ConfigSnapshot] configuration unchanged");
+            return;
+        }
+        Some(previous) => warn!(
+            "[ConfigSnapshot] configuration changed since the last snapshot: {}",
+            diff(&previous, &current).join("; ")
+        ),
+        None => info!(
+            "[ConfigSnapshot] recording the first snapshot of {} settings",
+            current.len() - 1
+        ),
+    }
+
+    let target = serde_json::to_string(&current).unwrap_or_default();
+    if let Some(path) = snapshots.file.clone() {
+        let contents = target.clone();
+        tokio::spawn(async move {
+            if let Err(err) = tokio::fs::write(&path, contents).await {
+                warn!("[ConfigSnapshot] unable to write {}: {}", path, err);
+            }
+        });
+    }
+    state
+        .audit
+        .record(ACTOR, ACTION, AuditOutcome::Success, Some(&target), None);
+}
+
+/// Serializes header names for the snapshot.
+pub fn header_names<S: Serializer>(names: &[HeaderName], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(names.iter().map(HeaderName::as_str))
+}
+
+/// Anything named a secret, a password, a key or a token, the alert and
+/// metrics push URLs, and URLs that carry credentials. `name` is a field
+/// name in snake or camel case.
+fn is_secret(name: &str, value: &Value) -> bool {
+    let name = setting_name(name);
+    let last = name.rsplit('_').next().unwrap_or_default();
+    name.split('_').any(|part| part == "SECRET")
+        || matches!(last, "PASSWORD" | "KEY" | "KEYS" | "TOKEN")
+        || matches!(name.as_str(), "ALERT_WEBHOOK_URL" | "METRICS_PUSH_URL")
+        || value.as_str().is_some_and(|value| {
+            reqwest::Url::parse(value)
+                .is_ok_and(|url| !url.username().is_empty() || url.password().is_some())
+        })
+}
+
+/// `turnstileSecretKey` and `turnstile_secret_key` as `TURNSTILE_SECRET_KEY`.
+fn setting_name(name: &str) -> String {
+    let mut setting = String::with_capacity(name.len() + 4);
+    for ch in name.chars() {
+        if ch.is_ascii_uppercase() && !setting.is_empty() {
+            setting.push('_');
+        }
+        setting.push(ch.to_ascii_uppercase());
+    }
+    setting
+}
+
+fn diff(previous: &Snapshot, current: &Snapshot) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (name, value) in current {
+        match previous.get(name) {
+            None => changes.push(format!("{name} set to {value:?}")),
+            Some(old) if old != value => changes.push(format!("{name} {old:?} -> {value:?}")),
+            Some(_) => {}
+        }
+    }
+    for name in previous.keys().filter(|name| !current.contains_key(*name)) {
+        changes.push(format!("{name} unset"));
+    }
+    changes
+}
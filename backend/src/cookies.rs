@@ -0,0 +1,136 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use axum::http::{HeaderMap, HeaderValue, header};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::AppConfig;
+
+/// Clients opt into cookie mode by sending `X-Token-Delivery: cookie`.
+pub const TOKEN_DELIVERY_HEADER: &str = "x-token-delivery";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Encrypts cookie values with AES-256-GCM under the keys in `COOKIE_KEYS`.
+/// The first key seals new cookies; every listed key can still open them, so
+/// a new key is rotated in by prepending it and the old one dropped once its
+/// cookies have expired. Each sealed value names its key by a short id and
+/// is bound to the cookie name.
+pub struct CookieKeys {
+    keys: Vec<(String, Aes256Gcm)>,
+}
+
+impl CookieKeys {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let keys = config
+            .cookie_keys
+            .iter()
+            .filter_map(|encoded| match STANDARD.decode(encoded.trim()) {
+                Ok(bytes) if bytes.len() == KEY_LEN => {
+                    let id = hex::encode(&Sha256::digest(&bytes)[..4]);
+                    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes));
+                    Some((id, cipher))
+                }
+                _ => {
+                    warn!("[Cookies] ignoring COOKIE_KEYS entry that is not 32 base64 bytes");
+                    None
+                }
+            })
+            .collect();
+
+        Self { keys }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub fn seal(&self, name: &str, value: &str) -> Option<String> {
+        let (id, cipher) = self.keys.first()?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: name.as_bytes(),
+                },
+            )
+            .ok()?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Some(format!("{id}.{}", URL_SAFE_NO_PAD.encode(sealed)))
+    }
+
+    pub fn open(&self, name: &str, sealed: &str) -> Option<String> {
+        let (id, encoded) = sealed.split_once('.')?;
+        let (_, cipher) = self.keys.iter().find(|(key_id, _)| key_id == id)?;
+        let bytes = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+        if bytes.len() <= NONCE_LEN {
+            return None;
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+}
+
+/// A fresh random key in the format `COOKIE_KEYS` expects.
+pub fn generate_key() -> String {
+    let mut key = [0u8; KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut key);
+    STANDARD.encode(key)
+}
+
+pub fn wants_cookie(headers: &HeaderMap) -> bool {
+    headers
+        .get(TOKEN_DELIVERY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("cookie"))
+}
+
+pub fn read_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// `Set-Cookie` for the refresh token cookie, scoped to the auth routes.
+pub fn set_cookie(config: &AppConfig, value: &str, max_age: u64) -> Option<HeaderValue> {
+    let mut cookie = format!(
+        "{}={}; Path=/api/auth; Max-Age={}; HttpOnly; SameSite=Strict",
+        config.auth_cookie_name, value, max_age
+    );
+    if config.auth_cookie_secure {
+        cookie.push_str("; Secure");
+    }
+    if let Some(domain) = &config.auth_cookie_domain {
+        cookie.push_str("; Domain=");
+        cookie.push_str(domain);
+    }
+    HeaderValue::from_str(&cookie).ok()
+}
+
+pub fn clear_cookie(config: &AppConfig) -> Option<HeaderValue> {
+    set_cookie(config, "", 0)
+}
@@ -0,0 +1,90 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use reqwest::RequestBuilder;
+
+use crate::AppState;
+
+/// Client-supplied addressing headers such as `x-forwarded-for` are left out:
+/// Keycloak would take them for the portal's own proxy chain.
+pub const DEFAULT_CORRELATION_HEADERS: &str = "x-request-id,traceparent,tracestate";
+
+tokio::task_local! {
+    static CORRELATION: HeaderMap;
+}
+
+/// Picks the `CORRELATION_HEADERS` out of the inbound request so Keycloak
+/// calls made while handling it carry them through [`WithCorrelation`].
+pub async fn capture(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let mut forwarded = HeaderMap::new();
+    for name in &state.config.correlation_headers {
+        for value in request.headers().get_all(name) {
+            forwarded.append(name.clone(), value.clone());
+        }
+    }
+
+    if forwarded.is_empty() {
+        return next.run(request).await;
+    }
+    CORRELATION.scope(forwarded, next.run(request)).await
+}
+
+/// The trace ID of the inbound `traceparent`, when the caller sampled the
+/// trace, so that a metric can point at it. Nothing unless `traceparent`
+/// is one of the `CORRELATION_HEADERS`.
+pub fn sampled_trace_id() -> Option<String> {
+    CORRELATION
+        .try_with(|headers| {
+            headers
+                .get("traceparent")
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_traceparent)
+        })
+        .ok()
+        .flatten()
+}
+
+/// `<version>-<trace id>-<parent id>-<flags>`, per W3C Trace Context; only
+/// the trace ID of a sampled trace is returned.
+fn parse_traceparent(value: &str) -> Option<String> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let hex = |part: &str, len: usize| {
+        part.len() == len
+            && part
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+    };
+    if !hex(version, 2) || version == "ff" || !hex(trace_id, 32) || !hex(parent_id, 16) {
+        return None;
+    }
+    if trace_id.bytes().all(|byte| byte == b'0') {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    (flags & 1 == 1).then(|| trace_id.to_owned())
+}
+
+pub fn parse_header_names(names: &[String]) -> Vec<HeaderName> {
+    names
+        .iter()
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect()
+}
+
+pub trait WithCorrelation {
+    fn with_correlation(self) -> Self;
+}
+
+impl WithCorrelation for RequestBuilder {
+    fn with_correlation(self) -> Self {
+        match CORRELATION.try_with(HeaderMap::clone) {
+            Ok(headers) => self.headers(headers),
+            Err(_) => self,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use reqwest::RequestBuilder;
+use tokio::time::timeout;
+use tracing::warn;
+
+use crate::error_codes::ErrorCode;
+use crate::extract::Rejection;
+use crate::models::user::ErrorResponse;
+use crate::{AppConfig, AppState};
+
+/// Time kept back from the request budget so an upstream call times out, and
+/// the handler can still answer, before the route deadline itself fires.
+const UPSTREAM_MARGIN: Duration = Duration::from_millis(250);
+const UPSTREAM_MIN_TIMEOUT: Duration = Duration::from_millis(50);
+
+tokio::task_local! {
+    static DEADLINE: Option<Instant>;
+}
+
+/// Budget for `path`: the longest matching `ROUTE_TIMEOUTS` prefix, otherwise
+/// `REQUEST_TIMEOUT_SECS`.
+pub fn route_budget(config: &AppConfig, path: &str) -> Duration {
+    config
+        .route_timeouts
+        .iter()
+        .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, budget)| *budget)
+        .unwrap_or(config.request_timeout)
+}
+
+/// Runs the rest of the stack under the route's deadline. Upstream calls made
+/// from within pick it up through [`WithDeadline`]; a handler that overruns
+/// anyway is cancelled, which also drops any request it still has in flight.
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let budget = route_budget(&state.config, request.uri().path());
+    if budget.is_zero() {
+        return next.run(request).await;
+    }
+
+    let path = request.uri().path().to_owned();
+    let deadline = Instant::now() + budget;
+    match timeout(budget, DEADLINE.scope(Some(deadline), next.run(request))).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(
+                "[Deadline] path={} exceeded budget of {}ms",
+                path,
+                budget.as_millis()
+            );
+            exceeded().into_response()
+        }
+    }
+}
+
+pub fn exceeded() -> Rejection {
+    (
+        StatusCode::GATEWAY_TIMEOUT,
+        Json(ErrorResponse::new(
+            ErrorCode::UpstreamTimeout,
+            "Identity provider did not respond in time".to_owned(),
+        )),
+    )
+}
+
+/// What is left of the current request's budget for an upstream call, or
+/// `None` outside a request.
+pub fn upstream_timeout() -> Option<Duration> {
+    DEADLINE
+        .try_with(|deadline| {
+            deadline.map(|deadline| {
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .saturating_sub(UPSTREAM_MARGIN)
+                    .max(UPSTREAM_MIN_TIMEOUT)
+            })
+        })
+        .ok()
+        .flatten()
+}
+
+/// Runs `future` without the current request's deadline, for work such as
+/// a token refresh whose result other requests share.
+pub async fn detached<F: Future>(future: F) -> F::Output {
+    DEADLINE.scope(None, future).await
+}
+
+pub trait WithDeadline {
+    fn with_deadline(self) -> Self;
+}
+
+impl WithDeadline for RequestBuilder {
+    fn with_deadline(self) -> Self {
+        match upstream_timeout() {
+            Some(remaining) => self.timeout(remaining),
+            None => self,
+        }
+    }
+}
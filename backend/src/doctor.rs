@@ -0,0 +1,245 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::{Client, Url, header::DATE};
+use tokio::net::{UdpSocket, lookup_host};
+use tokio::time::timeout;
+
+use crate::AppConfig;
+use crate::captcha::CaptchaProvider;
+use crate::keycloak::KeycloakService;
+use crate::metrics::Metrics;
+use crate::session::{self, SessionBackend};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Seconds between the NTP era (1900) and the Unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// One line of the `doctor` report.
+struct Check {
+    name: String,
+    outcome: Result<String, String>,
+}
+
+/// Runs the pre-flight checks against the current configuration, prints a
+/// pass/fail report and returns whether every check passed.
+pub async fn run(config: &AppConfig, keycloak_client: Client) -> bool {
+    let mut checks = Vec::new();
+
+    let mut hosts = vec![("Keycloak", config.keycloak_base_url.clone())];
+    if config.captcha_provider == CaptchaProvider::Turnstile
+        && config.turnstile_secret_key.is_some()
+    {
+        hosts.push(("Turnstile", config.turnstile_verify_url.clone()));
+    }
+
+    for (label, url) in &hosts {
+        checks.push(Check {
+            name: format!("{label} DNS"),
+            outcome: resolve(url).await,
+        });
+        checks.push(Check {
+            name: format!("{label} TLS"),
+            outcome: verify_tls(url).await,
+        });
+    }
+
+    let keycloak = KeycloakService::new(config, keycloak_client.clone(), Arc::new(Metrics::new()));
+    checks.push(Check {
+        name: "Admin credentials".to_owned(),
+        outcome: within(keycloak.ensure_token())
+            .await
+            .and_then(|result| result.map_err(|err| err.to_string()))
+            .map(|_| format!("token issued to {}", config.keycloak_admin_client_id)),
+    });
+    checks.push(Check {
+        name: "Realm".to_owned(),
+        outcome: within(keycloak.realm_exists())
+            .await
+            .and_then(|result| result.map_err(|err| err.to_string()))
+            .and_then(|exists| {
+                let realm = &config.keycloak_realm;
+                if exists {
+                    Ok(format!("{realm} exists"))
+                } else {
+                    Err(format!("{realm} not found"))
+                }
+            }),
+    });
+
+    checks.push(Check {
+        name: "Keycloak clock".to_owned(),
+        outcome: check_keycloak_clock(config, &keycloak_client).await,
+    });
+    if let Some(server) = &config.ntp_server {
+        checks.push(Check {
+            name: "NTP clock".to_owned(),
+            outcome: check_ntp_clock(config, server).await,
+        });
+    }
+
+    if config.session_backend == SessionBackend::Redis {
+        checks.push(Check {
+            name: "Redis".to_owned(),
+            outcome: check_redis(config).await,
+        });
+    }
+
+    let mut healthy = true;
+    for check in &checks {
+        let (status, detail) = match &check.outcome {
+            Ok(detail) => ("PASS", detail),
+            Err(detail) => {
+                healthy = false;
+                ("FAIL", detail)
+            }
+        };
+        println!("[{status}] {:<20} {detail}", check.name);
+    }
+    println!(
+        "{} of {} checks passed",
+        checks.iter().filter(|check| check.outcome.is_ok()).count(),
+        checks.len()
+    );
+
+    healthy
+}
+
+async fn within<F: Future>(future: F) -> Result<F::Output, String> {
+    timeout(CHECK_TIMEOUT, future)
+        .await
+        .map_err(|_| format!("timed out after {}s", CHECK_TIMEOUT.as_secs()))
+}
+
+async fn resolve(url: &str) -> Result<String, String> {
+    let url = Url::parse(url).map_err(|err| format!("invalid URL {url}: {err}"))?;
+    let host = url.host_str().ok_or("URL has no host")?.to_owned();
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addresses: Vec<_> = within(lookup_host((host.as_str(), port)))
+        .await?
+        .map_err(|err| format!("{host}: {err}"))?
+        .collect();
+    match addresses.first() {
+        Some(address) => Ok(format!("{host} -> {}", address.ip())),
+        None => Err(format!("{host} did not resolve")),
+    }
+}
+
+/// Any HTTP answer over a certificate-verifying client proves the chain and
+/// hostname are valid; `KEYCLOAK_TLS_INSECURE` is deliberately ignored here.
+async fn verify_tls(url: &str) -> Result<String, String> {
+    let parsed = Url::parse(url).map_err(|err| format!("invalid URL {url}: {err}"))?;
+    if parsed.scheme() != "https" {
+        return Err(format!(
+            "{} is not served over https",
+            parsed.origin().ascii_serialization()
+        ));
+    }
+
+    let client = Client::new();
+    within(client.head(parsed.clone()).send())
+        .await?
+        .map(|_| "certificate valid".to_owned())
+        .map_err(|err| format!("{err:#}"))
+}
+
+/// Token lifetimes are measured on Keycloak's clock, so this host's clock is
+/// compared with the `Date` header Keycloak answers with. The header only has
+/// whole seconds, which is well inside any useful leeway.
+async fn check_keycloak_clock(config: &AppConfig, client: &Client) -> Result<String, String> {
+    let sent = SystemTime::now();
+    let response = within(client.get(config.keycloak_realm_endpoint()).send())
+        .await?
+        .map_err(|err| format!("{err:#}"))?;
+    let received = SystemTime::now();
+
+    let date = response
+        .headers()
+        .get(DATE)
+        .and_then(|value| value.to_str().ok())
+        .ok_or("Keycloak sent no Date header")?;
+    let remote =
+        httpdate::parse_http_date(date).map_err(|_| format!("unreadable Date header {date:?}"))?;
+    clock_drift(config, "Keycloak", remote, sent, received)
+}
+
+/// A single SNTP exchange with `NTP_SERVER`.
+async fn check_ntp_clock(config: &AppConfig, server: &str) -> Result<String, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|err| err.to_string())?;
+    within(socket.connect((server, 123)))
+        .await?
+        .map_err(|err| format!("{server}: {err}"))?;
+
+    // Leap indicator 0, version 3, client mode.
+    let mut packet = [0u8; 48];
+    packet[0] = 0x1b;
+    let sent = SystemTime::now();
+    within(socket.send(&packet))
+        .await?
+        .map_err(|err| format!("{server}: {err}"))?;
+    let length = within(socket.recv(&mut packet))
+        .await?
+        .map_err(|err| format!("{server}: {err}"))?;
+    let received = SystemTime::now();
+    if length < packet.len() {
+        return Err(format!("{server} sent a short reply"));
+    }
+
+    let seconds = u64::from(u32::from_be_bytes(
+        packet[40..44].try_into().unwrap_or_default(),
+    ));
+    let fraction = u64::from(u32::from_be_bytes(
+        packet[44..48].try_into().unwrap_or_default(),
+    ));
+    let seconds = seconds
+        .checked_sub(NTP_UNIX_OFFSET)
+        .ok_or_else(|| format!("{server} sent an invalid timestamp"))?;
+    let remote = UNIX_EPOCH
+        + Duration::from_secs(seconds)
+        + Duration::from_nanos((fraction * 1_000_000_000) >> 32);
+    clock_drift(config, server, remote, sent, received)
+}
+
+/// Compares a remote timestamp with the midpoint of the exchange and fails
+/// when they are further apart than `CLOCK_SKEW_LEEWAY_SECS`.
+fn clock_drift(
+    config: &AppConfig,
+    source: &str,
+    remote: SystemTime,
+    sent: SystemTime,
+    received: SystemTime,
+) -> Result<String, String> {
+    let local = sent + received.duration_since(sent).unwrap_or_default() / 2;
+    let (drift, direction) = match remote.duration_since(local) {
+        Ok(behind) => (behind, "behind"),
+        Err(err) => (err.duration(), "ahead of"),
+    };
+    let leeway = config.clock_skew_leeway;
+    let summary = format!("{:.1}s {direction} {source}", drift.as_secs_f64());
+    if drift > leeway {
+        Err(format!(
+            "{summary}, more than the {}s CLOCK_SKEW_LEEWAY_SECS; check NTP on this host",
+            leeway.as_secs()
+        ))
+    } else {
+        Ok(format!("{summary}, within {}s leeway", leeway.as_secs()))
+    }
+}
+
+async fn check_redis(config: &AppConfig) -> Result<String, String> {
+    let store = within(session::connect(config))
+        .await?
+        .map_err(|err| err.to_string())?;
+
+    let key = "doctor:probe";
+    within(store.set(key, "ok", Duration::from_secs(10)))
+        .await?
+        .map_err(|err| err.to_string())?;
+    within(store.delete(key))
+        .await?
+        .map_err(|err| err.to_string())?;
+    Ok("session store reachable".to_owned())
+}
@@ -0,0 +1,169 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::{HeaderMap, Method};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::Jwk};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::AppConfig;
+use crate::session::SessionStore;
+use crate::util::unix_now;
+
+pub const DPOP_HEADER: &str = "dpop";
+const PROOF_TYPE: &str = "dpop+jwt";
+const REPLAY_KEY_PREFIX: &str = "dpop:jti:";
+
+#[derive(Debug, Error)]
+pub enum DpopError {
+    #[error("DPoP proof is malformed")]
+    Malformed,
+    #[error("DPoP proof signature is invalid")]
+    InvalidSignature,
+    #[error("DPoP proof does not match this request")]
+    RequestMismatch,
+    #[error("DPoP proof is outside the accepted time window")]
+    Stale,
+    #[error("DPoP proof was already used")]
+    Replayed,
+    #[error("DPoP replay check is unavailable")]
+    ReplayCheckUnavailable,
+    #[error("DPoP proof key does not match the token binding")]
+    KeyMismatch,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: u64,
+    #[serde(default)]
+    ath: Option<String>,
+}
+
+/// Validates RFC 9449 DPoP proofs presented with sender-constrained access
+/// tokens. Proof `htu` values are compared against `DPOP_PUBLIC_URL` plus the
+/// request path when set, otherwise against the path alone.
+pub struct DpopVerifier {
+    public_url: Option<String>,
+    max_age: Duration,
+    leeway: Duration,
+    sessions: Arc<dyn SessionStore>,
+}
+
+impl DpopVerifier {
+    pub fn from_config(config: &AppConfig, sessions: Arc<dyn SessionStore>) -> Self {
+        Self {
+            public_url: config
+                .dpop_public_url
+                .as_deref()
+                .map(|url| url.trim_end_matches('/').to_owned()),
+            max_age: config.dpop_proof_max_age,
+            leeway: config.clock_skew_leeway,
+            sessions,
+        }
+    }
+
+    /// Checks `proof` for this request and, when `access_token` is given, its
+    /// `ath` hash. Returns the JWK thumbprint of the proof key.
+    pub async fn verify(
+        &self,
+        proof: &str,
+        method: &Method,
+        path: &str,
+        access_token: Option<&str>,
+    ) -> Result<String, DpopError> {
+        let header = decode_header(proof).map_err(|_| DpopError::Malformed)?;
+        if header.typ.as_deref() != Some(PROOF_TYPE)
+            || matches!(
+                header.alg,
+                Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+            )
+        {
+            return Err(DpopError::Malformed);
+        }
+        let jwk = header.jwk.ok_or(DpopError::Malformed)?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|_| DpopError::Malformed)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        validation.leeway = self.leeway.as_secs();
+        let claims = decode::<ProofClaims>(proof, &key, &validation)
+            .map_err(|_| DpopError::InvalidSignature)?
+            .claims;
+
+        if !claims.htm.eq_ignore_ascii_case(method.as_str())
+            || !self.matches_target(&claims.htu, path)
+        {
+            return Err(DpopError::RequestMismatch);
+        }
+
+        let now = unix_now();
+        // The client's clock may be off from ours by up to the skew leeway.
+        if claims.iat.abs_diff(now) > (self.max_age + self.leeway).as_secs() {
+            return Err(DpopError::Stale);
+        }
+
+        if let Some(token) = access_token {
+            let expected = URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()));
+            if claims.ath.as_deref() != Some(expected.as_str()) {
+                return Err(DpopError::RequestMismatch);
+            }
+        }
+
+        // A proof stays acceptable for `max_age` plus the leeway either side
+        // of its iat, so its jti is remembered for twice that.
+        let fresh = self
+            .sessions
+            .set_if_absent(
+                &format!("{REPLAY_KEY_PREFIX}{}", claims.jti),
+                "1",
+                (self.max_age + self.leeway) * 2,
+            )
+            .await
+            .map_err(|_| DpopError::ReplayCheckUnavailable)?;
+        if !fresh {
+            return Err(DpopError::Replayed);
+        }
+
+        thumbprint(&jwk).ok_or(DpopError::Malformed)
+    }
+
+    fn matches_target(&self, htu: &str, path: &str) -> bool {
+        let htu = htu.split(['?', '#']).next().unwrap_or_default();
+        match &self.public_url {
+            Some(base) => htu == format!("{base}{path}"),
+            None => htu
+                .split_once("://")
+                .and_then(|(_, rest)| rest.find('/').map(|index| &rest[index..]))
+                .is_some_and(|htu_path| htu_path == path),
+        }
+    }
+}
+
+pub fn proof_header(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(DPOP_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// RFC 7638 thumbprint: SHA-256 over the key's required members in
+/// lexicographic order.
+fn thumbprint(jwk: &Jwk) -> Option<String> {
+    let value = serde_json::to_value(jwk).ok()?;
+    let member = |name: &str| value.get(name).cloned().unwrap_or(Value::Null);
+    let canonical = match value.get("kty")?.as_str()? {
+        "EC" => json!({ "crv": member("crv"), "kty": "EC", "x": member("x"), "y": member("y") }),
+        "RSA" => json!({ "e": member("e"), "kty": "RSA", "n": member("n") }),
+        "OKP" => json!({ "crv": member("crv"), "kty": "OKP", "x": member("x") }),
+        _ => return None,
+    };
+    Some(URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.to_string().as_bytes())))
+}
@@ -0,0 +1,76 @@
+pub const CANONICAL_EMAIL_ATTRIBUTE: &str = "canonicalEmail";
+
+const GMAIL_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+/// Which alias tricks `canonicalize` undoes, from `EMAIL_STRIP_PLUS_TAGS`
+/// and `EMAIL_STRIP_GMAIL_DOTS`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AliasRules {
+    pub strip_plus_tags: bool,
+    pub strip_gmail_dots: bool,
+}
+
+impl AliasRules {
+    pub fn any(self) -> bool {
+        self.strip_plus_tags || self.strip_gmail_dots
+    }
+}
+
+/// Trimmed, lowercased address used as the Keycloak username and for login.
+pub fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Canonical mailbox used to detect accounts that only differ by alias
+/// tricks. Which rewrites apply is deployment policy.
+pub fn canonicalize(rules: AliasRules, email: &str) -> String {
+    let normalized = normalize(email);
+    let Some((local, domain)) = normalized.rsplit_once('@') else {
+        return normalized;
+    };
+
+    let mut local = local.to_owned();
+    let mut domain = domain.to_owned();
+
+    if rules.strip_plus_tags
+        && let Some((base, _tag)) = local.split_once('+')
+        && !base.is_empty()
+    {
+        local = base.to_owned();
+    }
+
+    if rules.strip_gmail_dots && GMAIL_DOMAINS.contains(&domain.as_str()) {
+        local.retain(|ch| ch != '.');
+        domain = GMAIL_DOMAINS[0].to_owned();
+    }
+
+    format!("{local}@{domain}")
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn rules() -> impl Strategy<Value = AliasRules> {
+        (any::<bool>(), any::<bool>()).prop_map(|(strip_plus_tags, strip_gmail_dots)| AliasRules {
+            strip_plus_tags,
+            strip_gmail_dots,
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn normalize_is_idempotent(address in ".*") {
+            let normalized = normalize(&address);
+            prop_assert_eq!(normalize(&normalized), normalized);
+        }
+
+        #[test]
+        fn canonicalize_is_idempotent(rules in rules(), address in ".*(\\+.*)?@(gmail\\.com|.*)") {
+            let canonical = canonicalize(rules, &address);
+            prop_assert_eq!(canonicalize(rules, &canonical), canonical);
+        }
+    }
+}
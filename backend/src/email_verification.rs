@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::keycloak::{KeycloakError, KeycloakService};
+use crate::locale;
+use crate::models::user::UserRepresentation;
+use crate::preferences::{self, LOCALE_ATTRIBUTE};
+use crate::verified_redirect::VerifiedRedirects;
+
+/// What `UNVERIFIED_LOGIN` does with a user whose email is not verified once
+/// the password grant succeeded: `allow` leaves it to the realm, `block`
+/// holds the tokens back behind an `email_unverified` challenge until the
+/// address is verified, and `limited` releases them flagged with
+/// `emailUnverified` so the client can restrict what the user sees.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnverifiedLogin {
+    Allow,
+    Block,
+    Limited,
+}
+
+impl UnverifiedLogin {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "allow" => Some(Self::Allow),
+            "block" => Some(Self::Block),
+            "limited" => Some(Self::Limited),
+            _ => None,
+        }
+    }
+}
+
+pub struct EmailVerification {
+    keycloak: Arc<KeycloakService>,
+    policy: UnverifiedLogin,
+    redirects: VerifiedRedirects,
+}
+
+impl EmailVerification {
+    pub fn from_config(config: &AppConfig, keycloak: Arc<KeycloakService>) -> Self {
+        Self {
+            keycloak,
+            policy: config.unverified_login,
+            redirects: VerifiedRedirects::from_config(config),
+        }
+    }
+
+    pub fn policy(&self) -> UnverifiedLogin {
+        self.policy
+    }
+
+    pub fn redirects(&self) -> &VerifiedRedirects {
+        &self.redirects
+    }
+
+    /// Whether the user's email is verified, read fresh from Keycloak so a
+    /// continuation sees a verification that just happened. `None` when the
+    /// policy is `allow` or the lookup failed, which does not block the
+    /// login.
+    pub async fn is_verified(&self, email: &str) -> Option<bool> {
+        if self.policy == UnverifiedLogin::Allow {
+            return None;
+        }
+
+        let id = self.user(email).await?.id;
+        self.keycloak
+            .get_user(&id)
+            .await
+            .inspect_err(|err| warn!("[Login] user lookup failed for {}: {}", email, err))
+            .ok()
+            .map(|user| user.email_verified)
+    }
+
+    /// Has Keycloak send the verification email again, leading back to the
+    /// verified callback when one is configured. The email is in the user's
+    /// stored locale, or the first of the browser's `accept_language`.
+    pub async fn resend(
+        &self,
+        email: &str,
+        accept_language: Option<&str>,
+    ) -> Result<(), KeycloakError> {
+        let Some(user) = self.user(email).await else {
+            return Ok(());
+        };
+        let stored = preferences::attribute(&user, LOCALE_ATTRIBUTE);
+        let locale = locale::preferred(stored, accept_language)
+            .into_iter()
+            .next();
+        let redirect_uri = self.redirects.callback_for(&user.id, locale.as_deref());
+        self.keycloak
+            .send_verify_email(&user.id, redirect_uri.as_deref(), locale.as_deref())
+            .await
+    }
+
+    async fn user(&self, email: &str) -> Option<UserRepresentation> {
+        let users = self
+            .keycloak
+            .find_users_by_email(email)
+            .await
+            .inspect_err(|err| warn!("[Login] user lookup failed for {}: {}", email, err))
+            .ok()?;
+        match users.as_slice() {
+            [user] => Some(user.clone()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use axum::http::StatusCode;
+use serde::Serialize;
+
+/// Declares `ErrorCode` and its `ALL` list from one set of variants, so no
+/// code can be left out of the registry.
+macro_rules! error_codes {
+    ($(#[$meta:meta])* $($variant:ident,)*) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize)]
+        #[serde(rename_all = "snake_case")]
+        pub enum ErrorCode {
+            $($variant,)*
+        }
+
+        impl ErrorCode {
+            pub const ALL: &'static [ErrorCode] = &[$(Self::$variant,)*];
+        }
+    };
+}
+
+error_codes! {
+    /// Stable, machine-readable reason sent as `code` in every error response.
+    /// Clients should branch on these rather than on the English `error` text,
+    /// which may be reworded at any time. Codes are only ever added; a code that
+    /// is no longer produced stays listed so old clients keep compiling against
+    /// the registry at `/api/error-codes`.
+    InvalidRequest,
+    MalformedBody,
+    UnsupportedMediaType,
+    NotAcceptable,
+    PayloadTooLarge,
+    ValidationFailed,
+    Underage,
+    InvalidCredentials,
+    UseSocialLogin,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    RateLimited,
+    CaptchaRequired,
+    CaptchaRejected,
+    CaptchaUnavailable,
+    EmailExists,
+    UsernameTaken,
+    InvalidPartnerAssertion,
+    InvalidReferral,
+    RegistrationExpired,
+    NotSignedIn,
+    SessionEnded,
+    TooManySessions,
+    TermsChanged,
+    VerificationThrottled,
+    UpgradeRequired,
+    SignatureRequired,
+    SignatureInvalid,
+    SignatureStale,
+    RequestReplayed,
+    SourceBlocked,
+    UpstreamTimeout,
+    UpstreamUnavailable,
+    UpstreamError,
+    ServiceUnavailable,
+    ReadOnly,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::MalformedBody => "malformed_body",
+            Self::UnsupportedMediaType => "unsupported_media_type",
+            Self::NotAcceptable => "not_acceptable",
+            Self::PayloadTooLarge => "payload_too_large",
+            Self::ValidationFailed => "validation_failed",
+            Self::Underage => "underage",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::UseSocialLogin => "use_social_login",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::NotFound => "not_found",
+            Self::Conflict => "conflict",
+            Self::RateLimited => "rate_limited",
+            Self::CaptchaRequired => "captcha_required",
+            Self::CaptchaRejected => "captcha_rejected",
+            Self::CaptchaUnavailable => "captcha_unavailable",
+            Self::EmailExists => "email_exists",
+            Self::UsernameTaken => "username_taken",
+            Self::InvalidPartnerAssertion => "invalid_partner_assertion",
+            Self::InvalidReferral => "invalid_referral",
+            Self::RegistrationExpired => "registration_expired",
+            Self::NotSignedIn => "not_signed_in",
+            Self::SessionEnded => "session_ended",
+            Self::TooManySessions => "too_many_sessions",
+            Self::TermsChanged => "terms_changed",
+            Self::VerificationThrottled => "verification_throttled",
+            Self::UpgradeRequired => "upgrade_required",
+            Self::SignatureRequired => "signature_required",
+            Self::SignatureInvalid => "signature_invalid",
+            Self::SignatureStale => "signature_stale",
+            Self::RequestReplayed => "request_replayed",
+            Self::SourceBlocked => "source_blocked",
+            Self::UpstreamTimeout => "upstream_timeout",
+            Self::UpstreamUnavailable => "upstream_unavailable",
+            Self::UpstreamError => "upstream_error",
+            Self::ServiceUnavailable => "service_unavailable",
+            Self::ReadOnly => "read_only",
+            Self::InternalError => "internal_error",
+        }
+    }
+
+    /// The status the code is normally sent with.
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::InvalidRequest | Self::MalformedBody | Self::CaptchaRequired => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::ValidationFailed
+            | Self::Underage
+            | Self::CaptchaRejected
+            | Self::InvalidReferral => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::InvalidCredentials
+            | Self::Unauthorized
+            | Self::InvalidPartnerAssertion
+            | Self::NotSignedIn
+            | Self::SessionEnded
+            | Self::SignatureRequired
+            | Self::SignatureInvalid
+            | Self::SignatureStale => StatusCode::UNAUTHORIZED,
+            Self::Forbidden | Self::SourceBlocked => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict
+            | Self::EmailExists
+            | Self::UseSocialLogin
+            | Self::UsernameTaken
+            | Self::TooManySessions
+            | Self::TermsChanged
+            | Self::RequestReplayed => StatusCode::CONFLICT,
+            Self::RateLimited | Self::VerificationThrottled => StatusCode::TOO_MANY_REQUESTS,
+            Self::RegistrationExpired => StatusCode::BAD_REQUEST,
+            Self::UpgradeRequired => StatusCode::UPGRADE_REQUIRED,
+            Self::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::UpstreamUnavailable | Self::UpstreamError | Self::CaptchaUnavailable => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::ServiceUnavailable | Self::ReadOnly => StatusCode::SERVICE_UNAVAILABLE,
+            Self::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::InvalidRequest => {
+                "The request is missing a required value or has one that is not allowed."
+            }
+            Self::MalformedBody => "The body could not be parsed as the declared content type.",
+            Self::UnsupportedMediaType => {
+                "The body was sent with a content type the endpoint does not accept."
+            }
+            Self::NotAcceptable => {
+                "`Accept` names only response versions the server does not provide."
+            }
+            Self::PayloadTooLarge => "The body is larger than the endpoint accepts.",
+            Self::ValidationFailed => "One or more fields are invalid; see `fields` for details.",
+            Self::Underage => "The date of birth is below the minimum registration age.",
+            Self::InvalidCredentials => "The email and password, or one-time code, do not match.",
+            Self::UseSocialLogin => {
+                "The account has no password; sign in with a provider from `hint.identityProviders`."
+            }
+            Self::Unauthorized => "The bearer token is missing, expired or revoked.",
+            Self::Forbidden => "The caller is authenticated but lacks the required scope or role.",
+            Self::NotFound => "The addressed resource does not exist.",
+            Self::Conflict => "The request conflicts with the current state of the resource.",
+            Self::RateLimited => "Too many requests; retry after the interval in `Retry-After`.",
+            Self::CaptchaRequired => "A captcha token is required for this action.",
+            Self::CaptchaRejected => "The captcha token or exemption was not accepted.",
+            Self::CaptchaUnavailable => "The captcha provider could not be reached; retry shortly.",
+            Self::EmailExists => "An account with this email already exists.",
+            Self::UsernameTaken => "An account with this username already exists.",
+            Self::InvalidPartnerAssertion => {
+                "The partner assertion is invalid or names another email."
+            }
+            Self::InvalidReferral => "The referral code is unknown or no longer valid.",
+            Self::RegistrationExpired => {
+                "The registration form is too old; reload it and submit again."
+            }
+            Self::NotSignedIn => "There is no session to check.",
+            Self::SessionEnded => "The session was signed out or revoked; sign in again.",
+            Self::TooManySessions => "The user is signed in on as many devices as allowed.",
+            Self::TermsChanged => "The terms of service changed while the user was reviewing them.",
+            Self::VerificationThrottled => "A verification email was sent moments ago.",
+            Self::UpgradeRequired => "The app version is below the supported minimum.",
+            Self::SignatureRequired => "The internal request is not signed.",
+            Self::SignatureInvalid => "The internal request signature does not match.",
+            Self::SignatureStale => "The signed request timestamp is outside the accepted window.",
+            Self::RequestReplayed => "The signed request was already received.",
+            Self::SourceBlocked => "Registrations from the client's network are not accepted.",
+            Self::UpstreamTimeout => {
+                "The identity provider did not answer before the request deadline."
+            }
+            Self::UpstreamUnavailable => "The identity provider could not be reached.",
+            Self::UpstreamError => "The identity provider answered with an unexpected error.",
+            Self::ServiceUnavailable => "A dependency of this endpoint is temporarily unavailable.",
+            Self::ReadOnly => {
+                "Changes are paused while the portal runs read-only; reads and sign-in still work."
+            }
+            Self::InternalError => "The server could not complete the request.",
+        }
+    }
+
+    /// The generic code for a status, for helpers that only know the status.
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => Self::InvalidRequest,
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::FORBIDDEN => Self::Forbidden,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::CONFLICT => Self::Conflict,
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => Self::UnsupportedMediaType,
+            StatusCode::NOT_ACCEPTABLE => Self::NotAcceptable,
+            StatusCode::UNPROCESSABLE_ENTITY => Self::ValidationFailed,
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            StatusCode::BAD_GATEWAY => Self::UpstreamError,
+            StatusCode::SERVICE_UNAVAILABLE => Self::ServiceUnavailable,
+            StatusCode::GATEWAY_TIMEOUT => Self::UpstreamTimeout,
+            _ => Self::InternalError,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorCodeEntry {
+    pub code: ErrorCode,
+    pub status: u16,
+    pub description: &'static str,
+}
+
+pub fn registry() -> Vec<ErrorCodeEntry> {
+    ErrorCode::ALL
+        .iter()
+        .map(|&code| ErrorCodeEntry {
+            code,
+            status: code.status().as_u16(),
+            description: code.description(),
+        })
+        .collect()
+}
+
+/// The registry as a Markdown table, for pasting into integration docs.
+pub fn registry_markdown() -> String {
+    let mut table = String::from("| Code | Status | Description |\n| --- | --- | --- |\n");
+    for entry in registry() {
+        table.push_str(&format!(
+            "| `{}` | {} | {} |\n",
+            entry.code.as_str(),
+            entry.status,
+            entry.description
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn as_str_matches_the_serialized_code() {
+        for &code in ErrorCode::ALL {
+            assert_eq!(
+                serde_json::to_value(code).unwrap(),
+                serde_json::Value::from(code.as_str())
+            );
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::AppConfig;
+use crate::keycloak::{KeycloakError, KeycloakEvent, KeycloakService};
+use crate::keycloak_limiter::{Priority, prioritized};
+use crate::session::SessionStore;
+use crate::util::{unix_millis, unix_now};
+use crate::webhooks::WebhookService;
+
+/// Events fetched per page; a poll pages back until it reaches events it
+/// has seen, for at most `MAX_PAGES`.
+const POLL_BATCH: usize = 100;
+const MAX_PAGES: usize = 50;
+
+const CURSOR_KEY: &str = "event-bridge:cursor";
+const CURSOR_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Portal event type for a Keycloak event type. Types without a dedicated
+/// name are published as `keycloak.<type>`, e.g. `keycloak.code_to_token`.
+fn portal_event(kind: &str) -> String {
+    match kind {
+        "LOGIN_ERROR" => "user.login_failed".to_owned(),
+        "UPDATE_PASSWORD" => "user.password_changed".to_owned(),
+        "DELETE_ACCOUNT" => "user.deleted".to_owned(),
+        other => format!("keycloak.{}", other.to_ascii_lowercase()),
+    }
+}
+
+/// Polls the realm's user events every `KEYCLOAK_EVENT_POLL_SECS` and
+/// republishes those in `KEYCLOAK_EVENT_TYPES` as webhook events, so
+/// receivers only have to integrate with the portal. Every replica runs the
+/// timer, but only the one that claims the interval in the session store
+/// polls; the cursor is kept there too, so a restart or another replica
+/// carries on where the last poll stopped. Without a stored cursor only
+/// events from then on are forwarded.
+pub fn spawn(
+    config: &AppConfig,
+    keycloak: Arc<KeycloakService>,
+    webhooks: Arc<WebhookService>,
+    sessions: Arc<dyn SessionStore>,
+) {
+    let interval = config.keycloak_event_poll_interval;
+    let types = config.keycloak_event_types.clone();
+    if interval.is_zero() || types.is_empty() {
+        return;
+    }
+
+    info!(
+        "[EventBridge] forwarding Keycloak events {} every {}s",
+        types.join(","),
+        interval.as_secs()
+    );
+    tokio::spawn(prioritized(Priority::Background, async move {
+        loop {
+            sleep(interval).await;
+            let slot = unix_now() / interval.as_secs().max(1);
+            match sessions
+                .set_if_absent(&format!("event-bridge:poll:{slot}"), "1", interval)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    warn!("[EventBridge] unable to claim the poll: {}", err);
+                    continue;
+                }
+            }
+            let mut cursor = match sessions.get(CURSOR_KEY).await {
+                Ok(stored) => stored
+                    .and_then(|stored| serde_json::from_str(&stored).ok())
+                    .unwrap_or_else(Cursor::starting_now),
+                Err(err) => {
+                    warn!("[EventBridge] unable to load the cursor: {}", err);
+                    continue;
+                }
+            };
+            let events = match poll(&keycloak, &types, &cursor).await {
+                Ok(events) => events,
+                Err(err) => {
+                    warn!("[EventBridge] unable to fetch Keycloak events: {}", err);
+                    continue;
+                }
+            };
+            for event in cursor.advance(events) {
+                publish(&webhooks, event);
+            }
+            let stored = serde_json::to_string(&cursor).unwrap_or_default();
+            if let Err(err) = sessions.set(CURSOR_KEY, &stored, CURSOR_TTL).await {
+                warn!("[EventBridge] unable to store the cursor: {}", err);
+            }
+        }
+    }));
+}
+
+/// Pages through the events, newest first, until one predates `cursor`.
+async fn poll(
+    keycloak: &KeycloakService,
+    types: &[String],
+    cursor: &Cursor,
+) -> Result<Vec<KeycloakEvent>, KeycloakError> {
+    let mut events = Vec::new();
+    for page in 0..MAX_PAGES {
+        let batch = keycloak
+            .user_events(types, page * POLL_BATCH, POLL_BATCH)
+            .await?;
+        let caught_up =
+            batch.len() < POLL_BATCH || batch.iter().any(|event| event.time < cursor.time);
+        events.extend(batch);
+        if caught_up {
+            return Ok(events);
+        }
+    }
+    warn!(
+        "[EventBridge] more than {} events since the last poll; older ones were skipped",
+        MAX_PAGES * POLL_BATCH
+    );
+    Ok(events)
+}
+
+fn publish(webhooks: &WebhookService, event: KeycloakEvent) {
+    let name = portal_event(&event.kind);
+    debug!("[EventBridge] {} -> {}", event.kind, name);
+    webhooks.publish(
+        &name,
+        json!({
+            "keycloakEventId": event.id,
+            "keycloakEventType": event.kind,
+            "occurredAt": event.time,
+            "userId": event.user_id,
+            "sessionId": event.session_id,
+            "ipAddress": event.ip_address,
+            "clientId": event.client_id,
+            "error": event.error,
+            "details": event.details,
+        }),
+    );
+}
+
+/// Position in the event stream. Keycloak timestamps have millisecond
+/// resolution, so events sharing the newest timestamp are remembered to
+/// avoid forwarding them twice.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    time: u64,
+    seen: HashSet<String>,
+}
+
+impl Cursor {
+    fn starting_now() -> Self {
+        Self {
+            time: unix_millis(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Returns the events not seen before, oldest first.
+    /// Pages may overlap when events arrive while paging, so duplicates
+    /// are dropped as well.
+    fn advance(&mut self, events: Vec<KeycloakEvent>) -> Vec<KeycloakEvent> {
+        let mut batch = HashSet::new();
+        let mut fresh: Vec<KeycloakEvent> = events
+            .into_iter()
+            .filter(|event| {
+                let fingerprint = fingerprint(event);
+                (event.time > self.time
+                    || (event.time == self.time && !self.seen.contains(&fingerprint)))
+                    && batch.insert(fingerprint)
+            })
+            .collect();
+        fresh.sort_by_key(|event| event.time);
+
+        if let Some(newest) = fresh.last().map(|event| event.time) {
+            if newest > self.time {
+                self.time = newest;
+                self.seen.clear();
+            }
+            self.seen.extend(
+                fresh
+                    .iter()
+                    .filter(|event| event.time == newest)
+                    .map(fingerprint),
+            );
+        }
+        fresh
+    }
+}
+
+fn fingerprint(event: &KeycloakEvent) -> String {
+    event.id.clone().unwrap_or_else(|| {
+        format!(
+            "{}:{}:{}:{}",
+            event.time,
+            event.kind,
+            event.user_id.as_deref().unwrap_or_default(),
+            event.session_id.as_deref().unwrap_or_default()
+        )
+    })
+}
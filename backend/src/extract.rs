@@ -0,0 +1,235 @@
+use axum::{
+    Json, async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{
+        HeaderMap, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+    },
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::de::DeserializeOwned;
+
+use crate::error_codes::ErrorCode;
+use crate::models::auth::LoginRequest;
+use crate::models::user::{ErrorResponse, FieldError};
+
+pub const CAPTCHA_TOKEN_HEADER: &str = "x-turnstile-token";
+
+pub type Rejection = (StatusCode, Json<ErrorResponse>);
+
+/// Drop-in replacement for `axum::Json` that reports malformed bodies as
+/// JSON error responses naming the offending field.
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Rejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(req.headers()) {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(ErrorResponse::new(
+                    ErrorCode::UnsupportedMediaType,
+                    "Expected request with `Content-Type: application/json`".to_owned(),
+                )),
+            ));
+        }
+
+        let bytes = read_body(req, state).await?;
+        decode_json(&bytes).map(Self)
+    }
+}
+
+/// Accepts either a JSON or an `application/x-www-form-urlencoded` body,
+/// chosen by the request's `Content-Type`.
+pub struct JsonOrForm<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for JsonOrForm<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Rejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if is_form_encoded(req.headers()) {
+            let bytes = read_body(req, state).await?;
+            return decode_form(&bytes).map(Self);
+        }
+
+        let ApiJson(value) = ApiJson::<T>::from_request(req, state).await?;
+        Ok(Self(value))
+    }
+}
+
+/// Login credentials taken from an `Authorization: Basic` header when present,
+/// otherwise from the JSON or form body. Header-based callers pass their
+/// captcha token in `X-Turnstile-Token`.
+pub struct LoginCredentials(pub LoginRequest);
+
+#[async_trait]
+impl<S> FromRequest<S> for LoginCredentials
+where
+    S: Send + Sync,
+{
+    type Rejection = Rejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some((email, password)) = basic_credentials(req.headers()) {
+            let captcha_token = req
+                .headers()
+                .get(CAPTCHA_TOKEN_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned);
+
+            return Ok(Self(LoginRequest {
+                email,
+                password,
+                otp: None,
+                captcha_token,
+                scope: None,
+                extra: Default::default(),
+            }));
+        }
+
+        let JsonOrForm(payload) = JsonOrForm::<LoginRequest>::from_request(req, state).await?;
+        Ok(Self(payload))
+    }
+}
+
+pub fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_owned(), password.to_owned()))
+}
+
+pub fn is_form_encoded(headers: &HeaderMap) -> bool {
+    content_type(headers)
+        .map(|value| value.starts_with("application/x-www-form-urlencoded"))
+        .unwrap_or(false)
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    content_type(headers)
+        .map(|value| {
+            let essence = value.split(';').next().unwrap_or_default().trim();
+            essence == "application/json" || essence.ends_with("+json")
+        })
+        .unwrap_or(false)
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase())
+}
+
+async fn read_body<S>(req: Request, state: &S) -> Result<Bytes, Rejection>
+where
+    S: Send + Sync,
+{
+    Bytes::from_request(req, state).await.map_err(|rejection| {
+        (
+            rejection.status(),
+            Json(ErrorResponse::new(
+                ErrorCode::from_status(rejection.status()),
+                rejection.body_text(),
+            )),
+        )
+    })
+}
+
+fn decode_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Rejection> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+        let path = err.path().to_string();
+        let inner = err.into_inner();
+        if inner.is_data() {
+            invalid_field(&path, &inner.to_string())
+        } else {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(
+                    ErrorCode::MalformedBody,
+                    format!("Malformed JSON body: {inner}"),
+                )),
+            )
+        }
+    })?;
+
+    deserializer.end().map_err(|err| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                ErrorCode::MalformedBody,
+                format!("Malformed JSON body: {err}"),
+            )),
+        )
+    })?;
+
+    Ok(value)
+}
+
+fn decode_form<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Rejection> {
+    let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(bytes));
+    serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        let path = err.path().to_string();
+        invalid_field(&path, &err.into_inner().to_string())
+    })
+}
+
+fn invalid_field(path: &str, raw_message: &str) -> Rejection {
+    let message = strip_location(raw_message);
+    let field = if path.is_empty() || path == "." {
+        backticked(message).unwrap_or_default().to_owned()
+    } else {
+        path.to_owned()
+    };
+    let expected = message
+        .split_once(", expected ")
+        .map(|(_, expected)| expected.to_owned());
+
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(
+            ErrorResponse::new(
+                ErrorCode::ValidationFailed,
+                "Invalid request body".to_owned(),
+            )
+            .with_fields(vec![FieldError {
+                field,
+                expected,
+                message: message.to_owned(),
+            }]),
+        ),
+    )
+}
+
+fn strip_location(message: &str) -> &str {
+    match message.rfind(" at line ") {
+        Some(index) => &message[..index],
+        None => message,
+    }
+}
+
+fn backticked(message: &str) -> Option<&str> {
+    let start = message.find('`')? + 1;
+    let len = message[start..].find('`')?;
+    Some(&message[start..start + len])
+}
@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::attributes;
+use crate::email::{self, AliasRules};
+
+/// Feeds `data`, read as the JSON body of a registration, through the
+/// extraction of its extra fields into Keycloak attributes.
+pub fn register_extras(data: &[u8]) {
+    if let Ok(extra) = serde_json::from_slice::<HashMap<String, Value>>(data) {
+        let _ = attributes::extract(&extra);
+    }
+}
+
+/// Feeds `data`, read as an email address, through normalization and
+/// canonicalization with every alias rule on.
+pub fn email_address(data: &[u8]) {
+    if let Ok(address) = std::str::from_utf8(data) {
+        let _ = email::normalize(address);
+        let rules = AliasRules {
+            strip_plus_tags: true,
+            strip_gmail_dots: true,
+        };
+        let _ = email::canonicalize(rules, address);
+    }
+}
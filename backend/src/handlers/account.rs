@@ -0,0 +1,734 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+    extract::{Path, Query, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{
+            ACCEPT_LANGUAGE, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+        request::Parts,
+    },
+    response::{IntoResponse, Redirect, Response},
+};
+use serde_json::json;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+use crate::AppState;
+use crate::admin::{bearer_token, introspect};
+use crate::audit::AuditOutcome;
+use crate::avatar::{self, AVATAR_ATTRIBUTE, ImageError, ImageFormat};
+use crate::client_ip::ClientIp;
+use crate::deadline;
+use crate::email;
+use crate::error_codes::ErrorCode;
+use crate::extract::{ApiJson, Rejection};
+use crate::keycloak::{KeycloakError, path_segment};
+use crate::locale;
+use crate::models::account::{
+    AccountResponse, AvatarQuery, AvatarResponse, NotMeRequest, Preferences, PreferencesUpdate,
+    ReactivateRequest, ReactivationRequest, VerifiedCallbackQuery,
+};
+use crate::models::user::{ErrorResponse, UserRepresentation};
+use crate::preferences::{self, LOCALE_ATTRIBUTE};
+use crate::reactivation::{DEACTIVATED_AT_ATTRIBUTE, deactivated_at};
+use crate::util::unix_now;
+use crate::verified_redirect::{EMAIL_VERIFIED_AT_ATTRIBUTE, VerificationStatus, accept_language};
+
+const AVATAR_IMMUTABLE_CACHE: &str = "public, max-age=31536000, immutable";
+const ATTRIBUTE_LOCK_TTL: Duration = Duration::from_secs(10);
+const ATTRIBUTE_LOCK_RETRY: Duration = Duration::from_millis(50);
+const ATTRIBUTE_LOCK_ATTEMPTS: usize = 40;
+
+/// Disables the signed-in user's account without deleting anything. Their
+/// sessions end and the account can later be reactivated by its owner
+/// through a reactivation link.
+pub async fn deactivate_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    mut parts: Parts,
+) -> Result<StatusCode, Rejection> {
+    let introspection = introspect(&mut parts, &state).await?;
+    let Some(user_id) = introspection.sub.as_deref() else {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"));
+    };
+
+    let mut user = state
+        .keycloak
+        .get_user(user_id)
+        .await
+        .map_err(|err| upstream_error("deactivate", err))?;
+    user.attributes.insert(
+        DEACTIVATED_AT_ATTRIBUTE.to_owned(),
+        vec![unix_now().to_string()],
+    );
+    state
+        .keycloak
+        .update_user(&user, false)
+        .await
+        .map_err(|err| upstream_error("deactivate", err))?;
+
+    if let Err(err) = state.keycloak.logout_user_sessions(&user.id).await {
+        warn!("[Account] unable to end sessions of {}: {}", user.id, err);
+    }
+    state.introspections.evict_user(&user.id);
+    if let Some(token) = bearer_token(&parts) {
+        state.revocations.revoke(&token).await;
+    }
+
+    let actor = display_name(&user);
+    info!("[Account] user={} deactivated", actor);
+    state.audit.record(
+        actor,
+        "user.deactivate",
+        AuditOutcome::Success,
+        Some(&user.id),
+        Some(client_ip),
+    );
+    state.webhooks.publish(
+        "user.deactivated",
+        json!({ "userId": user.id, "email": user.email }),
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Issues a reactivation link for an account its owner deactivated. The
+/// portal does not send mail: the link goes to the mail integration alone as
+/// a `user.reactivation_link` event, while subscriptions only see
+/// `user.reactivation_requested` without it. The answer is the same whether
+/// or not a link was issued.
+pub async fn request_reactivation_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    ApiJson(payload): ApiJson<ReactivationRequest>,
+) -> Result<(StatusCode, Json<AccountResponse>), Rejection> {
+    let email = email::normalize(&payload.email);
+    if email.is_empty() {
+        return Err(reject(StatusCode::BAD_REQUEST, "Email is required"));
+    }
+
+    let users = state
+        .keycloak
+        .find_users_by_email(&email)
+        .await
+        .map_err(|err| upstream_error("reactivation request", err))?;
+    if let [user] = users.as_slice()
+        && !user.enabled
+        && let Some(link) = state.reactivation.issue(user)
+    {
+        info!("[Account] user={} requested reactivation", email);
+        state.audit.record(
+            &email,
+            "user.reactivate.request",
+            AuditOutcome::Success,
+            Some(&user.id),
+            Some(client_ip),
+        );
+        state.webhooks.publish(
+            "user.reactivation_requested",
+            json!({
+                "userId": user.id,
+                "email": user.email,
+                "expiresAt": link.expires_at,
+            }),
+        );
+        state.webhooks.send_mail(
+            "user.reactivation_link",
+            json!({
+                "userId": user.id,
+                "email": user.email,
+                "reactivationUrl": link.url,
+                "expiresAt": link.expires_at,
+            }),
+        );
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(AccountResponse::new(
+            "If the account can be reactivated, a link has been sent",
+        )),
+    ))
+}
+
+pub async fn reactivate_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    ApiJson(payload): ApiJson<ReactivateRequest>,
+) -> Result<Json<AccountResponse>, Rejection> {
+    let invalid = || {
+        reject(
+            StatusCode::BAD_REQUEST,
+            "Invalid or expired reactivation link",
+        )
+    };
+
+    let Some(claim) = state.reactivation.verify(&payload.token) else {
+        return Err(invalid());
+    };
+    let mut user = match state.keycloak.get_user(&claim.user_id).await {
+        Ok(user) => user,
+        Err(KeycloakError::UnexpectedStatus { status, .. }) if status == StatusCode::NOT_FOUND => {
+            return Err(invalid());
+        }
+        Err(err) => return Err(upstream_error("reactivate", err)),
+    };
+    if user.enabled || deactivated_at(&user) != Some(claim.deactivated_at.as_str()) {
+        warn!("[Account] rejected reactivation link for {}", user.id);
+        return Err(invalid());
+    }
+
+    user.attributes.remove(DEACTIVATED_AT_ATTRIBUTE);
+    state
+        .keycloak
+        .update_user(&user, true)
+        .await
+        .map_err(|err| upstream_error("reactivate", err))?;
+
+    let actor = display_name(&user);
+    info!("[Account] user={} reactivated", actor);
+    state.audit.record(
+        actor,
+        "user.reactivate",
+        AuditOutcome::Success,
+        Some(&user.id),
+        Some(client_ip),
+    );
+    state.webhooks.publish(
+        "user.reactivated",
+        json!({ "userId": user.id, "email": user.email }),
+    );
+
+    Ok(Json(AccountResponse::new("Account reactivated")))
+}
+
+/// The "this wasn't me" link from a login notification: every session of
+/// the account ends and Keycloak emails a password reset link.
+pub async fn not_me_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<NotMeRequest>,
+) -> Result<Json<AccountResponse>, Rejection> {
+    let Some(user_id) = state.login_notifications.verify(&payload.token) else {
+        return Err(reject(StatusCode::BAD_REQUEST, "Invalid or expired link"));
+    };
+    let user = match state.keycloak.get_user(&user_id).await {
+        Ok(user) => user,
+        Err(KeycloakError::UnexpectedStatus { status, .. }) if status == StatusCode::NOT_FOUND => {
+            return Err(reject(StatusCode::BAD_REQUEST, "Invalid or expired link"));
+        }
+        Err(err) => return Err(upstream_error("not me", err)),
+    };
+
+    state
+        .keycloak
+        .logout_user_sessions(&user.id)
+        .await
+        .map_err(|err| upstream_error("not me", err))?;
+    state.introspections.evict_user(&user.id);
+    let stored = preferences::attribute(&user, LOCALE_ATTRIBUTE);
+    let locale = locale::preferred(stored, locale::header(&headers))
+        .into_iter()
+        .next();
+    state
+        .keycloak
+        .execute_actions_email(&user.id, &["UPDATE_PASSWORD"], locale.as_deref())
+        .await
+        .map_err(|err| upstream_error("not me", err))?;
+
+    let actor = display_name(&user);
+    warn!("[Account] user={} disowned a login", actor);
+    state.audit.record(
+        actor,
+        "user.login_disowned",
+        AuditOutcome::Success,
+        Some(&user.id),
+        Some(client_ip),
+    );
+    state.webhooks.publish(
+        "user.login_disowned",
+        json!({ "userId": user.id, "email": user.email }),
+    );
+
+    Ok(Json(AccountResponse::new(
+        "All sessions ended; check your email to reset your password",
+    )))
+}
+
+pub async fn preferences_handler(
+    State(state): State<AppState>,
+    mut parts: Parts,
+) -> Result<Json<Preferences>, Rejection> {
+    let user = signed_in_user(&state, &mut parts, "preferences").await?;
+    Ok(Json(preferences::read(
+        &user,
+        state.config.login_notifications_default,
+    )))
+}
+
+/// Changes the sent preferences and publishes the result as
+/// `user.preferences_updated`, so the mail integration can honour a
+/// marketing opt-out.
+pub async fn update_preferences_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    mut parts: Parts,
+    ApiJson(payload): ApiJson<PreferencesUpdate>,
+) -> Result<Json<Preferences>, Rejection> {
+    let user_id = signed_in_user_id(&state, &mut parts).await?;
+    let lock = lock_attributes(&state, &user_id).await?;
+    let result = update_preferences(&state, &user_id, payload).await;
+    unlock_attributes(&state, &lock).await;
+    let user = result?;
+
+    let updated = preferences::read(&user, state.config.login_notifications_default);
+    let actor = display_name(&user);
+    info!("[Account] user={} updated preferences", actor);
+    state.audit.record(
+        actor,
+        "user.preferences_updated",
+        AuditOutcome::Success,
+        Some(&user.id),
+        Some(client_ip),
+    );
+    state.webhooks.publish(
+        "user.preferences_updated",
+        json!({ "userId": user.id, "email": user.email, "preferences": updated }),
+    );
+
+    Ok(Json(updated))
+}
+
+/// Replaces the signed-in user's avatar with the image in the body, sent
+/// as `image/jpeg`, `image/png` or `image/webp` and at most
+/// `AVATAR_MAX_BYTES` and `AVATAR_MAX_PIXELS` (over all frames, so small
+/// files that would decode into huge canvases are turned away). The file
+/// must really be of the declared type and nothing else. It is decoded and
+/// encoded again, scaled down to fit `AVATAR_MAX_SIDE` pixels each way, so
+/// no metadata survives; its URL is saved as the account's `avatarUrl`
+/// attribute and published as `user.avatar_updated`.
+pub async fn upload_avatar_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    mut parts: Parts,
+    body: Body,
+) -> Result<Json<AvatarResponse>, Rejection> {
+    if !state.storage.is_enabled() {
+        return Err(reject(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Avatar uploads are not configured",
+        ));
+    }
+    let Some(format) = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(ImageFormat::from_content_type)
+    else {
+        return Err(reject(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected an image/jpeg, image/png or image/webp body",
+        ));
+    };
+    let mut user = signed_in_user(&state, &mut parts, "avatar upload").await?;
+
+    let limit = state.config.avatar_max_bytes;
+    let bytes = to_bytes(body, limit).await.map_err(|_| {
+        reject(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!("Avatars may be at most {limit} bytes"),
+        )
+    })?;
+    if ImageFormat::sniff(&bytes) != Some(format) {
+        return Err(reject(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            &format!("The body is not an {} image", format.content_type()),
+        ));
+    }
+    let image = avatar::sanitize(format, &bytes).map_err(image_rejection)?;
+    let max_pixels = state.config.avatar_max_pixels;
+    if image.pixels() > max_pixels {
+        return Err(reject(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            &format!(
+                "Avatars may have at most {max_pixels} pixels; this one is {}x{} with {} frame(s)",
+                image.width, image.height, image.frames
+            ),
+        ));
+    }
+
+    let max_side = state.config.avatar_max_side;
+    let bytes =
+        tokio::task::spawn_blocking(move || avatar::reencode(format, &image, max_side, max_pixels))
+            .await
+            .map_err(|err| ImageError::Encoding(err.to_string()))
+            .and_then(|reencoded| reencoded)
+            .map_err(image_rejection)?;
+
+    let version = avatar::version(&bytes);
+    if let Err(err) = state
+        .storage
+        .put(&avatar::object_key(&user.id, &version), bytes)
+        .await
+    {
+        error!(
+            "[Account] storing the avatar of {} failed: {}",
+            user.id, err
+        );
+        return Err(reject(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Avatar storage unavailable",
+        ));
+    }
+    let avatar_url = avatar::url(
+        state.config.avatar_public_url.as_deref(),
+        &user.id,
+        &version,
+    );
+    user.attributes
+        .insert(AVATAR_ATTRIBUTE.to_owned(), vec![avatar_url.clone()]);
+    state
+        .keycloak
+        .update_user(&user, user.enabled)
+        .await
+        .map_err(|err| upstream_error("avatar upload", err))?;
+
+    let actor = display_name(&user);
+    info!("[Account] user={} updated their avatar", actor);
+    state.audit.record(
+        actor,
+        "user.avatar_updated",
+        AuditOutcome::Success,
+        Some(&user.id),
+        Some(client_ip),
+    );
+    state.webhooks.publish(
+        "user.avatar_updated",
+        json!({ "userId": user.id, "email": user.email, "avatarUrl": avatar_url }),
+    );
+
+    Ok(Json(AvatarResponse { avatar_url }))
+}
+
+fn image_rejection(err: ImageError) -> Rejection {
+    match err {
+        ImageError::Malformed => reject(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The image is damaged or truncated",
+        ),
+        ImageError::TrailingData => reject(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "The body holds data besides the image",
+        ),
+        ImageError::Encoding(err) => {
+            error!("[Account] re-encoding an avatar failed: {}", err);
+            reject(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "The image could not be processed",
+            )
+        }
+    }
+}
+
+/// Serves a user's avatar by the `?v=` of its `avatarUrl` attribute. The
+/// image under a version never changes and is cached for a year. Only
+/// storage is read: requests without a version, or for ids that are not a
+/// plain path segment, get a 404 without asking Keycloak, so the route
+/// cannot tell which users exist.
+pub async fn avatar_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    Query(query): Query<AvatarQuery>,
+    headers: HeaderMap,
+) -> Result<Response, Rejection> {
+    let not_found = || reject(StatusCode::NOT_FOUND, "No avatar");
+    let version = query
+        .v
+        .filter(|version| avatar::is_version(version))
+        .ok_or_else(not_found)?;
+    if user_id.is_empty() || path_segment(&user_id) != user_id {
+        return Err(not_found());
+    }
+    let cache_control = AVATAR_IMMUTABLE_CACHE;
+
+    let etag = format!("\"{version}\"");
+    let cached = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag));
+    let caching = [
+        (CACHE_CONTROL, HeaderValue::from_static(cache_control)),
+        (
+            ETAG,
+            HeaderValue::from_str(&etag).expect("hex is a valid header"),
+        ),
+    ];
+    if cached {
+        return Ok((StatusCode::NOT_MODIFIED, caching).into_response());
+    }
+
+    let image = match state
+        .storage
+        .get(&avatar::object_key(&user_id, &version))
+        .await
+    {
+        Ok(Some(image)) => image,
+        Ok(None) => return Err(not_found()),
+        Err(err) => {
+            error!(
+                "[Account] reading the avatar of {} failed: {}",
+                user_id, err
+            );
+            return Err(reject(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Avatar storage unavailable",
+            ));
+        }
+    };
+    // Stored images were sniffed on the way in.
+    let content_type =
+        ImageFormat::sniff(&image).map_or("application/octet-stream", ImageFormat::content_type);
+
+    Ok((
+        caching,
+        [
+            (CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        ],
+        image,
+    )
+        .into_response())
+}
+
+async fn signed_in_user(
+    state: &AppState,
+    parts: &mut Parts,
+    action: &str,
+) -> Result<UserRepresentation, Rejection> {
+    let user_id = signed_in_user_id(state, parts).await?;
+    state
+        .keycloak
+        .get_user(&user_id)
+        .await
+        .map_err(|err| upstream_error(action, err))
+}
+
+async fn signed_in_user_id(state: &AppState, parts: &mut Parts) -> Result<String, Rejection> {
+    introspect(parts, state)
+        .await?
+        .sub
+        .ok_or_else(|| reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"))
+}
+
+async fn update_preferences(
+    state: &AppState,
+    user_id: &str,
+    payload: PreferencesUpdate,
+) -> Result<UserRepresentation, Rejection> {
+    let mut user = state
+        .keycloak
+        .get_user(user_id)
+        .await
+        .map_err(|err| upstream_error("preferences update", err))?;
+    if let Err(fields) = preferences::apply(&mut user, payload) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(
+                ErrorResponse::new(
+                    ErrorCode::ValidationFailed,
+                    "Invalid preferences".to_owned(),
+                )
+                .with_fields(fields),
+            ),
+        ));
+    }
+    state
+        .keycloak
+        .update_user(&user, user.enabled)
+        .await
+        .map_err(|err| upstream_error("preferences update", err))?;
+    Ok(user)
+}
+
+/// Keycloak replaces the whole attribute map on every update, so two
+/// overlapping read-modify-write updates of one account would drop each
+/// other's changes. They take turns through a lock in the session store,
+/// which every replica shares; the lock expires on its own should its
+/// holder die.
+async fn lock_attributes(state: &AppState, user_id: &str) -> Result<String, Rejection> {
+    let key = format!("attributes-lock:{user_id}");
+    for _ in 0..ATTRIBUTE_LOCK_ATTEMPTS {
+        match state
+            .sessions
+            .set_if_absent(&key, "1", ATTRIBUTE_LOCK_TTL)
+            .await
+        {
+            Ok(true) => return Ok(key),
+            Ok(false) => sleep(ATTRIBUTE_LOCK_RETRY).await,
+            Err(err) => {
+                error!(
+                    "[Account] unable to lock attributes of {}: {}",
+                    user_id, err
+                );
+                return Err(reject(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Session store unavailable",
+                ));
+            }
+        }
+    }
+    Err((
+        StatusCode::CONFLICT,
+        Json(ErrorResponse::new(
+            ErrorCode::Conflict,
+            "The account is being updated, try again".to_owned(),
+        )),
+    ))
+}
+
+async fn unlock_attributes(state: &AppState, key: &str) {
+    if let Err(err) = state.sessions.delete(key).await {
+        warn!("[Account] unable to release {}: {}", key, err);
+    }
+}
+
+fn display_name(user: &UserRepresentation) -> &str {
+    user.email.as_deref().unwrap_or(&user.username)
+}
+
+/// Where Keycloak sends users who followed a verification email. The first
+/// visit after the address is verified is audited as `user.email_verified`,
+/// stamped on the account and published as a webhook; the user is then
+/// redirected to the frontend page for their locale with `?status=`
+/// `verified`, `pending` or `error`. The locale is taken from the link, the
+/// account's `locale` attribute or `Accept-Language`, in that order. A user
+/// id without its `sig` is treated as unknown and never looked up.
+pub async fn verified_callback_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    Query(query): Query<VerifiedCallbackQuery>,
+) -> Result<Response, Rejection> {
+    let signed = query
+        .user
+        .as_deref()
+        .zip(query.sig.as_deref())
+        .and_then(|(user, sig)| {
+            state
+                .email_verification
+                .redirects()
+                .verified_user(user, sig)
+        });
+    if signed.is_none() && query.user.is_some() {
+        warn!("[Account] verified callback with an unsigned or forged user id");
+    }
+    let user = match signed {
+        Some(id) => state
+            .keycloak
+            .get_user(id)
+            .await
+            .inspect_err(|err| {
+                warn!(
+                    "[Account] verified callback lookup of {} failed: {}",
+                    id, err
+                )
+            })
+            .ok(),
+        None => None,
+    };
+    let status = match &user {
+        Some(user) if user.email_verified => VerificationStatus::Verified,
+        Some(_) => VerificationStatus::Pending,
+        None => VerificationStatus::Error,
+    };
+
+    if let Some(mut user) = user.clone()
+        && status == VerificationStatus::Verified
+        && !user.attributes.contains_key(EMAIL_VERIFIED_AT_ATTRIBUTE)
+    {
+        let verified_at = unix_now();
+        user.attributes.insert(
+            EMAIL_VERIFIED_AT_ATTRIBUTE.to_owned(),
+            vec![verified_at.to_string()],
+        );
+        // Without the stamp a later visit would publish the event again,
+        // so only publish once it is stored.
+        match state.keycloak.update_user(&user, user.enabled).await {
+            Ok(()) => {
+                let actor = display_name(&user);
+                info!("[Account] user={} verified their email", actor);
+                state.audit.record(
+                    actor,
+                    "user.email_verified",
+                    AuditOutcome::Success,
+                    Some(&user.id),
+                    Some(client_ip),
+                );
+                state.webhooks.publish(
+                    "user.email_verified",
+                    json!({
+                        "userId": user.id,
+                        "email": user.email,
+                        "verifiedAt": verified_at,
+                    }),
+                );
+            }
+            Err(err) => warn!(
+                "[Account] unable to record verification of {}: {}",
+                user.id, err
+            ),
+        }
+    }
+
+    let user_locale = user
+        .as_ref()
+        .and_then(|user| preferences::attribute(user, LOCALE_ATTRIBUTE));
+    let header_locales = headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .map(accept_language)
+        .into_iter()
+        .flatten();
+    let locales = query
+        .locale
+        .as_deref()
+        .into_iter()
+        .chain(user_locale)
+        .chain(header_locales);
+    match state.email_verification.redirects().target(locales, status) {
+        Some(url) => Ok(Redirect::to(&url).into_response()),
+        None => Err(reject(
+            StatusCode::NOT_FOUND,
+            "No verification redirect is configured",
+        )),
+    }
+}
+
+fn upstream_error(action: &str, error: KeycloakError) -> Rejection {
+    match error {
+        KeycloakError::DeadlineExceeded => {
+            warn!("[Account] {action} timed out");
+            deadline::exceeded()
+        }
+        err => {
+            error!("[Account] {action} failed: {}", err);
+            reject(StatusCode::BAD_GATEWAY, "Identity provider unavailable")
+        }
+    }
+}
+
+fn reject(status: StatusCode, message: &str) -> Rejection {
+    (
+        status,
+        Json(ErrorResponse::new(
+            ErrorCode::from_status(status),
+            message.to_owned(),
+        )),
+    )
+}
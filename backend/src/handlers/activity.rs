@@ -0,0 +1,20 @@
+use axum::{Json, extract::State, http::request::Parts};
+
+use crate::AppState;
+use crate::admin::introspect;
+use crate::extract::Rejection;
+use crate::models::auth::LoginActivityResponse;
+
+/// Recent login attempts on the signed-in user's account, newest first.
+pub async fn activity_handler(
+    State(state): State<AppState>,
+    mut parts: Parts,
+) -> Result<Json<LoginActivityResponse>, Rejection> {
+    let introspection = introspect(&mut parts, &state).await?;
+    let attempts = match introspection.username.as_deref() {
+        Some(username) => state.login_history.recent(username).await,
+        None => Vec::new(),
+    };
+
+    Ok(Json(LoginActivityResponse { attempts }))
+}
@@ -0,0 +1,991 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures_util::{StreamExt, TryStreamExt, stream};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::AppState;
+use crate::abac;
+use crate::account_merge::{self, MERGED_INTO_ATTRIBUTE};
+use crate::admin::{AdminPrincipal, AuditorPrincipal};
+use crate::anomaly::Alert;
+use crate::api_keys::ApiKeyError;
+use crate::audit::{AuditFilter, AuditOutcome, to_csv};
+use crate::body_logging::BodyLogChanges;
+use crate::captcha::CaptchaAction;
+use crate::captcha_exemption::ExemptionError;
+use crate::client_ip::parse_networks;
+use crate::deadline;
+use crate::error_codes::ErrorCode;
+use crate::extract::{ApiJson, Rejection};
+use crate::keycloak::KeycloakError;
+use crate::models::admin::{
+    AlertsQuery, ApiKeyListResponse, ApiKeyRequest, ApiKeyResponse, AuditPageResponse, AuditQuery,
+    CaptchaExemptionListResponse, CaptchaExemptionRequest, CaptchaExemptionResponse, DrainResponse,
+    FailedLoginStats, IdentityProviderStatus, LoggingRequest, LoggingResponse, MergeUsersRequest,
+    MergeUsersResponse, OverviewResponse, RateLimitOverrideListResponse, RateLimitOverrideRequest,
+    ReadOnlyRequest, ReadOnlyResponse, RealmExportQuery, ReferralCodeStats, ReferralStatsResponse,
+    ReloadResponse, SloResponse,
+};
+use crate::models::user::ErrorResponse;
+use crate::rate_limit::{NewOverride, Override, OverrideError};
+use crate::realm_backup::{BackupError, Snapshot};
+use crate::reload;
+use crate::util::{unix_now, unix_secs};
+
+const AUDIT_PAGE_SIZE_DEFAULT: usize = 50;
+const AUDIT_PAGE_SIZE_MAX: usize = 500;
+const FAILED_LOGIN_WINDOW: Duration = Duration::from_secs(15 * 60);
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Everything the admin home page shows, in one call. Registration and login
+/// figures come from the audit log, so they only reach back as far as the
+/// entries it still holds.
+pub async fn overview_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+) -> Json<OverviewResponse> {
+    state.audit.record(
+        admin.display_name(),
+        "admin.overview.view",
+        AuditOutcome::Success,
+        None,
+        None,
+    );
+
+    let (user_count, active_sessions) = tokio::join!(
+        state.keycloak.user_count(),
+        state.keycloak.active_session_count()
+    );
+    let user_count = user_count
+        .inspect_err(|err| warn!("[Admin] overview user count failed: {}", err))
+        .ok();
+    let active_sessions = active_sessions
+        .inspect_err(|err| warn!("[Admin] overview session count failed: {}", err))
+        .ok();
+
+    let now = unix_now();
+    let today = now - now % DAY_SECS;
+    // 1970-01-01 was a Thursday; weeks start on Monday.
+    let this_week = today - ((today / DAY_SECS + 3) % 7) * DAY_SECS;
+
+    let registrations = state.audit.query(&AuditFilter {
+        action: Some("user.register"),
+        from: Some(this_week),
+        ..AuditFilter::default()
+    });
+    let registrations = registrations
+        .iter()
+        .filter(|event| event.outcome == AuditOutcome::Success);
+
+    let logins = state.audit.query(&AuditFilter {
+        action: Some("auth.login"),
+        from: Some(now.saturating_sub(FAILED_LOGIN_WINDOW.as_secs())),
+        ..AuditFilter::default()
+    });
+    let failures = logins
+        .iter()
+        .filter(|event| event.outcome == AuditOutcome::Failure)
+        .count();
+
+    Json(OverviewResponse {
+        user_count,
+        active_sessions,
+        registrations_today: registrations
+            .clone()
+            .filter(|event| event.timestamp >= today)
+            .count(),
+        registrations_this_week: registrations.count(),
+        failed_logins: FailedLoginStats {
+            window_secs: FAILED_LOGIN_WINDOW.as_secs(),
+            attempts: logins.len(),
+            failures,
+            rate: if logins.is_empty() {
+                0.0
+            } else {
+                failures as f64 / logins.len() as f64
+            },
+        },
+        identity_provider: IdentityProviderStatus {
+            status: if state.keycloak_health.is_unavailable() {
+                "unavailable"
+            } else {
+                "up"
+            },
+            token_refresh_failures: state.keycloak.refresh_failures(),
+        },
+    })
+}
+
+pub async fn referral_stats_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+) -> Json<ReferralStatsResponse> {
+    info!(
+        "[Admin] user={} viewed referral stats",
+        admin.display_name()
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.referral_stats.view",
+        AuditOutcome::Success,
+        None,
+        None,
+    );
+
+    let since = unix_secs(state.referrals.started_at());
+
+    let codes: Vec<ReferralCodeStats> = state
+        .referrals
+        .snapshot()
+        .await
+        .into_iter()
+        .map(|(code, counts)| ReferralCodeStats {
+            code,
+            conversions: counts.conversions,
+            rejected: counts.rejected,
+        })
+        .collect();
+
+    Json(ReferralStatsResponse {
+        since,
+        total_conversions: codes.iter().map(|stats| stats.conversions).sum(),
+        codes,
+    })
+}
+
+/// Pages through the audit log, newest first. `format=csv` (or an
+/// `Accept: text/csv` header) exports every matching entry instead.
+pub async fn audit_handler(
+    State(state): State<AppState>,
+    AuditorPrincipal(auditor): AuditorPrincipal,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    let filter = AuditFilter {
+        actor: query.actor.as_deref().filter(|value| !value.is_empty()),
+        action: query.action.as_deref().filter(|value| !value.is_empty()),
+        from: query.from,
+        to: query.to,
+    };
+    let entries = state.audit.query(&filter);
+
+    let wants_csv = query
+        .format
+        .as_deref()
+        .map(|format| format.eq_ignore_ascii_case("csv"))
+        .unwrap_or_else(|| {
+            headers
+                .get(header::ACCEPT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.contains("text/csv"))
+                .unwrap_or(false)
+        });
+
+    state.audit.record(
+        auditor.display_name(),
+        if wants_csv {
+            "admin.audit.export"
+        } else {
+            "admin.audit.view"
+        },
+        AuditOutcome::Success,
+        None,
+        None,
+    );
+
+    if wants_csv {
+        info!(
+            "[Admin] user={} exported {} audit entries",
+            auditor.display_name(),
+            entries.len()
+        );
+        return (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"audit.csv\"",
+                ),
+            ],
+            to_csv(&entries),
+        )
+            .into_response();
+    }
+
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query
+        .page_size
+        .unwrap_or(AUDIT_PAGE_SIZE_DEFAULT)
+        .clamp(1, AUDIT_PAGE_SIZE_MAX);
+    let total = entries.len();
+    let entries = entries
+        .into_iter()
+        .skip((page - 1).saturating_mul(page_size))
+        .take(page_size)
+        .collect();
+
+    Json(AuditPageResponse {
+        page,
+        page_size,
+        total,
+        entries,
+    })
+    .into_response()
+}
+
+/// Re-reads the configuration like SIGHUP does, on this replica only.
+pub async fn reload_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+) -> Json<ReloadResponse> {
+    Json(ReloadResponse {
+        changed: reload::reload(&state, admin.display_name()),
+    })
+}
+
+pub async fn drain_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+) -> (StatusCode, Json<DrainResponse>) {
+    let grace = state.config.drain_grace;
+    let started = state.lifecycle.begin_drain(grace);
+    if started {
+        info!("[Admin] user={} started drain", admin.display_name());
+        state.audit.record(
+            admin.display_name(),
+            "admin.drain",
+            AuditOutcome::Success,
+            None,
+            None,
+        );
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(DrainResponse {
+            status: if started {
+                "draining"
+            } else {
+                "already_draining"
+            },
+            grace_secs: grace.as_secs(),
+        }),
+    )
+}
+
+pub async fn alerts_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+    Query(query): Query<AlertsQuery>,
+) -> Json<Vec<Alert>> {
+    Json(state.anomalies.alerts(!query.all.unwrap_or(false)))
+}
+
+pub async fn acknowledge_alert_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<u64>,
+) -> Result<Json<Alert>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(alert) = state.anomalies.acknowledge(id, admin.display_name()) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                ErrorCode::NotFound,
+                "Alert not found".to_owned(),
+            )),
+        ));
+    };
+
+    info!(
+        "[Admin] user={} acknowledged alert {}",
+        admin.display_name(),
+        id
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.alert.acknowledge",
+        AuditOutcome::Success,
+        Some(&id.to_string()),
+        None,
+    );
+
+    Ok(Json(alert))
+}
+
+pub async fn list_captcha_exemptions_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+) -> Result<Json<CaptchaExemptionListResponse>, Rejection> {
+    let exemptions = state
+        .captcha_exemptions
+        .list()
+        .await
+        .map_err(exemption_rejection)?;
+    Ok(Json(CaptchaExemptionListResponse { exemptions }))
+}
+
+/// Issues a captcha exemption for automated test traffic. The token is only
+/// returned in this response.
+pub async fn create_captcha_exemption_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    ApiJson(payload): ApiJson<CaptchaExemptionRequest>,
+) -> Result<(StatusCode, Json<CaptchaExemptionResponse>), Rejection> {
+    let scopes = match payload.scopes {
+        Some(scopes) => scopes
+            .iter()
+            .map(|scope| CaptchaAction::parse(scope))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ExemptionError::Invalid(
+                "scopes must be \"login\" or \"register\"",
+            ))
+            .map_err(exemption_rejection)?,
+        None => vec![CaptchaAction::Login, CaptchaAction::Register],
+    };
+    let (exemption, token) = state
+        .captcha_exemptions
+        .issue(
+            &payload.label,
+            &scopes,
+            payload.ttl_secs.map(Duration::from_secs),
+            admin.display_name(),
+        )
+        .await
+        .map_err(exemption_rejection)?;
+
+    info!(
+        "[Admin] user={} issued captcha exemption {} ({}) for {}",
+        admin.display_name(),
+        exemption.id,
+        exemption.label,
+        exemption.scopes.join(", ")
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.captcha_exemption.create",
+        AuditOutcome::Success,
+        Some(&exemption.id),
+        None,
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CaptchaExemptionResponse {
+            exemption,
+            token: Some(token),
+        }),
+    ))
+}
+
+pub async fn revoke_captcha_exemption_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<Json<CaptchaExemptionResponse>, Rejection> {
+    let exemption = state
+        .captcha_exemptions
+        .revoke(&id)
+        .await
+        .map_err(exemption_rejection)?;
+
+    info!(
+        "[Admin] user={} revoked captcha exemption {}",
+        admin.display_name(),
+        id
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.captcha_exemption.revoke",
+        AuditOutcome::Success,
+        Some(&id),
+        None,
+    );
+
+    Ok(Json(CaptchaExemptionResponse {
+        exemption,
+        token: None,
+    }))
+}
+
+fn exemption_rejection(error: ExemptionError) -> Rejection {
+    let (status, message) = match error {
+        ExemptionError::Disabled => (StatusCode::NOT_FOUND, "Captcha exemptions are not enabled"),
+        ExemptionError::NotFound => (StatusCode::NOT_FOUND, "Captcha exemption not found"),
+        ExemptionError::Invalid(message) => (StatusCode::UNPROCESSABLE_ENTITY, message),
+        ExemptionError::Unavailable => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Captcha exemptions are temporarily unavailable",
+        ),
+    };
+    (
+        status,
+        Json(ErrorResponse::new(
+            ErrorCode::from_status(status),
+            message.to_owned(),
+        )),
+    )
+}
+
+pub async fn list_api_keys_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+) -> Result<Json<ApiKeyListResponse>, Rejection> {
+    let keys = state.api_keys.list().await.map_err(api_key_rejection)?;
+    Ok(Json(ApiKeyListResponse { keys }))
+}
+
+/// Issues an API key. The key is only returned in this response; the
+/// portal keeps nothing but its hash.
+pub async fn create_api_key_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    ApiJson(payload): ApiJson<ApiKeyRequest>,
+) -> Result<(StatusCode, Json<ApiKeyResponse>), Rejection> {
+    let (key, raw) = state
+        .api_keys
+        .issue(
+            &payload.label,
+            payload.ttl_secs.map(Duration::from_secs),
+            admin.display_name(),
+        )
+        .await
+        .map_err(api_key_rejection)?;
+
+    info!(
+        "[Admin] user={} issued API key {} ({})",
+        admin.display_name(),
+        key.prefix,
+        key.label
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.api_key.create",
+        AuditOutcome::Success,
+        Some(&key.prefix),
+        None,
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiKeyResponse {
+            key,
+            raw: Some(raw),
+        }),
+    ))
+}
+
+pub async fn revoke_api_key_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(prefix): Path<String>,
+) -> Result<Json<ApiKeyResponse>, Rejection> {
+    let key = state
+        .api_keys
+        .revoke(&prefix)
+        .await
+        .map_err(api_key_rejection)?;
+
+    info!(
+        "[Admin] user={} revoked API key {}",
+        admin.display_name(),
+        prefix
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.api_key.revoke",
+        AuditOutcome::Success,
+        Some(&prefix),
+        None,
+    );
+
+    Ok(Json(ApiKeyResponse { key, raw: None }))
+}
+
+fn api_key_rejection(error: ApiKeyError) -> Rejection {
+    let (status, message) = match error {
+        ApiKeyError::NotFound => (StatusCode::NOT_FOUND, "API key not found"),
+        ApiKeyError::Invalid(message) => (StatusCode::UNPROCESSABLE_ENTITY, message),
+        ApiKeyError::Unavailable => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "API keys are temporarily unavailable",
+        ),
+    };
+    (
+        status,
+        Json(ErrorResponse::new(
+            ErrorCode::from_status(status),
+            message.to_owned(),
+        )),
+    )
+}
+
+pub async fn list_rate_limit_overrides_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+) -> Result<Json<RateLimitOverrideListResponse>, Rejection> {
+    let overrides = state.rate_limits.list().await.map_err(override_rejection)?;
+    Ok(Json(RateLimitOverrideListResponse { overrides }))
+}
+
+/// Adds a rate-limit override; it applies on every replica from the next
+/// request.
+pub async fn create_rate_limit_override_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    ApiJson(payload): ApiJson<RateLimitOverrideRequest>,
+) -> Result<(StatusCode, Json<Override>), Rejection> {
+    let networks = parse_networks(&payload.networks.join(",")).map_err(|_| {
+        override_rejection(OverrideError::Invalid(
+            "networks must be addresses or CIDRs",
+        ))
+    })?;
+    let entry = state
+        .rate_limits
+        .add(
+            NewOverride {
+                label: payload.label,
+                networks,
+                api_key: payload.api_key,
+                bucket: payload.bucket,
+                limit: payload.limit,
+                ttl: payload.ttl_secs.map(Duration::from_secs),
+            },
+            admin.display_name(),
+        )
+        .await
+        .map_err(override_rejection)?;
+
+    info!(
+        "[Admin] user={} added rate-limit override {} ({})",
+        admin.display_name(),
+        entry.id,
+        entry.label
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.rate_limit_override.create",
+        AuditOutcome::Success,
+        Some(&entry.id),
+        None,
+    );
+
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+pub async fn delete_rate_limit_override_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<Json<Override>, Rejection> {
+    let entry = state
+        .rate_limits
+        .remove(&id)
+        .await
+        .map_err(override_rejection)?;
+
+    info!(
+        "[Admin] user={} removed rate-limit override {}",
+        admin.display_name(),
+        id
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.rate_limit_override.delete",
+        AuditOutcome::Success,
+        Some(&id),
+        None,
+    );
+
+    Ok(Json(entry))
+}
+
+fn override_rejection(error: OverrideError) -> Rejection {
+    let (status, message) = match error {
+        OverrideError::NotFound => (StatusCode::NOT_FOUND, "Rate-limit override not found"),
+        OverrideError::Invalid(message) => (StatusCode::UNPROCESSABLE_ENTITY, message),
+        OverrideError::Unavailable => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Rate-limit overrides are temporarily unavailable",
+        ),
+    };
+    (
+        status,
+        Json(ErrorResponse::new(
+            ErrorCode::from_status(status),
+            message.to_owned(),
+        )),
+    )
+}
+
+/// Folds a duplicate account into the primary one; see
+/// [`account_merge::merge`]. The duplicate is kept, disabled, so the merge
+/// can be traced from either account.
+pub async fn merge_users_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    ApiJson(payload): ApiJson<MergeUsersRequest>,
+) -> Result<Json<MergeUsersResponse>, Rejection> {
+    let primary_id = payload.primary_id.trim();
+    let duplicate_id = payload.duplicate_id.trim();
+    if primary_id.is_empty() || duplicate_id.is_empty() {
+        return Err(reject(
+            StatusCode::BAD_REQUEST,
+            "primaryId and duplicateId are required",
+        ));
+    }
+    if primary_id == duplicate_id {
+        return Err(reject(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "An account cannot be merged into itself",
+        ));
+    }
+
+    let (primary, duplicate) = tokio::try_join!(
+        state.keycloak.get_user(primary_id),
+        state.keycloak.get_user(duplicate_id),
+    )
+    .map_err(|err| match err {
+        KeycloakError::UnexpectedStatus { status, .. } if status == StatusCode::NOT_FOUND => {
+            reject(StatusCode::NOT_FOUND, "User not found")
+        }
+        err => upstream_error("merge", err),
+    })?;
+    // Users outside the admin's tenant are reported as missing, so their
+    // existence is not given away.
+    let attribute = &state.config.admin_tenant_attribute;
+    for user in [&primary, &duplicate] {
+        if !abac::permits(admin.tenants.as_deref(), &user.attributes, attribute) {
+            warn!(
+                "[Admin] user={} denied merge: {} is outside their tenant",
+                admin.display_name(),
+                user.id
+            );
+            return Err(reject(StatusCode::NOT_FOUND, "User not found"));
+        }
+    }
+    if let Some(target) = duplicate
+        .attributes
+        .get(MERGED_INTO_ATTRIBUTE)
+        .and_then(|values| values.first())
+    {
+        return Err(reject(
+            StatusCode::CONFLICT,
+            &format!("The duplicate was already merged into {target}"),
+        ));
+    }
+    if primary.attributes.contains_key(MERGED_INTO_ATTRIBUTE) {
+        return Err(reject(
+            StatusCode::CONFLICT,
+            "The primary account was itself merged away",
+        ));
+    }
+
+    let detail = format!("{} into {}", duplicate.id, primary.id);
+    let merged = account_merge::merge(
+        &state.keycloak,
+        &primary,
+        &duplicate,
+        payload.attributes,
+        &state.config.admin_tenant_attribute,
+    )
+    .await;
+    // A merge that fails halfway may already have ended the duplicate's
+    // sessions.
+    state.introspections.evict_user(&duplicate.id);
+    let report = match merged {
+        Ok(report) => report,
+        Err(err) => {
+            state.audit.record(
+                admin.display_name(),
+                "admin.user.merge",
+                AuditOutcome::Failure,
+                Some(&detail),
+                None,
+            );
+            return Err(upstream_error("user merge", err));
+        }
+    };
+
+    info!("[Admin] user={} merged {}", admin.display_name(), detail);
+    state.audit.record(
+        admin.display_name(),
+        "admin.user.merge",
+        AuditOutcome::Success,
+        Some(&detail),
+        None,
+    );
+    state.webhooks.publish(
+        "user.merged",
+        json!({
+            "primaryId": primary.id,
+            "primaryEmail": primary.email,
+            "duplicateId": duplicate.id,
+            "duplicateEmail": duplicate.email,
+            "mergedBy": admin.display_name(),
+        }),
+    );
+
+    Ok(Json(MergeUsersResponse {
+        primary_id: primary.id,
+        duplicate_id: duplicate.id,
+        report,
+    }))
+}
+
+fn upstream_error(action: &str, error: KeycloakError) -> Rejection {
+    match error {
+        KeycloakError::DeadlineExceeded => {
+            warn!("[Admin] {action} timed out");
+            deadline::exceeded()
+        }
+        KeycloakError::Overloaded => {
+            warn!("[Admin] {action} shed while Keycloak is saturated");
+            reject(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Identity provider is busy, please retry later",
+            )
+        }
+        err => {
+            error!("[Admin] {action} failed: {}", err);
+            reject(StatusCode::BAD_GATEWAY, "Identity provider unavailable")
+        }
+    }
+}
+
+fn reject(status: StatusCode, message: &str) -> Rejection {
+    (
+        status,
+        Json(ErrorResponse::new(
+            ErrorCode::from_status(status),
+            message.to_owned(),
+        )),
+    )
+}
+
+/// Downloads the realm configuration as JSON, with `?users=true` including
+/// its users. Unlike stored snapshots the download is not encrypted. The
+/// body streams as the export is produced; a failure after the first piece
+/// cuts the download short rather than turning into an error response.
+pub async fn realm_export_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Query(query): Query<RealmExportQuery>,
+) -> Result<Response, Rejection> {
+    let detail = if query.users {
+        "with users"
+    } else {
+        "config only"
+    };
+    let mut export = Box::pin(state.realm_backups.export(query.users));
+    let first = match export.try_next().await {
+        Ok(first) => first.unwrap_or_default(),
+        Err(err) => {
+            state.audit.record(
+                admin.display_name(),
+                "admin.realm.export",
+                AuditOutcome::Failure,
+                Some(detail),
+                None,
+            );
+            return Err(upstream_error("realm export", err));
+        }
+    };
+
+    info!(
+        "[Admin] user={} exported the realm ({})",
+        admin.display_name(),
+        detail
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.realm.export",
+        AuditOutcome::Success,
+        Some(detail),
+        None,
+    );
+
+    let rest = export.inspect_err(|err| error!("[Admin] realm export stopped: {}", err));
+    let body = Body::from_stream(stream::once(async { Ok(first) }).chain(rest));
+    let filename = format!("{}-realm.json", state.config.keycloak_realm);
+    Ok((
+        [
+            (header::CACHE_CONTROL, "no-store".to_owned()),
+            (header::CONTENT_TYPE, "application/json".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Takes an encrypted snapshot now and stores it on the configured targets.
+pub async fn create_realm_backup_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Query(query): Query<RealmExportQuery>,
+) -> Result<(StatusCode, Json<Snapshot>), Rejection> {
+    match state.realm_backups.snapshot(query.users).await {
+        Ok(snapshot) => {
+            state.audit.record(
+                admin.display_name(),
+                "admin.realm.backup",
+                AuditOutcome::Success,
+                Some(&snapshot.name),
+                None,
+            );
+            Ok((StatusCode::CREATED, Json(snapshot)))
+        }
+        Err(BackupError::Disabled) => Err(reject(
+            StatusCode::NOT_FOUND,
+            "Realm backups are not configured",
+        )),
+        Err(err) => {
+            state.audit.record(
+                admin.display_name(),
+                "admin.realm.backup",
+                AuditOutcome::Failure,
+                None,
+                None,
+            );
+            match err {
+                BackupError::Keycloak(err) => Err(upstream_error("realm backup", err)),
+                err => {
+                    error!("[Admin] realm backup failed: {}", err);
+                    Err(reject(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Unable to store the realm backup",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+pub async fn logging_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+) -> Json<LoggingResponse> {
+    Json(LoggingResponse {
+        body_logging: state.body_logging.settings(),
+    })
+}
+
+/// Changes debug logging at runtime; fields left out keep their value. The
+/// change lasts until the process restarts.
+pub async fn update_logging_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    ApiJson(payload): ApiJson<LoggingRequest>,
+) -> Result<Json<LoggingResponse>, Rejection> {
+    let body_logging = match payload.body_logging {
+        Some(changes) => state
+            .body_logging
+            .update(BodyLogChanges {
+                enabled: changes.enabled,
+                sample_rate: changes.sample_rate,
+                routes: changes.routes,
+                max_bytes: changes.max_bytes,
+            })
+            .map_err(|message| {
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(ErrorResponse::new(
+                        ErrorCode::ValidationFailed,
+                        message.to_owned(),
+                    )),
+                )
+            })?,
+        None => state.body_logging.settings(),
+    };
+
+    info!(
+        "[Admin] user={} set body logging enabled={} sample_rate={} routes={:?}",
+        admin.display_name(),
+        body_logging.enabled,
+        body_logging.sample_rate,
+        body_logging.routes
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.logging.update",
+        AuditOutcome::Success,
+        None,
+        None,
+    );
+
+    Ok(Json(LoggingResponse { body_logging }))
+}
+
+pub async fn read_only_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+) -> Json<ReadOnlyResponse> {
+    Json(read_only_status(&state).await)
+}
+
+/// Switches read-only mode on or off by hand on every replica. With
+/// `READ_ONLY_ON_OUTAGE` it still comes on by itself during a Keycloak
+/// outage.
+pub async fn update_read_only_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    ApiJson(payload): ApiJson<ReadOnlyRequest>,
+) -> Result<Json<ReadOnlyResponse>, Rejection> {
+    if let Err(err) = state.read_only.set_manual(payload.enabled).await {
+        error!("[Admin] unable to switch read-only mode: {}", err);
+        return Err(reject(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Read-only mode cannot be switched right now",
+        ));
+    }
+    info!(
+        "[Admin] user={} set read-only mode enabled={}",
+        admin.display_name(),
+        payload.enabled
+    );
+    state.audit.record(
+        admin.display_name(),
+        "admin.read_only.update",
+        AuditOutcome::Success,
+        Some(if payload.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }),
+        None,
+    );
+    Ok(Json(read_only_status(&state).await))
+}
+
+async fn read_only_status(state: &AppState) -> ReadOnlyResponse {
+    let reason = state.read_only.reason().await;
+    ReadOnlyResponse {
+        enabled: reason.is_some(),
+        manual: state.read_only.is_manual().await,
+        reason,
+    }
+}
+
+/// Availability and latency of each `SLO_TARGETS` route, with how fast its
+/// error budget is burning.
+pub async fn slo_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+) -> Json<SloResponse> {
+    Json(SloResponse {
+        window_secs: state.slo.window().as_secs(),
+        routes: state.slo.report(),
+    })
+}
@@ -1,103 +1,1138 @@
-use axum::{Json, extract::State, http::StatusCode};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, PRAGMA, SET_COOKIE, USER_AGENT},
+        request::Parts,
+    },
+    response::{Html, IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha2::{Digest, Sha256};
 use tracing::{error, info, warn};
 
 use crate::AppState;
-use crate::captcha::{captcha_error_status, ensure_valid};
+use crate::admin;
+use crate::anomaly::AnomalyKind;
+use crate::api_version::{self, ApiVersion};
+use crate::audit::AuditOutcome;
+use crate::captcha::{
+    CaptchaAction, CaptchaContext, CaptchaError, captcha_error_status, ensure_valid,
+};
+use crate::captcha_exemption::exemption_header;
+use crate::client_ip::ClientIp;
+use crate::cookies;
+use crate::deadline;
+use crate::dpop;
+use crate::email;
+use crate::email_verification::UnverifiedLogin;
+use crate::error_codes::ErrorCode;
+use crate::extract::{ApiJson, JsonOrForm, LoginCredentials, Rejection};
 use crate::keycloak::{KeycloakError, UserTokenSet};
-use crate::models::auth::{AuthResponse, LoginRequest, LogoutRequest, RefreshRequest};
-use crate::models::user::ErrorResponse;
-
-const DEFAULT_SCOPE: &str = "openid";
+use crate::keycloak_limiter::{Priority, prioritized};
+use crate::locale;
+use crate::login_challenge::{ChallengeKind, PendingLogin};
+use crate::login_notification::LoginDetails;
+use crate::login_scope;
+use crate::models::auth::{
+    AcceptTermsRequest, AccessTokenClaims, AuthCheckResponse, AuthResponse,
+    FrontChannelLogoutQuery, LoginChallenge, LoginContinueRequest, LoginRequest, LoginResponse,
+    LogoutRequest, RefreshRequest, ResendVerificationRequest, SessionInfoResponse, Timestamp,
+};
+use crate::models::user::{AccountHint, ErrorResponse, FieldError};
+use crate::preferences::{self, TIMEZONE_ATTRIBUTE};
+use crate::refresh_hint;
+use crate::session_limit::SessionCheck;
+use crate::tenant::ResolvedTenant;
+use crate::username;
+use crate::util::{unix_now, unverified_claims};
+use crate::validation::reject_unknown_fields;
 
 pub async fn login_handler(
     State(state): State<AppState>,
-    Json(payload): Json<LoginRequest>,
-) -> Result<(StatusCode, Json<AuthResponse>), (StatusCode, Json<ErrorResponse>)> {
+    tenant: ResolvedTenant,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    LoginCredentials(payload): LoginCredentials,
+) -> Result<LoginReply, Rejection> {
     let LoginRequest {
         email,
         password,
+        otp,
         captcha_token,
+        scope,
+        extra,
     } = payload;
 
-    let email = email.trim();
+    reject_unknown_fields(&state.live.current(), &extra)?;
+    let allowed = login_scope::allowed(&state.config, tenant.as_ref());
+    let scope = login_scope::resolve(scope.as_deref(), allowed)
+        .map_err(|unknown| unknown_scopes(allowed, &unknown))?;
+
+    let email = email::normalize(&email);
     if email.is_empty() || password.trim().is_empty() {
         return Err(invalid_request("Email and password are required"));
     }
 
-    if let Err(error) = ensure_valid(&state, captcha_token.as_deref()).await {
-        let (status, message) = captcha_error_status(error);
-        return Err((status, Json(ErrorResponse::new(message.to_owned()))));
+    let captcha = CaptchaContext {
+        action: CaptchaAction::Login,
+        tenant: tenant.as_ref(),
+        remote_ip: Some(client_ip),
+        exemption: exemption_header(&headers),
+    };
+    ensure_valid(&state, &captcha, captcha_token.as_deref())
+        .await
+        .map_err(captcha_rejected)?;
+    // Only after the captcha, so usernames cannot be probed for free.
+    let email = login_email(&state, email).await;
+    let email = email.as_str();
+
+    let login = Login {
+        state: &state,
+        tenant: &tenant,
+        headers: &headers,
+        client_ip,
+        email,
+        scope: &scope,
+    };
+    login.authenticate(&password, otp.as_deref(), true).await
+}
+
+/// The password login for callers other than `/api/auth/login`, such as
+/// the OAuth token endpoint, once they have passed the captcha: the same
+/// grant, challenges and post-grant checks, history and notifications.
+pub(crate) async fn password_login(
+    state: &AppState,
+    tenant: &ResolvedTenant,
+    headers: &HeaderMap,
+    client_ip: IpAddr,
+    identifier: &str,
+    password: &str,
+    scope: &str,
+) -> Result<LoginReply, Rejection> {
+    let email = login_email(state, email::normalize(identifier)).await;
+    let login = Login {
+        state,
+        tenant,
+        headers,
+        client_ip,
+        email: &email,
+        scope,
+    };
+    login.authenticate(password, None, true).await
+}
+
+fn captcha_rejected(error: CaptchaError) -> Rejection {
+    let (status, code, message) = captcha_error_status(error);
+    (status, Json(ErrorResponse::new(code, message.to_owned())))
+}
+
+fn unknown_scopes(allowed: &[String], unknown: &[String]) -> Rejection {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(
+            ErrorResponse::new(
+                ErrorCode::ValidationFailed,
+                "Invalid request body".to_owned(),
+            )
+            .with_fields(vec![FieldError {
+                field: "scope".to_owned(),
+                expected: Some(allowed.join(" ")),
+                message: format!("unknown scope {}", unknown.join(" ")),
+            }]),
+        ),
+    )
+}
+
+/// The email behind a login identifier. With distinct usernames users may
+/// sign in with either; a username is swapped for the account's email so
+/// every later step keys on the email as before. Unknown usernames are
+/// passed through and fail the grant like a wrong password would.
+async fn login_email(state: &AppState, identifier: String) -> String {
+    if !state.config.username_mode.distinct() || !username::is_username(&identifier) {
+        return identifier;
+    }
+    match state.keycloak.find_users_by_username(&identifier).await {
+        Ok(users) => match users.as_slice() {
+            [user] => user.email.clone().unwrap_or(identifier),
+            _ => identifier,
+        },
+        Err(err) => {
+            warn!("[Login] username lookup failed for {}: {}", identifier, err);
+            identifier
+        }
+    }
+}
+
+/// Resumes a login that ended in a challenge. Challenges that held on to
+/// the tokens only rerun the remaining checks; the others repeat the grant
+/// with the password (and one-time code) sent here. Every continuation
+/// passes the login captcha, and a user gets five one-time code attempts
+/// per challenge lifetime. A continuation that fails never raises a new
+/// `mfa_required` challenge.
+pub async fn login_continue_handler(
+    State(state): State<AppState>,
+    tenant: ResolvedTenant,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<LoginContinueRequest>,
+) -> Result<LoginReply, Rejection> {
+    reject_unknown_fields(&state.live.current(), &payload.extra)?;
+
+    let captcha = CaptchaContext {
+        action: CaptchaAction::Login,
+        tenant: tenant.as_ref(),
+        remote_ip: Some(client_ip),
+        exemption: exemption_header(&headers),
+    };
+    ensure_valid(&state, &captcha, payload.captcha_token.as_deref())
+        .await
+        .map_err(captcha_rejected)?;
+
+    let Some(pending) = state
+        .login_challenges
+        .take(&payload.continuation_token)
+        .await
+    else {
+        warn!("[Login] continuation with an unknown or expired token");
+        return Err(invalid_request("Invalid or expired continuation token"));
+    };
+
+    let login = Login {
+        state: &state,
+        tenant: &tenant,
+        headers: &headers,
+        client_ip,
+        email: &pending.email,
+        scope: pending.scope.as_deref().unwrap_or(login_scope::OPENID),
+    };
+    info!(
+        "[Login] user={} continuing after {:?}",
+        login.email, pending.kind
+    );
+    match pending.tokens {
+        Some(tokens) => login.complete(tokens).await,
+        None => {
+            let password = payload.password.unwrap_or_default();
+            if password.trim().is_empty() {
+                return Err(invalid_request("Password is required"));
+            }
+            let mfa = pending.kind == ChallengeKind::MfaRequired;
+            if mfa && !state.login_challenges.claim_mfa_attempt(login.email).await {
+                warn!("[Login] user={} out of one-time code attempts", login.email);
+                return Err((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(ErrorResponse::new(
+                        ErrorCode::RateLimited,
+                        "Too many attempts, please sign in again later".to_owned(),
+                    )),
+                ));
+            }
+            login
+                .authenticate(&password, payload.otp.as_deref(), !mfa)
+                .await
+        }
+    }
+}
+
+/// Resends the verification email for a pending `email_unverified`
+/// challenge. The continuation token stays valid so the client can continue
+/// once the link was followed.
+pub async fn resend_verification_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<ResendVerificationRequest>,
+) -> Result<StatusCode, Rejection> {
+    let pending = state
+        .login_challenges
+        .peek(&payload.continuation_token)
+        .await
+        .filter(|pending| pending.kind == ChallengeKind::EmailUnverified);
+    let Some(pending) = pending else {
+        return Err(invalid_request("Invalid or expired continuation token"));
+    };
+
+    if !state.login_challenges.claim_resend(&pending.email).await {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse::new(
+                ErrorCode::VerificationThrottled,
+                "Verification email was just sent, please wait a minute".to_owned(),
+            )),
+        ));
     }
 
     match state
-        .keycloak
-        .password_grant(email, password.as_str(), Some(DEFAULT_SCOPE))
+        .email_verification
+        .resend(&pending.email, locale::header(&headers))
         .await
     {
-        Ok(tokens) => {
-            info!("[Login] user={} result=200", email);
-            Ok((StatusCode::OK, Json(to_auth_response(tokens))))
+        Ok(()) => {
+            info!("[Login] user={} verification email resent", pending.email);
+            Ok(StatusCode::ACCEPTED)
         }
-        Err(err) => Err(map_token_error("login", email, err)),
+        Err(err) => Err(map_token_error("resend verification", &pending.email, err)),
     }
 }
 
+/// Records acceptance of the current terms for a pending
+/// `terms_update_required` challenge and finishes the login with the tokens
+/// it held back.
+pub async fn accept_terms_handler(
+    State(state): State<AppState>,
+    tenant: ResolvedTenant,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<AcceptTermsRequest>,
+) -> Result<LoginReply, Rejection> {
+    let invalid = || invalid_request("Invalid or expired continuation token");
+    let pending = state
+        .login_challenges
+        .peek(&payload.continuation_token)
+        .await
+        .filter(|pending| pending.kind == ChallengeKind::TermsUpdateRequired)
+        .ok_or_else(invalid)?;
+
+    if state.terms.current_version() != Some(payload.version.trim()) {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(
+                ErrorCode::TermsChanged,
+                "The terms of service have changed, please review them again".to_owned(),
+            )),
+        ));
+    }
+
+    // Taken only now, so that a stale version can be retried with the same
+    // continuation token.
+    let tokens = state
+        .login_challenges
+        .take(&payload.continuation_token)
+        .await
+        .and_then(|pending| pending.tokens)
+        .ok_or_else(invalid)?;
+
+    let email = pending.email.as_str();
+    if let Err(err) = state.terms.accept(email).await {
+        return Err(map_token_error("accept terms", email, err));
+    }
+    info!(
+        "[Login] user={} accepted terms version {}",
+        email, payload.version
+    );
+    state.audit.record(
+        email,
+        "user.terms_accepted",
+        AuditOutcome::Success,
+        Some(payload.version.trim()),
+        Some(client_ip),
+    );
+
+    let login = Login {
+        state: &state,
+        tenant: &tenant,
+        headers: &headers,
+        client_ip,
+        email,
+        scope: pending.scope.as_deref().unwrap_or(login_scope::OPENID),
+    };
+    login.complete(tokens).await
+}
+
 pub async fn refresh_handler(
     State(state): State<AppState>,
-    Json(payload): Json<RefreshRequest>,
-) -> Result<(StatusCode, Json<AuthResponse>), (StatusCode, Json<ErrorResponse>)> {
-    if payload.refresh_token.trim().is_empty() {
+    tenant: ResolvedTenant,
+    headers: HeaderMap,
+    JsonOrForm(payload): JsonOrForm<RefreshRequest>,
+) -> Result<(StatusCode, HeaderMap, Json<AuthResponse>), (StatusCode, Json<ErrorResponse>)> {
+    reject_unknown_fields(&state.live.current(), &payload.extra)?;
+
+    let Some(refresh_token) = refresh_token(&state, &headers, payload.refresh_token) else {
         return Err(invalid_request("Refresh token is required"));
+    };
+    if state.revocations.is_revoked(&refresh_token).await {
+        warn!("[Login] refresh with a revoked token");
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse::new(
+                ErrorCode::SessionEnded,
+                "Session has ended".to_owned(),
+            )),
+        ));
     }
 
     match state
         .keycloak
-        .refresh_user_token(payload.refresh_token.as_str(), Some(DEFAULT_SCOPE))
+        .refresh_user_token(
+            refresh_token.as_str(),
+            None,
+            dpop::proof_header(&headers),
+            state.keycloak.public_client(tenant.as_ref()),
+        )
         .await
     {
         Ok(tokens) => {
             info!("[Login] refresh result=200");
-            Ok((StatusCode::OK, Json(to_auth_response(tokens))))
+            Ok(deliver(&state, &headers, tokens))
         }
         Err(err) => Err(map_token_error("refresh", "<hidden>", err)),
     }
 }
 
+/// Cheap session probe for cookie-mode clients, meant to be polled on tab
+/// focus. A bearer token that is still valid for longer than
+/// `AUTH_CHECK_REFRESH_WINDOW_SECS` is answered from one introspection;
+/// otherwise the session is refreshed from the refresh cookie and the new
+/// access token returned. Without a refresh cookie the answer is 401 without
+/// asking Keycloak at all.
+pub async fn check_handler(
+    State(state): State<AppState>,
+    tenant: ResolvedTenant,
+    mut parts: Parts,
+) -> Response {
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    let name = state.config.auth_cookie_name.as_str();
+    if !state.cookies.is_enabled() || cookies::read_cookie(&parts.headers, name).is_none() {
+        return session_ended(response_headers);
+    }
+
+    if admin::bearer_token(&parts).is_some()
+        && let Ok(introspection) = admin::introspect(&mut parts, &state).await
+    {
+        // `exp` is on Keycloak's clock; count it as expiring early by the
+        // skew leeway so a drifted host does not trust a token it refuses.
+        let expires_in = introspection
+            .exp
+            .map(|exp| exp.saturating_sub(unix_now() + state.config.clock_skew_leeway.as_secs()))
+            .unwrap_or_default();
+        if expires_in > state.config.auth_check_refresh_window.as_secs() {
+            let response = AuthCheckResponse {
+                sub: introspection.sub,
+                username: introspection.username,
+                expires_in,
+                tokens: None,
+            };
+            return (response_headers, Json(response)).into_response();
+        }
+    }
+
+    let headers = &parts.headers;
+    let Some(refresh_token) = refresh_token(&state, headers, String::new()) else {
+        return session_ended(clearing_cookie(&state, response_headers));
+    };
+    if state.revocations.is_revoked(&refresh_token).await {
+        return session_ended(clearing_cookie(&state, response_headers));
+    }
+
+    let tokens = match state
+        .keycloak
+        .refresh_user_token(
+            refresh_token.as_str(),
+            None,
+            dpop::proof_header(headers),
+            state.keycloak.public_client(tenant.as_ref()),
+        )
+        .await
+    {
+        Ok(tokens) => tokens,
+        Err(KeycloakError::InvalidGrant { .. }) => {
+            info!("[Login] check found an ended session");
+            return session_ended(clearing_cookie(&state, response_headers));
+        }
+        Err(err) => return map_token_error("check", "<hidden>", err).into_response(),
+    };
+
+    let identity = state
+        .keycloak
+        .introspect_token(&tokens.access_token)
+        .await
+        .inspect_err(|err| warn!("[Login] check could not introspect new token: {}", err))
+        .unwrap_or_default();
+    let mut response = to_auth_response(tokens);
+    refresh_hint::apply(&state.config, &mut response_headers, response.expires_in);
+    seal_refresh_cookie(&state, &mut response, &mut response_headers);
+    versioned(headers, &mut response, &mut response_headers);
+    info!("[Login] check refreshed the session");
+
+    let response = AuthCheckResponse {
+        sub: identity.sub,
+        username: identity.username,
+        expires_in: response.expires_in,
+        tokens: Some(response),
+    };
+    (response_headers, Json(response)).into_response()
+}
+
+/// What is known about the caller's session, for answering "why was I
+/// signed out": when the token was issued and expires, the authentication
+/// level and client, and, from Keycloak, when the session started and was
+/// last used. In cookie mode the refresh token's expiry is included, which
+/// is when an idle session ends. Lookups beyond the token are best effort.
+pub async fn session_handler(
+    State(state): State<AppState>,
+    mut parts: Parts,
+) -> Result<(HeaderMap, Json<SessionInfoResponse>), Rejection> {
+    let introspection = admin::introspect(&mut parts, &state).await?;
+
+    let (session, timezone) = match introspection.sub.as_deref() {
+        Some(user_id) => {
+            let (sessions, user) = tokio::join!(
+                state.keycloak.user_sessions(user_id),
+                state.keycloak.get_user(user_id)
+            );
+            let session = sessions
+                .inspect_err(|err| warn!("[Login] session lookup failed for {}: {}", user_id, err))
+                .ok()
+                .and_then(|sessions| {
+                    sessions
+                        .into_iter()
+                        .find(|session| Some(&session.id) == introspection.sid.as_ref())
+                });
+            let timezone = user.ok().and_then(|user| {
+                preferences::attribute(&user, TIMEZONE_ATTRIBUTE).map(str::to_owned)
+            });
+            (session, timezone)
+        }
+        None => (None, None),
+    };
+    let refresh_expires_at = refresh_token(&state, &parts.headers, String::new())
+        .as_deref()
+        .and_then(unverified_expiry);
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok((
+        response_headers,
+        Json(SessionInfoResponse {
+            session_id: introspection.sid,
+            client_id: introspection.azp.or(introspection.client_id),
+            acr: introspection.acr,
+            authenticated_at: introspection.auth_time.map(Timestamp::new),
+            issued_at: introspection.iat.map(Timestamp::new),
+            expires_at: introspection.exp.map(Timestamp::new),
+            refresh_expires_at: refresh_expires_at.map(Timestamp::new),
+            session_started_at: session
+                .as_ref()
+                .map(|session| Timestamp::new(session.start / 1_000)),
+            last_access_at: session.map(|session| Timestamp::new(session.last_access / 1_000)),
+            timezone,
+            server_time: Timestamp::new(unix_now()),
+        }),
+    ))
+}
+
+/// `exp` of a JWT, read without checking the signature. Only used to show
+/// the user when their refresh token lapses; Keycloak still decides.
+fn unverified_expiry(token: &str) -> Option<u64> {
+    let claims: serde_json::Value = unverified_claims(token)?;
+    claims.get("exp")?.as_u64()
+}
+
+/// Who a freshly issued access token belongs to and which client it was
+/// issued to.
+#[derive(Default, serde::Deserialize)]
+struct IssuedTo {
+    sub: Option<String>,
+    azp: Option<String>,
+}
+
+fn session_ended(headers: HeaderMap) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        headers,
+        Json(ErrorResponse::new(
+            ErrorCode::NotSignedIn,
+            "Not signed in".to_owned(),
+        )),
+    )
+        .into_response()
+}
+
+fn clearing_cookie(state: &AppState, mut headers: HeaderMap) -> HeaderMap {
+    if let Some(cookie) = cookies::clear_cookie(&state.config) {
+        headers.insert(SET_COOKIE, cookie);
+    }
+    headers
+}
+
 pub async fn logout_handler(
     State(state): State<AppState>,
-    Json(payload): Json<LogoutRequest>,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    if payload.refresh_token.trim().is_empty() {
-        return Err(invalid_request("Refresh token is required"));
+    tenant: ResolvedTenant,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<LogoutRequest>,
+) -> Result<(StatusCode, HeaderMap), (StatusCode, HeaderMap, Json<ErrorResponse>)> {
+    // The cookie is cleared whatever Keycloak answers, so a failed logout
+    // never leaves the browser holding the refresh token.
+    let mut response_headers = HeaderMap::new();
+    if cookies::read_cookie(&headers, &state.config.auth_cookie_name).is_some()
+        && let Some(cookie) = cookies::clear_cookie(&state.config)
+    {
+        response_headers.insert(SET_COOKIE, cookie);
+    }
+
+    let Some(refresh_token) = refresh_token(&state, &headers, payload.refresh_token) else {
+        let (status, body) = invalid_request("Refresh token is required");
+        return Err((status, response_headers, body));
+    };
+
+    // Other replicas may have cached state for these tokens; tell them now
+    // rather than leaving it to expire.
+    state.revocations.revoke(&refresh_token).await;
+    if let Some(access_token) = admin::bearer_token_from(&headers) {
+        state.revocations.revoke(&access_token).await;
     }
 
+    let client = state.keycloak.public_client(tenant.as_ref());
     match state
         .keycloak
-        .logout_user(payload.refresh_token.as_str())
+        .logout_user(refresh_token.as_str(), client)
         .await
     {
         Ok(_) => {
             info!("[Login] logout result=204");
-            Ok(StatusCode::NO_CONTENT)
+            Ok((StatusCode::NO_CONTENT, response_headers))
         }
         Err(KeycloakError::InvalidGrant { .. }) => {
             warn!("[Login] logout invalid grant");
-            Ok(StatusCode::NO_CONTENT)
+            Ok((StatusCode::NO_CONTENT, response_headers))
+        }
+        Err(err) => {
+            let (status, body) = map_logout_error(err);
+            Err((status, response_headers, body))
+        }
+    }
+}
+
+/// Page Keycloak loads in a hidden iframe when a session ends elsewhere,
+/// registered as the public client's front-channel logout URL through
+/// `FRONTCHANNEL_LOGOUT_URL`. It clears the refresh cookie, revokes the token
+/// it held, and posts `{type: "logout", sid}` on the `argus-auth`
+/// `BroadcastChannel` so open SPA tabs drop their access tokens. Browsers
+/// only send the `SameSite=Strict` cookie and share the channel with the SPA
+/// when Keycloak and the portal are on the same site.
+pub async fn frontchannel_logout_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    Query(query): Query<FrontChannelLogoutQuery>,
+) -> Response {
+    if query
+        .iss
+        .as_deref()
+        .is_some_and(|iss| iss != state.config.keycloak_issuer())
+    {
+        warn!(
+            "[Login] front-channel logout from unknown issuer {:?}",
+            query.iss
+        );
+        return invalid_request("Unknown issuer").into_response();
+    }
+
+    let mut response_headers = HeaderMap::new();
+    if let Some(refresh_token) = refresh_token(&state, &headers, String::new()) {
+        state.revocations.revoke(&refresh_token).await;
+    }
+    if cookies::read_cookie(&headers, &state.config.auth_cookie_name).is_some()
+        && let Some(cookie) = cookies::clear_cookie(&state.config)
+    {
+        response_headers.insert(SET_COOKIE, cookie);
+    }
+
+    let sid = query.sid.as_deref().unwrap_or_default();
+    info!("[Login] front-channel logout sid={}", sid);
+    state.audit.record(
+        "keycloak",
+        "auth.frontchannel_logout",
+        AuditOutcome::Success,
+        (!sid.is_empty()).then_some(sid),
+        Some(client_ip),
+    );
+
+    // `<` is escaped so a crafted `sid` cannot close the script element.
+    let message = serde_json::json!({ "type": "logout", "sid": sid })
+        .to_string()
+        .replace('<', "\\u003c");
+    let script = format!("new BroadcastChannel(\"argus-auth\").postMessage({message});");
+    let script_hash = STANDARD.encode(Sha256::digest(script.as_bytes()));
+    let frame_ancestors = reqwest::Url::parse(&state.config.keycloak_issuer())
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_else(|_| "'none'".to_owned());
+    let policy = format!(
+        "default-src 'none'; script-src 'sha256-{script_hash}'; frame-ancestors {frame_ancestors}"
+    );
+    if let Ok(policy) = HeaderValue::from_str(&policy) {
+        response_headers.insert(CONTENT_SECURITY_POLICY, policy);
+    }
+    response_headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("no-cache, no-store"),
+    );
+    response_headers.insert(PRAGMA, HeaderValue::from_static("no-cache"));
+
+    let page = format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><title>Signed out</title></head><body><script>{script}</script></body></html>"
+    );
+    (response_headers, Html(page)).into_response()
+}
+
+pub(crate) type LoginReply = (StatusCode, HeaderMap, Json<LoginResponse>);
+
+/// One login attempt, from the password grant to the tokens or a challenge.
+struct Login<'a> {
+    state: &'a AppState,
+    tenant: &'a ResolvedTenant,
+    headers: &'a HeaderMap,
+    client_ip: IpAddr,
+    email: &'a str,
+    /// Sent with the grant, and again when a challenge repeats it.
+    scope: &'a str,
+}
+
+impl Login<'_> {
+    /// Runs the password grant. When it fails for an account with an OTP
+    /// credential and no code was sent, `mfa_challenge` allows answering
+    /// with an `mfa_required` challenge, but only once the password itself
+    /// was shown to be right.
+    async fn authenticate(
+        &self,
+        password: &str,
+        otp: Option<&str>,
+        mfa_challenge: bool,
+    ) -> Result<LoginReply, Rejection> {
+        let state = self.state;
+        let result = state
+            .keycloak
+            .password_grant(
+                self.email,
+                password,
+                otp,
+                Some(self.scope),
+                dpop::proof_header(self.headers),
+                state.keycloak.public_client(self.tenant.as_ref()),
+            )
+            .await;
+
+        match result {
+            Ok(tokens) => self.complete(tokens).await,
+            Err(err) => {
+                if !matches!(err, KeycloakError::InvalidGrant { .. }) {
+                    return Err(map_token_error("login", self.email, err));
+                }
+
+                self.record(AuditOutcome::Failure).await;
+                state.anomalies.observe(
+                    AnomalyKind::FailedLogin,
+                    self.client_ip,
+                    &state.metrics,
+                    &state.http_client,
+                );
+                // Only look the account up when something may come of it:
+                // social hints are opt-in, and `mfa_required` needs the
+                // password check client.
+                let check_mfa =
+                    mfa_challenge && otp.is_none() && state.login_challenges.checks_passwords();
+                let social = state.config.social_login_hints;
+                if check_mfa || social {
+                    let facts = state.login_challenges.inspect(self.email, social).await;
+                    if let Some(providers) = facts.social_providers {
+                        info!(
+                            "[Login] user={} has no password, signs in with {}",
+                            self.email,
+                            providers.join(", ")
+                        );
+                        return Err(use_social_login(providers));
+                    }
+                    if check_mfa
+                        && facts.otp
+                        && state
+                            .login_challenges
+                            .password_matches(self.email, password)
+                            .await
+                    {
+                        return self.challenge(ChallengeKind::MfaRequired, None, None).await;
+                    }
+                }
+                Err(map_token_error("login", self.email, err))
+            }
+        }
+    }
+
+    /// Checks that run once the grant succeeded; the tokens are only
+    /// released when none of them raises a challenge.
+    async fn complete(&self, tokens: UserTokenSet) -> Result<LoginReply, Rejection> {
+        let state = self.state;
+        let password_expires_in_days = state.password_expiry.days_remaining(self.email).await;
+        if password_expires_in_days.is_some_and(|days| days <= 0) {
+            return self.password_expired(tokens).await;
+        }
+
+        let issued: IssuedTo = unverified_claims(&tokens.access_token).unwrap_or_default();
+        match state
+            .session_limits
+            .enforce(self.email, issued.sub.as_deref(), issued.azp.as_deref())
+            .await
+        {
+            SessionCheck::Within => {}
+            SessionCheck::Evicted(count) => state.audit.record(
+                self.email,
+                "auth.session_evicted",
+                AuditOutcome::Success,
+                Some(&count.to_string()),
+                Some(self.client_ip),
+            ),
+            SessionCheck::Exceeded { active, max } => {
+                return self.too_many_sessions(tokens, active, max).await;
+            }
+            SessionCheck::Unknown => return self.session_unchecked(tokens).await,
+        }
+
+        let email_verified = state.email_verification.is_verified(self.email).await;
+        let email_unverified = email_verified == Some(false);
+        if email_unverified && state.email_verification.policy() == UnverifiedLogin::Block {
+            info!("[Login] user={} email not verified", self.email);
+            state.audit.record(
+                self.email,
+                "auth.email_unverified",
+                AuditOutcome::Failure,
+                None,
+                Some(self.client_ip),
+            );
+            return self
+                .challenge(ChallengeKind::EmailUnverified, Some(tokens), None)
+                .await;
+        }
+
+        if state.terms.needs_acceptance(self.email).await {
+            info!(
+                "[Login] user={} has not accepted the current terms",
+                self.email
+            );
+            return self
+                .challenge(
+                    ChallengeKind::TermsUpdateRequired,
+                    Some(tokens),
+                    state.terms.url(),
+                )
+                .await;
+        }
+
+        info!("[Login] user={} result=200", self.email);
+        self.record(AuditOutcome::Success).await;
+        self.notify();
+        let (status, response_headers, Json(mut response)) = deliver(state, self.headers, tokens);
+        response.password_expires_in_days = password_expires_in_days;
+        response.email_unverified = email_unverified;
+        Ok((
+            status,
+            response_headers,
+            Json(LoginResponse::Tokens(response)),
+        ))
+    }
+
+    /// The password is past `PASSWORD_MAX_AGE_DAYS`: the new session is
+    /// ended again and the client is sent to change the password, then
+    /// continues with the new one.
+    async fn password_expired(&self, tokens: UserTokenSet) -> Result<LoginReply, Rejection> {
+        let state = self.state;
+        info!("[Login] user={} password expired", self.email);
+        state.audit.record(
+            self.email,
+            "auth.password_expired",
+            AuditOutcome::Failure,
+            None,
+            Some(self.client_ip),
+        );
+
+        let client = state.keycloak.public_client(self.tenant.as_ref());
+        if let Err(err) = state
+            .keycloak
+            .logout_user(&tokens.refresh_token, client)
+            .await
+        {
+            warn!("[Login] unable to end session of expired password: {}", err);
+        }
+
+        self.challenge(
+            ChallengeKind::PasswordExpired,
+            None,
+            Some(state.password_expiry.change_url()),
+        )
+        .await
+    }
+
+    /// The user already holds as many sessions as they may: the new one is
+    /// ended again and the login is rejected.
+    async fn too_many_sessions(
+        &self,
+        tokens: UserTokenSet,
+        active: usize,
+        max: usize,
+    ) -> Result<LoginReply, Rejection> {
+        let state = self.state;
+        info!(
+            "[Login] user={} has {} sessions, limit is {}",
+            self.email, active, max
+        );
+        state.audit.record(
+            self.email,
+            "auth.too_many_sessions",
+            AuditOutcome::Failure,
+            None,
+            Some(self.client_ip),
+        );
+
+        self.end_session(&tokens).await;
+        Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(
+                ErrorCode::TooManySessions,
+                format!(
+                    "You are signed in on too many devices, sign out of one to continue (limit {max})"
+                ),
+            )),
+        ))
+    }
+
+    /// The session cap could not be checked, so the new session is ended
+    /// instead of being let past it.
+    async fn session_unchecked(&self, tokens: UserTokenSet) -> Result<LoginReply, Rejection> {
+        self.end_session(&tokens).await;
+        Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse::new(
+                ErrorCode::ServiceUnavailable,
+                "Login temporarily unavailable".to_owned(),
+            )),
+        ))
+    }
+
+    async fn end_session(&self, tokens: &UserTokenSet) {
+        let client = self.state.keycloak.public_client(self.tenant.as_ref());
+        if let Err(err) = self
+            .state
+            .keycloak
+            .logout_user(&tokens.refresh_token, client)
+            .await
+        {
+            warn!("[Login] unable to end session {}: {}", self.email, err);
+        }
+    }
+
+    async fn challenge(
+        &self,
+        kind: ChallengeKind,
+        tokens: Option<UserTokenSet>,
+        action_url: Option<&str>,
+    ) -> Result<LoginReply, Rejection> {
+        let challenges = &self.state.login_challenges;
+        let pending = PendingLogin {
+            email: self.email.to_owned(),
+            kind,
+            tokens,
+            scope: Some(self.scope.to_owned()),
+        };
+        let Some(continuation_token) = challenges.issue(&pending).await else {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse::new(
+                    ErrorCode::ServiceUnavailable,
+                    "Login temporarily unavailable".to_owned(),
+                )),
+            ));
+        };
+
+        info!("[Login] user={} challenge={:?}", self.email, kind);
+        let challenge = LoginChallenge {
+            kind,
+            continuation_token,
+            expires_in: challenges.ttl().as_secs(),
+            message: kind.message().to_owned(),
+            action_url: action_url.map(str::to_owned),
+            actions: match kind {
+                ChallengeKind::EmailUnverified => vec!["resend_verification"],
+                ChallengeKind::TermsUpdateRequired => vec!["accept_terms"],
+                _ => Vec::new(),
+            },
+            terms_version: (kind == ChallengeKind::TermsUpdateRequired)
+                .then(|| self.state.terms.current_version().map(str::to_owned))
+                .flatten(),
+        };
+        Ok((
+            StatusCode::OK,
+            HeaderMap::new(),
+            Json(LoginResponse::Challenge(challenge)),
+        ))
+    }
+
+    /// Sends the new-login notice in the background so the mail event never
+    /// delays the response.
+    fn notify(&self) {
+        let state = self.state;
+        if !state.login_notifications.is_enabled() {
+            return;
         }
-        Err(err) => Err(map_logout_error(err)),
+        let header = |name: &str| {
+            self.headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        let login = LoginDetails {
+            email: self.email.to_owned(),
+            ip: self.client_ip,
+            user_agent: header(USER_AGENT.as_str()),
+            location: state
+                .config
+                .login_location_header
+                .as_deref()
+                .and_then(header),
+        };
+        let notifications = Arc::clone(&state.login_notifications);
+        tokio::spawn(prioritized(Priority::Background, async move {
+            notifications.notify(login).await
+        }));
+    }
+
+    async fn record(&self, outcome: AuditOutcome) {
+        let state = self.state;
+        let user_agent = self
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok());
+        state.audit.record(
+            self.email,
+            "auth.login",
+            outcome,
+            None,
+            Some(self.client_ip),
+        );
+        state
+            .login_history
+            .record(self.email, outcome, Some(self.client_ip), user_agent)
+            .await;
+    }
+}
+
+/// The refresh token from the request body or, failing that, the encrypted
+/// refresh cookie.
+fn refresh_token(state: &AppState, headers: &HeaderMap, body: String) -> Option<String> {
+    if !body.trim().is_empty() {
+        return Some(body);
+    }
+
+    let name = state.config.auth_cookie_name.as_str();
+    let sealed = cookies::read_cookie(headers, name)?;
+    let token = state.cookies.open(name, sealed);
+    if token.is_none() {
+        warn!("[Login] refresh cookie could not be decrypted");
+    }
+    token
+}
+
+/// In cookie mode the refresh token is moved out of the body into an
+/// encrypted `HttpOnly` cookie, re-sealed with the current key each time.
+/// Every delivery carries the refresh hint headers.
+fn deliver(
+    state: &AppState,
+    headers: &HeaderMap,
+    tokens: UserTokenSet,
+) -> (StatusCode, HeaderMap, Json<AuthResponse>) {
+    let mut response = to_auth_response(tokens);
+    if state.live.current().login_response_roles {
+        with_roles(&mut response);
+    }
+    let mut response_headers = HeaderMap::new();
+    refresh_hint::apply(&state.config, &mut response_headers, response.expires_in);
+
+    if cookies::wants_cookie(headers) && state.cookies.is_enabled() {
+        seal_refresh_cookie(state, &mut response, &mut response_headers);
+    }
+    versioned(headers, &mut response, &mut response_headers);
+
+    (StatusCode::OK, response_headers, Json(response))
+}
+
+/// Shapes a token response for the version in `Accept`: v2 adds the access
+/// token's claims.
+fn versioned(headers: &HeaderMap, response: &mut AuthResponse, response_headers: &mut HeaderMap) {
+    let version = ApiVersion::of(headers);
+    if version >= ApiVersion::V2 {
+        response.claims = unverified_claims::<AccessTokenClaims>(&response.access_token)
+            .map(|claims| Box::new(claims.into()));
+    }
+    api_version::apply(version, response_headers);
+}
+
+fn with_roles(response: &mut AuthResponse) {
+    let claims = unverified_claims::<AccessTokenClaims>(&response.access_token).unwrap_or_default();
+    response.realm_roles = Some(
+        claims
+            .realm_access
+            .map(|access| access.roles)
+            .unwrap_or_default(),
+    );
+    response.client_roles = Some(
+        claims
+            .resource_access
+            .into_iter()
+            .map(|(client, access)| (client, access.roles))
+            .collect(),
+    );
+    response.groups = Some(claims.groups);
+}
+
+fn seal_refresh_cookie(state: &AppState, response: &mut AuthResponse, headers: &mut HeaderMap) {
+    let name = state.config.auth_cookie_name.as_str();
+    let max_age = response.refresh_expires_in.unwrap_or(response.expires_in);
+    let cookie = state
+        .cookies
+        .seal(name, &response.refresh_token)
+        .and_then(|sealed| cookies::set_cookie(&state.config, &sealed, max_age));
+    if let Some(cookie) = cookie {
+        headers.insert(SET_COOKIE, cookie);
+        response.refresh_token.clear();
     }
 }
 
 fn to_auth_response(tokens: UserTokenSet) -> AuthResponse {
     AuthResponse {
+        dpop_bound: tokens.token_type.eq_ignore_ascii_case("DPoP"),
         token_type: tokens.token_type,
         access_token: tokens.access_token,
         refresh_token: tokens.refresh_token,
         expires_in: tokens.expires_in,
         refresh_expires_in: tokens.refresh_expires_in,
+        password_expires_in_days: None,
+        email_unverified: false,
+        claims: None,
+        realm_roles: None,
+        client_roles: None,
+        groups: None,
     }
 }
 
+/// The password grant failed for an account that only signs in through
+/// identity providers. Only answered with `SOCIAL_LOGIN_HINTS` on, since it
+/// tells anyone which emails have accounts.
+fn use_social_login(providers: Vec<String>) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::CONFLICT,
+        Json(
+            ErrorResponse::new(
+                ErrorCode::UseSocialLogin,
+                format!("This account signs in with {}", providers.join(" or ")),
+            )
+            .with_hint(AccountHint::use_social_login(providers)),
+        ),
+    )
+}
+
 fn map_token_error(
     action: &str,
     subject: &str,
@@ -111,14 +1146,22 @@ fn map_token_error(
             );
             (
                 StatusCode::UNAUTHORIZED,
-                Json(ErrorResponse::new("Invalid email or password".to_owned())),
+                Json(ErrorResponse::new(
+                    ErrorCode::InvalidCredentials,
+                    "Invalid email or password".to_owned(),
+                )),
             )
         }
+        KeycloakError::DeadlineExceeded => {
+            warn!("[Login] {action} timed out subject={subject}");
+            deadline::exceeded()
+        }
         KeycloakError::Request(source) => {
             error!(?source, "[Login] {action} request failed");
             (
                 StatusCode::BAD_GATEWAY,
                 Json(ErrorResponse::new(
+                    ErrorCode::UpstreamUnavailable,
                     "Identity provider unavailable".to_owned(),
                 )),
             )
@@ -127,7 +1170,20 @@ fn map_token_error(
             error!("[Login] {action} unexpected status={status} body={message}");
             (
                 StatusCode::BAD_GATEWAY,
-                Json(ErrorResponse::new("Identity provider error".to_owned())),
+                Json(ErrorResponse::new(
+                    ErrorCode::UpstreamError,
+                    "Identity provider error".to_owned(),
+                )),
+            )
+        }
+        KeycloakError::Overloaded => {
+            warn!("[Login] {action} shed while Keycloak is saturated");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse::new(
+                    ErrorCode::ServiceUnavailable,
+                    "Identity provider unavailable".to_owned(),
+                )),
             )
         }
         KeycloakError::TokenUnavailable => {
@@ -135,6 +1191,7 @@ fn map_token_error(
             (
                 StatusCode::SERVICE_UNAVAILABLE,
                 Json(ErrorResponse::new(
+                    ErrorCode::ServiceUnavailable,
                     "Identity provider unavailable".to_owned(),
                 )),
             )
@@ -145,17 +1202,25 @@ fn map_token_error(
 fn invalid_request(message: &str) -> (StatusCode, Json<ErrorResponse>) {
     (
         StatusCode::BAD_REQUEST,
-        Json(ErrorResponse::new(message.to_owned())),
+        Json(ErrorResponse::new(
+            ErrorCode::InvalidRequest,
+            message.to_owned(),
+        )),
     )
 }
 
 fn map_logout_error(error: KeycloakError) -> (StatusCode, Json<ErrorResponse>) {
     match error {
+        KeycloakError::DeadlineExceeded => {
+            warn!("[Login] logout timed out");
+            deadline::exceeded()
+        }
         KeycloakError::Request(source) => {
             error!(?source, "[Login] logout request failed");
             (
                 StatusCode::BAD_GATEWAY,
                 Json(ErrorResponse::new(
+                    ErrorCode::UpstreamUnavailable,
                     "Identity provider unavailable".to_owned(),
                 )),
             )
@@ -164,7 +1229,20 @@ fn map_logout_error(error: KeycloakError) -> (StatusCode, Json<ErrorResponse>) {
             error!("[Login] logout unexpected status={status} body={message}");
             (
                 StatusCode::BAD_GATEWAY,
-                Json(ErrorResponse::new("Identity provider error".to_owned())),
+                Json(ErrorResponse::new(
+                    ErrorCode::UpstreamError,
+                    "Identity provider error".to_owned(),
+                )),
+            )
+        }
+        KeycloakError::Overloaded => {
+            warn!("[Login] logout shed while Keycloak is saturated");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse::new(
+                    ErrorCode::ServiceUnavailable,
+                    "Identity provider unavailable".to_owned(),
+                )),
             )
         }
         KeycloakError::TokenUnavailable => {
@@ -172,13 +1250,17 @@ fn map_logout_error(error: KeycloakError) -> (StatusCode, Json<ErrorResponse>) {
             (
                 StatusCode::SERVICE_UNAVAILABLE,
                 Json(ErrorResponse::new(
+                    ErrorCode::ServiceUnavailable,
                     "Identity provider unavailable".to_owned(),
                 )),
             )
         }
         KeycloakError::InvalidGrant { .. } => (
             StatusCode::BAD_REQUEST,
-            Json(ErrorResponse::new("Invalid refresh token".to_owned())),
+            Json(ErrorResponse::new(
+                ErrorCode::InvalidRequest,
+                "Invalid refresh token".to_owned(),
+            )),
         ),
     }
 }
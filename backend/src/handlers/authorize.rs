@@ -0,0 +1,137 @@
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use tracing::{error, info, warn};
+
+use crate::AppState;
+use crate::deadline;
+use crate::error_codes::ErrorCode;
+use crate::extract::{ApiJson, Rejection};
+use crate::keycloak::KeycloakError;
+use crate::locale;
+use crate::login_scope;
+use crate::models::oauth::{AuthorizeRequest, AuthorizeResponse};
+use crate::models::user::ErrorResponse;
+use crate::tenant::ResolvedTenant;
+
+/// Starts the browser OIDC flow with a pushed authorization request: the
+/// SPA posts its PKCE challenge and redirect here, the backend pushes them to
+/// Keycloak, and the browser is sent to Keycloak with only `client_id` and
+/// `request_uri` on the front channel. Keycloak's screens follow
+/// `uiLocales`, then the browser's `Accept-Language`.
+pub async fn authorize_handler(
+    State(state): State<AppState>,
+    tenant: ResolvedTenant,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<AuthorizeRequest>,
+) -> Result<Json<AuthorizeResponse>, Rejection> {
+    let redirect_uri = payload.redirect_uri.trim();
+    let allowed = &state.config.oidc_redirect_uris;
+    if !allowed.is_empty() && !allowed.iter().any(|uri| uri == redirect_uri) {
+        warn!("[Authorize] rejected redirect_uri={redirect_uri}");
+        return Err(bad_request("redirect_uri is not allowed"));
+    }
+    if payload.state.trim().is_empty() || payload.code_challenge.trim().is_empty() {
+        return Err(bad_request("state and codeChallenge are required"));
+    }
+
+    let method = payload.code_challenge_method.as_deref().unwrap_or("S256");
+    if method != "S256" {
+        return Err(bad_request(
+            "Only the S256 code challenge method is supported",
+        ));
+    }
+
+    let scope = login_scope::resolve(
+        payload.scope.as_deref(),
+        login_scope::allowed(&state.config, tenant.as_ref()),
+    )
+    .map_err(|unknown| {
+        warn!("[Authorize] rejected scope={}", unknown.join(" "));
+        bad_request("scope is not allowed")
+    })?;
+
+    let mut params = vec![
+        ("response_type", "code"),
+        ("redirect_uri", redirect_uri),
+        ("scope", scope.as_str()),
+        ("state", payload.state.as_str()),
+        ("code_challenge", payload.code_challenge.as_str()),
+        ("code_challenge_method", method),
+    ];
+    for (name, value) in [
+        ("nonce", &payload.nonce),
+        ("prompt", &payload.prompt),
+        ("login_hint", &payload.login_hint),
+    ] {
+        if let Some(value) = value.as_deref().filter(|value| !value.is_empty()) {
+            params.push((name, value));
+        }
+    }
+    let ui_locales =
+        locale::preferred(payload.ui_locales.as_deref(), locale::header(&headers)).join(" ");
+    if !ui_locales.is_empty() {
+        params.push(("ui_locales", ui_locales.as_str()));
+    }
+
+    let pushed = state
+        .keycloak
+        .push_authorization_request(&params)
+        .await
+        .map_err(map_par_error)?;
+
+    info!(
+        "[Authorize] pushed authorization request expires_in={}s",
+        pushed.expires_in
+    );
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("client_id", &state.config.keycloak_public_client_id)
+        .append_pair("request_uri", &pushed.request_uri)
+        .finish();
+
+    Ok(Json(AuthorizeResponse {
+        authorization_url: format!(
+            "{}?{}",
+            state.config.keycloak_authorization_endpoint(),
+            query
+        ),
+        request_uri: pushed.request_uri,
+        expires_in: pushed.expires_in,
+    }))
+}
+
+fn bad_request(message: &str) -> Rejection {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse::new(
+            ErrorCode::InvalidRequest,
+            message.to_owned(),
+        )),
+    )
+}
+
+fn map_par_error(err: KeycloakError) -> Rejection {
+    match err {
+        KeycloakError::InvalidGrant { error, description } => {
+            warn!("[Authorize] Keycloak rejected request error={error} desc={description:?}");
+            bad_request(
+                description
+                    .as_deref()
+                    .unwrap_or("Invalid authorization request"),
+            )
+        }
+        KeycloakError::DeadlineExceeded => deadline::exceeded(),
+        other => {
+            error!(?other, "[Authorize] pushed authorization request failed");
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(ErrorResponse::new(
+                    ErrorCode::UpstreamUnavailable,
+                    "Identity provider unavailable".to_owned(),
+                )),
+            )
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+};
+
+use crate::AppState;
+use crate::altcha::AltchaChallenge;
+use crate::error_codes::ErrorCode;
+use crate::models::user::ErrorResponse;
+
+pub async fn challenge_handler(
+    State(state): State<AppState>,
+) -> Result<(HeaderMap, Json<AltchaChallenge>), (StatusCode, Json<ErrorResponse>)> {
+    let Some(altcha) = state.altcha.as_ref() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                ErrorCode::NotFound,
+                "Proof-of-work captcha is not enabled".to_owned(),
+            )),
+        ));
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Ok((headers, Json(altcha.create_challenge())))
+}
@@ -0,0 +1,77 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{
+        HeaderMap, HeaderValue,
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::AppState;
+use crate::captcha::turnstile_keys;
+use crate::error_codes::{registry, registry_markdown};
+use crate::login_scope;
+use crate::models::config::{PublicConfigResponse, RegistrationSchemaResponse};
+use crate::registration_schema::registration_fields;
+use crate::tenant::ResolvedTenant;
+
+pub async fn config_handler(
+    State(state): State<AppState>,
+    tenant: ResolvedTenant,
+) -> (HeaderMap, Json<PublicConfigResponse>) {
+    let keys = turnstile_keys(&state.config, tenant.as_ref());
+
+    // A form token is unique to this response, so it must not be cached.
+    let mut headers = HeaderMap::new();
+    if state.bot_trap.time_trap_enabled() {
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+
+    let response = Json(PublicConfigResponse {
+        tenant: tenant.as_ref().map(|tenant| tenant.id.clone()),
+        captcha_provider: state.config.captcha_provider.as_str(),
+        turnstile_site_key: keys.site_key.to_owned(),
+        form_token: state
+            .bot_trap
+            .time_trap_enabled()
+            .then(|| state.bot_trap.issue_form_token()),
+        honeypot_field: state.bot_trap.honeypot_field().map(str::to_owned),
+        scopes: login_scope::allowed(&state.config, tenant.as_ref()).to_vec(),
+    });
+    (headers, response)
+}
+
+/// Fields the signup form may send, so the SPA can render it per deployment.
+pub async fn registration_schema_handler(
+    State(state): State<AppState>,
+) -> Json<RegistrationSchemaResponse> {
+    Json(RegistrationSchemaResponse {
+        fields: registration_fields(&state.config),
+        additional_attributes: state.config.registration_attributes.is_empty(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErrorCodesQuery {
+    #[serde(default)]
+    format: Option<String>,
+}
+
+/// Every `code` an error response can carry, as JSON or, with
+/// `?format=markdown`, as a Markdown table.
+pub async fn error_codes_handler(Query(query): Query<ErrorCodesQuery>) -> Response {
+    if query
+        .format
+        .as_deref()
+        .is_some_and(|format| format.eq_ignore_ascii_case("markdown"))
+    {
+        return (
+            [(CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            registry_markdown(),
+        )
+            .into_response();
+    }
+    Json(registry()).into_response()
+}
@@ -0,0 +1,56 @@
+use axum::{Json, extract::State, http::StatusCode};
+
+use crate::AppState;
+use crate::models::health::HealthResponse;
+
+pub async fn liveness_handler() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok",
+        keycloak: None,
+    })
+}
+
+/// Ready while the admin token for Keycloak is held and the health poller has
+/// not seen Keycloak down for longer than `KEYCLOAK_UNAVAILABLE_AFTER_SECS`.
+/// Registration cannot work without either.
+pub async fn readiness_handler(
+    State(state): State<AppState>,
+) -> (StatusCode, Json<HealthResponse>) {
+    if state.lifecycle.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(HealthResponse {
+                status: "draining",
+                keycloak: None,
+            }),
+        );
+    }
+
+    if state.keycloak_health.is_unavailable() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(HealthResponse {
+                status: "unavailable",
+                keycloak: Some("unreachable"),
+            }),
+        );
+    }
+
+    if state.keycloak.has_valid_token().await {
+        (
+            StatusCode::OK,
+            Json(HealthResponse {
+                status: "ok",
+                keycloak: Some("ok"),
+            }),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(HealthResponse {
+                status: "unavailable",
+                keycloak: Some("token_unavailable"),
+            }),
+        )
+    }
+}
@@ -0,0 +1,160 @@
+use axum::{Json, extract::State, http::StatusCode};
+use tracing::{error, info, warn};
+
+use crate::AppState;
+use crate::audit::AuditOutcome;
+use crate::deadline;
+use crate::error_codes::ErrorCode;
+use crate::extract::{ApiJson, Rejection};
+use crate::internal::ServiceIdentity;
+use crate::keycloak::KeycloakError;
+use crate::models::internal::{ServiceIdentityResponse, ServiceTokenRequest, ServiceTokenResponse};
+use crate::models::user::ErrorResponse;
+
+pub async fn identity_handler(
+    State(state): State<AppState>,
+    identity: ServiceIdentity,
+) -> Json<ServiceIdentityResponse> {
+    state.audit.record(
+        &identity.name,
+        "internal.identity.view",
+        AuditOutcome::Success,
+        Some(&identity.san),
+        None,
+    );
+
+    Json(ServiceIdentityResponse {
+        service: identity.name,
+        san: identity.san,
+    })
+}
+
+/// Issues a Keycloak service token for the calling service, under the client
+/// `INTERNAL_SERVICE_CLIENTS_FILE` lists for it and only for the audiences
+/// and scopes listed there. Tokens are reused per (service, audience, scope)
+/// until close to expiry, and concurrent misses share a single upstream
+/// request.
+pub async fn service_token_handler(
+    State(state): State<AppState>,
+    identity: ServiceIdentity,
+    ApiJson(payload): ApiJson<ServiceTokenRequest>,
+) -> Result<Json<ServiceTokenResponse>, Rejection> {
+    let audience = normalized(payload.audience);
+    let scope = normalized_scope(payload.scope);
+
+    let Some(client) = state
+        .config
+        .internal_service_clients
+        .iter()
+        .find(|client| client.service == identity.name)
+    else {
+        warn!(
+            "[Internal] service token refused service={}: no client configured",
+            identity.name
+        );
+        state.audit.record(
+            &identity.name,
+            "internal.token.issue",
+            AuditOutcome::Failure,
+            None,
+            None,
+        );
+        return Err(forbidden("No service client configured for this service"));
+    };
+    if !client.permits(audience.as_deref(), scope.as_deref()) {
+        warn!(
+            "[Internal] service token refused service={} audience={:?} scope={:?}",
+            identity.name, audience, scope
+        );
+        state.audit.record(
+            &identity.name,
+            "internal.token.issue",
+            AuditOutcome::Failure,
+            audience.as_deref(),
+            None,
+        );
+        return Err(forbidden("Requested audience or scope is not allowed"));
+    }
+
+    let key = (identity.name.clone(), audience.clone(), scope.clone());
+
+    let token = state
+        .service_tokens
+        .get_or_fetch(&key, || async {
+            info!(
+                "[Internal] fetching service token service={} audience={:?} scope={:?}",
+                identity.name, audience, scope
+            );
+            state
+                .keycloak
+                .service_token(client, audience.as_deref(), scope.as_deref())
+                .await
+        })
+        .await
+        .map_err(|err| map_service_token_error(&identity.name, err))?;
+
+    Ok(Json(ServiceTokenResponse {
+        expires_in: token.expires_in(),
+        access_token: token.access_token,
+        token_type: token.token_type,
+    }))
+}
+
+fn normalized(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+}
+
+/// Scopes sorted and deduplicated, so one grant has one cache key.
+fn normalized_scope(value: Option<String>) -> Option<String> {
+    let mut scopes: Vec<&str> = value
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect();
+    scopes.sort_unstable();
+    scopes.dedup();
+    (!scopes.is_empty()).then(|| scopes.join(" "))
+}
+
+fn forbidden(message: &str) -> Rejection {
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse::new(ErrorCode::Forbidden, message.to_owned())),
+    )
+}
+
+fn map_service_token_error(service: &str, err: KeycloakError) -> Rejection {
+    match err {
+        KeycloakError::DeadlineExceeded => {
+            warn!("[Internal] service token timed out service={service}");
+            deadline::exceeded()
+        }
+        KeycloakError::UnexpectedStatus { status, message } if status.is_client_error() => {
+            warn!(
+                "[Internal] service token refused service={service} status={status} body={message}"
+            );
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(
+                    ErrorCode::InvalidRequest,
+                    "Requested audience or scope is not available".to_owned(),
+                )),
+            )
+        }
+        other => {
+            error!(
+                ?other,
+                "[Internal] service token request failed service={service}"
+            );
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(ErrorResponse::new(
+                    ErrorCode::UpstreamUnavailable,
+                    "Identity provider unavailable".to_owned(),
+                )),
+            )
+        }
+    }
+}
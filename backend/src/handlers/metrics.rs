@@ -0,0 +1,61 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, header},
+    response::IntoResponse,
+};
+
+use crate::AppState;
+
+const OPENMETRICS: &str = "application/openmetrics-text";
+
+/// Serves the text exposition format, or OpenMetrics with trace exemplars
+/// to a scraper that asks for it.
+pub async fn metrics_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let openmetrics = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains(OPENMETRICS));
+    if openmetrics {
+        refresh(&state).await;
+        return (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(
+                    "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                ),
+            )],
+            state.metrics.render_openmetrics(),
+        );
+    }
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        )],
+        render(&state).await,
+    )
+}
+
+/// Brings the gauges that are only read on demand up to date and renders
+/// every metric in the text exposition format.
+pub async fn render(state: &AppState) -> String {
+    refresh(state).await;
+    state.metrics.render()
+}
+
+async fn refresh(state: &AppState) {
+    let remaining = state.keycloak.token_remaining().await;
+    state
+        .metrics
+        .admin_token_remaining_seconds
+        .set(remaining.as_secs_f64());
+    state
+        .metrics
+        .admin_token_refresh_failures
+        .set(state.keycloak.refresh_failures() as i64);
+    state.slo.export(&state.metrics);
+}
@@ -1,2 +1,14 @@
+pub mod account;
+pub mod activity;
+pub mod admin;
 pub mod auth;
+pub mod authorize;
+pub mod captcha;
+pub mod config;
+pub mod health;
+pub mod internal;
+pub mod metrics;
+pub mod oauth;
+pub mod permissions;
 pub mod register;
+pub mod webhooks;
@@ -0,0 +1,271 @@
+use axum::{
+    Form, Json,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+};
+use tracing::{error, info, warn};
+
+use crate::AppState;
+use crate::captcha::{CaptchaAction, CaptchaContext, captcha_error_status, ensure_valid};
+use crate::captcha_exemption::exemption_header;
+use crate::client_ip::ClientIp;
+use crate::dpop;
+use crate::error_codes::ErrorCode;
+use crate::extract::Rejection;
+use crate::handlers::auth;
+use crate::keycloak::{KeycloakError, UserTokenSet};
+use crate::login_scope;
+use crate::models::auth::LoginResponse;
+use crate::models::oauth::{OAuthErrorResponse, OAuthTokenRequest, OAuthTokenResponse};
+use crate::refresh_hint;
+use crate::tenant::ResolvedTenant;
+
+type OAuthResult = Result<
+    (StatusCode, HeaderMap, Json<OAuthTokenResponse>),
+    (StatusCode, HeaderMap, Json<OAuthErrorResponse>),
+>;
+
+/// RFC 6749 style token endpoint so OAuth libraries can use the portal
+/// directly. Supports the `password` and `refresh_token` grants. A password
+/// grant that ends in a login challenge is answered with
+/// `interaction_required` and the challenge, to be resolved through
+/// `/api/auth/login/continue`.
+pub async fn token_handler(
+    State(state): State<AppState>,
+    tenant: ResolvedTenant,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    Form(payload): Form<OAuthTokenRequest>,
+) -> OAuthResult {
+    let requested = payload
+        .scope
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+    let scope = login_scope::resolve(
+        requested,
+        login_scope::allowed(&state.config, tenant.as_ref()),
+    )
+    .map_err(|unknown| {
+        warn!("[OAuth] rejected scope={}", unknown.join(" "));
+        oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_scope",
+            "The requested scope is not allowed",
+        )
+    })?;
+
+    let result = match payload.grant_type.as_str() {
+        "password" => {
+            let username = payload.username.as_deref().map(str::trim).unwrap_or("");
+            let password = payload.password.as_deref().unwrap_or("");
+            if username.is_empty() || password.trim().is_empty() {
+                return Err(oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request",
+                    "username and password are required",
+                ));
+            }
+
+            let captcha = CaptchaContext {
+                action: CaptchaAction::Login,
+                tenant: tenant.as_ref(),
+                remote_ip: Some(client_ip),
+                exemption: exemption_header(&headers),
+            };
+            if let Err(error) =
+                ensure_valid(&state, &captcha, payload.captcha_token.as_deref()).await
+            {
+                let (status, _, message) = captcha_error_status(error);
+                return Err(oauth_error(status, "invalid_request", message));
+            }
+
+            // The same pipeline as `/api/auth/login`, so the grant gets its
+            // challenges, session cap and login history.
+            let reply = auth::password_login(
+                &state, &tenant, &headers, client_ip, username, password, &scope,
+            )
+            .await;
+            return match reply {
+                Ok((_, reply_headers, Json(LoginResponse::Tokens(response)))) => {
+                    info!("[OAuth] grant=password result=200");
+                    let mut headers = no_store_headers();
+                    headers.extend(reply_headers);
+                    Ok((StatusCode::OK, headers, Json(response.into())))
+                }
+                Ok((_, _, Json(LoginResponse::Challenge(challenge)))) => {
+                    info!("[OAuth] grant=password challenge={:?}", challenge.kind);
+                    let mut response =
+                        OAuthErrorResponse::new("interaction_required", challenge.message.clone());
+                    response.challenge = Some(challenge);
+                    Err((StatusCode::BAD_REQUEST, no_store_headers(), Json(response)))
+                }
+                Err(rejection) => Err(map_login_rejection(rejection)),
+            };
+        }
+        "refresh_token" => {
+            let refresh_token = payload
+                .refresh_token
+                .as_deref()
+                .map(str::trim)
+                .unwrap_or("");
+            if refresh_token.is_empty() {
+                return Err(oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request",
+                    "refresh_token is required",
+                ));
+            }
+
+            state
+                .keycloak
+                .refresh_user_token(
+                    refresh_token,
+                    // Without a `scope` the session keeps the one it has.
+                    requested.map(|_| scope.as_str()),
+                    dpop::proof_header(&headers),
+                    state.keycloak.public_client(tenant.as_ref()),
+                )
+                .await
+        }
+        other => {
+            warn!("[OAuth] unsupported grant_type={other}");
+            return Err(oauth_error(
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                "Only password and refresh_token grants are supported",
+            ));
+        }
+    };
+
+    match result {
+        Ok(tokens) => {
+            info!("[OAuth] grant={} result=200", payload.grant_type);
+            let mut headers = no_store_headers();
+            refresh_hint::apply(&state.config, &mut headers, tokens.expires_in);
+            Ok((StatusCode::OK, headers, Json(to_oauth_response(tokens))))
+        }
+        Err(err) => Err(map_oauth_error(&payload.grant_type, err)),
+    }
+}
+
+fn to_oauth_response(tokens: UserTokenSet) -> OAuthTokenResponse {
+    OAuthTokenResponse {
+        access_token: tokens.access_token,
+        token_type: tokens.token_type,
+        expires_in: tokens.expires_in,
+        refresh_token: tokens.refresh_token,
+        refresh_expires_in: tokens.refresh_expires_in,
+        realm_roles: None,
+        client_roles: None,
+        groups: None,
+    }
+}
+
+/// A failed login as an RFC 6749 error: anything about the credentials or
+/// the account is `invalid_grant`, upstream trouble is retryable.
+fn map_login_rejection(
+    (status, Json(error)): Rejection,
+) -> (StatusCode, HeaderMap, Json<OAuthErrorResponse>) {
+    let code = match error.code {
+        ErrorCode::InvalidCredentials | ErrorCode::UseSocialLogin | ErrorCode::TooManySessions => {
+            "invalid_grant"
+        }
+        ErrorCode::UpstreamError | ErrorCode::InternalError => "server_error",
+        _ if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS => {
+            "temporarily_unavailable"
+        }
+        _ => "invalid_request",
+    };
+    let status = if code == "invalid_grant" {
+        StatusCode::BAD_REQUEST
+    } else {
+        status
+    };
+    warn!("[OAuth] grant=password {} code={:?}", code, error.code);
+    // Token clients cannot act on a social login hint; they get the same
+    // answer as for a wrong password.
+    let description = match error.code {
+        ErrorCode::UseSocialLogin => "Invalid email or password",
+        _ => &error.error,
+    };
+    oauth_error(status, code, description)
+}
+
+fn map_oauth_error(
+    grant_type: &str,
+    error: KeycloakError,
+) -> (StatusCode, HeaderMap, Json<OAuthErrorResponse>) {
+    match error {
+        KeycloakError::InvalidGrant { description, .. } => {
+            warn!(
+                "[OAuth] grant={grant_type} invalid_grant desc={:?}",
+                description
+            );
+            oauth_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Invalid credentials or refresh token",
+            )
+        }
+        KeycloakError::DeadlineExceeded => {
+            warn!("[OAuth] grant={grant_type} timed out");
+            oauth_error(
+                StatusCode::GATEWAY_TIMEOUT,
+                "temporarily_unavailable",
+                "Identity provider did not respond in time",
+            )
+        }
+        KeycloakError::Request(source) => {
+            error!(?source, "[OAuth] grant={grant_type} request failed");
+            oauth_error(
+                StatusCode::BAD_GATEWAY,
+                "temporarily_unavailable",
+                "Identity provider unavailable",
+            )
+        }
+        KeycloakError::UnexpectedStatus { status, message } => {
+            error!("[OAuth] grant={grant_type} unexpected status={status} body={message}");
+            oauth_error(
+                StatusCode::BAD_GATEWAY,
+                "server_error",
+                "Identity provider error",
+            )
+        }
+        KeycloakError::Overloaded => {
+            warn!("[OAuth] grant={grant_type} shed while Keycloak is saturated");
+            oauth_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "temporarily_unavailable",
+                "Identity provider unavailable",
+            )
+        }
+        KeycloakError::TokenUnavailable => {
+            error!("[OAuth] grant={grant_type} token unavailable");
+            oauth_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "temporarily_unavailable",
+                "Identity provider unavailable",
+            )
+        }
+    }
+}
+
+fn oauth_error(
+    status: StatusCode,
+    error: &'static str,
+    description: &str,
+) -> (StatusCode, HeaderMap, Json<OAuthErrorResponse>) {
+    (
+        status,
+        no_store_headers(),
+        Json(OAuthErrorResponse::new(error, description)),
+    )
+}
+
+fn no_store_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+    headers
+}
@@ -0,0 +1,91 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{StatusCode, request::Parts},
+};
+use tracing::{error, warn};
+
+use crate::AppState;
+use crate::admin::{bearer_token, introspect};
+use crate::deadline;
+use crate::error_codes::ErrorCode;
+use crate::extract::Rejection;
+use crate::keycloak::KeycloakError;
+use crate::models::auth::{PermissionQuery, PermissionResponse, UserPermissionsResponse};
+use crate::models::user::ErrorResponse;
+
+/// Lets a signed-in client ask whether its token grants `scope` on
+/// `resource`, e.g. to decide which controls to show.
+pub async fn permission_handler(
+    State(state): State<AppState>,
+    Query(query): Query<PermissionQuery>,
+    parts: Parts,
+) -> Result<Json<PermissionResponse>, Rejection> {
+    let Some(token) = bearer_token(&parts) else {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse::new(
+                ErrorCode::Unauthorized,
+                "Missing bearer token".to_owned(),
+            )),
+        ));
+    };
+
+    let resource = query.resource.trim();
+    if resource.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                ErrorCode::InvalidRequest,
+                "resource is required".to_owned(),
+            )),
+        ));
+    }
+    let scope = query
+        .scope
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+
+    match state
+        .permissions
+        .is_permitted(&token, resource, scope)
+        .await
+    {
+        Ok(granted) => Ok(Json(PermissionResponse {
+            resource: resource.to_owned(),
+            scope: scope.map(str::to_owned),
+            granted,
+        })),
+        Err(KeycloakError::DeadlineExceeded) => {
+            warn!("[Permissions] decision timed out resource={resource}");
+            Err(deadline::exceeded())
+        }
+        Err(err) => {
+            error!(
+                ?err,
+                "[Permissions] decision request failed resource={resource}"
+            );
+            Err((
+                StatusCode::BAD_GATEWAY,
+                Json(ErrorResponse::new(
+                    ErrorCode::UpstreamUnavailable,
+                    "Identity provider unavailable".to_owned(),
+                )),
+            ))
+        }
+    }
+}
+
+/// The portal permissions the signed-in user's client roles map to through
+/// `PERMISSIONS_FILE`, sorted.
+pub async fn my_permissions_handler(
+    State(state): State<AppState>,
+    mut parts: Parts,
+) -> Result<Json<UserPermissionsResponse>, Rejection> {
+    let introspection = introspect(&mut parts, &state).await?;
+    let permissions = state.role_permissions.permissions(&introspection);
+    Ok(Json(UserPermissionsResponse {
+        permissions: permissions.into_iter().collect(),
+    }))
+}
@@ -1,32 +1,549 @@
-use axum::{Json, extract::State, http::StatusCode};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header::CACHE_CONTROL},
+};
+use serde_json::json;
 use tracing::{error, info, warn};
 
-use crate::AppState;
-use crate::captcha::{captcha_error_status, ensure_valid};
+use crate::anomaly::AnomalyKind;
+use crate::api_keys::api_key_header;
+use crate::audit::AuditOutcome;
+use crate::bot_trap::log_rejection;
+use crate::captcha::{CaptchaAction, CaptchaContext, captcha_error_status, ensure_valid};
+use crate::captcha_exemption::exemption_header;
+use crate::client_ip::ClientIp;
+use crate::deadline;
+use crate::email::{self, CANONICAL_EMAIL_ATTRIBUTE};
+use crate::email_verification::UnverifiedLogin;
+use crate::error_codes::ErrorCode;
+use crate::extract::ApiJson;
+use crate::ip_reputation::RiskDecision;
 use crate::keycloak::{CreateUserResult, KeycloakError};
-use crate::models::user::{ErrorResponse, KeycloakUser, RegisterRequest, RegisterResponse};
+use crate::locale;
+use crate::models::user::{
+    AccountHint, ErrorResponse, FieldError, KeycloakUser, RegisterRequest, RegisterResponse,
+    UsernameAvailabilityQuery, UsernameAvailabilityResponse,
+};
+use crate::partner::{PARTNER_ID_ATTRIBUTE, PartnerError};
+use crate::rate_limit::Caller;
+use crate::referral::{REFERRAL_CODE_ATTRIBUTE, ReferralError};
+use crate::registration_schema;
+use crate::tenant::ResolvedTenant;
+use crate::username::{self, UsernameMode};
+use crate::validation::{
+    DATE_OF_BIRTH_ATTRIBUTE, check_date_of_birth, check_register_attributes, check_register_extra,
+};
+use crate::{AppConfig, AppState};
+
+const USERNAME_CHECK_WINDOW: Duration = Duration::from_secs(60);
+const DUPLICATE_CHECK_WINDOW: Duration = Duration::from_secs(10 * 60);
 
 pub async fn register_handler(
     State(state): State<AppState>,
-    Json(payload): Json<RegisterRequest>,
+    tenant: ResolvedTenant,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    ApiJson(mut payload): ApiJson<RegisterRequest>,
 ) -> Result<(StatusCode, Json<RegisterResponse>), (StatusCode, Json<ErrorResponse>)> {
-    if let Err(error) = ensure_valid(&state, payload.captcha_token.as_deref()).await {
-        let (status, message) = captcha_error_status(error);
-        return Err((status, Json(ErrorResponse::new(message.to_owned()))));
+    let live = state.live.current();
+    check_register_extra(&live, &payload.extra)?;
+
+    let date_of_birth = payload
+        .date_of_birth
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned);
+    match &date_of_birth {
+        Some(value) => check_date_of_birth(&state.config, value)?,
+        None if state.config.register_min_age > 0 => {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(
+                    ErrorResponse::new(
+                        ErrorCode::ValidationFailed,
+                        "Invalid request body".to_owned(),
+                    )
+                    .with_fields(vec![FieldError {
+                        field: "dateOfBirth".to_owned(),
+                        expected: Some("date formatted as YYYY-MM-DD".to_owned()),
+                        message: "missing field".to_owned(),
+                    }]),
+                ),
+            ));
+        }
+        None => {}
     }
 
-    let keycloak_user = KeycloakUser::from_request(&payload);
+    payload.email = email::normalize(&payload.email);
+    payload.username = check_username(state.config.username_mode, payload.username.take())?;
+    check_register_attributes(&live, &payload.extra)?;
+
+    let partner_id = match payload.partner_assertion.as_deref() {
+        Some(assertion) => Some(verify_partner(&state, assertion, &payload.email)?),
+        None => None,
+    };
+
+    if partner_id.is_none() {
+        screen_submission(&state, &tenant, client_ip, &headers, &mut payload).await?;
+    }
+
+    let referral_code = match payload
+        .referral_code
+        .as_deref()
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+    {
+        Some(code) => Some(validate_referral(&state, code, &payload.email).await?),
+        None => None,
+    };
+
+    let alias_rules = state.config.email_alias_rules();
+    let canonical_email = email::canonicalize(alias_rules, &payload.email);
+    let canonical = alias_rules.any().then_some(canonical_email.as_str());
+    if live.register_duplicate_check {
+        let caller = Caller {
+            ip: client_ip,
+            api_key: api_key_header(&headers),
+        };
+        check_duplicate(&state, &live, &caller, &payload.email, canonical).await?;
+    } else if let Some(canonical) = canonical {
+        match state
+            .keycloak
+            .find_users_by_attribute(CANONICAL_EMAIL_ATTRIBUTE, canonical)
+            .await
+        {
+            Ok(existing) if !existing.is_empty() => {
+                warn!(
+                    "[Register] user={} conflicts with existing canonical email {}",
+                    payload.email, canonical
+                );
+                return Err((
+                    StatusCode::CONFLICT,
+                    Json(ErrorResponse::new(
+                        ErrorCode::EmailExists,
+                        "Email already exists".to_owned(),
+                    )),
+                ));
+            }
+            Ok(_) => {}
+            Err(err) => return Err(map_keycloak_error(err)),
+        }
+    }
+
+    registration_schema::retain_declared(&live, &mut payload.extra);
+    let mut keycloak_user = KeycloakUser::from_request(&payload);
+    // With an `UNVERIFIED_LOGIN` policy the address has to be proven; only
+    // a partner vouching for it counts as that up front.
+    keycloak_user.email_verified = state.email_verification.policy() == UnverifiedLogin::Allow;
+    if let Some(partner_id) = partner_id {
+        info!(
+            "[Register] user={} vouched for by partner={}",
+            payload.email, partner_id
+        );
+        keycloak_user.email_verified = true;
+        keycloak_user
+            .attributes
+            .insert(PARTNER_ID_ATTRIBUTE.to_owned(), vec![partner_id]);
+    }
+    if let Some(value) = date_of_birth.filter(|_| live.store_date_of_birth) {
+        keycloak_user
+            .attributes
+            .insert(DATE_OF_BIRTH_ATTRIBUTE.to_owned(), vec![value]);
+    }
+    if let Some(code) = &referral_code {
+        keycloak_user
+            .attributes
+            .insert(REFERRAL_CODE_ATTRIBUTE.to_owned(), vec![code.clone()]);
+    }
+    keycloak_user
+        .attributes
+        .insert(CANONICAL_EMAIL_ATTRIBUTE.to_owned(), vec![canonical_email]);
+    state.user_profile.check(&keycloak_user).await?;
     log_keycloak_payload(&state, &keycloak_user);
 
     match state.keycloak.create_user(&keycloak_user).await {
         Ok(CreateUserResult::Created) => {
+            if let Some(code) = &referral_code {
+                state.referrals.record_conversion(code).await;
+            }
+            state.audit.record(
+                &payload.email,
+                "user.register",
+                AuditOutcome::Success,
+                None,
+                Some(client_ip),
+            );
+            state
+                .webhooks
+                .publish("user.registered", json!({ "email": payload.email }));
+            if !keycloak_user.email_verified
+                && let Err(err) = state
+                    .email_verification
+                    .resend(&payload.email, locale::header(&headers))
+                    .await
+            {
+                warn!(
+                    "[Register] unable to send verification email to {}: {}",
+                    payload.email, err
+                );
+            }
+            state.anomalies.observe(
+                AnomalyKind::Registration,
+                client_ip,
+                &state.metrics,
+                &state.http_client,
+            );
             Ok((StatusCode::CREATED, Json(RegisterResponse::success())))
         }
-        Ok(CreateUserResult::Conflict(_)) => Err((
+        Ok(CreateUserResult::Conflict(reason)) => Err(map_conflict(&payload, &reason)),
+        Err(err) => Err(map_keycloak_error(err)),
+    }
+}
+
+/// Whether `username` can still be registered, for checking as the user
+/// types. Only offered when `USERNAME_MODE` gives accounts their own
+/// usernames. Each client address gets `USERNAME_CHECK_LIMIT` checks per
+/// minute so the endpoint cannot be used to list accounts.
+pub async fn username_availability_handler(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    request_headers: HeaderMap,
+    Query(query): Query<UsernameAvailabilityQuery>,
+) -> Result<(HeaderMap, Json<UsernameAvailabilityResponse>), (StatusCode, Json<ErrorResponse>)> {
+    if !state.config.username_mode.distinct() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                ErrorCode::NotFound,
+                "Usernames are not enabled".to_owned(),
+            )),
+        ));
+    }
+    let caller = Caller {
+        ip: client_ip,
+        api_key: api_key_header(&request_headers),
+    };
+    if !state
+        .rate_limits
+        .allow(
+            "username-check",
+            &caller,
+            state.live.current().username_check_limit,
+            USERNAME_CHECK_WINDOW,
+        )
+        .await
+    {
+        warn!("[Register] username checks throttled for {}", client_ip);
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse::new(
+                ErrorCode::RateLimited,
+                "Too many username checks, try again later".to_owned(),
+            )),
+        ));
+    }
+
+    let username = username::normalize(&query.username);
+    username::check(&username).map_err(|field| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(
+                ErrorResponse::new(ErrorCode::ValidationFailed, "Invalid username".to_owned())
+                    .with_fields(vec![field]),
+            ),
+        )
+    })?;
+
+    let users = state
+        .keycloak
+        .find_users_by_username(&username)
+        .await
+        .map_err(map_keycloak_error)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok((
+        headers,
+        Json(UsernameAvailabilityResponse {
+            available: users.is_empty(),
+            username,
+        }),
+    ))
+}
+
+/// The username to register with, normalized and checked; `None` means the
+/// email is used. Usernames sent while `USERNAME_MODE=email` are ignored so
+/// clients can ship the field ahead of the switch.
+fn check_username(
+    mode: UsernameMode,
+    requested: Option<String>,
+) -> Result<Option<String>, (StatusCode, Json<ErrorResponse>)> {
+    if !mode.distinct() {
+        return Ok(None);
+    }
+    let requested = requested
+        .map(|value| username::normalize(&value))
+        .filter(|value| !value.is_empty());
+
+    let error = match requested {
+        Some(value) => match username::check(&value) {
+            Ok(()) => return Ok(Some(value)),
+            Err(field) => field,
+        },
+        None if mode == UsernameMode::Required => FieldError {
+            field: "username".to_owned(),
+            expected: None,
+            message: "missing field".to_owned(),
+        },
+        None => return Ok(None),
+    };
+    Err((
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(
+            ErrorResponse::new(
+                ErrorCode::ValidationFailed,
+                "Invalid request body".to_owned(),
+            )
+            .with_fields(vec![error]),
+        ),
+    ))
+}
+
+/// `REGISTER_DUPLICATE_CHECK`: an email that is already registered, or
+/// whose `canonical` form under the alias rules is, is answered before the
+/// user is sent to Keycloak, with a hint to sign in or reset the password
+/// and the identity providers the account is linked to. Like Keycloak's own
+/// conflict, this tells callers the address has an account, so it comes
+/// after the captcha and each client address gets
+/// `REGISTER_DUPLICATE_CHECK_LIMIT` checks per ten minutes.
+async fn check_duplicate(
+    state: &AppState,
+    live: &AppConfig,
+    caller: &Caller<'_>,
+    email: &str,
+    canonical: Option<&str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if !state
+        .rate_limits
+        .allow(
+            "duplicate-check",
+            caller,
+            live.register_duplicate_check_limit,
+            DUPLICATE_CHECK_WINDOW,
+        )
+        .await
+    {
+        warn!("[Register] duplicate checks throttled for {}", caller.ip);
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse::new(
+                ErrorCode::RateLimited,
+                "Too many registration attempts, try again later".to_owned(),
+            )),
+        ));
+    }
+
+    let mut existing = state
+        .keycloak
+        .find_users_by_email(email)
+        .await
+        .map_err(map_keycloak_error)?;
+    if existing.is_empty()
+        && let Some(canonical) = canonical
+    {
+        existing = state
+            .keycloak
+            .find_users_by_attribute(CANONICAL_EMAIL_ATTRIBUTE, canonical)
+            .await
+            .map_err(map_keycloak_error)?;
+    }
+    let Some(user) = existing.first() else {
+        return Ok(());
+    };
+
+    let identity_providers = match state.keycloak.federated_identities(&user.id).await {
+        Ok(identities) => identities
+            .into_iter()
+            .map(|identity| identity.identity_provider)
+            .collect(),
+        Err(err) => {
+            warn!(
+                "[Register] identity providers of {} unavailable: {}",
+                user.id, err
+            );
+            Vec::new()
+        }
+    };
+    info!("[Register] user={} is already registered", email);
+    Err((
+        StatusCode::CONFLICT,
+        Json(
+            ErrorResponse::new(ErrorCode::EmailExists, "Email already exists".to_owned())
+                .with_hint(AccountHint::login_or_reset(identity_providers)),
+        ),
+    ))
+}
+
+/// Keycloak answers `User exists with same username` or `... same email`;
+/// only a chosen username can collide on its own.
+fn map_conflict(payload: &RegisterRequest, reason: &str) -> (StatusCode, Json<ErrorResponse>) {
+    let reason = reason.to_ascii_lowercase();
+    if payload.username.is_some() && reason.contains("username") && !reason.contains("email") {
+        return (
             StatusCode::CONFLICT,
-            Json(ErrorResponse::new("Email already exists".to_owned())),
+            Json(
+                ErrorResponse::new(
+                    ErrorCode::UsernameTaken,
+                    "Username already exists".to_owned(),
+                )
+                .with_fields(vec![FieldError {
+                    field: "username".to_owned(),
+                    expected: None,
+                    message: "already taken".to_owned(),
+                }]),
+            ),
+        );
+    }
+    (
+        StatusCode::CONFLICT,
+        Json(ErrorResponse::new(
+            ErrorCode::EmailExists,
+            "Email already exists".to_owned(),
         )),
-        Err(err) => Err(map_keycloak_error(err)),
+    )
+}
+
+async fn screen_submission(
+    state: &AppState,
+    tenant: &ResolvedTenant,
+    client_ip: IpAddr,
+    headers: &HeaderMap,
+    payload: &mut RegisterRequest,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if let Err(error) = state
+        .bot_trap
+        .check(payload.form_token.as_deref(), &payload.extra)
+    {
+        log_rejection(error, &payload.email);
+        state
+            .metrics
+            .bot_trap_rejections
+            .with_label_values(&[error.reason()])
+            .inc();
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                ErrorCode::RegistrationExpired,
+                "Unable to accept registration, please reload the form and try again".to_owned(),
+            )),
+        ));
+    }
+
+    if let Some(field) = state.bot_trap.honeypot_field() {
+        payload.extra.remove(field);
+    }
+
+    let risk = state.ip_reputation.assess(client_ip, &state.metrics).await;
+    if risk == RiskDecision::Block {
+        warn!(
+            "[Register] user={} rejected, ip={} is high risk",
+            payload.email, client_ip
+        );
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new(
+                ErrorCode::SourceBlocked,
+                "Unable to accept registration from this network".to_owned(),
+            )),
+        ));
+    }
+
+    let captcha = CaptchaContext {
+        action: CaptchaAction::Register,
+        tenant: tenant.as_ref(),
+        remote_ip: Some(client_ip),
+        // Risky sources have to solve the captcha themselves.
+        exemption: exemption_header(headers).filter(|_| risk == RiskDecision::Allow),
+    };
+    if let Err(error) = ensure_valid(state, &captcha, payload.captcha_token.as_deref()).await {
+        let (status, code, message) = captcha_error_status(error);
+        return Err((status, Json(ErrorResponse::new(code, message.to_owned()))));
+    }
+
+    Ok(())
+}
+
+fn verify_partner(
+    state: &AppState,
+    assertion: &str,
+    email: &str,
+) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    state.partners.verify(assertion, email).map_err(|error| {
+        warn!(
+            "[Register] user={} partner assertion rejected: {:?}",
+            email, error
+        );
+        let message = match error {
+            PartnerError::EmailMismatch => "Partner assertion does not match email",
+            PartnerError::UnknownPartner | PartnerError::InvalidAssertion => {
+                "Invalid partner assertion"
+            }
+        };
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse::new(
+                ErrorCode::InvalidPartnerAssertion,
+                message.to_owned(),
+            )),
+        )
+    })
+}
+
+async fn validate_referral(
+    state: &AppState,
+    code: &str,
+    email: &str,
+) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    match state.referrals.validate(&state.http_client, code).await {
+        Ok(code) => Ok(code),
+        Err(ReferralError::Unavailable(reason)) => {
+            error!(
+                "[Register] user={} referral check failed: {}",
+                email, reason
+            );
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse::new(
+                    ErrorCode::ServiceUnavailable,
+                    "Referral validation temporarily unavailable".to_owned(),
+                )),
+            ))
+        }
+        Err(error) => {
+            warn!(
+                "[Register] user={} referral code {} rejected: {:?}",
+                email, code, error
+            );
+            Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(
+                    ErrorResponse::new(
+                        ErrorCode::InvalidReferral,
+                        "Invalid request body".to_owned(),
+                    )
+                    .with_fields(vec![FieldError {
+                        field: "referralCode".to_owned(),
+                        expected: None,
+                        message: "unknown referral code".to_owned(),
+                    }]),
+                ),
+            ))
+        }
     }
 }
 
@@ -37,15 +554,31 @@ fn map_keycloak_error(err: KeycloakError) -> (StatusCode, Json<ErrorResponse>) {
             (
                 StatusCode::SERVICE_UNAVAILABLE,
                 Json(ErrorResponse::new(
+                    ErrorCode::ServiceUnavailable,
+                    "Registration temporarily unavailable".to_owned(),
+                )),
+            )
+        }
+        KeycloakError::Overloaded => {
+            warn!("[Register] Keycloak call shed while Keycloak is saturated");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse::new(
+                    ErrorCode::ServiceUnavailable,
                     "Registration temporarily unavailable".to_owned(),
                 )),
             )
         }
+        KeycloakError::DeadlineExceeded => {
+            warn!("Keycloak did not answer within the registration deadline");
+            deadline::exceeded()
+        }
         KeycloakError::Request(source) => {
             error!(?source, "Keycloak request failed");
             (
                 StatusCode::BAD_GATEWAY,
                 Json(ErrorResponse::new(
+                    ErrorCode::UpstreamUnavailable,
                     "Unable to reach identity service".to_owned(),
                 )),
             )
@@ -58,14 +591,20 @@ fn map_keycloak_error(err: KeycloakError) -> (StatusCode, Json<ErrorResponse>) {
             );
             (
                 StatusCode::BAD_GATEWAY,
-                Json(ErrorResponse::new("Identity service error".to_owned())),
+                Json(ErrorResponse::new(
+                    ErrorCode::UpstreamError,
+                    "Identity service error".to_owned(),
+                )),
             )
         }
         KeycloakError::InvalidGrant { .. } => {
             error!("Unexpected invalid grant while registering user");
             (
                 StatusCode::BAD_GATEWAY,
-                Json(ErrorResponse::new("Identity service error".to_owned())),
+                Json(ErrorResponse::new(
+                    ErrorCode::UpstreamError,
+                    "Identity service error".to_owned(),
+                )),
             )
         }
     }
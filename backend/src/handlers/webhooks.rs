@@ -0,0 +1,237 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use tracing::info;
+
+use crate::AppState;
+use crate::admin::AdminPrincipal;
+use crate::audit::AuditOutcome;
+use crate::error_codes::ErrorCode;
+use crate::extract::{ApiJson, Rejection};
+use crate::models::user::ErrorResponse;
+use crate::models::webhook::{
+    DeadLetterListResponse, DeadLetterQuery, DeliveryListResponse, WebhookListResponse,
+    WebhookRequest, WebhookResponse,
+};
+use crate::webhooks::{Delivery, SubscriptionChanges, WebhookError};
+
+pub async fn list_webhooks_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+) -> Json<WebhookListResponse> {
+    Json(WebhookListResponse {
+        webhooks: state.webhooks.list().into_iter().map(Into::into).collect(),
+    })
+}
+
+pub async fn create_webhook_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    ApiJson(payload): ApiJson<WebhookRequest>,
+) -> Result<(StatusCode, Json<WebhookResponse>), Rejection> {
+    let subscription = state.webhooks.create(changes(payload)).map_err(reject)?;
+
+    info!(
+        "[Admin] user={} created webhook {} url={}",
+        admin.display_name(),
+        subscription.id,
+        subscription.url
+    );
+    record(
+        &state,
+        admin.display_name(),
+        "admin.webhook.create",
+        &subscription.id,
+    );
+
+    let secret = subscription.secret.clone();
+    let mut response = WebhookResponse::from(subscription);
+    response.secret = Some(secret);
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn get_webhook_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<Json<WebhookResponse>, Rejection> {
+    state
+        .webhooks
+        .get(&id)
+        .map(|subscription| Json(subscription.into()))
+        .ok_or_else(|| reject(WebhookError::NotFound))
+}
+
+/// Changes only the fields present in the body.
+pub async fn update_webhook_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<String>,
+    ApiJson(payload): ApiJson<WebhookRequest>,
+) -> Result<Json<WebhookResponse>, Rejection> {
+    let subscription = state
+        .webhooks
+        .update(&id, changes(payload))
+        .map_err(reject)?;
+
+    info!(
+        "[Admin] user={} updated webhook {}",
+        admin.display_name(),
+        id
+    );
+    record(&state, admin.display_name(), "admin.webhook.update", &id);
+    Ok(Json(subscription.into()))
+}
+
+pub async fn delete_webhook_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<StatusCode, Rejection> {
+    state.webhooks.delete(&id).map_err(reject)?;
+
+    info!(
+        "[Admin] user={} deleted webhook {}",
+        admin.display_name(),
+        id
+    );
+    record(&state, admin.display_name(), "admin.webhook.delete", &id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn test_webhook_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<Delivery>), Rejection> {
+    let delivery = state.webhooks.send_test(&id).map_err(reject)?;
+
+    record(&state, admin.display_name(), "admin.webhook.test", &id);
+    Ok((StatusCode::ACCEPTED, Json(delivery)))
+}
+
+pub async fn webhook_deliveries_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<Json<DeliveryListResponse>, Rejection> {
+    if state.webhooks.get(&id).is_none() {
+        return Err(reject(WebhookError::NotFound));
+    }
+    Ok(Json(DeliveryListResponse {
+        deliveries: state.webhooks.deliveries(&id),
+    }))
+}
+
+/// Replays every dead letter of the subscription, e.g. once its receiver is
+/// back after an outage.
+pub async fn replay_webhook_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<DeliveryListResponse>), Rejection> {
+    let deliveries = state.webhooks.replay_all(&id).map_err(reject)?;
+
+    info!(
+        "[Admin] user={} replayed {} dead letters for webhook {}",
+        admin.display_name(),
+        deliveries.len(),
+        id
+    );
+    record(&state, admin.display_name(), "admin.webhook.replay", &id);
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(DeliveryListResponse { deliveries }),
+    ))
+}
+
+pub async fn dead_letters_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(_admin): AdminPrincipal,
+    Query(query): Query<DeadLetterQuery>,
+) -> Json<DeadLetterListResponse> {
+    let subscription = query.subscription.as_deref().filter(|id| !id.is_empty());
+    Json(DeadLetterListResponse {
+        dead_letters: state
+            .webhooks
+            .dead_letters(subscription)
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+    })
+}
+
+pub async fn replay_dead_letter_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<Delivery>), Rejection> {
+    let delivery = state.webhooks.replay(&id).map_err(reject)?;
+
+    record(
+        &state,
+        admin.display_name(),
+        "admin.webhook.dead_letter.replay",
+        &id,
+    );
+    Ok((StatusCode::ACCEPTED, Json(delivery)))
+}
+
+pub async fn discard_dead_letter_handler(
+    State(state): State<AppState>,
+    AdminPrincipal(admin): AdminPrincipal,
+    Path(id): Path<String>,
+) -> Result<StatusCode, Rejection> {
+    state.webhooks.discard(&id).map_err(reject)?;
+
+    info!(
+        "[Admin] user={} discarded dead letter {}",
+        admin.display_name(),
+        id
+    );
+    record(
+        &state,
+        admin.display_name(),
+        "admin.webhook.dead_letter.discard",
+        &id,
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn changes(payload: WebhookRequest) -> SubscriptionChanges {
+    SubscriptionChanges {
+        url: payload.url.map(|url| url.trim().to_owned()),
+        secret: payload.secret,
+        events: payload.events.map(|events| {
+            events
+                .into_iter()
+                .map(|event| event.trim().to_owned())
+                .collect()
+        }),
+        enabled: payload.enabled,
+    }
+}
+
+fn record(state: &AppState, actor: &str, action: &str, id: &str) {
+    state
+        .audit
+        .record(actor, action, AuditOutcome::Success, Some(id), None);
+}
+
+fn reject(error: WebhookError) -> Rejection {
+    let status = match error {
+        WebhookError::NotFound | WebhookError::DeadLetterNotFound => StatusCode::NOT_FOUND,
+        WebhookError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    let message = match &error {
+        WebhookError::NotFound => "Webhook not found".to_owned(),
+        WebhookError::DeadLetterNotFound => "Dead letter not found".to_owned(),
+        WebhookError::Invalid(message) => (*message).to_owned(),
+    };
+    (
+        status,
+        Json(ErrorResponse::new(ErrorCode::from_status(status), message)),
+    )
+}
@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+use std::fs;
+
+use tracing::{error, info, warn};
+
+use crate::AppConfig;
+use crate::email;
+use crate::keycloak::{CreateUserResult, KeycloakError, KeycloakService};
+use crate::models::user::{KeycloakCredential, KeycloakUser};
+
+/// Seeds the first admin from `INITIAL_ADMIN_EMAIL` and
+/// `INITIAL_ADMIN_PASSWORD_FILE` at startup. Nothing happens once any user
+/// holds `ADMIN_ROLE`, directly, through a composite role or through a
+/// group, so the variables can stay set. Only an account created here is
+/// made admin: if the email already belongs to someone, whoever signed up
+/// with it would otherwise be handed the realm, so seeding stops and the
+/// role has to be granted by hand. Failures are logged and do not stop the
+/// server.
+pub async fn seed(config: &AppConfig, keycloak: &KeycloakService) {
+    let Some(email) = config.initial_admin_email.as_deref() else {
+        return;
+    };
+    let Some(path) = config.initial_admin_password_file.as_deref() else {
+        error!("[Admin] INITIAL_ADMIN_EMAIL is set without INITIAL_ADMIN_PASSWORD_FILE");
+        return;
+    };
+    let password = match fs::read_to_string(path) {
+        Ok(contents) => contents.trim_end_matches(['\r', '\n']).to_owned(),
+        Err(err) => {
+            error!(%path, "[Admin] unable to read initial admin password: {}", err);
+            return;
+        }
+    };
+    if password.is_empty() {
+        error!(%path, "[Admin] initial admin password file is empty");
+        return;
+    }
+
+    let email = email::normalize(email);
+    if let Err(err) = ensure_admin(config, keycloak, &email, password).await {
+        error!("[Admin] unable to seed initial admin {}: {}", email, err);
+    }
+}
+
+async fn ensure_admin(
+    config: &AppConfig,
+    keycloak: &KeycloakService,
+    email: &str,
+    password: String,
+) -> Result<(), KeycloakError> {
+    let role_name = config.admin_role.as_str();
+    let role = match keycloak.realm_role(role_name).await? {
+        Some(role) => role,
+        None => {
+            info!("[Admin] creating realm role {}", role_name);
+            keycloak.create_realm_role(role_name).await?;
+            keycloak
+                .realm_role(role_name)
+                .await?
+                .ok_or(KeycloakError::UnexpectedStatus {
+                    status: reqwest::StatusCode::NOT_FOUND,
+                    message: format!("role {role_name} missing after creation"),
+                })?
+        }
+    };
+
+    if admin_exists(keycloak, role_name).await? {
+        info!("[Admin] an admin already exists; skipping initial admin");
+        return Ok(());
+    }
+
+    if !keycloak.find_users_by_email(email).await?.is_empty() {
+        warn!(
+            "[Admin] {} already has an account; not promoting it to {}",
+            email, role_name
+        );
+        return Ok(());
+    }
+
+    let user = KeycloakUser {
+        username: email.to_owned(),
+        email: email.to_owned(),
+        first_name: None,
+        last_name: None,
+        enabled: true,
+        email_verified: true,
+        attributes: Default::default(),
+        credentials: vec![KeycloakCredential {
+            r#type: "password".to_owned(),
+            temporary: false,
+            value: password,
+        }],
+        required_actions: Vec::new(),
+    };
+    if let CreateUserResult::Conflict(reason) = keycloak.create_user(&user).await? {
+        warn!(
+            "[Admin] initial admin {} was created meanwhile, not promoting it: {}",
+            email, reason
+        );
+        return Ok(());
+    }
+
+    let users = keycloak.find_users_by_email(email).await?;
+    let [user] = users.as_slice() else {
+        return Err(KeycloakError::UnexpectedStatus {
+            status: reqwest::StatusCode::CONFLICT,
+            message: format!("{} users match {email}", users.len()),
+        });
+    };
+    keycloak.grant_realm_role(&user.id, &role).await?;
+    info!("[Admin] granted {} to initial admin {}", role_name, email);
+    Ok(())
+}
+
+/// Whether anyone holds `role_name`: directly, through a group (or a parent
+/// group) it is mapped to, or through a realm composite that includes it.
+async fn admin_exists(keycloak: &KeycloakService, role_name: &str) -> Result<bool, KeycloakError> {
+    for role in granting_roles(keycloak, role_name).await? {
+        if !keycloak.role_members(&role, 1).await?.is_empty() {
+            return Ok(true);
+        }
+        let mut groups = keycloak.role_groups(&role).await?;
+        while let Some(group) = groups.pop() {
+            if !keycloak.group_members(&group.id, 1).await?.is_empty() {
+                return Ok(true);
+            }
+            groups.extend(keycloak.group_children(&group.id).await?);
+        }
+    }
+    Ok(false)
+}
+
+/// `role_name` and every realm composite containing it, at any depth.
+async fn granting_roles(
+    keycloak: &KeycloakService,
+    role_name: &str,
+) -> Result<HashSet<String>, KeycloakError> {
+    let mut composites = Vec::new();
+    for role in keycloak.realm_roles().await? {
+        if role.composite {
+            let contained = keycloak.role_composites(&role.name).await?;
+            composites.push((role.name, contained));
+        }
+    }
+
+    let mut granting = HashSet::from([role_name.to_owned()]);
+    loop {
+        let before = granting.len();
+        for (name, contained) in &composites {
+            if contained.iter().any(|role| granting.contains(&role.name)) {
+                granting.insert(name.clone());
+            }
+        }
+        if granting.len() == before {
+            return Ok(granting);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use axum::{
+    Json, async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{StatusCode, request::Parts},
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use crate::AppState;
+use crate::error_codes::ErrorCode;
+use crate::extract::Rejection;
+use crate::models::user::ErrorResponse;
+
+/// Subject alternative names of the client certificate presented on the
+/// internal TLS listener, attached to each request of that connection.
+#[derive(Debug, Clone, Default)]
+pub struct ClientCertificate {
+    pub sans: Vec<String>,
+}
+
+/// Service tokens are cached per calling service, audience and scope.
+pub type ServiceTokenKey = (String, Option<String>, Option<String>);
+
+/// Keycloak client a service's tokens are issued under, from
+/// `INTERNAL_SERVICE_CLIENTS_FILE`. A service without an entry gets no
+/// tokens, and one with an entry only gets the audiences and scopes listed
+/// for it, so holding a certificate never yields the admin client's grants.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceClient {
+    /// Service name, as mapped by `INTERNAL_SERVICE_IDENTITIES`.
+    pub service: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl ServiceClient {
+    /// Whether the requested audience and every requested scope are among
+    /// the ones listed for the service.
+    pub fn permits(&self, audience: Option<&str>, scope: Option<&str>) -> bool {
+        audience.is_none_or(|audience| self.audiences.iter().any(|allowed| allowed == audience))
+            && scope
+                .into_iter()
+                .flat_map(str::split_whitespace)
+                .all(|scope| self.scopes.iter().any(|allowed| allowed == scope))
+    }
+}
+
+pub fn load_service_clients(path: Option<&str>) -> Vec<ServiceClient> {
+    let Some(path) = path.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Vec::new();
+    };
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!(%path, ?err, "Unable to read service clients file; service tokens disabled");
+            return Vec::new();
+        }
+    };
+
+    match serde_json::from_str::<Vec<ServiceClient>>(&contents) {
+        Ok(clients) => clients,
+        Err(err) => {
+            warn!(%path, ?err, "Unable to parse service clients file; service tokens disabled");
+            Vec::new()
+        }
+    }
+}
+
+/// Calling service on an `/api/internal` route. The certificate comes from
+/// the internal mTLS listener or, when the peer is a trusted proxy, from the
+/// header named by `INTERNAL_CLIENT_CERT_HEADER`; its first SAN listed in
+/// `INTERNAL_SERVICE_IDENTITIES` names the service.
+#[derive(Debug, Clone)]
+pub struct ServiceIdentity {
+    pub name: String,
+    pub san: String,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ServiceIdentity {
+    type Rejection = Rejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let sans = match parts.extensions.get::<ClientCertificate>() {
+            Some(certificate) => certificate.sans.clone(),
+            None => forwarded_sans(parts, state),
+        };
+
+        if sans.is_empty() {
+            return Err(reject(
+                StatusCode::UNAUTHORIZED,
+                "Client certificate required",
+            ));
+        }
+
+        let identity = sans.iter().find_map(|san| {
+            state
+                .config
+                .internal_service_identities
+                .iter()
+                .find(|(pattern, _)| pattern.eq_ignore_ascii_case(san))
+                .map(|(_, name)| Self {
+                    name: name.clone(),
+                    san: san.clone(),
+                })
+        });
+
+        identity.ok_or_else(|| {
+            warn!("[Internal] unmapped client certificate sans={:?}", sans);
+            reject(StatusCode::FORBIDDEN, "Unknown service identity")
+        })
+    }
+}
+
+fn forwarded_sans(parts: &Parts, state: &AppState) -> Vec<String> {
+    let Some(header) = state.config.internal_client_cert_header.as_deref() else {
+        return Vec::new();
+    };
+
+    let peer = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    if !state
+        .config
+        .trusted_proxies
+        .iter()
+        .any(|net| net.contains(&peer))
+    {
+        return Vec::new();
+    }
+
+    parts
+        .headers
+        .get_all(header)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(parse_forwarded_cert)
+        .collect()
+}
+
+/// Understands both a plain comma separated SAN list and Envoy's
+/// `X-Forwarded-Client-Cert` format (`By=...;URI=...;DNS=...`). A header
+/// with any `key=` in it is read as the latter only, taking the `URI`,
+/// `DNS` and `Email` values and keeping quoted values such as a `Subject`
+/// with commas in one piece.
+fn parse_forwarded_cert(value: &str) -> Vec<String> {
+    if !value.contains('=') {
+        return value
+            .split(',')
+            .map(str::trim)
+            .filter(|san| !san.is_empty())
+            .map(str::to_owned)
+            .collect();
+    }
+
+    split_unquoted(value)
+        .into_iter()
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            let key = key.trim().to_ascii_lowercase();
+            matches!(key.as_str(), "uri" | "dns" | "email")
+                .then(|| value.trim().trim_matches('"').to_owned())
+        })
+        .filter(|san| !san.is_empty())
+        .collect()
+}
+
+/// Splits on `,` and `;` outside double quotes; `\"` inside quotes is kept.
+fn split_unquoted(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (index, ch) in value.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' | ';' if !quoted => {
+                parts.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+pub fn certificate_sans(der: &[u8]) -> Vec<String> {
+    let Ok((_, certificate)) = X509Certificate::from_der(der) else {
+        return Vec::new();
+    };
+    let Ok(Some(extension)) = certificate.subject_alternative_name() else {
+        return Vec::new();
+    };
+
+    extension
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(value)
+            | GeneralName::URI(value)
+            | GeneralName::RFC822Name(value) => Some((*value).to_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn reject(status: StatusCode, message: &str) -> Rejection {
+    (
+        status,
+        Json(ErrorResponse::new(
+            ErrorCode::from_status(status),
+            message.to_owned(),
+        )),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> ServiceClient {
+        ServiceClient {
+            service: "billing".to_owned(),
+            client_id: "billing-service".to_owned(),
+            client_secret: "secret".to_owned(),
+            audiences: vec!["ledger".to_owned()],
+            scopes: vec!["ledger.read".to_owned(), "ledger.write".to_owned()],
+        }
+    }
+
+    #[test]
+    fn permits_listed_audiences_and_scopes() {
+        let client = client();
+        assert!(client.permits(None, None));
+        assert!(client.permits(Some("ledger"), Some("ledger.read")));
+        assert!(client.permits(None, Some("ledger.write ledger.read")));
+    }
+
+    #[test]
+    fn refuses_anything_not_listed() {
+        let client = client();
+        assert!(!client.permits(Some("realm-management"), None));
+        assert!(!client.permits(None, Some("ledger.read manage-users")));
+        assert!(!client.permits(None, Some("ledger")));
+    }
+
+    #[test]
+    fn permits_only_unscoped_calls_when_nothing_is_listed() {
+        let client = ServiceClient {
+            audiences: Vec::new(),
+            scopes: Vec::new(),
+            ..client()
+        };
+        assert!(client.permits(None, None));
+        assert!(!client.permits(Some("ledger"), None));
+        assert!(!client.permits(None, Some("ledger.read")));
+    }
+
+    #[test]
+    fn reads_only_san_fields_from_a_subject_with_commas() {
+        let header = r#"By=spiffe://mesh/portal;Hash=abc;Subject="CN=billing,OU=URI=spiffe://evil,O=Org";URI=spiffe://mesh/billing,By=spiffe://mesh/portal;DNS=billing.internal"#;
+        assert_eq!(
+            parse_forwarded_cert(header),
+            vec!["spiffe://mesh/billing", "billing.internal"]
+        );
+        assert_eq!(
+            parse_forwarded_cert("spiffe://mesh/billing, billing.internal"),
+            vec!["spiffe://mesh/billing", "billing.internal"]
+        );
+    }
+}
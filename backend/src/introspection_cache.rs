@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::AppConfig;
+use crate::keycloak::{KeycloakError, KeycloakService};
+use crate::keycloak_health::KeycloakHealth;
+use crate::models::auth::TokenIntrospection;
+use crate::util::unix_now;
+
+/// Past this many tokens new results are not cached until stale ones are
+/// dropped.
+const MAX_ENTRIES: usize = 10_000;
+
+struct Entry {
+    introspection: TokenIntrospection,
+    fetched_at: Instant,
+    refreshing: bool,
+}
+
+/// Bearer token introspection results, reused for
+/// `INTROSPECTION_CACHE_SECS` (0, the default, turns the cache off). While
+/// [`KeycloakHealth`] reports Keycloak unavailable, a result older than that
+/// is still served for up to `INTROSPECTION_MAX_STALE_SECS` more while one
+/// background call to Keycloak tries to replace it; otherwise, and past
+/// that, the caller waits for Keycloak again. Only active results are kept,
+/// keyed by a digest of the token, and none is served after the token's
+/// `exp`. Deactivating an account, "this wasn't me", merges and session
+/// revocations drop the user's results through [`Self::evict_user`]; other
+/// logouts through the portal are caught by the local revocation list
+/// before the cache is asked.
+pub struct IntrospectionCache {
+    keycloak: Arc<KeycloakService>,
+    health: Arc<KeycloakHealth>,
+    ttl: Duration,
+    max_stale: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl IntrospectionCache {
+    pub fn from_config(
+        config: &AppConfig,
+        keycloak: Arc<KeycloakService>,
+        health: Arc<KeycloakHealth>,
+    ) -> Self {
+        Self {
+            keycloak,
+            health,
+            ttl: config.introspection_cache_ttl,
+            max_stale: config.introspection_max_stale,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub async fn introspect(&self, token: &str) -> Result<TokenIntrospection, KeycloakError> {
+        if self.ttl.is_zero() {
+            return self.keycloak.introspect_token(token).await;
+        }
+
+        let key = hex::encode(Sha256::digest(token));
+        if let Some(introspection) = self.cached(&key, token) {
+            return Ok(introspection);
+        }
+
+        let introspection = self.keycloak.introspect_token(token).await?;
+        store(
+            &self.entries,
+            key,
+            &introspection,
+            self.ttl + self.max_stale,
+        );
+        Ok(introspection)
+    }
+
+    /// Forgets every cached result for tokens of `user_id`.
+    pub fn evict_user(&self, user_id: &str) {
+        self.entries
+            .lock()
+            .expect("introspection cache lock poisoned")
+            .retain(|_, entry| entry.introspection.sub.as_deref() != Some(user_id));
+    }
+
+    /// A usable cached result, starting its refresh when it is stale. Stale
+    /// results are only used during a Keycloak outage.
+    fn cached(&self, key: &str, token: &str) -> Option<TokenIntrospection> {
+        let mut entries = self
+            .entries
+            .lock()
+            .expect("introspection cache lock poisoned");
+        let entry = entries.get_mut(key)?;
+        let age = entry.fetched_at.elapsed();
+        let expired = entry.introspection.exp.is_some_and(|exp| exp <= unix_now());
+        let stale = age > self.ttl;
+        if expired || age > self.ttl + self.max_stale || (stale && !self.health.is_unavailable()) {
+            entries.remove(key);
+            return None;
+        }
+
+        if stale && !entry.refreshing {
+            entry.refreshing = true;
+            self.revalidate(key.to_owned(), token.to_owned());
+        }
+        Some(entry.introspection.clone())
+    }
+
+    fn revalidate(&self, key: String, token: String) {
+        let keycloak = self.keycloak.clone();
+        let entries = self.entries.clone();
+        let retained = self.ttl + self.max_stale;
+        tokio::spawn(async move {
+            match keycloak.introspect_token(&token).await {
+                Ok(introspection) => store(&entries, key, &introspection, retained),
+                Err(err) => {
+                    warn!("[Introspection] background refresh failed: {}", err);
+                    if let Some(entry) = entries
+                        .lock()
+                        .expect("introspection cache lock poisoned")
+                        .get_mut(&key)
+                    {
+                        entry.refreshing = false;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Caches an active result, or forgets the token once Keycloak calls it
+/// inactive.
+fn store(
+    entries: &Mutex<HashMap<String, Entry>>,
+    key: String,
+    introspection: &TokenIntrospection,
+    retained: Duration,
+) {
+    let mut entries = entries.lock().expect("introspection cache lock poisoned");
+    if !introspection.active {
+        entries.remove(&key);
+        return;
+    }
+    if entries.len() >= MAX_ENTRIES && !entries.contains_key(&key) {
+        let now = unix_now();
+        entries.retain(|_, entry| {
+            entry.fetched_at.elapsed() <= retained
+                && entry.introspection.exp.is_none_or(|exp| exp > now)
+        });
+        if entries.len() >= MAX_ENTRIES {
+            debug!("[Introspection] cache full, result not kept");
+            return;
+        }
+    }
+    entries.insert(
+        key,
+        Entry {
+            introspection: introspection.clone(),
+            fetched_at: Instant::now(),
+            refreshing: false,
+        },
+    );
+}
@@ -0,0 +1,264 @@
+use std::fs;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::client_ip::{ClientIp, parse_networks};
+use crate::error_codes::ErrorCode;
+use crate::models::problem::{PROBLEM_JSON, ProblemDetails};
+use crate::{AppConfig, AppState};
+
+const ADMIN_ROUTE_PREFIX: &str = "/api/admin/";
+
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    /// Deny entries always win; a non-empty allow list admits only its members.
+    fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteGroup {
+    pub prefix: String,
+    pub list: AccessList,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IpFilterRules {
+    pub global: AccessList,
+    pub groups: Vec<RouteGroup>,
+}
+
+impl IpFilterRules {
+    fn from_env_config(config: &AppConfig) -> Self {
+        let mut groups = Vec::new();
+        let admin = AccessList {
+            allow: config.admin_ip_allowlist.clone(),
+            deny: config.admin_ip_denylist.clone(),
+        };
+        if !admin.is_empty() {
+            groups.push(RouteGroup {
+                prefix: ADMIN_ROUTE_PREFIX.to_owned(),
+                list: admin,
+            });
+        }
+
+        Self {
+            global: AccessList {
+                allow: config.ip_allowlist.clone(),
+                deny: config.ip_denylist.clone(),
+            },
+            groups,
+        }
+    }
+
+    /// Checks the global list, then the group with the longest matching
+    /// path prefix.
+    pub fn permits(&self, ip: IpAddr, path: &str) -> bool {
+        if !self.global.permits(ip) {
+            return false;
+        }
+
+        self.groups
+            .iter()
+            .filter(|group| path.starts_with(&group.prefix))
+            .max_by_key(|group| group.prefix.len())
+            .is_none_or(|group| group.list.permits(ip))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+    #[serde(default)]
+    groups: Vec<GroupFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupFile {
+    prefix: String,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// Allow/deny CIDR lists checked against the resolved client address. Lists
+/// come from `IP_ALLOWLIST`/`IP_DENYLIST` and `ADMIN_IP_ALLOWLIST`/
+/// `ADMIN_IP_DENYLIST`, or from `IP_FILTER_FILE`, which replaces them and is
+/// re-read whenever it changes on disk.
+pub struct IpFilter {
+    rules: RwLock<Arc<IpFilterRules>>,
+    file: Option<String>,
+    reload_interval: Duration,
+}
+
+impl IpFilter {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let file = config.ip_filter_file.clone();
+        let rules = file
+            .as_deref()
+            .and_then(load_rules_file)
+            .unwrap_or_else(|| IpFilterRules::from_env_config(config));
+
+        Self {
+            rules: RwLock::new(Arc::new(rules)),
+            file,
+            reload_interval: config.ip_filter_reload_interval,
+        }
+    }
+
+    pub fn rules(&self) -> Arc<IpFilterRules> {
+        Arc::clone(&self.rules.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Re-reads `IP_FILTER_FILE`, or takes the lists from `config` when
+    /// there is none. A file that cannot be read keeps the current lists.
+    pub fn reload(&self, config: &AppConfig) {
+        let rules = match self.file.as_deref() {
+            Some(path) => match load_rules_file(path) {
+                Some(rules) => rules,
+                None => return,
+            },
+            None => IpFilterRules::from_env_config(config),
+        };
+        *self.rules.write().unwrap_or_else(|err| err.into_inner()) = Arc::new(rules);
+    }
+
+    pub fn spawn_reload_task(self: &Arc<Self>) {
+        let Some(path) = self.file.clone() else {
+            return;
+        };
+        if self.reload_interval.is_zero() {
+            return;
+        }
+
+        let filter = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut last_modified = modified_at(&path);
+            loop {
+                sleep(filter.reload_interval).await;
+                let modified = modified_at(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+
+                if let Some(rules) = load_rules_file(&path) {
+                    info!(%path, "[IpFilter] reloaded access lists");
+                    *filter.rules.write().unwrap_or_else(|err| err.into_inner()) = Arc::new(rules);
+                }
+            }
+        });
+    }
+}
+
+pub async fn enforce(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if state.ip_filter.rules().permits(client_ip, path) {
+        return next.run(request).await;
+    }
+
+    warn!("[IpFilter] blocked ip={} path={}", client_ip, path);
+    (
+        StatusCode::FORBIDDEN,
+        [(header::CONTENT_TYPE, PROBLEM_JSON)],
+        Json(ProblemDetails {
+            problem_type: "about:blank".to_owned(),
+            title: "Forbidden".to_owned(),
+            status: StatusCode::FORBIDDEN.as_u16(),
+            detail: "Requests from your network address are not permitted for this resource"
+                .to_owned(),
+            code: ErrorCode::SourceBlocked,
+        }),
+    )
+        .into_response()
+}
+
+/// Checks that `IP_FILTER_FILE`, when set, loads, for startup validation;
+/// falling back to the environment lists could leave the filter open.
+pub fn check(config: &AppConfig) -> Result<(), String> {
+    match config.ip_filter_file.as_deref() {
+        Some(path) => read_rules_file(path)
+            .map(drop)
+            .map_err(|err| format!("IP_FILTER_FILE {path}: {err}")),
+        None => Ok(()),
+    }
+}
+
+fn load_rules_file(path: &str) -> Option<IpFilterRules> {
+    match read_rules_file(path) {
+        Ok(rules) => Some(rules),
+        Err(err) => {
+            warn!(%path, %err, "Unable to load IP filter file; keeping previous lists");
+            None
+        }
+    }
+}
+
+fn read_rules_file(path: &str) -> Result<IpFilterRules, String> {
+    fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<RulesFile>(&contents).map_err(|err| err.to_string())
+        })
+        .and_then(|file| {
+            let list = |entries: &[String]| parse_networks(&entries.join(","));
+            Ok(IpFilterRules {
+                global: AccessList {
+                    allow: list(&file.allow)?,
+                    deny: list(&file.deny)?,
+                },
+                groups: file
+                    .groups
+                    .into_iter()
+                    .map(|group| {
+                        Ok(RouteGroup {
+                            list: AccessList {
+                                allow: list(&group.allow)?,
+                                deny: list(&group.deny)?,
+                            },
+                            prefix: group.prefix,
+                        })
+                    })
+                    .collect::<Result<_, String>>()?,
+            })
+        })
+}
+
+fn modified_at(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
@@ -0,0 +1,240 @@
+use std::fs;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use ipnet::IpNet;
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::AppConfig;
+use crate::cache::TtlLruCache;
+use crate::metrics::Metrics;
+
+pub const DEFAULT_ABUSEIPDB_URL: &str = "https://api.abuseipdb.com/api/v2/check";
+
+/// Score given to addresses on the local blocklist.
+const BLOCKLIST_SCORE: u8 = 100;
+
+/// What registration does with a client address, by its 0–100 risk score.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RiskDecision {
+    Allow,
+    /// The captcha must be solved; exemptions are not honoured.
+    Challenge,
+    Block,
+}
+
+impl RiskDecision {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Challenge => "challenge",
+            Self::Block => "block",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AbuseIpDbResponse {
+    data: AbuseIpDbData,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbuseIpDbData {
+    abuse_confidence_score: u8,
+}
+
+/// Scores registration sources against `IP_BLOCKLIST_FILE` (addresses or
+/// CIDRs, one per line) and, with `ABUSEIPDB_API_KEY`, AbuseIPDB's
+/// confidence score. Scores from `IP_REPUTATION_CHALLENGE_SCORE` up must pass
+/// the captcha and those from `IP_REPUTATION_BLOCK_SCORE` up are refused.
+/// The blocklist is re-read every `IP_BLOCKLIST_RELOAD_SECS` once its
+/// modification time changes, so a cron job refreshing it takes effect
+/// without a restart. AbuseIPDB answers are cached per address for
+/// `IP_REPUTATION_CACHE_TTL_SECS`. A failed lookup allows the source unless
+/// `IP_REPUTATION_FAIL_OPEN=false`, in which case it is blocked.
+pub struct IpReputation {
+    client: Client,
+    abuseipdb_url: String,
+    abuseipdb_key: Option<String>,
+    blocklist_file: Option<String>,
+    blocklist: RwLock<Arc<Vec<IpNet>>>,
+    blocklist_reload_interval: Duration,
+    challenge_score: u8,
+    block_score: u8,
+    fail_open: bool,
+    scores: Mutex<TtlLruCache<IpAddr, u8>>,
+}
+
+impl IpReputation {
+    pub fn from_config(config: &AppConfig, client: Client) -> Self {
+        let blocklist_file = config.ip_blocklist_file.clone();
+        let blocklist = blocklist_file
+            .as_deref()
+            .and_then(load_blocklist)
+            .unwrap_or_default();
+
+        Self {
+            client,
+            abuseipdb_url: config.abuseipdb_url.clone(),
+            abuseipdb_key: config.abuseipdb_api_key.clone(),
+            blocklist_file,
+            blocklist: RwLock::new(Arc::new(blocklist)),
+            blocklist_reload_interval: config.ip_blocklist_reload_interval,
+            challenge_score: config.ip_reputation_challenge_score,
+            block_score: config.ip_reputation_block_score,
+            fail_open: config.ip_reputation_fail_open,
+            scores: Mutex::new(TtlLruCache::new(10_000, config.ip_reputation_cache_ttl)),
+        }
+    }
+
+    pub fn spawn_reload_task(self: &Arc<Self>) {
+        let Some(path) = self.blocklist_file.clone() else {
+            return;
+        };
+        if self.blocklist_reload_interval.is_zero() {
+            return;
+        }
+
+        // The file is polled and read off the runtime's worker threads; a
+        // large feed is parsed on the blocking pool as well.
+        let reputation = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut last_modified = modified_at(&path).await;
+            loop {
+                sleep(reputation.blocklist_reload_interval).await;
+                let modified = modified_at(&path).await;
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+
+                let reload = path.clone();
+                let loaded = tokio::task::spawn_blocking(move || load_blocklist(&reload))
+                    .await
+                    .ok()
+                    .flatten();
+                if let Some(networks) = loaded {
+                    *reputation
+                        .blocklist
+                        .write()
+                        .unwrap_or_else(|err| err.into_inner()) = Arc::new(networks);
+                }
+            }
+        });
+    }
+
+    fn blocklist(&self) -> Arc<Vec<IpNet>> {
+        Arc::clone(&self.blocklist.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    pub async fn assess(&self, ip: IpAddr, metrics: &Metrics) -> RiskDecision {
+        let decision = match self.score(ip).await {
+            Some(score) if score >= self.block_score => RiskDecision::Block,
+            Some(score) if score >= self.challenge_score => RiskDecision::Challenge,
+            Some(_) => RiskDecision::Allow,
+            None if self.fail_open => RiskDecision::Allow,
+            None => RiskDecision::Block,
+        };
+        if decision != RiskDecision::Allow {
+            info!("[IpReputation] ip={} decision={}", ip, decision.as_str());
+        }
+        metrics
+            .ip_reputation_decisions
+            .with_label_values(&[decision.as_str()])
+            .inc();
+        decision
+    }
+
+    /// `None` when the score could not be determined.
+    async fn score(&self, ip: IpAddr) -> Option<u8> {
+        if self.blocklist().iter().any(|net| net.contains(&ip)) {
+            return Some(BLOCKLIST_SCORE);
+        }
+
+        let Some(key) = self.abuseipdb_key.as_deref() else {
+            return Some(0);
+        };
+        if let Some(score) = self.scores.lock().await.get(&ip) {
+            return Some(score);
+        }
+        let score = self.lookup(key, ip).await?;
+        debug!("[IpReputation] ip={} abuseipdb score={}", ip, score);
+        self.scores.lock().await.insert(ip, score);
+        Some(score)
+    }
+
+    async fn lookup(&self, key: &str, ip: IpAddr) -> Option<u8> {
+        let result = self
+            .client
+            .get(&self.abuseipdb_url)
+            .header("Key", key)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .query(&[
+                ("ipAddress", ip.to_string().as_str()),
+                ("maxAgeInDays", "90"),
+            ])
+            .timeout(Duration::from_secs(3))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(response) => match response.json::<AbuseIpDbResponse>().await {
+                Ok(body) => Some(body.data.abuse_confidence_score.min(100)),
+                Err(err) => {
+                    warn!("[IpReputation] unreadable AbuseIPDB answer: {}", err);
+                    None
+                }
+            },
+            Err(err) => {
+                warn!("[IpReputation] AbuseIPDB lookup failed: {}", err);
+                None
+            }
+        }
+    }
+}
+
+/// `None` when the file cannot be read, so the previous list is kept.
+fn load_blocklist(path: &str) -> Option<Vec<IpNet>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            let networks = parse_blocklist(&contents);
+            info!(
+                "[IpReputation] loaded {} blocklist entries from {}",
+                networks.len(),
+                path
+            );
+            Some(networks)
+        }
+        Err(err) => {
+            warn!("[IpReputation] unable to read {}: {}", path, err);
+            None
+        }
+    }
+}
+
+async fn modified_at(path: &str) -> Option<SystemTime> {
+    tokio::fs::metadata(path)
+        .await
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+fn parse_blocklist(contents: &str) -> Vec<IpNet> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .ok()
+        })
+        .collect()
+}
@@ -0,0 +1,118 @@
+use std::fs::{File, OpenOptions};
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::oneshot;
+use tracing::error;
+
+enum Write {
+    Append(String),
+    Replace(String),
+    /// Answered once everything written before it is on disk.
+    Flush(oneshot::Sender<()>),
+}
+
+/// An append-only file of JSON lines, written by a background task so the
+/// callers never touch the disk, or hold their locks across it. Writes land
+/// in the order they were made, and each batch the task picks up is flushed
+/// and synced to disk before it waits for more. `replace` swaps the whole
+/// file for a compacted version through a synced temporary file and a
+/// rename.
+pub struct Journal {
+    sender: UnboundedSender<Write>,
+}
+
+impl Journal {
+    /// Opens `path` for appending; `label` prefixes the log lines of the
+    /// writer task.
+    pub fn open(path: &str, label: &'static str) -> Result<Self, String> {
+        let file = open_append(path)?;
+        let (sender, mut writes) = mpsc::unbounded_channel::<Write>();
+        let path = path.to_owned();
+        let mut file = tokio::fs::File::from_std(file);
+        tokio::spawn(async move {
+            while let Some(first) = writes.recv().await {
+                let mut next = Some(first);
+                let mut flushed = Vec::new();
+                while let Some(write) = next {
+                    let result = match write {
+                        Write::Append(mut line) => {
+                            line.push('\n');
+                            file.write_all(line.as_bytes()).await
+                        }
+                        Write::Replace(contents) => match replace(&path, &contents).await {
+                            Ok(reopened) => {
+                                file = reopened;
+                                Ok(())
+                            }
+                            Err(err) => Err(err),
+                        },
+                        Write::Flush(done) => {
+                            flushed.push(done);
+                            Ok(())
+                        }
+                    };
+                    if let Err(err) = result {
+                        error!("[{}] writing to {} failed: {}", label, path, err);
+                    }
+                    next = writes.try_recv().ok();
+                }
+                if let Err(err) = sync(&mut file).await {
+                    error!("[{}] syncing {} failed: {}", label, path, err);
+                }
+                for done in flushed {
+                    let _ = done.send(());
+                }
+            }
+        });
+        Ok(Self { sender })
+    }
+
+    pub fn append(&self, line: String) {
+        let _ = self.sender.send(Write::Append(line));
+    }
+
+    /// Replaces the file with `lines`, each written as one line.
+    pub fn replace(&self, lines: Vec<String>) {
+        let mut contents = lines.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        let _ = self.sender.send(Write::Replace(contents));
+    }
+
+    /// Waits until every write made so far is on disk, for shutdown.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.sender.send(Write::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+fn open_append(path: &str) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("cannot open {path}: {err}"))
+}
+
+async fn sync(file: &mut tokio::fs::File) -> std::io::Result<()> {
+    file.flush().await?;
+    file.sync_data().await
+}
+
+async fn replace(path: &str, contents: &str) -> std::io::Result<tokio::fs::File> {
+    let staging = format!("{path}.tmp");
+    let mut file = tokio::fs::File::create(&staging).await?;
+    file.write_all(contents.as_bytes()).await?;
+    sync(&mut file).await?;
+    drop(file);
+    tokio::fs::rename(&staging, path).await?;
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
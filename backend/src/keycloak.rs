@@ -1,18 +1,40 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 
-use reqwest::{Client, StatusCode};
-use serde::Deserialize;
+use futures_util::stream::{self, Stream, TryStreamExt};
+use rand::Rng;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use thiserror::Error;
 use tokio::sync::{Mutex, RwLock};
 use tokio::time::sleep;
 use tracing::{debug, error, info, warn};
 
 use crate::AppConfig;
-use crate::models::user::KeycloakUser;
+use crate::cache::TtlLruCache;
+#[cfg(any(test, feature = "cassette"))]
+use crate::cassette::Cassette;
+use crate::clock_skew::usable_lifetime;
+use crate::correlation::{self, WithCorrelation};
+use crate::deadline::{self, WithDeadline};
+use crate::dpop::DPOP_HEADER;
+use crate::internal::ServiceClient;
+use crate::keycloak_limiter::AdminLimiter;
+use crate::metrics::Metrics;
+use crate::models::auth::TokenIntrospection;
+use crate::models::user::{KeycloakUser, UserRepresentation};
+use crate::tenant::TenantConfig;
+use crate::token_cache::CachedToken;
+use crate::user_profile::UserProfileConfig;
 
 const TOKEN_REFRESH_LEEWAY: Duration = Duration::from_secs(60);
 const TOKEN_REFRESH_MIN_LEEWAY_SECS: u64 = 1;
+const TOKEN_REFRESH_JITTER_RATIO: f64 = 0.1;
+const TOKEN_REFRESH_HIGH_LOAD_RATIO: f64 = 0.2;
 
 fn compute_refresh_schedule(expires_in: u64, issued_at: Instant) -> (Instant, Instant) {
     let expires_duration = Duration::from_secs(expires_in);
@@ -44,14 +66,68 @@ fn compute_refresh_schedule(expires_in: u64, issued_at: Instant) -> (Instant, In
 
     (expires_at, refresh_at)
 }
+
+/// Pulls the refresh earlier by a random slice of the delay so replicas that
+/// started together do not refresh in lockstep, and by a further fifth while
+/// the token is in heavy use so a slow or failing refresh has more headroom.
+fn jittered_refresh_at(issued_at: Instant, refresh_at: Instant, high_load: bool) -> Instant {
+    let delay = refresh_at.saturating_duration_since(issued_at);
+    let mut earlier = delay.mul_f64(rand::thread_rng().gen_range(0.0..TOKEN_REFRESH_JITTER_RATIO));
+    if high_load {
+        earlier += delay.mul_f64(TOKEN_REFRESH_HIGH_LOAD_RATIO);
+    }
+
+    let floor = Duration::from_secs(TOKEN_REFRESH_MIN_LEEWAY_SECS).min(delay);
+    issued_at + delay.saturating_sub(earlier).max(floor)
+}
+
+/// `endpoint` with `query` appended, or unchanged when there is none.
+fn with_query(endpoint: String, query: &[(&str, &str)]) -> String {
+    if query.is_empty() {
+        return endpoint;
+    }
+    reqwest::Url::parse_with_params(&endpoint, query).map_or(endpoint, String::from)
+}
+
+/// `response` when it succeeded; its status and body as the error if not.
+async fn admin_success(response: Response) -> Result<Response, KeycloakError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response.text().await.unwrap_or_default();
+    Err(KeycloakError::UnexpectedStatus { status, message })
+}
+
+/// `value` percent-encoded as a single path segment, so an id or name
+/// cannot step into another admin resource.
+pub fn path_segment(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
 const TOKEN_RETRY_DELAY: Duration = Duration::from_secs(30);
+const USER_PAGE_SIZE_MAX: u32 = 500;
+const HEALTH_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const UMA_TICKET_GRANT: &str = "urn:ietf:params:oauth:grant-type:uma-ticket";
 
 #[derive(Debug, Error)]
 pub enum KeycloakError {
     #[error("keycloak admin token is unavailable")]
     TokenUnavailable,
     #[error("keycloak request failed: {0}")]
-    Request(#[from] reqwest::Error),
+    Request(reqwest::Error),
+    #[error("keycloak did not respond within the request deadline")]
+    DeadlineExceeded,
+    #[error("keycloak is saturated; background call shed")]
+    Overloaded,
     #[error("unexpected keycloak status {status}: {message}")]
     UnexpectedStatus { status: StatusCode, message: String },
     #[error("invalid grant: {error}")]
@@ -61,6 +137,16 @@ pub enum KeycloakError {
     },
 }
 
+impl From<reqwest::Error> for KeycloakError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::DeadlineExceeded
+        } else {
+            Self::Request(err)
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum RefreshSource {
     Bootstrap,
@@ -80,17 +166,37 @@ pub struct KeycloakService {
     settings: KeycloakSettings,
     state: Arc<RwLock<Option<TokenState>>>,
     refresh_lock: Arc<Mutex<()>>,
+    user_lookup_cache: Arc<Mutex<TtlLruCache<String, Vec<UserRepresentation>>>>,
+    token_demand: Arc<AtomicU64>,
+    refresh_failures: Arc<AtomicU64>,
+    metrics: Arc<Metrics>,
+    #[cfg(any(test, feature = "cassette"))]
+    cassette: Option<Arc<Cassette>>,
+    admin_limiter: Arc<AdminLimiter>,
 }
 
 #[derive(Clone)]
 struct KeycloakSettings {
     token_endpoint: String,
     logout_endpoint: String,
+    introspect_endpoint: String,
+    par_endpoint: String,
     users_endpoint: String,
+    session_stats_endpoint: String,
+    sessions_endpoint: String,
+    events_endpoint: String,
+    roles_endpoint: String,
+    health_endpoint: String,
+    realm_endpoint: String,
     admin_client_id: String,
     admin_client_secret: String,
     public_client_id: String,
     public_client_secret: Option<String>,
+    user_lookup_cache_capacity: usize,
+    user_lookup_cache_ttl: Duration,
+    token_high_load_rps: f64,
+    slow_call_threshold: Option<Duration>,
+    clock_skew_leeway: Duration,
 }
 
 #[derive(Debug, Clone)]
@@ -108,6 +214,25 @@ struct TokenResponse {
     expires_in: Option<u64>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PushedAuthorization {
+    pub request_uri: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct UmaDecision {
+    #[serde(default)]
+    result: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceTokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+    token_type: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct UserTokenResponse {
     access_token: String,
@@ -117,6 +242,122 @@ struct UserTokenResponse {
     token_type: Option<String>,
 }
 
+/// Keycloak reports the counts as strings, e.g. `{"active": "3"}`.
+#[derive(Debug, Deserialize)]
+struct ClientSessionStats {
+    #[serde(default)]
+    active: Value,
+}
+
+impl ClientSessionStats {
+    fn active(&self) -> u64 {
+        match &self.active {
+            Value::Number(count) => count.as_u64().unwrap_or_default(),
+            Value::String(count) => count.parse().unwrap_or_default(),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleRepresentation {
+    pub id: String,
+    pub name: String,
+}
+
+/// A realm role as listed by `GET /roles`, which says whether it is a
+/// composite but not what it contains.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoleSummary {
+    pub name: String,
+    #[serde(default)]
+    pub composite: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GroupRepresentation {
+    pub id: String,
+    #[serde(default)]
+    pub path: String,
+}
+
+/// A user's directly assigned roles (`GET /users/{id}/role-mappings`).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleMappings {
+    #[serde(default)]
+    pub realm_mappings: Vec<RoleRepresentation>,
+    /// Keyed by client id.
+    #[serde(default)]
+    pub client_mappings: HashMap<String, ClientRoleMappings>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientRoleMappings {
+    /// The client's internal id, which role-mapping paths use.
+    pub id: String,
+    #[serde(default)]
+    pub mappings: Vec<RoleRepresentation>,
+}
+
+/// A link to an account at an external identity provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FederatedIdentity {
+    pub identity_provider: String,
+    pub user_id: String,
+    #[serde(default)]
+    pub user_name: String,
+}
+
+/// An active session of a user (`GET /users/{id}/sessions`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSession {
+    pub id: String,
+    /// Milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub start: u64,
+    #[serde(default)]
+    pub last_access: u64,
+    #[serde(default)]
+    pub ip_address: Option<String>,
+}
+
+/// A user event from the realm's event store (`GET /events`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeycloakEvent {
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    pub time: u64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub ip_address: Option<String>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub details: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialRepresentation {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub created_date: Option<u64>,
+}
+
 #[derive(Debug, Deserialize)]
 struct KeycloakErrorResponse {
     error: String,
@@ -124,7 +365,15 @@ struct KeycloakErrorResponse {
     error_description: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+/// Keycloak client a user token is requested under. Refreshes and logouts
+/// must use the client that issued the token.
+#[derive(Debug, Clone, Copy)]
+pub struct PublicClient<'a> {
+    pub id: &'a str,
+    pub secret: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserTokenSet {
     pub token_type: String,
     pub access_token: String,
@@ -134,28 +383,99 @@ pub struct UserTokenSet {
 }
 
 impl KeycloakService {
-    pub async fn bootstrap(config: &AppConfig, client: Client) -> Arc<Self> {
+    pub async fn bootstrap(config: &AppConfig, client: Client, metrics: Arc<Metrics>) -> Arc<Self> {
+        let service = Self::new(config, client, metrics);
+        service.wait_for_initial_token().await;
+        service.spawn_refresh_task();
+
+        service
+    }
+
+    /// A service that has not fetched an admin token yet and runs no
+    /// background refresh; `bootstrap` is what the server uses.
+    pub fn new(config: &AppConfig, client: Client, metrics: Arc<Metrics>) -> Arc<Self> {
         let settings = KeycloakSettings::from_config(config);
-        let service = Arc::new(Self {
+        let user_lookup_cache = TtlLruCache::new(
+            settings.user_lookup_cache_capacity,
+            settings.user_lookup_cache_ttl,
+        );
+        Arc::new(Self {
             client,
             settings,
             state: Arc::new(RwLock::new(None)),
             refresh_lock: Arc::new(Mutex::new(())),
-        });
+            user_lookup_cache: Arc::new(Mutex::new(user_lookup_cache)),
+            token_demand: Arc::new(AtomicU64::new(0)),
+            refresh_failures: Arc::new(AtomicU64::new(0)),
+            admin_limiter: Arc::new(AdminLimiter::from_config(config, metrics.clone())),
+            metrics,
+            #[cfg(any(test, feature = "cassette"))]
+            cassette: Cassette::from_config(config).map(Arc::new),
+        })
+    }
 
-        service.wait_for_initial_token().await;
-        service.spawn_refresh_task();
+    /// Sends an outbound call within the request deadline and with the
+    /// caller's correlation headers, recording its latency under `endpoint`
+    /// and warning when it takes longer than `KEYCLOAK_SLOW_CALL_MS`.
+    async fn send(
+        &self,
+        endpoint: &'static str,
+        request: RequestBuilder,
+    ) -> Result<Response, reqwest::Error> {
+        let started = Instant::now();
+        let result = self
+            .exchange(request.with_correlation().with_deadline())
+            .await;
+        let elapsed = started.elapsed();
+
+        let outcome = match &result {
+            Ok(response) if response.status().is_success() => "success",
+            Ok(response) if response.status().is_client_error() => "client_error",
+            Ok(_) => "server_error",
+            Err(_) => "failed",
+        };
+        self.metrics.observe_keycloak_request(
+            endpoint,
+            outcome,
+            elapsed.as_secs_f64(),
+            correlation::sampled_trace_id(),
+        );
+
+        let millis = elapsed.as_millis();
+        match self.settings.slow_call_threshold {
+            Some(threshold) if elapsed >= threshold => warn!(
+                "[Keycloak] slow call endpoint={} outcome={} took {}ms (threshold {}ms)",
+                endpoint,
+                outcome,
+                millis,
+                threshold.as_millis()
+            ),
+            _ => debug!(
+                "[Keycloak] call endpoint={} outcome={} took {}ms",
+                endpoint, outcome, millis
+            ),
+        }
+        result
+    }
 
-        service
+    /// Sends the request, through the `KEYCLOAK_CASSETTE` when one is set.
+    async fn exchange(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        #[cfg(any(test, feature = "cassette"))]
+        if let Some(cassette) = &self.cassette {
+            return cassette.exchange(&self.client, request.build()?).await;
+        }
+        request.send().await
     }
 
     async fn wait_for_initial_token(self: &Arc<Self>) {
         loop {
             match self.fetch_and_store_token(RefreshSource::Bootstrap).await {
                 Ok(_state) => {
+                    self.refresh_failures.store(0, Ordering::Relaxed);
                     break;
                 }
                 Err(err) => {
+                    self.refresh_failures.fetch_add(1, Ordering::Relaxed);
                     warn!(
                         "[Keycloak] Unable to acquire admin token, retrying in {}s: {}",
                         TOKEN_RETRY_DELAY.as_secs(),
@@ -178,12 +498,14 @@ impl KeycloakService {
 
                 match svc.fetch_and_store_token(RefreshSource::Background).await {
                     Ok(state) => {
+                        svc.refresh_failures.store(0, Ordering::Relaxed);
                         info!(
                             "[Keycloak] Token refreshed (expires_in={}s)",
                             state.expires_in
                         );
                     }
                     Err(err) => {
+                        svc.refresh_failures.fetch_add(1, Ordering::Relaxed);
                         error!(
                             "[Keycloak] Token refresh failed, retrying in {}s: {}",
                             TOKEN_RETRY_DELAY.as_secs(),
@@ -222,7 +544,32 @@ impl KeycloakService {
         Duration::ZERO
     }
 
+    /// Whether an unexpired admin token is currently held, without fetching one.
+    pub async fn has_valid_token(&self) -> bool {
+        self.state
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|state| state.expires_at > Instant::now())
+    }
+
+    /// Remaining lifetime of the held admin token; zero when none is held.
+    pub async fn token_remaining(&self) -> Duration {
+        self.state
+            .read()
+            .await
+            .as_ref()
+            .map(|state| state.expires_at.saturating_duration_since(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    /// Background refresh attempts that have failed since the last success.
+    pub fn refresh_failures(&self) -> u64 {
+        self.refresh_failures.load(Ordering::Relaxed)
+    }
+
     pub async fn ensure_token(&self) -> Result<String, KeycloakError> {
+        self.token_demand.fetch_add(1, Ordering::Relaxed);
         {
             let guard = self.state.read().await;
             if let Some(state) = guard.as_ref()
@@ -232,7 +579,9 @@ impl KeycloakService {
             }
         }
 
-        self.fetch_and_store_token(RefreshSource::Demand)
+        // The token is shared, so a caller with little budget left must not
+        // cut its fetch short for everyone else.
+        deadline::detached(self.fetch_and_store_token(RefreshSource::Demand))
             .await
             .map(|state| state.access_token)
     }
@@ -243,9 +592,11 @@ impl KeycloakService {
     ) -> Result<TokenState, KeycloakError> {
         let _lock = self.refresh_lock.lock().await;
 
+        let mut previous_refresh_at = None;
         {
             let guard = self.state.read().await;
             if let Some(state) = guard.as_ref() {
+                previous_refresh_at = Some(state.last_refresh_at);
                 let now = Instant::now();
                 let threshold = now + Duration::from_secs(5);
 
@@ -262,16 +613,12 @@ impl KeycloakService {
             }
         }
 
-        let response = self
-            .client
-            .post(&self.settings.token_endpoint)
-            .form(&[
-                ("grant_type", "client_credentials"),
-                ("client_id", self.settings.admin_client_id.as_str()),
-                ("client_secret", self.settings.admin_client_secret.as_str()),
-            ])
-            .send()
-            .await?;
+        let request = self.client.post(&self.settings.token_endpoint).form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", self.settings.admin_client_id.as_str()),
+            ("client_secret", self.settings.admin_client_secret.as_str()),
+        ]);
+        let response = self.send("token", request).await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -283,9 +630,17 @@ impl KeycloakService {
         }
 
         let payload: TokenResponse = response.json().await?;
-        let expires_in = payload.expires_in.unwrap_or(300);
+        let expires_in = usable_lifetime(
+            payload.expires_in.unwrap_or(300),
+            self.settings.clock_skew_leeway,
+        );
         let issued_at = Instant::now();
         let (expires_at, next_refresh_at) = compute_refresh_schedule(expires_in, issued_at);
+        let next_refresh_at = jittered_refresh_at(
+            issued_at,
+            next_refresh_at,
+            self.is_high_load(previous_refresh_at),
+        );
         let state = TokenState {
             access_token: payload.access_token,
             expires_in,
@@ -313,6 +668,17 @@ impl KeycloakService {
         Ok(state)
     }
 
+    /// Whether admin calls since the previous refresh averaged more than
+    /// `ADMIN_TOKEN_HIGH_LOAD_RPS`. Resets the demand counter.
+    fn is_high_load(&self, previous_refresh_at: Option<Instant>) -> bool {
+        let demand = self.token_demand.swap(0, Ordering::Relaxed);
+        let Some(since) = previous_refresh_at else {
+            return false;
+        };
+        let elapsed = since.elapsed().as_secs_f64();
+        elapsed > 0.0 && demand as f64 / elapsed > self.settings.token_high_load_rps
+    }
+
     pub async fn create_user(
         &self,
         user: &KeycloakUser,
@@ -322,18 +688,18 @@ impl KeycloakService {
 
         while attempts_remaining > 0 {
             let token = self.ensure_token().await?;
-            let response = self
-                .client
-                .post(endpoint)
-                .bearer_auth(&token)
-                .json(user)
-                .send()
+            let request = self.client.post(endpoint).bearer_auth(&token).json(user);
+            let _permit = self
+                .admin_limiter
+                .acquire("users", &reqwest::Method::POST)
                 .await?;
+            let response = self.send("users", request).await?;
 
             let status = response.status();
             match status {
                 StatusCode::CREATED => {
                     info!("[Register] user={} result=201", user.email);
+                    self.invalidate_user_lookup(&user.email).await;
                     return Ok(CreateUserResult::Created);
                 }
                 StatusCode::CONFLICT => {
@@ -346,6 +712,7 @@ impl KeycloakService {
                         user.email,
                         reason.trim()
                     );
+                    self.invalidate_user_lookup(&user.email).await;
                     return Ok(CreateUserResult::Conflict(reason));
                 }
                 StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
@@ -361,10 +728,7 @@ impl KeycloakService {
                         "[Keycloak] Received {} while creating user {}, refreshing token",
                         status, user.email
                     );
-                    {
-                        let mut guard = self.state.write().await;
-                        *guard = None;
-                    }
+                    self.clear_token().await;
                     continue;
                 }
                 _ => {
@@ -380,36 +744,786 @@ impl KeycloakService {
         Err(KeycloakError::TokenUnavailable)
     }
 
+    pub async fn find_users_by_email(
+        &self,
+        email: &str,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        let key = email.trim().to_ascii_lowercase();
+        if let Some(users) = self.user_lookup_cache.lock().await.get(&key) {
+            debug!("[Keycloak] user lookup cache hit for {}", key);
+            return Ok(users);
+        }
+
+        let users: Vec<UserRepresentation> = self
+            .admin_get(
+                &self.settings.users_endpoint,
+                &[("email", key.as_str()), ("exact", "true")],
+            )
+            .await?;
+
+        self.user_lookup_cache
+            .lock()
+            .await
+            .insert(key, users.clone());
+
+        Ok(users)
+    }
+
+    pub async fn find_users_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        let key = username.trim().to_lowercase();
+        self.admin_get(
+            &self.settings.users_endpoint,
+            &[("username", key.as_str()), ("exact", "true")],
+        )
+        .await
+    }
+
+    /// Walks the realm's users page by page; the next page is only requested
+    /// once the consumer has drained the previous one. Users come as
+    /// `UserRepresentation` or, for exports, as Keycloak's full JSON.
+    pub fn list_users_stream<T: DeserializeOwned>(
+        &self,
+        page_size: u32,
+    ) -> impl Stream<Item = Result<T, KeycloakError>> + '_ {
+        let page_size = page_size.clamp(1, USER_PAGE_SIZE_MAX);
+
+        stream::try_unfold(Some(0u32), move |first| async move {
+            let Some(first) = first else {
+                return Ok::<_, KeycloakError>(None);
+            };
+
+            let page: Vec<T> = self.users_page(first, page_size).await?;
+            let next = if page.len() < page_size as usize {
+                None
+            } else {
+                Some(first + page_size)
+            };
+
+            Ok(Some((stream::iter(page.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+    }
+
+    /// One page of the realm's users, starting at offset `first`.
+    pub async fn users_page<T: DeserializeOwned>(
+        &self,
+        first: u32,
+        max: u32,
+    ) -> Result<Vec<T>, KeycloakError> {
+        let first_param = first.to_string();
+        let max_param = max.clamp(1, USER_PAGE_SIZE_MAX).to_string();
+        let page: Vec<T> = self
+            .admin_get(
+                &self.settings.users_endpoint,
+                &[
+                    ("first", first_param.as_str()),
+                    ("max", max_param.as_str()),
+                    ("briefRepresentation", "false"),
+                ],
+            )
+            .await?;
+
+        debug!(
+            "[Keycloak] fetched user page first={} size={}",
+            first,
+            page.len()
+        );
+        Ok(page)
+    }
+
+    pub async fn find_users_by_attribute(
+        &self,
+        name: &str,
+        value: &str,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        let query = format!("{name}:{value}");
+        self.admin_get(
+            &self.settings.users_endpoint,
+            &[("q", query.as_str()), ("exact", "true")],
+        )
+        .await
+    }
+
+    pub async fn get_user(&self, id: &str) -> Result<UserRepresentation, KeycloakError> {
+        let endpoint = format!("{}/{}", self.settings.users_endpoint, path_segment(id));
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    pub async fn user_credentials(
+        &self,
+        id: &str,
+    ) -> Result<Vec<CredentialRepresentation>, KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/credentials",
+            self.settings.users_endpoint,
+            path_segment(id)
+        );
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    /// Enables or disables a user and replaces their attributes. Keycloak
+    /// leaves fields missing from the update untouched.
+    pub async fn update_user(
+        &self,
+        user: &UserRepresentation,
+        enabled: bool,
+    ) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}",
+            self.settings.users_endpoint,
+            path_segment(&user.id)
+        );
+        let body = serde_json::json!({
+            "enabled": enabled,
+            "attributes": user.attributes,
+        });
+        self.admin_send(reqwest::Method::PUT, &endpoint, Some(&body))
+            .await?;
+        if let Some(email) = user.email.as_deref() {
+            self.invalidate_user_lookup(email).await;
+        }
+        Ok(())
+    }
+
+    /// Ends every session the user has, on all clients.
+    pub async fn logout_user_sessions(&self, id: &str) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/logout",
+            self.settings.users_endpoint,
+            path_segment(id)
+        );
+        self.admin_send(reqwest::Method::POST, &endpoint, None)
+            .await
+    }
+
+    /// The user's active sessions, across every client.
+    pub async fn user_sessions(&self, id: &str) -> Result<Vec<UserSession>, KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/sessions",
+            self.settings.users_endpoint,
+            path_segment(id)
+        );
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    /// Ends one session, leaving the user's others alone.
+    pub async fn delete_session(&self, session_id: &str) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}",
+            self.settings.sessions_endpoint,
+            path_segment(session_id)
+        );
+        self.admin_send(reqwest::Method::DELETE, &endpoint, None)
+            .await
+    }
+
+    /// Asks Keycloak to email the user a link that verifies their address.
+    /// Keycloak only honours `redirect_uri` alongside the client it belongs
+    /// to, so the public client is named with it. `locale` (as `kc_locale`)
+    /// picks the language of the email and its page for users without a
+    /// stored `locale` of their own.
+    pub async fn send_verify_email(
+        &self,
+        id: &str,
+        redirect_uri: Option<&str>,
+        locale: Option<&str>,
+    ) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/send-verify-email",
+            self.settings.users_endpoint,
+            path_segment(id)
+        );
+        let mut query = Vec::new();
+        if let Some(redirect_uri) = redirect_uri {
+            query.push(("client_id", self.settings.public_client_id.as_str()));
+            query.push(("redirect_uri", redirect_uri));
+        }
+        query.extend(locale.map(|locale| ("kc_locale", locale)));
+        let endpoint = with_query(endpoint, &query);
+        self.admin_send(reqwest::Method::PUT, &endpoint, None).await
+    }
+
+    /// Emails the user a link to perform Keycloak required actions such as
+    /// `UPDATE_PASSWORD`, in `locale` as with [`Self::send_verify_email`].
+    pub async fn execute_actions_email(
+        &self,
+        id: &str,
+        actions: &[&str],
+        locale: Option<&str>,
+    ) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/execute-actions-email",
+            self.settings.users_endpoint,
+            path_segment(id)
+        );
+        let query: Vec<_> = locale
+            .map(|locale| ("kc_locale", locale))
+            .into_iter()
+            .collect();
+        let endpoint = with_query(endpoint, &query);
+        self.admin_send(
+            reqwest::Method::PUT,
+            &endpoint,
+            Some(&serde_json::json!(actions)),
+        )
+        .await
+    }
+
+    async fn invalidate_user_lookup(&self, email: &str) {
+        let key = email.trim().to_ascii_lowercase();
+        self.user_lookup_cache.lock().await.remove(&key);
+    }
+
+    /// The realm's declarative user profile configuration.
+    pub async fn user_profile(&self) -> Result<UserProfileConfig, KeycloakError> {
+        let endpoint = format!("{}/profile", self.settings.users_endpoint);
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    /// Number of users in the realm.
+    pub async fn user_count(&self) -> Result<u64, KeycloakError> {
+        let endpoint = format!("{}/count", self.settings.users_endpoint);
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    /// Active user sessions summed over every client of the realm.
+    pub async fn active_session_count(&self) -> Result<u64, KeycloakError> {
+        let stats: Vec<ClientSessionStats> = self
+            .admin_get(&self.settings.session_stats_endpoint, &[])
+            .await?;
+        Ok(stats.iter().map(ClientSessionStats::active).sum())
+    }
+
+    /// Up to `max` stored user events of the given types from `first` on,
+    /// newest first. Keycloak only returns events when the realm has "Save
+    /// events" on.
+    pub async fn user_events(
+        &self,
+        types: &[String],
+        first: usize,
+        max: usize,
+    ) -> Result<Vec<KeycloakEvent>, KeycloakError> {
+        let first = first.to_string();
+        let max = max.to_string();
+        let mut query: Vec<(&str, &str)> =
+            types.iter().map(|kind| ("type", kind.as_str())).collect();
+        query.push(("first", &first));
+        query.push(("max", &max));
+        self.admin_get(&self.settings.events_endpoint, &query).await
+    }
+
+    /// The realm role `name`, or `None` when the realm has no such role.
+    pub async fn realm_role(
+        &self,
+        name: &str,
+    ) -> Result<Option<RoleRepresentation>, KeycloakError> {
+        let endpoint = format!("{}/{}", self.settings.roles_endpoint, path_segment(name));
+        match self.admin_get(&endpoint, &[]).await {
+            Ok(role) => Ok(Some(role)),
+            Err(KeycloakError::UnexpectedStatus { status, .. })
+                if status == StatusCode::NOT_FOUND =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn create_realm_role(&self, name: &str) -> Result<(), KeycloakError> {
+        let body = serde_json::json!({ "name": name });
+        self.admin_send(
+            reqwest::Method::POST,
+            &self.settings.roles_endpoint,
+            Some(&body),
+        )
+        .await
+    }
+
+    /// Up to `max` users holding the realm role `name` directly.
+    pub async fn role_members(
+        &self,
+        name: &str,
+        max: usize,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/users",
+            self.settings.roles_endpoint,
+            path_segment(name)
+        );
+        let max = max.to_string();
+        self.admin_get(&endpoint, &[("first", "0"), ("max", &max)])
+            .await
+    }
+
+    pub async fn realm_roles(&self) -> Result<Vec<RoleSummary>, KeycloakError> {
+        self.admin_get(
+            &self.settings.roles_endpoint,
+            &[("briefRepresentation", "false")],
+        )
+        .await
+    }
+
+    /// Realm roles the composite role `name` contains directly.
+    pub async fn role_composites(
+        &self,
+        name: &str,
+    ) -> Result<Vec<RoleRepresentation>, KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/composites/realm",
+            self.settings.roles_endpoint,
+            path_segment(name)
+        );
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    /// Groups the realm role `name` is mapped to directly.
+    pub async fn role_groups(&self, name: &str) -> Result<Vec<GroupRepresentation>, KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/groups",
+            self.settings.roles_endpoint,
+            path_segment(name)
+        );
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    pub async fn group_members(
+        &self,
+        group_id: &str,
+        max: usize,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        let endpoint = format!(
+            "{}/groups/{}/members",
+            self.settings.admin_realm_endpoint(),
+            path_segment(group_id)
+        );
+        let max = max.to_string();
+        self.admin_get(&endpoint, &[("first", "0"), ("max", &max)])
+            .await
+    }
+
+    pub async fn group_children(
+        &self,
+        group_id: &str,
+    ) -> Result<Vec<GroupRepresentation>, KeycloakError> {
+        let endpoint = format!(
+            "{}/groups/{}/children",
+            self.settings.admin_realm_endpoint(),
+            path_segment(group_id)
+        );
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    pub async fn grant_realm_role(
+        &self,
+        user_id: &str,
+        role: &RoleRepresentation,
+    ) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/role-mappings/realm",
+            self.settings.users_endpoint,
+            path_segment(user_id)
+        );
+        let body = serde_json::json!([role]);
+        self.admin_send(reqwest::Method::POST, &endpoint, Some(&body))
+            .await
+    }
+
+    pub async fn user_groups(&self, id: &str) -> Result<Vec<GroupRepresentation>, KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/groups",
+            self.settings.users_endpoint,
+            path_segment(id)
+        );
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    pub async fn join_group(&self, user_id: &str, group_id: &str) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/groups/{}",
+            self.settings.users_endpoint,
+            path_segment(user_id),
+            path_segment(group_id)
+        );
+        self.admin_send(reqwest::Method::PUT, &endpoint, None).await
+    }
+
+    pub async fn leave_group(&self, user_id: &str, group_id: &str) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/groups/{}",
+            self.settings.users_endpoint,
+            path_segment(user_id),
+            path_segment(group_id)
+        );
+        self.admin_send(reqwest::Method::DELETE, &endpoint, None)
+            .await
+    }
+
+    pub async fn role_mappings(&self, user_id: &str) -> Result<RoleMappings, KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/role-mappings",
+            self.settings.users_endpoint,
+            path_segment(user_id)
+        );
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    /// Adds (`POST`) or removes (`DELETE`) realm roles, or the roles of the
+    /// client with internal id `client` when one is given.
+    pub async fn change_role_mappings(
+        &self,
+        method: reqwest::Method,
+        user_id: &str,
+        client: Option<&str>,
+        roles: &[RoleRepresentation],
+    ) -> Result<(), KeycloakError> {
+        let endpoint = match client {
+            Some(client) => format!(
+                "{}/{}/role-mappings/clients/{}",
+                self.settings.users_endpoint,
+                path_segment(user_id),
+                path_segment(client)
+            ),
+            None => format!(
+                "{}/{}/role-mappings/realm",
+                self.settings.users_endpoint,
+                path_segment(user_id)
+            ),
+        };
+        let body = serde_json::json!(roles);
+        self.admin_send(method, &endpoint, Some(&body)).await
+    }
+
+    pub async fn federated_identities(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<FederatedIdentity>, KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/federated-identity",
+            self.settings.users_endpoint,
+            path_segment(user_id)
+        );
+        self.admin_get(&endpoint, &[]).await
+    }
+
+    pub async fn link_identity(
+        &self,
+        user_id: &str,
+        identity: &FederatedIdentity,
+    ) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/federated-identity/{}",
+            self.settings.users_endpoint,
+            path_segment(user_id),
+            path_segment(&identity.identity_provider)
+        );
+        let body = serde_json::json!(identity);
+        self.admin_send(reqwest::Method::POST, &endpoint, Some(&body))
+            .await
+    }
+
+    pub async fn unlink_identity(
+        &self,
+        user_id: &str,
+        provider: &str,
+    ) -> Result<(), KeycloakError> {
+        let endpoint = format!(
+            "{}/{}/federated-identity/{}",
+            self.settings.users_endpoint,
+            path_segment(user_id),
+            path_segment(provider)
+        );
+        self.admin_send(reqwest::Method::DELETE, &endpoint, None)
+            .await
+    }
+
+    /// The realm as Keycloak's partial export writes it: settings, clients,
+    /// groups and roles, with client secrets masked. Users are never part of
+    /// a partial export.
+    pub async fn partial_export(&self) -> Result<Value, KeycloakError> {
+        let endpoint = format!("{}/partial-export", self.settings.admin_realm_endpoint());
+        self.admin_fetch(
+            reqwest::Method::POST,
+            &endpoint,
+            &[("exportClients", "true"), ("exportGroupsAndRoles", "true")],
+        )
+        .await
+    }
+
+    async fn admin_get<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, KeycloakError> {
+        self.admin_fetch(reqwest::Method::GET, endpoint, query)
+            .await
+    }
+
+    async fn admin_fetch<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, KeycloakError> {
+        let response = self
+            .admin_request(method, endpoint, query, None, None)
+            .await?;
+        Ok(admin_success(response).await?.json().await?)
+    }
+
+    async fn admin_send(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+        body: Option<&Value>,
+    ) -> Result<(), KeycloakError> {
+        let response = self
+            .admin_request(method, endpoint, &[], body, None)
+            .await?;
+        admin_success(response).await.map(drop)
+    }
+
+    /// One admin REST call, through the admin limiter and [`Self::send`],
+    /// so it is metered and recorded like every other. Under `token` when
+    /// given, as realm provisioning signed in to the master realm does;
+    /// otherwise under the held admin token, refreshed once if Keycloak
+    /// turns it away. Any status is returned as the response.
+    pub async fn admin_request(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+        query: &[(&str, &str)],
+        body: Option<&Value>,
+        token: Option<&str>,
+    ) -> Result<Response, KeycloakError> {
+        let mut attempts_remaining = 2u8;
+
+        loop {
+            let bearer = match token {
+                Some(token) => token.to_owned(),
+                None => self.ensure_token().await?,
+            };
+            let mut request = self
+                .client
+                .request(method.clone(), endpoint)
+                .bearer_auth(&bearer)
+                .query(query);
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let label = self.settings.label(endpoint);
+            let _permit = self.admin_limiter.acquire(label, &method).await?;
+            let response = self.send(label, request).await?;
+
+            let status = response.status();
+            attempts_remaining -= 1;
+            if token.is_some()
+                || attempts_remaining == 0
+                || !matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+            {
+                return Ok(response);
+            }
+            warn!(
+                "[Keycloak] Received {} from {}, refreshing token",
+                status, endpoint
+            );
+            self.clear_token().await;
+        }
+    }
+
+    /// Password grant for `admin-cli` at `token_endpoint`, the master
+    /// realm's, for provisioning realms the backend's own admin client
+    /// cannot reach.
+    pub async fn admin_cli_token(
+        &self,
+        token_endpoint: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<String, KeycloakError> {
+        let request = self.client.post(token_endpoint).form(&[
+            ("grant_type", "password"),
+            ("client_id", "admin-cli"),
+            ("username", username),
+            ("password", password),
+        ]);
+        let response = admin_success(self.send("token", request).await?).await?;
+        let payload: TokenResponse = response.json().await?;
+        Ok(payload.access_token)
+    }
+
+    /// `client_credentials` grant with the internal caller's own client,
+    /// optionally narrowed to an audience and scope.
+    pub async fn service_token(
+        &self,
+        client: &ServiceClient,
+        audience: Option<&str>,
+        scope: Option<&str>,
+    ) -> Result<CachedToken, KeycloakError> {
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", client.client_id.as_str()),
+            ("client_secret", client.client_secret.as_str()),
+        ];
+        if let Some(audience) = audience {
+            form.push(("audience", audience));
+        }
+        if let Some(scope) = scope {
+            form.push(("scope", scope));
+        }
+
+        let request = self.client.post(&self.settings.token_endpoint).form(&form);
+        let response = self.send("token", request).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(KeycloakError::UnexpectedStatus {
+                status,
+                message: body,
+            });
+        }
+
+        let payload: ServiceTokenResponse = response.json().await?;
+        Ok(CachedToken::new(
+            payload.access_token,
+            payload.token_type.unwrap_or_else(|| "Bearer".to_owned()),
+            payload.expires_in.unwrap_or(300),
+        ))
+    }
+
+    /// RFC 9126 pushed authorization request for the public client. Keycloak
+    /// validates the parameters and returns a short-lived `request_uri` that
+    /// the browser presents instead of them.
+    pub async fn push_authorization_request(
+        &self,
+        params: &[(&str, &str)],
+    ) -> Result<PushedAuthorization, KeycloakError> {
+        let mut form = vec![("client_id", self.settings.public_client_id.as_str())];
+        if let Some(secret) = &self.settings.public_client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        form.extend_from_slice(params);
+
+        let request = self.client.post(&self.settings.par_endpoint).form(&form);
+        let response = self.send("par", request).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            if status == StatusCode::BAD_REQUEST
+                && let Ok(err_payload) = serde_json::from_str::<KeycloakErrorResponse>(&body)
+            {
+                return Err(KeycloakError::InvalidGrant {
+                    error: err_payload.error,
+                    description: err_payload.error_description,
+                });
+            }
+            return Err(KeycloakError::UnexpectedStatus {
+                status,
+                message: body,
+            });
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Asks Keycloak Authorization Services whether the holder of
+    /// `access_token` may perform `scope` on `resource`, using the UMA grant
+    /// in decision mode. A denial is `Ok(false)`, not an error.
+    pub async fn uma_decision(
+        &self,
+        access_token: &str,
+        audience: &str,
+        resource: &str,
+        scope: Option<&str>,
+    ) -> Result<bool, KeycloakError> {
+        let permission = match scope {
+            Some(scope) => format!("{resource}#{scope}"),
+            None => resource.to_owned(),
+        };
+
+        let request = self
+            .client
+            .post(&self.settings.token_endpoint)
+            .bearer_auth(access_token)
+            .form(&[
+                ("grant_type", UMA_TICKET_GRANT),
+                ("audience", audience),
+                ("permission", permission.as_str()),
+                ("response_mode", "decision"),
+            ]);
+        let response = self.send("token", request).await?;
+
+        let status = response.status();
+        if status == StatusCode::FORBIDDEN || status == StatusCode::UNAUTHORIZED {
+            return Ok(false);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(KeycloakError::UnexpectedStatus {
+                status,
+                message: body,
+            });
+        }
+
+        let decision: UmaDecision = response.json().await?;
+        Ok(decision.result)
+    }
+
+    async fn clear_token(&self) {
+        let mut guard = self.state.write().await;
+        *guard = None;
+    }
+
+    /// The tenant's own client when it names one, else the public client.
+    pub fn public_client<'a>(&'a self, tenant: Option<&'a TenantConfig>) -> PublicClient<'a> {
+        match tenant.and_then(|tenant| Some((tenant, tenant.keycloak_client_id.as_deref()?))) {
+            Some((tenant, id)) => PublicClient {
+                id,
+                secret: tenant.keycloak_client_secret.as_deref(),
+            },
+            None => PublicClient {
+                id: &self.settings.public_client_id,
+                secret: self.settings.public_client_secret.as_deref(),
+            },
+        }
+    }
+
     pub async fn password_grant(
         &self,
         username: &str,
         password: &str,
+        otp: Option<&str>,
         scope: Option<&str>,
+        dpop_proof: Option<&str>,
+        client: PublicClient<'_>,
     ) -> Result<UserTokenSet, KeycloakError> {
         let mut form = vec![
             ("grant_type".to_string(), "password".to_string()),
-            (
-                "client_id".to_string(),
-                self.settings.public_client_id.clone(),
-            ),
+            ("client_id".to_string(), client.id.to_owned()),
             ("username".to_string(), username.to_owned()),
             ("password".to_string(), password.to_owned()),
         ];
 
-        if let Some(secret) = &self.settings.public_client_secret {
-            form.push(("client_secret".to_string(), secret.clone()));
+        if let Some(otp) = otp {
+            form.push(("otp".to_string(), otp.to_owned()));
+        }
+
+        if let Some(secret) = client.secret {
+            form.push(("client_secret".to_string(), secret.to_owned()));
         }
 
         if let Some(scope) = scope {
             form.push(("scope".to_string(), scope.to_owned()));
         }
 
-        let response = self
-            .client
-            .post(&self.settings.token_endpoint)
-            .form(&form)
-            .send()
-            .await?;
+        let mut request = self.client.post(&self.settings.token_endpoint).form(&form);
+        if let Some(proof) = dpop_proof {
+            request = request.header(DPOP_HEADER, proof);
+        }
+        let response = self.send("token", request).await?;
 
         self.handle_user_token_response(response).await
     }
@@ -418,53 +1532,48 @@ impl KeycloakService {
         &self,
         refresh_token: &str,
         scope: Option<&str>,
+        dpop_proof: Option<&str>,
+        client: PublicClient<'_>,
     ) -> Result<UserTokenSet, KeycloakError> {
         let mut form = vec![
             ("grant_type".to_string(), "refresh_token".to_string()),
-            (
-                "client_id".to_string(),
-                self.settings.public_client_id.clone(),
-            ),
+            ("client_id".to_string(), client.id.to_owned()),
             ("refresh_token".to_string(), refresh_token.to_owned()),
         ];
 
-        if let Some(secret) = &self.settings.public_client_secret {
-            form.push(("client_secret".to_string(), secret.clone()));
+        if let Some(secret) = client.secret {
+            form.push(("client_secret".to_string(), secret.to_owned()));
         }
 
         if let Some(scope) = scope {
             form.push(("scope".to_string(), scope.to_owned()));
         }
 
-        let response = self
-            .client
-            .post(&self.settings.token_endpoint)
-            .form(&form)
-            .send()
-            .await?;
+        let mut request = self.client.post(&self.settings.token_endpoint).form(&form);
+        if let Some(proof) = dpop_proof {
+            request = request.header(DPOP_HEADER, proof);
+        }
+        let response = self.send("token", request).await?;
 
         self.handle_user_token_response(response).await
     }
 
-    pub async fn logout_user(&self, refresh_token: &str) -> Result<(), KeycloakError> {
+    pub async fn logout_user(
+        &self,
+        refresh_token: &str,
+        client: PublicClient<'_>,
+    ) -> Result<(), KeycloakError> {
         let mut form = vec![
-            (
-                "client_id".to_string(),
-                self.settings.public_client_id.clone(),
-            ),
+            ("client_id".to_string(), client.id.to_owned()),
             ("refresh_token".to_string(), refresh_token.to_owned()),
         ];
 
-        if let Some(secret) = &self.settings.public_client_secret {
-            form.push(("client_secret".to_string(), secret.clone()));
+        if let Some(secret) = client.secret {
+            form.push(("client_secret".to_string(), secret.to_owned()));
         }
 
-        let response = self
-            .client
-            .post(&self.settings.logout_endpoint)
-            .form(&form)
-            .send()
-            .await?;
+        let request = self.client.post(&self.settings.logout_endpoint).form(&form);
+        let response = self.send("logout", request).await?;
 
         let status = response.status();
         if status.is_success() {
@@ -485,6 +1594,69 @@ impl KeycloakService {
         }
     }
 
+    /// Asks Keycloak whether a user access token is still active, using the
+    /// admin client's credentials.
+    pub async fn introspect_token(&self, token: &str) -> Result<TokenIntrospection, KeycloakError> {
+        let request = self.client.post(&self.settings.introspect_endpoint).form(&[
+            ("token", token),
+            ("client_id", self.settings.admin_client_id.as_str()),
+            ("client_secret", self.settings.admin_client_secret.as_str()),
+        ]);
+        let response = self.send("introspect", request).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(KeycloakError::UnexpectedStatus {
+                status,
+                message: body,
+            });
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Whether the configured realm is served, judged by its public metadata.
+    pub async fn realm_exists(&self) -> Result<bool, KeycloakError> {
+        let request = self.client.get(&self.settings.realm_endpoint);
+        let response = self.send("realm", request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => {
+                let body = response.text().await.unwrap_or_default();
+                Err(KeycloakError::UnexpectedStatus {
+                    status,
+                    message: body,
+                })
+            }
+        }
+    }
+
+    /// Unauthenticated reachability check against the configured health
+    /// endpoint; any 2xx answer counts as healthy.
+    pub async fn probe_health(&self) -> Result<(), KeycloakError> {
+        let response = self
+            .exchange(
+                self.client
+                    .get(&self.settings.health_endpoint)
+                    .timeout(HEALTH_PROBE_TIMEOUT),
+            )
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            let body = response.text().await.unwrap_or_default();
+            Err(KeycloakError::UnexpectedStatus {
+                status,
+                message: body,
+            })
+        }
+    }
+
     async fn handle_user_token_response(
         &self,
         response: reqwest::Response,
@@ -492,13 +1664,13 @@ impl KeycloakService {
         let status = response.status();
         if !status.is_success() {
             let body = response.text().await.unwrap_or_default();
-            if status == StatusCode::BAD_REQUEST || status == StatusCode::UNAUTHORIZED {
-                if let Ok(err_payload) = serde_json::from_str::<KeycloakErrorResponse>(&body) {
-                    return Err(KeycloakError::InvalidGrant {
-                        error: err_payload.error,
-                        description: err_payload.error_description,
-                    });
-                }
+            if (status == StatusCode::BAD_REQUEST || status == StatusCode::UNAUTHORIZED)
+                && let Ok(err_payload) = serde_json::from_str::<KeycloakErrorResponse>(&body)
+            {
+                return Err(KeycloakError::InvalidGrant {
+                    error: err_payload.error,
+                    description: err_payload.error_description,
+                });
             }
             return Err(KeycloakError::UnexpectedStatus {
                 status,
@@ -537,11 +1709,45 @@ impl KeycloakSettings {
         Self {
             token_endpoint: config.keycloak_token_endpoint(),
             logout_endpoint: config.keycloak_logout_endpoint(),
+            introspect_endpoint: config.keycloak_introspect_endpoint(),
+            par_endpoint: config.keycloak_par_endpoint(),
             users_endpoint: config.keycloak_users_endpoint(),
+            session_stats_endpoint: config.keycloak_session_stats_endpoint(),
+            sessions_endpoint: config.keycloak_sessions_endpoint(),
+            events_endpoint: config.keycloak_events_endpoint(),
+            roles_endpoint: config.keycloak_roles_endpoint(),
+            health_endpoint: config.keycloak_health_endpoint(),
+            realm_endpoint: config.keycloak_realm_endpoint(),
             admin_client_id: config.keycloak_admin_client_id.clone(),
             admin_client_secret: config.keycloak_admin_client_secret.clone(),
             public_client_id: config.keycloak_public_client_id.clone(),
             public_client_secret: config.keycloak_public_client_secret.clone(),
+            user_lookup_cache_capacity: config.user_lookup_cache_capacity,
+            user_lookup_cache_ttl: config.user_lookup_cache_ttl,
+            token_high_load_rps: config.admin_token_high_load_rps,
+            slow_call_threshold: config.keycloak_slow_call_threshold,
+            clock_skew_leeway: config.clock_skew_leeway,
         }
     }
+
+    /// `{base}/admin/realms/{realm}`, which every admin endpoint sits under.
+    fn admin_realm_endpoint(&self) -> &str {
+        self.users_endpoint
+            .strip_suffix("/users")
+            .unwrap_or(&self.users_endpoint)
+    }
+
+    /// Metric label for an admin API URL, by the resource it addresses.
+    fn label(&self, endpoint: &str) -> &'static str {
+        [
+            (&self.users_endpoint, "users"),
+            (&self.roles_endpoint, "roles"),
+            (&self.sessions_endpoint, "sessions"),
+            (&self.session_stats_endpoint, "sessions"),
+            (&self.events_endpoint, "events"),
+        ]
+        .into_iter()
+        .find(|(prefix, _)| endpoint.starts_with(prefix.as_str()))
+        .map_or("admin", |(_, label)| label)
+    }
 }
@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::AppConfig;
+use crate::keycloak::{KeycloakError, KeycloakService};
+use crate::metrics::Metrics;
+
+/// Periodically probes Keycloak so readiness can fail once the IdP has been
+/// unreachable for `KEYCLOAK_UNAVAILABLE_AFTER_SECS`, rather than on the first
+/// dropped request.
+pub struct KeycloakHealth {
+    interval: Duration,
+    unavailable_after: Duration,
+    down_since: Mutex<Option<Instant>>,
+}
+
+impl KeycloakHealth {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            interval: config.keycloak_health_interval,
+            unavailable_after: config.keycloak_unavailable_after,
+            down_since: Mutex::new(None),
+        }
+    }
+
+    pub fn is_unavailable(&self) -> bool {
+        self.down_since
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .is_some_and(|since| since.elapsed() >= self.unavailable_after)
+    }
+
+    pub fn spawn_poller(self: &Arc<Self>, keycloak: Arc<KeycloakService>, metrics: Arc<Metrics>) {
+        if self.interval.is_zero() {
+            return;
+        }
+
+        let health = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                let started = Instant::now();
+                let result = keycloak.probe_health().await;
+                let outcome = if result.is_ok() { "up" } else { "down" };
+                metrics
+                    .keycloak_health_check_seconds
+                    .with_label_values(&[outcome])
+                    .observe(started.elapsed().as_secs_f64());
+                metrics.keycloak_up.set(i64::from(result.is_ok()));
+                health.record(result);
+
+                sleep(health.interval).await;
+            }
+        });
+    }
+
+    fn record(&self, result: Result<(), KeycloakError>) {
+        let mut down_since = self
+            .down_since
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        match result {
+            Ok(()) => {
+                if let Some(since) = down_since.take() {
+                    info!(
+                        "[Keycloak] health check recovered after {}s",
+                        since.elapsed().as_secs()
+                    );
+                }
+            }
+            Err(err) => {
+                if down_since.is_none() {
+                    warn!(?err, "[Keycloak] health check failed");
+                    *down_since = Some(Instant::now());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use prometheus::IntGauge;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, oneshot};
+use tokio::time::timeout;
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::deadline;
+use crate::keycloak::KeycloakError;
+use crate::metrics::Metrics;
+
+const NEVER_CLOSED: &str = "admin semaphores are never closed";
+
+/// Who an admin API call is for, most urgent first.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Priority {
+    /// Sign-in, registration and token routes.
+    Interactive,
+    /// Any other request, such as profile reads and admin screens.
+    Profile,
+    /// Work marked with [`prioritized`]: scheduled and spawned tasks such
+    /// as backups, and bulk routes such as the realm export.
+    Background,
+}
+
+impl Priority {
+    /// The class of the running task: the one it was marked with,
+    /// `Profile` inside a request that set none, and `Interactive`
+    /// otherwise, so unmarked work (or a request without a deadline) is
+    /// never shed.
+    fn current() -> Self {
+        PRIORITY.try_with(|priority| *priority).unwrap_or_else(|_| {
+            if deadline::upstream_timeout().is_some() {
+                Self::Profile
+            } else {
+                Self::Interactive
+            }
+        })
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Profile => "profile",
+            Self::Background => "background",
+        }
+    }
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+/// Runs `future` with its Keycloak admin calls in `priority`.
+pub fn prioritized<F: Future>(priority: Priority, future: F) -> impl Future<Output = F::Output> {
+    PRIORITY.scope(priority, future)
+}
+
+/// Route layer for sign-in routes.
+pub async fn interactive(request: Request, next: Next) -> Response {
+    prioritized(Priority::Interactive, next.run(request)).await
+}
+
+/// Route layer for bulk routes.
+pub async fn background(request: Request, next: Next) -> Response {
+    prioritized(Priority::Background, next.run(request)).await
+}
+
+/// Caps the Keycloak admin API calls in flight at
+/// `KEYCLOAK_ADMIN_CONCURRENCY` (0, the default, leaves them uncapped).
+/// Calls queue per [`Priority`], endpoint and method, such as
+/// `interactive:users:GET` or `background:users:DELETE`, and one queue may
+/// hold at most half of the permits, or what `KEYCLOAK_ADMIN_QUEUE_LIMITS`
+/// gives it: `users:GET=4` for every priority, `background:users:GET=1`
+/// for one. A realm export paging through users or a purge deleting them
+/// thus waits in its own queue, not in the one sign-in lookups use, while
+/// registrations still find permits.
+///
+/// Freed permits go to the most urgent waiting [`Priority`]. Inside a
+/// request the wait counts against its deadline. Background calls are shed
+/// with [`KeycloakError::Overloaded`] rather than queued while more urgent
+/// calls are already waiting, and once they have waited
+/// `KEYCLOAK_BACKGROUND_WAIT_SECS`.
+pub struct AdminLimiter {
+    total: Option<Arc<Gate>>,
+    default_limit: usize,
+    limits: HashMap<String, usize>,
+    background_wait: Duration,
+    queues: Mutex<HashMap<String, Arc<Semaphore>>>,
+    metrics: Arc<Metrics>,
+}
+
+/// Held while the call is in flight.
+pub struct AdminPermit {
+    _queue: OwnedSemaphorePermit,
+    _total: GatePermit,
+    in_flight: IntGauge,
+}
+
+impl Drop for AdminPermit {
+    fn drop(&mut self) {
+        self.in_flight.dec();
+    }
+}
+
+impl AdminLimiter {
+    pub fn from_config(config: &AppConfig, metrics: Arc<Metrics>) -> Self {
+        let concurrency = config.keycloak_admin_concurrency;
+        Self {
+            total: (concurrency > 0).then(|| Arc::new(Gate::new(concurrency))),
+            default_limit: (concurrency / 2).max(1),
+            limits: config
+                .keycloak_admin_queue_limits
+                .iter()
+                .map(|(queue, limit)| (queue.clone(), (*limit).clamp(1, concurrency.max(1))))
+                .collect(),
+            background_wait: config.keycloak_background_wait,
+            queues: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    /// Waits for a permit for a call to `endpoint` (the metrics label) with
+    /// `method`; `None` when calls are uncapped.
+    pub async fn acquire(
+        &self,
+        endpoint: &str,
+        method: &reqwest::Method,
+    ) -> Result<Option<AdminPermit>, KeycloakError> {
+        let Some(total) = &self.total else {
+            return Ok(None);
+        };
+        let priority = Priority::current();
+        let name = format!("{}:{endpoint}:{method}", priority.label());
+        if priority == Priority::Background && total.is_contended() {
+            return Err(self.shed(&name, "more urgent calls are waiting"));
+        }
+        let queue = self.queue(&name);
+
+        let depth = self
+            .metrics
+            .keycloak_admin_queue_depth
+            .with_label_values(&[&name]);
+        depth.inc();
+        let waited = async {
+            let queued = queue.acquire_owned().await.expect(NEVER_CLOSED);
+            let permit = total.acquire(priority).await;
+            (queued, permit)
+        };
+        let budget = match priority {
+            Priority::Background => Some(
+                deadline::upstream_timeout()
+                    .map_or(self.background_wait, |left| left.min(self.background_wait)),
+            ),
+            _ => deadline::upstream_timeout(),
+        };
+        let acquired = match budget {
+            Some(budget) => timeout(budget, waited).await,
+            None => Ok(waited.await),
+        };
+        depth.dec();
+
+        match acquired {
+            Ok((queued, permit)) => {
+                let in_flight = self.metrics.keycloak_admin_in_flight.clone();
+                in_flight.inc();
+                Ok(Some(AdminPermit {
+                    _queue: queued,
+                    _total: permit,
+                    in_flight,
+                }))
+            }
+            Err(_) if priority == Priority::Background => {
+                Err(self.shed(&name, "no permit within the wait budget"))
+            }
+            Err(_) => {
+                warn!("[Keycloak] {} call timed out waiting in its queue", name);
+                Err(KeycloakError::DeadlineExceeded)
+            }
+        }
+    }
+
+    fn shed(&self, name: &str, reason: &str) -> KeycloakError {
+        warn!("[Keycloak] {} call shed: {}", name, reason);
+        self.metrics
+            .keycloak_admin_shed
+            .with_label_values(&[name])
+            .inc();
+        KeycloakError::Overloaded
+    }
+
+    fn queue(&self, name: &str) -> Arc<Semaphore> {
+        let mut queues = self.queues.lock().expect("admin queue lock poisoned");
+        queues
+            .entry(name.to_owned())
+            .or_insert_with(|| {
+                let endpoint = name.split_once(':').map_or(name, |(_, endpoint)| endpoint);
+                let limit = self
+                    .limits
+                    .get(name)
+                    .or_else(|| self.limits.get(endpoint))
+                    .copied()
+                    .unwrap_or(self.default_limit);
+                Arc::new(Semaphore::new(limit))
+            })
+            .clone()
+    }
+}
+
+/// A counting semaphore that hands freed permits to the most urgent
+/// waiter, first come first served within a priority.
+struct Gate {
+    state: Mutex<GateState>,
+}
+
+struct GateState {
+    available: usize,
+    /// One queue per [`Priority`], in its order.
+    waiting: [VecDeque<oneshot::Sender<()>>; 3],
+}
+
+struct GatePermit {
+    gate: Arc<Gate>,
+}
+
+impl Drop for GatePermit {
+    fn drop(&mut self) {
+        self.gate.release();
+    }
+}
+
+impl Gate {
+    fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(GateState {
+                available: permits,
+                waiting: Default::default(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, GateState> {
+        self.state.lock().expect("admin gate lock poisoned")
+    }
+
+    /// Whether calls other than background ones are waiting.
+    fn is_contended(&self) -> bool {
+        let state = self.lock();
+        state.waiting[..Priority::Background as usize]
+            .iter()
+            .any(|queue| queue.iter().any(|waiter| !waiter.is_closed()))
+    }
+
+    async fn acquire(self: &Arc<Self>, priority: Priority) -> GatePermit {
+        let receiver = {
+            let mut state = self.lock();
+            // Permits are only left over once every queue is empty.
+            if state.available > 0 {
+                state.available -= 1;
+                return GatePermit { gate: self.clone() };
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiting[priority as usize].push_back(sender);
+            receiver
+        };
+
+        let mut waiter = Waiter {
+            gate: self.clone(),
+            receiver,
+            granted: false,
+        };
+        (&mut waiter.receiver)
+            .await
+            .expect("admin gate waiters are answered before they are dropped");
+        waiter.granted = true;
+        GatePermit { gate: self.clone() }
+    }
+
+    /// Passes the permit on to the most urgent live waiter, or returns it.
+    fn release(&self) {
+        let mut state = self.lock();
+        for queue in state.waiting.iter_mut() {
+            while let Some(waiter) = queue.pop_front() {
+                if waiter.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
+
+/// A queued `acquire`. Dropped before the permit arrives (a timeout), it
+/// gives back a permit that was handed over in the meantime.
+struct Waiter {
+    gate: Arc<Gate>,
+    receiver: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if self.granted {
+            return;
+        }
+        self.receiver.close();
+        if self.receiver.try_recv().is_ok() {
+            self.gate.release();
+        }
+    }
+}
@@ -0,0 +1,9 @@
+//! Request-input handling that needs no configuration and no I/O, built as
+//! a library so that the fuzz targets in `fuzz/` can link against it. The
+//! service itself is the `backend` binary, which uses these modules as its
+//! own.
+
+pub mod attributes;
+pub mod email;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
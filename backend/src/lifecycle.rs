@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{Notify, watch};
+use tokio::time::{sleep, timeout};
+use tracing::{error, info, warn};
+
+/// Tracks whether the process is draining or shutting down and how many
+/// connections are still open, so listeners can stop accepting and finish
+/// in-flight requests before exit.
+pub struct Lifecycle {
+    draining: AtomicBool,
+    shutdown: watch::Sender<bool>,
+    open_connections: AtomicUsize,
+    idle: Notify,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            draining: AtomicBool::new(false),
+            shutdown,
+            open_connections: AtomicUsize::new(0),
+            idle: Notify::new(),
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Marks the service not ready, then starts shutting down once `grace`
+    /// has passed so load balancers can deregister it first. Returns `false`
+    /// when a drain is already in progress.
+    pub fn begin_drain(self: &Arc<Self>, grace: Duration) -> bool {
+        if self.draining.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+
+        info!(
+            grace_secs = grace.as_secs(),
+            "Draining: readiness is now failing"
+        );
+        let lifecycle = Arc::clone(self);
+        tokio::spawn(async move {
+            sleep(grace).await;
+            lifecycle.shutdown_now();
+        });
+        true
+    }
+
+    pub fn shutdown_now(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        if !*self.shutdown.borrow() {
+            info!("Shutting down: no longer accepting connections");
+        }
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    pub fn track_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.open_connections.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard {
+            lifecycle: Arc::clone(self),
+        }
+    }
+
+    /// Waits until every tracked connection has closed, or `limit` elapses.
+    pub async fn wait_for_connections(&self, limit: Duration) {
+        let drained = timeout(limit, async {
+            loop {
+                let idle = self.idle.notified();
+                if self.open_connections.load(Ordering::SeqCst) == 0 {
+                    break;
+                }
+                idle.await;
+            }
+        })
+        .await;
+
+        if drained.is_err() {
+            warn!(
+                open = self.open_connections.load(Ordering::SeqCst),
+                "Shutdown timeout reached with connections still open"
+            );
+        }
+    }
+
+    /// SIGUSR1 drains with the configured grace period; SIGTERM and SIGINT
+    /// shut down straight away, still letting in-flight requests finish.
+    pub fn spawn_signal_handlers(self: &Arc<Self>, grace: Duration) {
+        let lifecycle = Arc::clone(self);
+        tokio::spawn(async move {
+            let (mut usr1, mut term, mut int) = match (
+                signal(SignalKind::user_defined1()),
+                signal(SignalKind::terminate()),
+                signal(SignalKind::interrupt()),
+            ) {
+                (Ok(usr1), Ok(term), Ok(int)) => (usr1, term, int),
+                _ => {
+                    error!("Unable to install signal handlers");
+                    return;
+                }
+            };
+
+            loop {
+                tokio::select! {
+                    _ = usr1.recv() => {
+                        lifecycle.begin_drain(grace);
+                    }
+                    _ = term.recv() => lifecycle.shutdown_now(),
+                    _ = int.recv() => lifecycle.shutdown_now(),
+                }
+            }
+        });
+    }
+}
+
+pub struct ConnectionGuard {
+    lifecycle: Arc<Lifecycle>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if self
+            .lifecycle
+            .open_connections
+            .fetch_sub(1, Ordering::SeqCst)
+            == 1
+        {
+            self.lifecycle.idle.notify_waiters();
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use reqwest::Client;
+use serde_json::{Value, json};
+use tokio::sync::Semaphore;
+use tokio::time::{MissedTickBehavior, interval};
+
+use crate::MOCK_SUCCESS_TOKEN;
+
+pub const USAGE: &str = "usage: backend loadtest [--target URL] [--rps N] [--duration SECS] \
+[--scenario login,refresh,register] [--email EMAIL] [--password PASSWORD] \
+[--concurrency N] [--captcha-token TOKEN]";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Scenario {
+    Login,
+    Refresh,
+    Register,
+}
+
+impl Scenario {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "login" => Some(Self::Login),
+            "refresh" => Some(Self::Refresh),
+            "register" => Some(Self::Register),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::Refresh => "refresh",
+            Self::Register => "register",
+        }
+    }
+
+    fn path(self) -> &'static str {
+        match self {
+            Self::Login => "/api/auth/login",
+            Self::Refresh => "/api/auth/refresh",
+            Self::Register => "/api/auth/register",
+        }
+    }
+}
+
+/// `backend loadtest`: an open-loop load generator for capacity planning.
+/// Requests start at a fixed `--rps` whether or not earlier ones have
+/// answered, cycling through the `--scenario` list, so a slow portal shows
+/// up as latency rather than as a lower request rate. At most
+/// `--concurrency` are in flight; a tick that finds none free is counted as
+/// skipped. Login and refresh use `--email`/`--password` (or
+/// `LOADTEST_EMAIL`/`LOADTEST_PASSWORD`) of an existing account; register
+/// creates `loadtest-<run>-<n>@example.test` accounts that are left behind.
+/// The captcha token is the mock one, so the target must run with the dev
+/// site key or accept `mock-success`; `FORM_TOKEN_SECRET` must be unset for
+/// register.
+pub struct LoadTest {
+    target: String,
+    rps: u32,
+    duration: Duration,
+    scenarios: Vec<Scenario>,
+    email: Option<String>,
+    password: Option<String>,
+    concurrency: usize,
+    captcha_token: String,
+}
+
+impl LoadTest {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut test = Self {
+            target: "http://127.0.0.1:8000".to_owned(),
+            rps: 10,
+            duration: Duration::from_secs(30),
+            scenarios: vec![Scenario::Login],
+            email: env::var("LOADTEST_EMAIL").ok(),
+            password: env::var("LOADTEST_PASSWORD").ok(),
+            concurrency: 256,
+            captcha_token: MOCK_SUCCESS_TOKEN.to_owned(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("{flag} needs a value"))?;
+            let number = |value: &str| {
+                value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|number| *number > 0)
+                    .ok_or_else(|| format!("{flag} must be a positive number"))
+            };
+            match flag.as_str() {
+                "--target" => test.target = value.trim_end_matches('/').to_owned(),
+                "--rps" => {
+                    test.rps = u32::try_from(number(&value)?)
+                        .map_err(|_| "--rps is too large".to_owned())?;
+                }
+                "--duration" => test.duration = Duration::from_secs(number(&value)?),
+                "--scenario" => {
+                    test.scenarios = value
+                        .split(',')
+                        .map(|name| {
+                            Scenario::parse(name).ok_or_else(|| format!("unknown scenario {name}"))
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                "--email" => test.email = Some(value),
+                "--password" => test.password = Some(value),
+                "--concurrency" => test.concurrency = number(&value)? as usize,
+                "--captcha-token" => test.captcha_token = value,
+                _ => return Err(format!("unknown option {flag}")),
+            }
+        }
+
+        let needs_account = test
+            .scenarios
+            .iter()
+            .any(|scenario| *scenario != Scenario::Register);
+        if needs_account && (test.email.is_none() || test.password.is_none()) {
+            return Err("login and refresh need --email and --password".to_owned());
+        }
+        Ok(test)
+    }
+
+    pub async fn run(self) -> Result<Report, String> {
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .pool_max_idle_per_host(self.concurrency)
+            .build()
+            .map_err(|err| err.to_string())?;
+        let test = Arc::new(self);
+
+        let refresh_token = Arc::new(Mutex::new(String::new()));
+        if test.scenarios.contains(&Scenario::Refresh) {
+            let response = send(&client, &test, Scenario::Login, 0, "").await?;
+            let token = response
+                .filter(|(status, _)| status.is_success())
+                .and_then(|(_, body)| refresh_token_of(&body).map(str::to_owned))
+                .ok_or("the initial login returned no refreshToken (is the cookie mode on?)")?;
+            *refresh_token.lock().expect("refresh token lock poisoned") = token;
+        }
+
+        let stats = Arc::new(Mutex::new(
+            test.scenarios
+                .iter()
+                .map(|scenario| (*scenario, Stats::default()))
+                .collect::<Vec<_>>(),
+        ));
+        let permits = Arc::new(Semaphore::new(test.concurrency));
+        let run = rand::thread_rng().r#gen::<u32>();
+        let mut ticks = interval(Duration::from_secs(1) / test.rps);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+        println!(
+            "loadtest: {} req/s of {} against {} for {}s",
+            test.rps,
+            test.scenarios
+                .iter()
+                .map(|scenario| scenario.name())
+                .collect::<Vec<_>>()
+                .join(","),
+            test.target,
+            test.duration.as_secs()
+        );
+        let started = Instant::now();
+        let mut tasks = Vec::new();
+        let mut skipped = 0;
+        let mut sequence = 0u64;
+        while started.elapsed() < test.duration {
+            ticks.tick().await;
+            let Ok(permit) = permits.clone().try_acquire_owned() else {
+                skipped += 1;
+                continue;
+            };
+            let index = (sequence % test.scenarios.len() as u64) as usize;
+            let scenario = test.scenarios[index];
+            sequence += 1;
+
+            let (client, test, stats, refresh_token) = (
+                client.clone(),
+                test.clone(),
+                stats.clone(),
+                refresh_token.clone(),
+            );
+            let email = format!("loadtest-{run:08x}-{sequence}@example.test");
+            tasks.push(tokio::spawn(async move {
+                let token = refresh_token
+                    .lock()
+                    .expect("refresh token lock poisoned")
+                    .clone();
+                let identity = if scenario == Scenario::Register {
+                    email.as_str()
+                } else {
+                    token.as_str()
+                };
+                let begun = Instant::now();
+                let outcome = send(&client, &test, scenario, sequence, identity).await;
+                let latency = begun.elapsed();
+                drop(permit);
+
+                if let Ok(Some((status, body))) = &outcome
+                    && scenario == Scenario::Refresh
+                    && status.is_success()
+                    && let Some(rotated) = refresh_token_of(body)
+                {
+                    *refresh_token.lock().expect("refresh token lock poisoned") =
+                        rotated.to_owned();
+                }
+                let mut stats = stats.lock().expect("stats lock poisoned");
+                stats[index]
+                    .1
+                    .record(latency, outcome.ok().flatten().map(|(status, _)| status));
+            }));
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+
+        let elapsed = started.elapsed();
+        let stats = Arc::try_unwrap(stats)
+            .map_err(|_| "requests still running".to_owned())?
+            .into_inner()
+            .expect("stats lock poisoned");
+        Ok(Report {
+            elapsed,
+            skipped,
+            stats,
+        })
+    }
+}
+
+/// One request; `identity` is the refresh token for refresh and the email
+/// for register. `Ok(None)` when no answer arrived.
+async fn send(
+    client: &Client,
+    test: &LoadTest,
+    scenario: Scenario,
+    sequence: u64,
+    identity: &str,
+) -> Result<Option<(reqwest::StatusCode, Value)>, String> {
+    let body = match scenario {
+        Scenario::Login => json!({
+            "email": test.email,
+            "password": test.password,
+            "captchaToken": test.captcha_token,
+        }),
+        Scenario::Refresh => json!({ "refreshToken": identity }),
+        Scenario::Register => json!({
+            "email": identity,
+            "password": test
+                .password
+                .clone()
+                .unwrap_or_else(|| format!("Load-{sequence:x}-{:08x}!", rand::random::<u32>())),
+            "firstName": "Load",
+            "lastName": "Test",
+            "captchaToken": test.captcha_token,
+        }),
+    };
+    let response = client
+        .post(format!("{}{}", test.target, scenario.path()))
+        .json(&body)
+        .send()
+        .await;
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            let body = response.json().await.unwrap_or(Value::Null);
+            Ok(Some((status, body)))
+        }
+        Err(err) if sequence == 0 => Err(format!("{}: {err}", test.target)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Login answers carry the tokens under `tokens`, refresh answers at the
+/// top level.
+fn refresh_token_of(body: &Value) -> Option<&str> {
+    body.get("tokens")
+        .unwrap_or(body)
+        .get("refreshToken")?
+        .as_str()
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    ok: usize,
+    client_errors: usize,
+    server_errors: usize,
+    /// Timeouts and connection errors.
+    failures: usize,
+}
+
+impl Stats {
+    fn record(&mut self, latency: Duration, status: Option<reqwest::StatusCode>) {
+        match status {
+            Some(status) if status.is_success() => self.ok += 1,
+            Some(status) if status.is_client_error() => self.client_errors += 1,
+            Some(_) => self.server_errors += 1,
+            None => {
+                self.failures += 1;
+                return;
+            }
+        }
+        self.latencies.push(latency);
+    }
+}
+
+/// Nearest-rank percentile of sorted latencies.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+pub struct Report {
+    elapsed: Duration,
+    skipped: usize,
+    stats: Vec<(Scenario, Stats)>,
+}
+
+impl Report {
+    pub fn print(&self) {
+        println!(
+            "{:<9} {:>8} {:>8} {:>6} {:>6} {:>7} {:>9} {:>9} {:>9} {:>9}",
+            "scenario",
+            "requests",
+            "2xx",
+            "4xx",
+            "5xx",
+            "failed",
+            "p50 ms",
+            "p90 ms",
+            "p99 ms",
+            "max ms"
+        );
+        let mut total = 0;
+        for (scenario, stats) in &self.stats {
+            let mut sorted = stats.latencies.clone();
+            sorted.sort();
+            let requests = stats.ok + stats.client_errors + stats.server_errors + stats.failures;
+            total += requests;
+            let millis = |latency: Duration| format!("{:.1}", latency.as_secs_f64() * 1000.0);
+            println!(
+                "{:<9} {:>8} {:>8} {:>6} {:>6} {:>7} {:>9} {:>9} {:>9} {:>9}",
+                scenario.name(),
+                requests,
+                stats.ok,
+                stats.client_errors,
+                stats.server_errors,
+                stats.failures,
+                millis(percentile(&sorted, 50)),
+                millis(percentile(&sorted, 90)),
+                millis(percentile(&sorted, 99)),
+                millis(sorted.last().copied().unwrap_or_default()),
+            );
+        }
+        println!(
+            "{} requests in {:.1}s ({:.1} req/s), {} skipped at the concurrency limit",
+            total,
+            self.elapsed.as_secs_f64(),
+            total as f64 / self.elapsed.as_secs_f64(),
+            self.skipped
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use axum::http::{HeaderMap, header::ACCEPT_LANGUAGE};
+
+use crate::preferences::is_language_tag;
+use crate::verified_redirect::accept_language;
+
+/// More languages than this are of no use to Keycloak's fallback.
+const MAX_LOCALES: usize = 5;
+
+/// The request's `Accept-Language` value.
+pub fn header(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// The languages to ask Keycloak for, most preferred first: the `chosen`
+/// ones (the user's stored `locale`, or a client's space separated
+/// `uiLocales`), then the browser's `Accept-Language`. Keycloak shows its
+/// login, reset and verification screens and emails in the first one the
+/// realm supports. Malformed tags and repeats are dropped.
+pub fn preferred(chosen: Option<&str>, accepted: Option<&str>) -> Vec<String> {
+    let mut locales: Vec<String> = Vec::new();
+    for tag in chosen
+        .into_iter()
+        .flat_map(str::split_whitespace)
+        .chain(accepted.into_iter().flat_map(accept_language))
+    {
+        let tag = tag.replace('_', "-");
+        if is_language_tag(&tag) && !locales.iter().any(|known| known.eq_ignore_ascii_case(&tag)) {
+            locales.push(tag);
+        }
+        if locales.len() == MAX_LOCALES {
+            break;
+        }
+    }
+    locales
+}
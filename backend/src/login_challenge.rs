@@ -0,0 +1,332 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::keycloak::{KeycloakService, PublicClient, UserTokenSet};
+use crate::session::SessionStore;
+
+const NONCE_LEN: usize = 12;
+const RESEND_COOLDOWN: Duration = Duration::from_secs(60);
+/// One-time code attempts a user gets per `LOGIN_CHALLENGE_TTL_SECS`.
+const MFA_MAX_ATTEMPTS: usize = 5;
+
+/// Why a login stopped short of returning tokens.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeKind {
+    /// The password was right but the account has an OTP credential;
+    /// continue with the password and a one-time code. Only raised with
+    /// `MFA_CHECK_CLIENT_ID` set, since that is what proves the password.
+    MfaRequired,
+    /// Change the password at `actionUrl`, then continue with the new one.
+    PasswordExpired,
+    /// `TERMS_VERSION` is newer than what the user accepted; accept it
+    /// through `/api/auth/login/accept-terms`.
+    TermsUpdateRequired,
+    /// `UNVERIFIED_LOGIN=block`; continue once the address is verified,
+    /// after resending the verification email if needed.
+    EmailUnverified,
+}
+
+impl ChallengeKind {
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::MfaRequired => "A one-time code is required",
+            Self::PasswordExpired => "Password has expired",
+            Self::TermsUpdateRequired => "The updated terms of service must be accepted",
+            Self::EmailUnverified => "Email address is not verified",
+        }
+    }
+}
+
+/// A login waiting on a challenge. When the password grant already
+/// succeeded the tokens are held here and only released once the remaining
+/// checks pass; without them the continuation has to repeat the grant.
+#[derive(Debug, Clone)]
+pub struct PendingLogin {
+    pub email: String,
+    pub kind: ChallengeKind,
+    pub tokens: Option<UserTokenSet>,
+    /// What the grant asks for when it is repeated.
+    pub scope: Option<String>,
+}
+
+/// A pending login as kept in the store, with its tokens sealed under a key
+/// only the continuation token yields.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredLogin {
+    email: String,
+    kind: ChallengeKind,
+    #[serde(default)]
+    sealed_tokens: Option<String>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+/// Pending logins, kept in the session store under the hash of their
+/// continuation token for `LOGIN_CHALLENGE_TTL_SECS`. A continuation token
+/// can be used once. Held tokens are encrypted with a key derived from the
+/// continuation token, which the store never sees, so its contents alone
+/// do not hand out sessions.
+pub struct LoginChallenges {
+    store: Arc<dyn SessionStore>,
+    keycloak: Arc<KeycloakService>,
+    ttl: Duration,
+    check_client: Option<(String, Option<String>)>,
+}
+
+impl LoginChallenges {
+    pub fn from_config(
+        config: &AppConfig,
+        store: Arc<dyn SessionStore>,
+        keycloak: Arc<KeycloakService>,
+    ) -> Self {
+        Self {
+            store,
+            keycloak,
+            ttl: config.login_challenge_ttl,
+            check_client: config
+                .mfa_check_client_id
+                .clone()
+                .map(|id| (id, config.mfa_check_client_secret.clone())),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Stores the pending login and returns its continuation token, or
+    /// `None` when the session store is unavailable.
+    pub async fn issue(&self, pending: &PendingLogin) -> Option<String> {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+
+        let sealed_tokens = match &pending.tokens {
+            Some(tokens) => Some(seal_tokens(&token, tokens)?),
+            None => None,
+        };
+        let stored = StoredLogin {
+            email: pending.email.clone(),
+            kind: pending.kind,
+            sealed_tokens,
+            scope: pending.scope.clone(),
+        };
+        let encoded = serde_json::to_string(&stored).ok()?;
+        match self
+            .store
+            .set(&challenge_key(&token), &encoded, self.ttl)
+            .await
+        {
+            Ok(()) => Some(token),
+            Err(err) => {
+                warn!("[Login] unable to store login challenge: {}", err);
+                None
+            }
+        }
+    }
+
+    pub async fn take(&self, token: &str) -> Option<PendingLogin> {
+        let encoded = self
+            .store
+            .take(&challenge_key(token.trim()))
+            .await
+            .inspect_err(|err| warn!("[Login] unable to load login challenge: {}", err))
+            .ok()??;
+        open_login(token.trim(), &encoded)
+    }
+
+    /// Reads a pending login without using up its continuation token.
+    pub async fn peek(&self, token: &str) -> Option<PendingLogin> {
+        let encoded = self
+            .store
+            .get(&challenge_key(token.trim()))
+            .await
+            .inspect_err(|err| warn!("[Login] unable to load login challenge: {}", err))
+            .ok()??;
+        open_login(token.trim(), &encoded)
+    }
+
+    /// Claims the one resend of the verification email allowed per user
+    /// each minute.
+    pub async fn claim_resend(&self, email: &str) -> bool {
+        let key = format!(
+            "verify-email-resend:{}",
+            hex::encode(Sha256::digest(email.as_bytes()))
+        );
+        self.store
+            .set_if_absent(&key, "1", RESEND_COOLDOWN)
+            .await
+            .inspect_err(|err| warn!("[Login] unable to record verification resend: {}", err))
+            .unwrap_or(false)
+    }
+
+    /// Claims one of the `MFA_MAX_ATTEMPTS` one-time code attempts the user
+    /// has per challenge lifetime. Each attempt takes its own slot, so
+    /// concurrent continuations cannot share one.
+    pub async fn claim_mfa_attempt(&self, email: &str) -> bool {
+        let user = hex::encode(Sha256::digest(email.as_bytes()));
+        for slot in 0..MFA_MAX_ATTEMPTS {
+            let key = format!("mfa-attempt:{user}:{slot}");
+            match self.store.set_if_absent(&key, "1", self.ttl).await {
+                Ok(true) => return true,
+                Ok(false) => continue,
+                Err(err) => {
+                    warn!("[Login] unable to record MFA attempt: {}", err);
+                    return false;
+                }
+            }
+        }
+        false
+    }
+
+    /// Whether `password` is the user's password, judged by a password
+    /// grant under `MFA_CHECK_CLIENT_ID`, whose direct grant flow must not
+    /// ask for the one-time code. The session it opens is ended straight
+    /// away. Always `false` without that client.
+    pub async fn password_matches(&self, email: &str, password: &str) -> bool {
+        let Some((id, secret)) = &self.check_client else {
+            return false;
+        };
+        let client = PublicClient {
+            id,
+            secret: secret.as_deref(),
+        };
+        let tokens = match self
+            .keycloak
+            .password_grant(email, password, None, None, None, client)
+            .await
+        {
+            Ok(tokens) => tokens,
+            Err(_) => return false,
+        };
+        if let Err(err) = self
+            .keycloak
+            .logout_user(&tokens.refresh_token, client)
+            .await
+        {
+            warn!("[Login] unable to end password check session: {}", err);
+        }
+        true
+    }
+
+    /// Whether a password can be proven without the one-time code, which
+    /// `mfa_required` challenges depend on.
+    pub fn checks_passwords(&self) -> bool {
+        self.check_client.is_some()
+    }
+
+    /// What a failed password grant may reveal about the account, from one
+    /// user and one credential lookup. Keycloak answers a direct grant
+    /// without the code the same way as a wrong password, so `otp` is what
+    /// tells the two apart. Identity providers are only fetched with
+    /// `social` set and when the account has no password. Unknown emails
+    /// and failed lookups reveal nothing.
+    pub async fn inspect(&self, email: &str, social: bool) -> AccountFacts {
+        let Ok(users) = self.keycloak.find_users_by_email(email).await else {
+            return AccountFacts::default();
+        };
+        let [user] = users.as_slice() else {
+            return AccountFacts::default();
+        };
+        let credentials = match self.keycloak.user_credentials(&user.id).await {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                warn!("[Login] credential lookup failed for {}: {}", email, err);
+                return AccountFacts::default();
+            }
+        };
+        let has = |kind: &str| credentials.iter().any(|credential| credential.kind == kind);
+        let otp = has("otp");
+        if !social || has("password") {
+            return AccountFacts {
+                otp,
+                social_providers: None,
+            };
+        }
+
+        let providers: Vec<String> = match self.keycloak.federated_identities(&user.id).await {
+            Ok(identities) => identities
+                .into_iter()
+                .map(|identity| identity.identity_provider)
+                .collect(),
+            Err(err) => {
+                warn!("[Login] identity lookup failed for {}: {}", email, err);
+                Vec::new()
+            }
+        };
+        AccountFacts {
+            otp,
+            social_providers: (!providers.is_empty()).then_some(providers),
+        }
+    }
+}
+
+/// See [`LoginChallenges::inspect`].
+#[derive(Debug, Default)]
+pub struct AccountFacts {
+    /// The account has an OTP credential.
+    pub otp: bool,
+    /// The identity providers of an account without a password, so the
+    /// password grant can never succeed for it.
+    pub social_providers: Option<Vec<String>>,
+}
+
+fn open_login(token: &str, encoded: &str) -> Option<PendingLogin> {
+    let stored: StoredLogin = serde_json::from_str(encoded).ok()?;
+    let tokens = match &stored.sealed_tokens {
+        Some(sealed) => Some(open_tokens(token, sealed)?),
+        None => None,
+    };
+    Some(PendingLogin {
+        email: stored.email,
+        kind: stored.kind,
+        tokens,
+        scope: stored.scope,
+    })
+}
+
+fn token_cipher(token: &str) -> Aes256Gcm {
+    let key = Sha256::digest(format!("login-challenge-tokens:{token}").as_bytes());
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+fn seal_tokens(token: &str, tokens: &UserTokenSet) -> Option<String> {
+    let plaintext = serde_json::to_vec(tokens).ok()?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = token_cipher(token)
+        .encrypt(&nonce, plaintext.as_slice())
+        .ok()?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Some(URL_SAFE_NO_PAD.encode(sealed))
+}
+
+fn open_tokens(token: &str, sealed: &str) -> Option<UserTokenSet> {
+    let bytes = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+    if bytes.len() <= NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let plaintext = token_cipher(token)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .ok()?;
+    serde_json::from_slice(&plaintext).ok()
+}
+
+fn challenge_key(token: &str) -> String {
+    format!(
+        "login-challenge:{}",
+        hex::encode(Sha256::digest(token.as_bytes()))
+    )
+}
@@ -0,0 +1,122 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::audit::AuditOutcome;
+use crate::session::SessionStore;
+use crate::util::unix_now;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginAttempt {
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    pub outcome: AuditOutcome,
+}
+
+/// Longest user agent kept with an attempt.
+const USER_AGENT_MAX: usize = 256;
+
+/// Per-user record of login attempts, newest first, kept in the session
+/// store so it is shared between instances when that store is Redis. Entries
+/// older than `LOGIN_HISTORY_RETENTION_SECS` are dropped and at most
+/// `LOGIN_HISTORY_PER_USER` are kept for each user.
+///
+/// A history is started by a successful sign-in; failures are only added to
+/// one that exists, so guessing at made-up emails cannot fill the store.
+pub struct LoginHistory {
+    store: Arc<dyn SessionStore>,
+    retention: Duration,
+    per_user: usize,
+    write_lock: Mutex<()>,
+}
+
+impl LoginHistory {
+    pub fn from_config(config: &AppConfig, store: Arc<dyn SessionStore>) -> Self {
+        Self {
+            store,
+            retention: config.login_history_retention,
+            per_user: config.login_history_per_user,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_user > 0 && !self.retention.is_zero()
+    }
+
+    pub async fn record(
+        &self,
+        user: &str,
+        outcome: AuditOutcome,
+        ip: Option<IpAddr>,
+        user_agent: Option<&str>,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let attempt = LoginAttempt {
+            timestamp: unix_now(),
+            ip: ip.map(|ip| ip.to_string()),
+            user_agent: user_agent.map(|agent| agent.chars().take(USER_AGENT_MAX).collect()),
+            outcome,
+        };
+
+        let _guard = self.write_lock.lock().await;
+        let key = history_key(user);
+        let Some(mut attempts) = self.load(&key).await else {
+            return;
+        };
+        if attempts.is_empty() && outcome != AuditOutcome::Success {
+            return;
+        }
+        attempts.insert(0, attempt);
+        attempts.truncate(self.per_user);
+
+        let encoded = serde_json::to_string(&attempts).unwrap_or_default();
+        if let Err(err) = self.store.set(&key, &encoded, self.retention).await {
+            warn!("[LoginHistory] unable to record attempt: {}", err);
+        }
+    }
+
+    pub async fn recent(&self, user: &str) -> Vec<LoginAttempt> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        self.load(&history_key(user)).await.unwrap_or_default()
+    }
+
+    /// `None` when the store cannot be read.
+    async fn load(&self, key: &str) -> Option<Vec<LoginAttempt>> {
+        let stored = match self.store.get(key).await {
+            Ok(stored) => stored,
+            Err(err) => {
+                warn!("[LoginHistory] unable to load history: {}", err);
+                return None;
+            }
+        };
+
+        let cutoff = unix_now().saturating_sub(self.retention.as_secs());
+        let mut attempts: Vec<LoginAttempt> = stored
+            .and_then(|value| serde_json::from_str(&value).ok())
+            .unwrap_or_default();
+        attempts.retain(|attempt| attempt.timestamp >= cutoff);
+        Some(attempts)
+    }
+}
+
+/// Emails are hashed so they do not appear in store keys.
+fn history_key(user: &str) -> String {
+    let digest = Sha256::digest(user.trim().to_lowercase().as_bytes());
+    format!("login-history:{}", hex::encode(digest))
+}
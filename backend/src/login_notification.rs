@@ -0,0 +1,190 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::Mac;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::AppConfig;
+use crate::keycloak::KeycloakService;
+use crate::preferences;
+use crate::util::{HmacSha256, hmac_sha256, unix_now};
+use crate::webhooks::WebhookService;
+
+/// `"true"` or `"false"` on the account, set through the preferences API;
+/// accounts without it follow `LOGIN_NOTIFICATIONS_DEFAULT`.
+pub const LOGIN_NOTIFICATIONS_ATTRIBUTE: &str = "loginNotifications";
+
+/// Template name sent with the event so the mail integration renders the
+/// right message in the user's `locale`.
+const TEMPLATE: &str = "login_notification";
+
+/// What is known about a successful login at the time it happened.
+pub struct LoginDetails {
+    pub email: String,
+    pub ip: IpAddr,
+    pub user_agent: Option<String>,
+    pub location: Option<String>,
+}
+
+/// "New login to your account" notices. The portal does not send mail: each
+/// login of a user who wants notices is sent as a `user.login_notification`
+/// event to the mail integration alone (never to webhook subscriptions), with
+/// the device, address, location and a "this wasn't me" link to
+/// `LOGIN_NOTIFICATION_URL`. Following the link ends every session of the
+/// account and starts a password reset. Tokens are
+/// `<b64url user id>.<expiry>.<b64url HMAC>` under
+/// `LOGIN_NOTIFICATION_SECRET`, which the configuration requires whenever
+/// notices are on.
+pub struct LoginNotifications {
+    keycloak: Arc<KeycloakService>,
+    webhooks: Arc<WebhookService>,
+    secret: Vec<u8>,
+    ttl: Duration,
+    url: Option<String>,
+    default_enabled: bool,
+}
+
+impl LoginNotifications {
+    pub fn from_config(
+        config: &AppConfig,
+        keycloak: Arc<KeycloakService>,
+        webhooks: Arc<WebhookService>,
+    ) -> Self {
+        let secret = config
+            .login_notification_secret
+            .clone()
+            .unwrap_or_default()
+            .into_bytes();
+
+        Self {
+            keycloak,
+            webhooks,
+            secret,
+            ttl: config.login_notification_link_ttl,
+            url: config.login_notification_url.clone(),
+            default_enabled: config.login_notifications_default,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    /// Publishes the notice if the user wants one. Lookup failures are only
+    /// logged; a missed notice never fails the login.
+    pub async fn notify(&self, login: LoginDetails) {
+        let Some(base) = self.url.as_deref() else {
+            return;
+        };
+        let users = match self.keycloak.find_users_by_email(&login.email).await {
+            Ok(users) => users,
+            Err(err) => {
+                warn!(
+                    "[LoginNotification] user lookup failed for {}: {}",
+                    login.email, err
+                );
+                return;
+            }
+        };
+        let [user] = users.as_slice() else {
+            return;
+        };
+        let preferences = preferences::read(user, self.default_enabled);
+        if !preferences.login_alerts {
+            return;
+        }
+
+        let expires_at = unix_now() + self.ttl.as_secs();
+        let token = format!(
+            "{}.{expires_at}.{}",
+            URL_SAFE_NO_PAD.encode(user.id.as_bytes()),
+            URL_SAFE_NO_PAD.encode(self.sign(&user.id, expires_at)),
+        );
+        let separator = if base.contains('?') { '&' } else { '?' };
+
+        info!("[LoginNotification] user={} notified", login.email);
+        self.webhooks.send_mail(
+            "user.login_notification",
+            json!({
+                "userId": user.id,
+                "email": user.email,
+                "template": TEMPLATE,
+                "locale": preferences.locale,
+                "timezone": preferences.timezone,
+                "loginAt": unix_now(),
+                "ipAddress": login.ip.to_string(),
+                "userAgent": login.user_agent,
+                "device": login.user_agent.as_deref().map(device_summary),
+                "location": login.location,
+                "notMeUrl": format!("{base}{separator}token={token}"),
+                "expiresAt": expires_at,
+            }),
+        );
+    }
+
+    /// The user a "this wasn't me" token was issued to, if it is genuine and
+    /// not expired.
+    pub fn verify(&self, token: &str) -> Option<String> {
+        let mut parts = token.trim().splitn(3, '.');
+        let user_id = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+        let user_id = String::from_utf8(user_id).ok()?;
+        let expires_at: u64 = parts.next()?.parse().ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+        if expires_at < unix_now() {
+            return None;
+        }
+        self.mac(&user_id, expires_at)
+            .verify_slice(&signature)
+            .ok()
+            .map(|_| user_id)
+    }
+
+    fn sign(&self, user_id: &str, expires_at: u64) -> Vec<u8> {
+        self.mac(user_id, expires_at)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    fn mac(&self, user_id: &str, expires_at: u64) -> HmacSha256 {
+        let mut mac = hmac_sha256(&self.secret);
+        mac.update(format!("login-notification\n{user_id}\n{expires_at}").as_bytes());
+        mac
+    }
+}
+
+/// "Firefox on Windows" and the like, for a line in the email; the full
+/// `userAgent` is sent alongside.
+fn device_summary(user_agent: &str) -> String {
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map(|(_, name)| name);
+    let os = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map(|(_, name)| name);
+
+    match (browser, os) {
+        (Some(browser), Some(os)) => format!("{browser} on {os}"),
+        (Some(name), None) | (None, Some(name)) => name.to_owned(),
+        (None, None) => "Unknown device".to_owned(),
+    }
+}
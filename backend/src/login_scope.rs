@@ -0,0 +1,55 @@
+use crate::AppConfig;
+use crate::tenant::TenantConfig;
+
+/// Part of every login: without it Keycloak issues no ID token.
+pub const OPENID: &str = "openid";
+
+/// A scope list as in `AUTH_SCOPES=openid profile email`; commas work too.
+pub fn parse(value: &str) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|scope| !scope.is_empty())
+    {
+        if !scopes.iter().any(|known| known == scope) {
+            scopes.push(scope.to_owned());
+        }
+    }
+    scopes
+}
+
+/// What logins may ask Keycloak for: the tenant's `scopes`, or
+/// `AUTH_SCOPES` (`openid` by default).
+pub fn allowed<'a>(config: &'a AppConfig, tenant: Option<&'a TenantConfig>) -> &'a [String] {
+    tenant
+        .and_then(|tenant| tenant.scopes.as_deref())
+        .unwrap_or(&config.auth_scopes)
+}
+
+/// The `scope` to send with a grant. Clients may name a subset of
+/// `allowed`, space separated as in RFC 6749; without one they get all of
+/// it. `openid` is always included. Scopes outside `allowed` are returned
+/// as the error.
+pub fn resolve(requested: Option<&str>, allowed: &[String]) -> Result<String, Vec<String>> {
+    let requested = requested
+        .map(parse)
+        .filter(|scopes| !scopes.is_empty())
+        .unwrap_or_else(|| allowed.to_vec());
+    let unknown: Vec<String> = requested
+        .iter()
+        .filter(|scope| *scope != OPENID && !allowed.contains(scope))
+        .cloned()
+        .collect();
+    if !unknown.is_empty() {
+        return Err(unknown);
+    }
+
+    let mut scopes = vec![OPENID];
+    scopes.extend(
+        requested
+            .iter()
+            .map(String::as_str)
+            .filter(|scope| *scope != OPENID),
+    );
+    Ok(scopes.join(" "))
+}
@@ -1,21 +1,161 @@
 use std::env;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::Router;
+use axum::{Router, http::HeaderName};
+use backend::{attributes, email};
 use dotenvy::dotenv;
+use ipnet::IpNet;
 use reqwest::Client;
+use serde::Serialize;
 use tracing::{error, info};
 use tracing_subscriber::{EnvFilter, fmt};
 
+mod abac;
+mod account_merge;
+mod admin;
+mod altcha;
+mod anomaly;
+mod api_keys;
+mod api_version;
+mod audit;
+mod avatar;
+mod body_logging;
+mod bootstrap;
+mod bot_trap;
+mod cache;
 mod captcha;
+mod captcha_exemption;
+#[cfg(any(test, feature = "cassette"))]
+mod cassette;
+mod cli;
+mod client_ip;
+mod client_version;
+mod clock_skew;
+mod config_snapshot;
+mod cookies;
+mod correlation;
+mod deadline;
+mod doctor;
+mod dpop;
+mod email_verification;
+mod error_codes;
+mod event_bridge;
+mod extract;
 mod handlers;
+mod initial_admin;
+mod internal;
+mod introspection_cache;
+mod ip_filter;
+mod ip_reputation;
+mod journal;
 mod keycloak;
+mod keycloak_health;
+mod keycloak_limiter;
+mod lifecycle;
+mod loadtest;
+mod locale;
+mod login_challenge;
+mod login_history;
+mod login_notification;
+mod login_scope;
+mod metrics;
+mod metrics_push;
 mod models;
+mod outbox;
+mod partner;
+mod password_expiry;
+mod permissions;
+mod preferences;
+mod rate_limit;
+mod reactivation;
+mod read_only;
+mod realm_admin;
+mod realm_backup;
+mod realm_diff;
+mod realm_spec;
+mod referral;
+mod refresh_hint;
+mod registration_schema;
+mod reload;
+mod request_signing;
+mod response_cache;
+mod revocation;
+mod role_permissions;
 mod routes;
+mod s3;
+mod scope;
+mod server;
+mod session;
+mod session_limit;
+mod slo;
+mod storage;
+mod systemd;
+mod tenant;
+mod terms;
+mod token_cache;
+mod user_profile;
+mod username;
+mod util;
+mod validation;
+mod verified_redirect;
+mod webhooks;
 
+use altcha::AltchaService;
+use anomaly::AnomalyDetector;
+use api_keys::ApiKeys;
+use audit::AuditLog;
+use body_logging::BodyLogger;
+use bot_trap::BotTrap;
+use captcha::CaptchaProvider;
+use captcha_exemption::CaptchaExemptions;
+#[cfg(any(test, feature = "cassette"))]
+use cassette::CassetteMode;
+use config_snapshot::ConfigSnapshots;
+use cookies::CookieKeys;
+use correlation::{DEFAULT_CORRELATION_HEADERS, parse_header_names};
+use dpop::DpopVerifier;
+use email::AliasRules;
+use email_verification::{EmailVerification, UnverifiedLogin};
+use internal::{ServiceClient, ServiceTokenKey, load_service_clients};
+use introspection_cache::IntrospectionCache;
+use ip_filter::IpFilter;
+use ip_reputation::IpReputation;
 use keycloak::KeycloakService;
-use routes::create_router;
+use keycloak_health::KeycloakHealth;
+use lifecycle::Lifecycle;
+use login_challenge::LoginChallenges;
+use login_history::LoginHistory;
+use login_notification::LoginNotifications;
+use metrics::Metrics;
+use outbox::Outbox;
+use partner::PartnerRegistry;
+use password_expiry::PasswordExpiry;
+use permissions::PermissionService;
+use rate_limit::RateLimits;
+use reactivation::ReactivationLinks;
+use read_only::ReadOnlyMode;
+use realm_backup::RealmBackups;
+use referral::ReferralService;
+use registration_schema::{AttributeSpec, load_attributes};
+use reload::LiveConfig;
+use request_signing::RequestSigner;
+use response_cache::ResponseCache;
+use revocation::RevocationBus;
+use role_permissions::RolePermissions;
+use routes::{create_admin_router, create_internal_router, create_router};
+use server::{InternalTlsSettings, Listener, Protocols, public_tls, serve, serve_internal_tls};
+use session::{SessionBackend, SessionStore};
+use session_limit::{SessionLimitPolicy, SessionLimits};
+use slo::{SloTarget, SloTracker};
+use storage::Storage;
+use tenant::{TenantConfig, load_tenants};
+use terms::Terms;
+use token_cache::TokenCache;
+use user_profile::UserProfileService;
+use username::UsernameMode;
+use webhooks::WebhookService;
 
 pub const DEV_MOCK_SITE_KEY: &str = "dev-mock";
 pub const MOCK_SUCCESS_TOKEN: &str = "mock-success";
@@ -25,25 +165,191 @@ pub struct AppState {
     pub config: AppConfig,
     pub http_client: Client,
     pub keycloak: Arc<KeycloakService>,
+    pub altcha: Option<Arc<AltchaService>>,
+    pub metrics: Arc<Metrics>,
+    pub bot_trap: Arc<BotTrap>,
+    pub partners: Arc<PartnerRegistry>,
+    pub referrals: Arc<ReferralService>,
+    pub audit: Arc<AuditLog>,
+    pub anomalies: Arc<AnomalyDetector>,
+    pub ip_filter: Arc<IpFilter>,
+    pub lifecycle: Arc<Lifecycle>,
+    pub keycloak_health: Arc<KeycloakHealth>,
+    pub service_tokens: Arc<TokenCache<ServiceTokenKey>>,
+    pub permissions: Arc<PermissionService>,
+    pub dpop: Arc<DpopVerifier>,
+    pub sessions: Arc<dyn SessionStore>,
+    pub cookies: Arc<CookieKeys>,
+    pub response_cache: Arc<ResponseCache>,
+    pub login_history: Arc<LoginHistory>,
+    pub user_profile: Arc<UserProfileService>,
+    pub webhooks: Arc<WebhookService>,
+    pub revocations: Arc<RevocationBus>,
+    pub reactivation: Arc<ReactivationLinks>,
+    pub password_expiry: Arc<PasswordExpiry>,
+    pub login_challenges: Arc<LoginChallenges>,
+    pub email_verification: Arc<EmailVerification>,
+    pub terms: Arc<Terms>,
+    pub captcha_exemptions: Arc<CaptchaExemptions>,
+    pub api_keys: Arc<ApiKeys>,
+    pub rate_limits: Arc<RateLimits>,
+    pub request_signing: Arc<RequestSigner>,
+    pub body_logging: Arc<BodyLogger>,
+    pub slo: Arc<SloTracker>,
+    pub session_limits: Arc<SessionLimits>,
+    pub role_permissions: Arc<RolePermissions>,
+    pub ip_reputation: Arc<IpReputation>,
+    pub login_notifications: Arc<LoginNotifications>,
+    pub realm_backups: Arc<RealmBackups>,
+    pub storage: Arc<Storage>,
+    pub introspections: Arc<IntrospectionCache>,
+    pub read_only: Arc<ReadOnlyMode>,
+    pub live: Arc<LiveConfig>,
+    pub config_snapshots: Arc<ConfigSnapshots>,
 }
 
 impl AppState {
-    pub fn new(config: AppConfig, http_client: Client, keycloak: Arc<KeycloakService>) -> Self {
+    pub fn new(
+        config: AppConfig,
+        http_client: Client,
+        keycloak: Arc<KeycloakService>,
+        metrics: Arc<Metrics>,
+        sessions: Arc<dyn SessionStore>,
+    ) -> Self {
+        let altcha = (config.captcha_provider == CaptchaProvider::Altcha)
+            .then(|| Arc::new(AltchaService::from_config(&config, sessions.clone())));
+        let bot_trap = Arc::new(BotTrap::from_config(&config));
+        let partners = Arc::new(PartnerRegistry::from_config(&config));
+        let referrals = Arc::new(ReferralService::from_config(&config));
+        let audit = Arc::new(AuditLog::from_config(&config));
+        let anomalies = Arc::new(AnomalyDetector::from_config(&config));
+        let ip_filter = Arc::new(IpFilter::from_config(&config));
+        let lifecycle = Arc::new(Lifecycle::new());
+        let keycloak_health = Arc::new(KeycloakHealth::from_config(&config));
+        let service_tokens = Arc::new(TokenCache::new());
+        let permissions = Arc::new(PermissionService::from_config(&config, keycloak.clone()));
+        let dpop = Arc::new(DpopVerifier::from_config(&config, sessions.clone()));
+        let login_history = Arc::new(LoginHistory::from_config(&config, sessions.clone()));
+        let user_profile = Arc::new(UserProfileService::from_config(&config, keycloak.clone()));
+        let revocations = Arc::new(RevocationBus::from_config(
+            &config,
+            sessions.clone(),
+            permissions.clone(),
+        ));
+        let reactivation = Arc::new(ReactivationLinks::from_config(&config));
+        let password_expiry = Arc::new(PasswordExpiry::from_config(&config, keycloak.clone()));
+        let login_challenges = Arc::new(LoginChallenges::from_config(
+            &config,
+            sessions.clone(),
+            keycloak.clone(),
+        ));
+        let email_verification =
+            Arc::new(EmailVerification::from_config(&config, keycloak.clone()));
+        let terms = Arc::new(Terms::from_config(&config, keycloak.clone()));
+        let body_logging = Arc::new(BodyLogger::from_config(&config));
+        let slo = Arc::new(SloTracker::from_config(&config));
+        let session_limits = Arc::new(SessionLimits::from_config(&config, keycloak.clone()));
+        let role_permissions = Arc::new(RolePermissions::from_config(&config));
+        let ip_reputation = Arc::new(IpReputation::from_config(&config, http_client.clone()));
+        let request_signing = Arc::new(RequestSigner::from_config(&config, sessions.clone()));
+        let captcha_exemptions =
+            Arc::new(CaptchaExemptions::from_config(&config, sessions.clone()));
+        let api_keys = Arc::new(ApiKeys::from_config(&config, sessions.clone()));
+        let rate_limits = Arc::new(RateLimits::new(sessions.clone(), api_keys.clone()));
+        let outbox = Arc::new(Outbox::from_config(&config));
+        let webhooks = Arc::new(WebhookService::from_config(
+            &config,
+            http_client.clone(),
+            outbox,
+        ));
+        let login_notifications = Arc::new(LoginNotifications::from_config(
+            &config,
+            keycloak.clone(),
+            webhooks.clone(),
+        ));
+        let realm_backups = Arc::new(RealmBackups::from_config(
+            &config,
+            keycloak.clone(),
+            http_client.clone(),
+        ));
+        let storage = Arc::new(Storage::from_config(&config, http_client.clone()));
+        let introspections = Arc::new(IntrospectionCache::from_config(
+            &config,
+            keycloak.clone(),
+            keycloak_health.clone(),
+        ));
+        let read_only = Arc::new(ReadOnlyMode::from_config(
+            &config,
+            keycloak_health.clone(),
+            sessions.clone(),
+        ));
+        let cookies = Arc::new(CookieKeys::from_config(&config));
+        let response_cache = Arc::new(ResponseCache::from_config(&config));
+        let live = Arc::new(LiveConfig::new(&config));
+        let config_snapshots = Arc::new(ConfigSnapshots::from_config(&config));
+
         Self {
             config,
             http_client,
             keycloak,
+            altcha,
+            metrics,
+            bot_trap,
+            partners,
+            referrals,
+            audit,
+            anomalies,
+            ip_filter,
+            lifecycle,
+            keycloak_health,
+            service_tokens,
+            permissions,
+            dpop,
+            sessions,
+            cookies,
+            response_cache,
+            login_history,
+            user_profile,
+            webhooks,
+            revocations,
+            reactivation,
+            password_expiry,
+            login_challenges,
+            email_verification,
+            terms,
+            captcha_exemptions,
+            api_keys,
+            rate_limits,
+            request_signing,
+            body_logging,
+            slo,
+            session_limits,
+            role_permissions,
+            ip_reputation,
+            login_notifications,
+            realm_backups,
+            storage,
+            introspections,
+            read_only,
+            live,
+            config_snapshots,
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct AppConfig {
     pub bind_address: String,
     pub port: u16,
+    pub captcha_provider: CaptchaProvider,
+    pub altcha_hmac_key: Option<String>,
+    pub altcha_max_number: u64,
+    pub altcha_challenge_ttl: Duration,
     pub turnstile_site_key: String,
     pub turnstile_secret_key: Option<String>,
     pub turnstile_verify_url: String,
+    pub turnstile_expected_hostnames: Vec<String>,
+    pub turnstile_validate_action: bool,
     pub keycloak_base_url: String,
     pub keycloak_realm: String,
     pub keycloak_admin_client_id: String,
@@ -52,64 +358,1080 @@ pub struct AppConfig {
     pub keycloak_public_client_secret: Option<String>,
     pub keycloak_tls_insecure: bool,
     pub cors_allowed_origins: Vec<String>,
+    pub trusted_proxies: Vec<IpNet>,
+    /// Network list settings with entries that did not parse; `validate`
+    /// refuses to start while there are any.
+    #[serde(skip)]
+    pub invalid_networks: Vec<String>,
+    pub user_lookup_cache_capacity: usize,
+    pub user_lookup_cache_ttl: Duration,
+    pub strict_request_bodies: bool,
+    pub register_extra_max_keys: usize,
+    pub register_extra_max_key_len: usize,
+    pub register_extra_max_value_bytes: usize,
+    pub tenants: Vec<TenantConfig>,
+    pub register_honeypot_field: Option<String>,
+    pub register_min_fill_time: Duration,
+    pub form_token_secret: Option<String>,
+    pub form_token_max_age: Duration,
+    pub email_strip_plus_tags: bool,
+    pub email_strip_gmail_dots: bool,
+    pub partners_file: Option<String>,
+    pub partner_assertion_audience: String,
+    pub referral_codes: Vec<String>,
+    pub referral_validate_url: Option<String>,
+    pub admin_role: String,
+    pub admin_tenant_claim: String,
+    pub admin_tenant_attribute: String,
+    pub register_min_age: u32,
+    pub store_date_of_birth: bool,
+    pub auditor_role: String,
+    pub audit_log_file: Option<String>,
+    pub audit_log_capacity: usize,
+    pub anomaly_window: Duration,
+    pub anomaly_failed_login_threshold: usize,
+    pub anomaly_captcha_rejection_threshold: usize,
+    pub anomaly_registration_threshold: usize,
+    pub alert_webhook_url: Option<String>,
+    pub ip_allowlist: Vec<IpNet>,
+    pub ip_denylist: Vec<IpNet>,
+    pub admin_ip_allowlist: Vec<IpNet>,
+    pub admin_ip_denylist: Vec<IpNet>,
+    pub ip_filter_file: Option<String>,
+    pub ip_filter_reload_interval: Duration,
+    pub internal_tls_bind: Option<String>,
+    pub internal_tls_cert: Option<String>,
+    pub internal_tls_key: Option<String>,
+    pub internal_tls_client_ca: Option<String>,
+    pub internal_client_cert_header: Option<String>,
+    pub internal_service_identities: Vec<(String, String)>,
+    pub internal_service_clients: Vec<ServiceClient>,
+    pub admin_bind_address: Option<SocketAddr>,
+    pub listen_socket_path: Option<String>,
+    pub listen_socket_mode: Option<u32>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub http2_enabled: bool,
+    pub h2c_enabled: bool,
+    pub drain_grace: Duration,
+    pub shutdown_timeout: Duration,
+    pub keycloak_health_url: Option<String>,
+    pub keycloak_health_interval: Duration,
+    pub keycloak_unavailable_after: Duration,
+    pub admin_token_high_load_rps: f64,
+    pub request_timeout: Duration,
+    pub route_timeouts: Vec<(String, Duration)>,
+    pub uma_resource_server: String,
+    pub uma_decision_cache_capacity: usize,
+    pub uma_decision_cache_ttl: Duration,
+    pub oidc_redirect_uris: Vec<String>,
+    pub dpop_public_url: Option<String>,
+    pub dpop_proof_max_age: Duration,
+    pub session_backend: SessionBackend,
+    pub redis_url: String,
+    pub session_store_prefix: String,
+    pub cookie_keys: Vec<String>,
+    pub auth_cookie_name: String,
+    pub auth_cookie_secure: bool,
+    pub auth_cookie_domain: Option<String>,
+    #[serde(serialize_with = "config_snapshot::header_names")]
+    pub correlation_headers: Vec<HeaderName>,
+    pub response_cache_ttl: Duration,
+    pub response_cache_capacity: usize,
+    pub login_history_retention: Duration,
+    pub login_history_per_user: usize,
+    pub registration_attributes: Vec<AttributeSpec>,
+    pub keycloak_user_profile: bool,
+    pub user_profile_cache_ttl: Duration,
+    pub webhooks_file: Option<String>,
+    pub webhook_secrets_key: Option<String>,
+    pub mail_delivery_url: Option<String>,
+    pub mail_delivery_secret: Option<String>,
+    pub webhook_max_attempts: u32,
+    pub webhook_delivery_history: usize,
+    pub outbox_file: Option<String>,
+    pub outbox_poll_interval: Duration,
+    pub outbox_dead_letter_capacity: usize,
+    pub keycloak_event_poll_interval: Duration,
+    pub keycloak_event_types: Vec<String>,
+    pub revocation_ttl: Duration,
+    pub reactivation_secret: Option<String>,
+    pub reactivation_url: Option<String>,
+    pub reactivation_link_ttl: Duration,
+    pub password_max_age_days: u64,
+    pub password_change_url: Option<String>,
+    pub login_challenge_ttl: Duration,
+    pub mfa_check_client_id: Option<String>,
+    pub mfa_check_client_secret: Option<String>,
+    pub unverified_login: UnverifiedLogin,
+    pub terms_version: Option<String>,
+    pub terms_url: Option<String>,
+    pub keycloak_master_admin: Option<String>,
+    pub keycloak_master_password: Option<String>,
+    pub realm_spec_file: Option<String>,
+    pub realm_drift_check_interval: Duration,
+    pub initial_admin_email: Option<String>,
+    pub initial_admin_password_file: Option<String>,
+    pub captcha_exemption_secret: Option<String>,
+    pub captcha_exemption_max_ttl: Duration,
+    pub api_key_argon2_memory_kib: u32,
+    pub api_key_argon2_iterations: u32,
+    pub api_key_argon2_parallelism: u32,
+    pub api_key_max_ttl: Duration,
+    pub min_client_versions: Vec<(String, String)>,
+    pub client_upgrade_urls: Vec<(String, String)>,
+    pub request_signing_keys: Vec<(String, String)>,
+    pub request_signature_window: Duration,
+    pub body_log_enabled: bool,
+    pub body_log_sample_rate: f64,
+    pub body_log_routes: Vec<String>,
+    pub body_log_max_bytes: usize,
+    pub slo_targets: Vec<SloTarget>,
+    pub slo_window: Duration,
+    pub refresh_hint_ratio: f64,
+    pub refresh_hint_jitter: f64,
+    pub max_sessions_per_user: usize,
+    pub session_limit_policy: SessionLimitPolicy,
+    pub auth_check_refresh_window: Duration,
+    pub keycloak_slow_call_threshold: Option<Duration>,
+    pub clock_skew_leeway: Duration,
+    pub ntp_server: Option<String>,
+    pub frontchannel_logout_url: Option<String>,
+    pub verified_callback_url: Option<String>,
+    pub verified_callback_secret: Option<String>,
+    pub verified_redirect_url: Option<String>,
+    pub verified_redirect_urls: Vec<(String, String)>,
+    pub metrics_push_url: Option<String>,
+    pub metrics_push_interval: Duration,
+    pub metrics_push_job: String,
+    pub metrics_push_instance: String,
+    pub permissions_file: Option<String>,
+    pub ip_blocklist_file: Option<String>,
+    pub ip_blocklist_reload_interval: Duration,
+    pub abuseipdb_url: String,
+    pub abuseipdb_api_key: Option<String>,
+    pub ip_reputation_challenge_score: u8,
+    pub ip_reputation_block_score: u8,
+    pub ip_reputation_fail_open: bool,
+    pub ip_reputation_cache_ttl: Duration,
+    pub login_notification_url: Option<String>,
+    pub login_notification_secret: Option<String>,
+    pub login_notification_link_ttl: Duration,
+    pub login_notifications_default: bool,
+    pub social_login_hints: bool,
+    pub login_location_header: Option<String>,
+    pub username_mode: UsernameMode,
+    pub username_check_limit: u32,
+    pub realm_backup_key: Option<String>,
+    pub realm_backup_dir: Option<String>,
+    pub realm_backup_s3_bucket: Option<String>,
+    pub realm_backup_s3_region: String,
+    pub realm_backup_s3_endpoint: Option<String>,
+    pub realm_backup_s3_prefix: String,
+    pub realm_backup_interval: Option<Duration>,
+    pub realm_backup_include_users: bool,
+    pub realm_backup_retain: usize,
+    pub aws_access_key_id: Option<String>,
+    pub aws_secret_access_key: Option<String>,
+    pub aws_session_token: Option<String>,
+    pub storage_dir: Option<String>,
+    pub storage_s3_bucket: Option<String>,
+    pub storage_s3_region: String,
+    pub storage_s3_endpoint: Option<String>,
+    pub storage_s3_prefix: String,
+    pub avatar_max_bytes: usize,
+    pub avatar_public_url: Option<String>,
+    pub avatar_max_pixels: u64,
+    pub avatar_max_side: u32,
+    #[cfg(any(test, feature = "cassette"))]
+    pub keycloak_cassette: Option<String>,
+    #[cfg(any(test, feature = "cassette"))]
+    pub keycloak_cassette_mode: Option<CassetteMode>,
+    pub auth_scopes: Vec<String>,
+    pub login_response_roles: bool,
+    pub introspection_cache_ttl: Duration,
+    pub introspection_max_stale: Duration,
+    pub keycloak_admin_concurrency: usize,
+    pub keycloak_admin_queue_limits: Vec<(String, usize)>,
+    pub keycloak_background_wait: Duration,
+    pub read_only_mode: bool,
+    pub read_only_on_outage: bool,
+    pub register_duplicate_check: bool,
+    pub register_duplicate_check_limit: u32,
+    pub config_snapshot_interval: Option<Duration>,
+    pub config_snapshot_secret: Option<String>,
+    pub config_snapshot_file: Option<String>,
 }
 
 impl AppConfig {
     pub fn from_env() -> Self {
-        let bind_address =
-            env::var("BACKEND_BIND_ADDRESS").unwrap_or_else(|_| "127.0.0.1".to_owned());
-        let port = env::var("BACKEND_PORT")
+        Self::from_source(|name| env::var(name))
+    }
+
+    /// Builds the configuration from `var`, which looks a setting up by its
+    /// environment variable name.
+    pub fn from_source(var: impl Fn(&str) -> Result<String, env::VarError>) -> Self {
+        let bind_address = var("BACKEND_BIND_ADDRESS").unwrap_or_else(|_| "127.0.0.1".to_owned());
+        let port = var("BACKEND_PORT")
             .ok()
             .and_then(|value| value.parse::<u16>().ok())
             .unwrap_or(8000);
 
+        let captcha_provider = var("CAPTCHA_PROVIDER")
+            .ok()
+            .and_then(|value| CaptchaProvider::parse(&value))
+            .unwrap_or(CaptchaProvider::Turnstile);
+        let altcha_hmac_key = var("ALTCHA_HMAC_KEY").ok();
+        let altcha_max_number = var("ALTCHA_MAX_NUMBER")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(100_000);
+        let altcha_challenge_ttl = var("ALTCHA_CHALLENGE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(300));
+
         let turnstile_site_key =
-            env::var("VITE_TURNSTILE_SITE_KEY").unwrap_or_else(|_| DEV_MOCK_SITE_KEY.to_owned());
-        let turnstile_secret_key = env::var("TURNSTILE_SECRET_KEY").ok();
-        let turnstile_verify_url = env::var("TURNSTILE_VERIFY_URL")
+            var("VITE_TURNSTILE_SITE_KEY").unwrap_or_else(|_| DEV_MOCK_SITE_KEY.to_owned());
+        let turnstile_secret_key = var("TURNSTILE_SECRET_KEY").ok();
+        let turnstile_verify_url = var("TURNSTILE_VERIFY_URL")
             .unwrap_or_else(|_| "https://challenges.cloudflare.com/turnstile/v0/siteverify".into());
+        let turnstile_expected_hostnames = var("TURNSTILE_EXPECTED_HOSTNAMES")
+            .ok()
+            .map(|value| split_list(&value))
+            .unwrap_or_default();
+        let turnstile_validate_action = var("TURNSTILE_VALIDATE_ACTION")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(true);
 
         let keycloak_base_url =
-            env::var("KEYCLOAK_BASE_URL").unwrap_or_else(|_| "http://localhost:8080".into());
-        let keycloak_realm = env::var("KEYCLOAK_REALM").unwrap_or_else(|_| "argus".into());
+            var("KEYCLOAK_BASE_URL").unwrap_or_else(|_| "http://localhost:8080".into());
+        let keycloak_realm = var("KEYCLOAK_REALM").unwrap_or_else(|_| "argus".into());
         let keycloak_admin_client_id =
-            env::var("KEYCLOAK_ADMIN_CLIENT_ID").unwrap_or_else(|_| "argus-backend".into());
-        let keycloak_admin_client_secret = env::var("KEYCLOAK_ADMIN_CLIENT_SECRET")
-            .unwrap_or_else(|_| "argus-backend-secret".into());
+            var("KEYCLOAK_ADMIN_CLIENT_ID").unwrap_or_else(|_| "argus-backend".into());
+        let keycloak_admin_client_secret =
+            var("KEYCLOAK_ADMIN_CLIENT_SECRET").unwrap_or_else(|_| "argus-backend-secret".into());
         let keycloak_public_client_id =
-            env::var("KEYCLOAK_PUBLIC_CLIENT_ID").unwrap_or_else(|_| "argus-portal-web".into());
-        let keycloak_public_client_secret = env::var("KEYCLOAK_PUBLIC_CLIENT_SECRET").ok();
-        let keycloak_tls_insecure = env::var("KEYCLOAK_TLS_INSECURE")
+            var("KEYCLOAK_PUBLIC_CLIENT_ID").unwrap_or_else(|_| "argus-portal-web".into());
+        let keycloak_public_client_secret = var("KEYCLOAK_PUBLIC_CLIENT_SECRET").ok();
+        let keycloak_tls_insecure = var("KEYCLOAK_TLS_INSECURE")
             .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
             .unwrap_or(true);
-        let cors_allowed_origins = env::var("BACKEND_ALLOWED_ORIGINS")
+        let cors_allowed_origins = var("BACKEND_ALLOWED_ORIGINS")
+            .ok()
+            .map(|value| split_list(&value))
+            .unwrap_or_else(|| {
+                vec![
+                    "https://127.0.0.1:5173".to_owned(),
+                    "https://localhost:5173".to_owned(),
+                ]
+            });
+        let mut invalid_networks = Vec::new();
+        let mut networks =
+            |name: &str| match var(name).map(|value| client_ip::parse_networks(&value)) {
+                Ok(Ok(networks)) => networks,
+                Ok(Err(err)) => {
+                    invalid_networks.push(format!("{name}: {err}"));
+                    Vec::new()
+                }
+                Err(_) => Vec::new(),
+            };
+        let trusted_proxies = networks("TRUSTED_PROXIES");
+        let user_lookup_cache_capacity = var("USER_LOOKUP_CACHE_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(1024);
+        let user_lookup_cache_ttl = var("USER_LOOKUP_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let strict_request_bodies = var("STRICT_REQUEST_BODIES")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let register_extra_max_keys = var("REGISTER_EXTRA_MAX_KEYS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(16);
+        let register_extra_max_key_len = var("REGISTER_EXTRA_MAX_KEY_LEN")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(64);
+        let register_extra_max_value_bytes = var("REGISTER_EXTRA_MAX_VALUE_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(1024);
+        let tenants = load_tenants(var("TENANTS_FILE").ok().as_deref());
+        let register_honeypot_field = var("REGISTER_HONEYPOT_FIELD")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let register_min_fill_time = var("REGISTER_MIN_FILL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::ZERO);
+        let form_token_secret = var("FORM_TOKEN_SECRET").ok();
+        let form_token_max_age = var("FORM_TOKEN_MAX_AGE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(3600));
+        let email_strip_plus_tags = var("EMAIL_STRIP_PLUS_TAGS")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let email_strip_gmail_dots = var("EMAIL_STRIP_GMAIL_DOTS")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let partners_file = var("PARTNERS_FILE").ok();
+        let partner_assertion_audience =
+            var("PARTNER_ASSERTION_AUDIENCE").unwrap_or_else(|_| "argus-portal".into());
+        let referral_codes = var("REFERRAL_CODES")
+            .ok()
+            .map(|value| split_list(&value))
+            .unwrap_or_default();
+        let referral_validate_url = var("REFERRAL_VALIDATE_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let admin_role = var("ADMIN_ROLE").unwrap_or_else(|_| "argus-admin".into());
+        let admin_tenant_claim = var("ADMIN_TENANT_CLAIM").unwrap_or_else(|_| "tenant".into());
+        let admin_tenant_attribute =
+            var("ADMIN_TENANT_ATTRIBUTE").unwrap_or_else(|_| "tenant".into());
+        let register_min_age = var("REGISTER_MIN_AGE")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(0);
+        let store_date_of_birth = var("REGISTER_STORE_DOB")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let auditor_role = var("AUDITOR_ROLE").unwrap_or_else(|_| "auditor".into());
+        let audit_log_file = var("AUDIT_LOG_FILE")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let audit_log_capacity = var("AUDIT_LOG_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(10_000);
+        let anomaly_window = var("ANOMALY_WINDOW_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(300));
+        let anomaly_failed_login_threshold = var("ANOMALY_FAILED_LOGIN_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(20);
+        let anomaly_captcha_rejection_threshold = var("ANOMALY_CAPTCHA_REJECTION_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(20);
+        let anomaly_registration_threshold = var("ANOMALY_REGISTRATION_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(10);
+        let alert_webhook_url = var("ALERT_WEBHOOK_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let ip_allowlist = networks("IP_ALLOWLIST");
+        let ip_denylist = networks("IP_DENYLIST");
+        let admin_ip_allowlist = networks("ADMIN_IP_ALLOWLIST");
+        let admin_ip_denylist = networks("ADMIN_IP_DENYLIST");
+        let ip_filter_file = var("IP_FILTER_FILE")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let ip_filter_reload_interval = var("IP_FILTER_RELOAD_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let keycloak_health_url = var("KEYCLOAK_HEALTH_URL")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let keycloak_health_interval = var("KEYCLOAK_HEALTH_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(10));
+        let keycloak_unavailable_after = var("KEYCLOAK_UNAVAILABLE_AFTER_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let admin_token_high_load_rps = var("ADMIN_TOKEN_HIGH_LOAD_RPS")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .unwrap_or(20.0);
+        let request_timeout = var("REQUEST_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(10));
+        let route_timeouts = var("ROUTE_TIMEOUTS")
             .ok()
             .map(|value| {
-                value
-                    .split(',')
-                    .filter_map(|origin| {
-                        let trimmed = origin.trim();
-                        if trimmed.is_empty() {
-                            None
-                        } else {
-                            Some(trimmed.to_owned())
-                        }
+                split_list(&value)
+                    .into_iter()
+                    .filter_map(|entry| {
+                        let (prefix, secs) = entry.rsplit_once('=')?;
+                        let secs = secs.trim().parse::<u64>().ok()?;
+                        Some((prefix.trim().to_owned(), Duration::from_secs(secs)))
                     })
-                    .collect::<Vec<String>>()
+                    .collect()
             })
+            .unwrap_or_default();
+        let uma_resource_server = var("UMA_RESOURCE_SERVER")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| keycloak_admin_client_id.clone());
+        let uma_decision_cache_capacity = var("UMA_DECISION_CACHE_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(1024);
+        let uma_decision_cache_ttl = var("UMA_DECISION_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let oidc_redirect_uris = var("OIDC_REDIRECT_URIS")
+            .ok()
+            .map(|value| split_list(&value))
+            .unwrap_or_default();
+        let dpop_public_url = var("DPOP_PUBLIC_URL")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let dpop_proof_max_age = var("DPOP_PROOF_MAX_AGE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(60));
+        let session_backend = var("SESSION_STORE")
+            .ok()
+            .and_then(|value| SessionBackend::parse(&value))
+            .unwrap_or(SessionBackend::Memory);
+        let redis_url = var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_owned());
+        let session_store_prefix =
+            var("SESSION_STORE_PREFIX").unwrap_or_else(|_| "argus:".to_owned());
+        let cookie_keys = var("COOKIE_KEYS")
+            .ok()
+            .map(|value| split_list(&value))
+            .unwrap_or_default();
+        let auth_cookie_name =
+            var("AUTH_COOKIE_NAME").unwrap_or_else(|_| "argus_refresh".to_owned());
+        let auth_cookie_secure = var("AUTH_COOKIE_SECURE")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(true);
+        let auth_cookie_domain = var("AUTH_COOKIE_DOMAIN")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let correlation_headers = parse_header_names(&split_list(
+            &var("CORRELATION_HEADERS").unwrap_or_else(|_| DEFAULT_CORRELATION_HEADERS.to_owned()),
+        ));
+        let response_cache_ttl = var("RESPONSE_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let response_cache_capacity = var("RESPONSE_CACHE_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(64);
+        let login_history_retention = var("LOGIN_HISTORY_RETENTION_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(90 * 24 * 60 * 60));
+        let login_history_per_user = var("LOGIN_HISTORY_PER_USER")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(50);
+        let registration_attributes =
+            load_attributes(var("REGISTRATION_ATTRIBUTES_FILE").ok().as_deref());
+        let keycloak_user_profile = var("KEYCLOAK_USER_PROFILE")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let user_profile_cache_ttl = var("USER_PROFILE_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(300));
+        let webhooks_file = var("WEBHOOKS_FILE")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let webhook_secrets_key = var("WEBHOOK_SECRETS_KEY")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let mail_delivery_url = var("MAIL_DELIVERY_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let mail_delivery_secret = var("MAIL_DELIVERY_SECRET")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let webhook_max_attempts = var("WEBHOOK_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(5);
+        let webhook_delivery_history = var("WEBHOOK_DELIVERY_HISTORY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(50);
+        let outbox_file = var("OUTBOX_FILE")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let outbox_poll_interval = var("OUTBOX_POLL_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(1))
+            .max(Duration::from_secs(1));
+        let outbox_dead_letter_capacity = var("OUTBOX_DEAD_LETTER_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(1000);
+        let keycloak_event_poll_interval = var("KEYCLOAK_EVENT_POLL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::ZERO);
+        let keycloak_event_types = var("KEYCLOAK_EVENT_TYPES")
+            .ok()
+            .map(|value| split_list(&value))
             .unwrap_or_else(|| {
                 vec![
-                    "https://127.0.0.1:5173".to_owned(),
-                    "https://localhost:5173".to_owned(),
+                    "LOGIN_ERROR".to_owned(),
+                    "UPDATE_PASSWORD".to_owned(),
+                    "DELETE_ACCOUNT".to_owned(),
                 ]
             });
+        let revocation_ttl = var("REVOCATION_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(24 * 60 * 60));
+        let reactivation_secret = var("REACTIVATION_SECRET").ok();
+        let reactivation_url = var("REACTIVATION_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let reactivation_link_ttl = var("REACTIVATION_LINK_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(24 * 60 * 60));
+        let password_max_age_days = var("PASSWORD_MAX_AGE_DAYS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        let password_change_url = var("PASSWORD_CHANGE_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let login_challenge_ttl = var("LOGIN_CHALLENGE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(5 * 60));
+        let mfa_check_client_id = var("MFA_CHECK_CLIENT_ID")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let mfa_check_client_secret = var("MFA_CHECK_CLIENT_SECRET").ok();
+        let unverified_login = var("UNVERIFIED_LOGIN")
+            .ok()
+            .and_then(|value| UnverifiedLogin::parse(&value))
+            .unwrap_or(UnverifiedLogin::Allow);
+        let terms_version = var("TERMS_VERSION")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let terms_url = var("TERMS_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let keycloak_master_admin = var("KEYCLOAK_ADMIN").ok();
+        let keycloak_master_password = var("KEYCLOAK_ADMIN_PASSWORD").ok();
+        let realm_spec_file = var("REALM_SPEC_FILE").ok();
+        let realm_drift_check_interval = var("REALM_DRIFT_CHECK_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(60 * 60));
+        let initial_admin_email = var("INITIAL_ADMIN_EMAIL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let initial_admin_password_file = var("INITIAL_ADMIN_PASSWORD_FILE").ok();
+        let captcha_exemption_secret = var("CAPTCHA_EXEMPTION_SECRET").ok();
+        let captcha_exemption_max_ttl = var("CAPTCHA_EXEMPTION_MAX_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(7 * 24 * 60 * 60));
+        let api_key_argon2_memory_kib = var("API_KEY_ARGON2_MEMORY_KIB")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(19 * 1024);
+        let api_key_argon2_iterations = var("API_KEY_ARGON2_ITERATIONS")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(2);
+        let api_key_argon2_parallelism = var("API_KEY_ARGON2_PARALLELISM")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(1);
+        let api_key_max_ttl = var("API_KEY_MAX_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(365 * 24 * 60 * 60));
+        let min_client_versions = var("MIN_CLIENT_VERSIONS")
+            .ok()
+            .map(|value| parse_platform_map(&value))
+            .unwrap_or_default();
+        let client_upgrade_urls = var("CLIENT_UPGRADE_URLS")
+            .ok()
+            .map(|value| parse_platform_map(&value))
+            .unwrap_or_default();
+        let request_signing_keys = var("REQUEST_SIGNING_KEYS")
+            .ok()
+            .map(|value| {
+                split_list(&value)
+                    .into_iter()
+                    .filter_map(|entry| {
+                        let (service, key) = entry.split_once('=')?;
+                        Some((service.trim().to_owned(), key.trim().to_owned()))
+                    })
+                    .filter(|(service, key)| !service.is_empty() && !key.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let request_signature_window = var("REQUEST_SIGNATURE_WINDOW_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(300));
+        let body_log_enabled = var("BODY_LOG_ENABLED")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let body_log_sample_rate = var("BODY_LOG_SAMPLE_RATE")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .unwrap_or(0.1);
+        let body_log_routes = var("BODY_LOG_ROUTES")
+            .map(|value| split_list(&value))
+            .unwrap_or_default();
+        let body_log_max_bytes = var("BODY_LOG_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(4096);
+        let slo_targets = var("SLO_TARGETS")
+            .map(|value| {
+                split_list(&value)
+                    .iter()
+                    .filter_map(|entry| SloTarget::parse(entry))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let slo_window = var("SLO_WINDOW_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(28 * 24 * 60 * 60));
+        let refresh_hint_ratio = var("REFRESH_HINT_RATIO")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .unwrap_or(0.75);
+        let refresh_hint_jitter = var("REFRESH_HINT_JITTER")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .map(f64::abs)
+            .unwrap_or(0.1);
+        let max_sessions_per_user = var("MAX_SESSIONS_PER_USER")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(0);
+        let session_limit_policy = var("SESSION_LIMIT_POLICY")
+            .ok()
+            .and_then(|value| SessionLimitPolicy::parse(&value))
+            .unwrap_or(SessionLimitPolicy::EvictOldest);
+        let auth_check_refresh_window = var("AUTH_CHECK_REFRESH_WINDOW_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(60));
+        let keycloak_slow_call_threshold = var("KEYCLOAK_SLOW_CALL_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(1000);
+        let keycloak_slow_call_threshold = (keycloak_slow_call_threshold > 0)
+            .then(|| Duration::from_millis(keycloak_slow_call_threshold));
+        let clock_skew_leeway = var("CLOCK_SKEW_LEEWAY_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let ntp_server = var("NTP_SERVER")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let frontchannel_logout_url = var("FRONTCHANNEL_LOGOUT_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let verified_callback_url = var("VERIFIED_CALLBACK_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let verified_callback_secret = var("VERIFIED_CALLBACK_SECRET")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let verified_redirect_url = var("VERIFIED_REDIRECT_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let verified_redirect_urls = var("VERIFIED_REDIRECT_URLS")
+            .ok()
+            .map(|value| {
+                split_list(&value)
+                    .into_iter()
+                    .filter_map(|entry| {
+                        let (locale, url) = entry.split_once('=')?;
+                        Some((locale.trim().to_owned(), url.trim().to_owned()))
+                    })
+                    .filter(|(locale, url)| !locale.is_empty() && !url.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let metrics_push_url = var("METRICS_PUSH_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let metrics_push_interval = var("METRICS_PUSH_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(15));
+        let metrics_push_job = var("METRICS_PUSH_JOB")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "argus-portal".to_owned());
+        let metrics_push_instance = var("METRICS_PUSH_INSTANCE")
+            .or_else(|_| var("HOSTNAME"))
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "portal".to_owned());
+        let permissions_file = var("PERMISSIONS_FILE")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let ip_blocklist_file = var("IP_BLOCKLIST_FILE")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let ip_blocklist_reload_interval = var("IP_BLOCKLIST_RELOAD_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let abuseipdb_url = var("ABUSEIPDB_URL")
+            .unwrap_or_else(|_| ip_reputation::DEFAULT_ABUSEIPDB_URL.to_owned());
+        let abuseipdb_api_key = var("ABUSEIPDB_API_KEY")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let ip_reputation_challenge_score = var("IP_REPUTATION_CHALLENGE_SCORE")
+            .ok()
+            .and_then(|value| value.parse::<u8>().ok())
+            .unwrap_or(50);
+        let ip_reputation_block_score = var("IP_REPUTATION_BLOCK_SCORE")
+            .ok()
+            .and_then(|value| value.parse::<u8>().ok())
+            .unwrap_or(90);
+        let ip_reputation_fail_open = var("IP_REPUTATION_FAIL_OPEN")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(true);
+        let ip_reputation_cache_ttl = var("IP_REPUTATION_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(60 * 60));
+        let login_notification_url = var("LOGIN_NOTIFICATION_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let login_notification_secret = var("LOGIN_NOTIFICATION_SECRET")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let login_notification_link_ttl = var("LOGIN_NOTIFICATION_LINK_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(7 * 24 * 60 * 60));
+        let login_notifications_default = var("LOGIN_NOTIFICATIONS_DEFAULT")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let social_login_hints = var("SOCIAL_LOGIN_HINTS")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let login_location_header = var("LOGIN_LOCATION_HEADER")
+            .ok()
+            .map(|value| value.trim().to_ascii_lowercase())
+            .filter(|value| !value.is_empty());
+        let username_mode = var("USERNAME_MODE")
+            .ok()
+            .and_then(|value| UsernameMode::parse(&value))
+            .unwrap_or(UsernameMode::Email);
+        let username_check_limit = var("USERNAME_CHECK_LIMIT")
+            .ok()
+            .and_then(|value| value.trim().parse::<u32>().ok())
+            .unwrap_or(30);
+        let realm_backup_key = var("REALM_BACKUP_KEY")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let realm_backup_dir = var("REALM_BACKUP_DIR")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let realm_backup_s3_bucket = var("REALM_BACKUP_S3_BUCKET")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let realm_backup_s3_region = var("REALM_BACKUP_S3_REGION")
+            .or_else(|_| var("AWS_REGION"))
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "us-east-1".to_owned());
+        let realm_backup_s3_endpoint = var("REALM_BACKUP_S3_ENDPOINT")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let realm_backup_s3_prefix = var("REALM_BACKUP_S3_PREFIX")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_default();
+        let realm_backup_interval = var("REALM_BACKUP_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        let realm_backup_include_users = var("REALM_BACKUP_INCLUDE_USERS")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let realm_backup_retain = var("REALM_BACKUP_RETAIN")
+            .ok()
+            .and_then(|value| value.trim().parse::<usize>().ok())
+            .unwrap_or(14);
+        let aws_access_key_id = var("AWS_ACCESS_KEY_ID")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let aws_secret_access_key = var("AWS_SECRET_ACCESS_KEY")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let aws_session_token = var("AWS_SESSION_TOKEN")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let storage_dir = var("STORAGE_DIR")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let storage_s3_bucket = var("STORAGE_S3_BUCKET")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let storage_s3_region = var("STORAGE_S3_REGION")
+            .or_else(|_| var("AWS_REGION"))
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "us-east-1".to_owned());
+        let storage_s3_endpoint = var("STORAGE_S3_ENDPOINT")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let storage_s3_prefix = var("STORAGE_S3_PREFIX")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_default();
+        let avatar_max_bytes = var("AVATAR_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|bytes| *bytes > 0)
+            .unwrap_or(2 * 1024 * 1024);
+        let avatar_public_url = var("AVATAR_PUBLIC_URL")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let avatar_max_pixels = var("AVATAR_MAX_PIXELS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|pixels| *pixels > 0)
+            .unwrap_or(4096 * 4096);
+        let avatar_max_side = var("AVATAR_MAX_SIDE")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .filter(|side| *side > 0)
+            .unwrap_or(512);
+        #[cfg(any(test, feature = "cassette"))]
+        let keycloak_cassette = var("KEYCLOAK_CASSETTE")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        #[cfg(any(test, feature = "cassette"))]
+        let keycloak_cassette_mode = var("KEYCLOAK_CASSETTE_MODE")
+            .ok()
+            .and_then(|value| CassetteMode::parse(&value));
+        let auth_scopes = var("AUTH_SCOPES")
+            .ok()
+            .map(|value| login_scope::parse(&value))
+            .filter(|scopes| !scopes.is_empty())
+            .unwrap_or_else(|| vec![login_scope::OPENID.to_owned()]);
+        let login_response_roles = var("LOGIN_RESPONSE_ROLES")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let introspection_cache_ttl = var("INTROSPECTION_CACHE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::ZERO);
+        let introspection_max_stale = var("INTROSPECTION_MAX_STALE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let keycloak_admin_concurrency = var("KEYCLOAK_ADMIN_CONCURRENCY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(0);
+        let keycloak_admin_queue_limits = var("KEYCLOAK_ADMIN_QUEUE_LIMITS")
+            .map(|value| {
+                split_list(&value)
+                    .into_iter()
+                    .filter_map(|entry| {
+                        let (queue, limit) = entry.split_once('=')?;
+                        let limit = limit.trim().parse::<usize>().ok()?;
+                        Some((queue.trim().to_owned(), limit))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let keycloak_background_wait = var("KEYCLOAK_BACKGROUND_WAIT_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        let read_only_mode = var("READ_ONLY_MODE")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let read_only_on_outage = var("READ_ONLY_ON_OUTAGE")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let register_duplicate_check = var("REGISTER_DUPLICATE_CHECK")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let register_duplicate_check_limit = var("REGISTER_DUPLICATE_CHECK_LIMIT")
+            .ok()
+            .and_then(|value| value.trim().parse::<u32>().ok())
+            .unwrap_or(10);
+        let config_snapshot_secret = var("CONFIG_SNAPSHOT_SECRET")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let config_snapshot_file = var("CONFIG_SNAPSHOT_FILE")
+            .ok()
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty());
+        let config_snapshot_interval = var("CONFIG_SNAPSHOT_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map_or(Some(Duration::from_secs(3600)), |secs| {
+                (secs > 0).then(|| Duration::from_secs(secs))
+            });
+        let internal_tls_bind = var("INTERNAL_TLS_BIND").ok();
+        let internal_tls_cert = var("INTERNAL_TLS_CERT").ok();
+        let internal_tls_key = var("INTERNAL_TLS_KEY").ok();
+        let internal_tls_client_ca = var("INTERNAL_TLS_CLIENT_CA").ok();
+        let internal_client_cert_header = var("INTERNAL_CLIENT_CERT_HEADER")
+            .ok()
+            .map(|value| value.trim().to_ascii_lowercase())
+            .filter(|value| !value.is_empty());
+        let internal_service_identities = var("INTERNAL_SERVICE_IDENTITIES")
+            .ok()
+            .map(|value| {
+                split_list(&value)
+                    .into_iter()
+                    .filter_map(|entry| {
+                        let (san, name) = entry.rsplit_once('=')?;
+                        Some((san.trim().to_owned(), name.trim().to_owned()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let internal_service_clients =
+            load_service_clients(var("INTERNAL_SERVICE_CLIENTS_FILE").ok().as_deref());
+        let admin_bind_address = var("ADMIN_BIND_ADDRESS")
+            .ok()
+            .and_then(|value| value.trim().parse::<SocketAddr>().ok());
+        let listen_socket_path = var("BACKEND_LISTEN").ok().and_then(|value| {
+            value
+                .trim()
+                .strip_prefix("unix:")
+                .map(str::to_owned)
+                .filter(|path| !path.is_empty())
+        });
+        let listen_socket_mode = var("BACKEND_SOCKET_MODE")
+            .ok()
+            .and_then(|value| u32::from_str_radix(value.trim(), 8).ok());
+        let tls_cert = var("BACKEND_TLS_CERT").ok();
+        let tls_key = var("BACKEND_TLS_KEY").ok();
+        let http2_enabled = var("BACKEND_HTTP2")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(true);
+        let h2c_enabled = var("BACKEND_H2C")
+            .map(|value| matches_ignore_ascii_case(&value, ["1", "true", "yes", "on"]))
+            .unwrap_or(false);
+        let drain_grace = var("DRAIN_GRACE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(15));
+        let shutdown_timeout = var("SHUTDOWN_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
 
         Self {
             bind_address,
             port,
+            captcha_provider,
+            altcha_hmac_key,
+            altcha_max_number,
+            altcha_challenge_ttl,
             turnstile_site_key,
             turnstile_secret_key,
             turnstile_verify_url,
+            turnstile_expected_hostnames,
+            turnstile_validate_action,
             keycloak_base_url,
             keycloak_realm,
             keycloak_admin_client_id,
@@ -118,7 +1440,234 @@ impl AppConfig {
             keycloak_public_client_secret,
             keycloak_tls_insecure,
             cors_allowed_origins,
+            trusted_proxies,
+            invalid_networks,
+            user_lookup_cache_capacity,
+            user_lookup_cache_ttl,
+            strict_request_bodies,
+            register_extra_max_keys,
+            register_extra_max_key_len,
+            register_extra_max_value_bytes,
+            tenants,
+            register_honeypot_field,
+            register_min_fill_time,
+            form_token_secret,
+            form_token_max_age,
+            email_strip_plus_tags,
+            email_strip_gmail_dots,
+            partners_file,
+            partner_assertion_audience,
+            referral_codes,
+            referral_validate_url,
+            admin_role,
+            admin_tenant_claim,
+            admin_tenant_attribute,
+            register_min_age,
+            store_date_of_birth,
+            auditor_role,
+            audit_log_file,
+            audit_log_capacity,
+            anomaly_window,
+            anomaly_failed_login_threshold,
+            anomaly_captcha_rejection_threshold,
+            anomaly_registration_threshold,
+            alert_webhook_url,
+            ip_allowlist,
+            ip_denylist,
+            admin_ip_allowlist,
+            admin_ip_denylist,
+            ip_filter_file,
+            ip_filter_reload_interval,
+            internal_tls_bind,
+            internal_tls_cert,
+            internal_tls_key,
+            internal_tls_client_ca,
+            internal_client_cert_header,
+            internal_service_identities,
+            internal_service_clients,
+            admin_bind_address,
+            listen_socket_path,
+            listen_socket_mode,
+            tls_cert,
+            tls_key,
+            http2_enabled,
+            h2c_enabled,
+            drain_grace,
+            shutdown_timeout,
+            keycloak_health_url,
+            keycloak_health_interval,
+            keycloak_unavailable_after,
+            admin_token_high_load_rps,
+            request_timeout,
+            route_timeouts,
+            uma_resource_server,
+            uma_decision_cache_capacity,
+            uma_decision_cache_ttl,
+            oidc_redirect_uris,
+            dpop_public_url,
+            dpop_proof_max_age,
+            session_backend,
+            redis_url,
+            session_store_prefix,
+            cookie_keys,
+            auth_cookie_name,
+            auth_cookie_secure,
+            auth_cookie_domain,
+            correlation_headers,
+            response_cache_ttl,
+            response_cache_capacity,
+            login_history_retention,
+            login_history_per_user,
+            registration_attributes,
+            keycloak_user_profile,
+            user_profile_cache_ttl,
+            webhooks_file,
+            webhook_secrets_key,
+            mail_delivery_url,
+            mail_delivery_secret,
+            webhook_max_attempts,
+            webhook_delivery_history,
+            outbox_file,
+            outbox_poll_interval,
+            outbox_dead_letter_capacity,
+            keycloak_event_poll_interval,
+            keycloak_event_types,
+            revocation_ttl,
+            reactivation_secret,
+            reactivation_url,
+            reactivation_link_ttl,
+            password_max_age_days,
+            password_change_url,
+            login_challenge_ttl,
+            mfa_check_client_id,
+            mfa_check_client_secret,
+            unverified_login,
+            terms_version,
+            terms_url,
+            keycloak_master_admin,
+            keycloak_master_password,
+            realm_spec_file,
+            realm_drift_check_interval,
+            initial_admin_email,
+            initial_admin_password_file,
+            captcha_exemption_secret,
+            captcha_exemption_max_ttl,
+            api_key_argon2_memory_kib,
+            api_key_argon2_iterations,
+            api_key_argon2_parallelism,
+            api_key_max_ttl,
+            min_client_versions,
+            client_upgrade_urls,
+            request_signing_keys,
+            request_signature_window,
+            body_log_enabled,
+            body_log_sample_rate,
+            body_log_routes,
+            body_log_max_bytes,
+            slo_targets,
+            slo_window,
+            refresh_hint_ratio,
+            refresh_hint_jitter,
+            max_sessions_per_user,
+            session_limit_policy,
+            auth_check_refresh_window,
+            keycloak_slow_call_threshold,
+            clock_skew_leeway,
+            ntp_server,
+            frontchannel_logout_url,
+            verified_callback_url,
+            verified_callback_secret,
+            verified_redirect_url,
+            verified_redirect_urls,
+            metrics_push_url,
+            metrics_push_interval,
+            metrics_push_job,
+            metrics_push_instance,
+            permissions_file,
+            ip_blocklist_file,
+            ip_blocklist_reload_interval,
+            abuseipdb_url,
+            abuseipdb_api_key,
+            ip_reputation_challenge_score,
+            ip_reputation_block_score,
+            ip_reputation_fail_open,
+            ip_reputation_cache_ttl,
+            login_notification_url,
+            login_notification_secret,
+            login_notification_link_ttl,
+            login_notifications_default,
+            social_login_hints,
+            login_location_header,
+            username_mode,
+            username_check_limit,
+            realm_backup_key,
+            realm_backup_dir,
+            realm_backup_s3_bucket,
+            realm_backup_s3_region,
+            realm_backup_s3_endpoint,
+            realm_backup_s3_prefix,
+            realm_backup_interval,
+            realm_backup_include_users,
+            realm_backup_retain,
+            aws_access_key_id,
+            aws_secret_access_key,
+            aws_session_token,
+            storage_dir,
+            storage_s3_bucket,
+            storage_s3_region,
+            storage_s3_endpoint,
+            storage_s3_prefix,
+            avatar_max_bytes,
+            avatar_public_url,
+            avatar_max_pixels,
+            avatar_max_side,
+            #[cfg(any(test, feature = "cassette"))]
+            keycloak_cassette,
+            #[cfg(any(test, feature = "cassette"))]
+            keycloak_cassette_mode,
+            auth_scopes,
+            login_response_roles,
+            introspection_cache_ttl,
+            introspection_max_stale,
+            keycloak_admin_concurrency,
+            keycloak_admin_queue_limits,
+            keycloak_background_wait,
+            read_only_mode,
+            read_only_on_outage,
+            register_duplicate_check,
+            register_duplicate_check_limit,
+            config_snapshot_interval,
+            config_snapshot_secret,
+            config_snapshot_file,
+        }
+    }
+
+    /// Settings that cannot work as given, which stop the server from
+    /// starting rather than run with a feature silently weakened.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(invalid) = self.invalid_networks.first() {
+            return Err(invalid.clone());
+        }
+        if self.verified_callback_url.is_some() && self.verified_callback_secret.is_none() {
+            return Err("VERIFIED_CALLBACK_URL requires VERIFIED_CALLBACK_SECRET".to_owned());
+        }
+        #[cfg(any(test, feature = "cassette"))]
+        cassette::check(self)?;
+        ip_filter::check(self)?;
+        api_keys::argon2_params(self)
+            .map_err(|err| format!("invalid API_KEY_ARGON2_* parameters: {err}"))?;
+        webhooks::check(self)?;
+        registration_schema::check(self)?;
+        if !self.cookie_keys.is_empty() && self.cors_allowed_origins.is_empty() {
+            return Err("COOKIE_KEYS requires BACKEND_ALLOWED_ORIGINS".to_owned());
+        }
+        if self.mail_delivery_url.is_some() && self.mail_delivery_secret.is_none() {
+            return Err("MAIL_DELIVERY_URL requires MAIL_DELIVERY_SECRET".to_owned());
         }
+        if self.login_notification_url.is_some() && self.login_notification_secret.is_none() {
+            return Err("LOGIN_NOTIFICATION_URL requires LOGIN_NOTIFICATION_SECRET".to_owned());
+        }
+        Ok(())
     }
 
     pub fn socket_addr(&self) -> SocketAddr {
@@ -127,6 +1676,13 @@ impl AppConfig {
             .unwrap_or_else(|_| SocketAddr::from(([127, 0, 0, 1], self.port)))
     }
 
+    pub fn email_alias_rules(&self) -> AliasRules {
+        AliasRules {
+            strip_plus_tags: self.email_strip_plus_tags,
+            strip_gmail_dots: self.email_strip_gmail_dots,
+        }
+    }
+
     pub fn keycloak_users_endpoint(&self) -> String {
         format!(
             "{}/admin/realms/{}/users",
@@ -135,6 +1691,48 @@ impl AppConfig {
         )
     }
 
+    pub fn keycloak_roles_endpoint(&self) -> String {
+        format!(
+            "{}/admin/realms/{}/roles",
+            self.keycloak_base(),
+            self.keycloak_realm
+        )
+    }
+
+    pub fn keycloak_events_endpoint(&self) -> String {
+        format!(
+            "{}/admin/realms/{}/events",
+            self.keycloak_base(),
+            self.keycloak_realm
+        )
+    }
+
+    pub fn password_change_url(&self) -> String {
+        self.password_change_url.clone().unwrap_or_else(|| {
+            format!(
+                "{}/realms/{}/account/#/security/signingin",
+                self.keycloak_base(),
+                self.keycloak_realm
+            )
+        })
+    }
+
+    pub fn keycloak_sessions_endpoint(&self) -> String {
+        format!(
+            "{}/admin/realms/{}/sessions",
+            self.keycloak_base(),
+            self.keycloak_realm
+        )
+    }
+
+    pub fn keycloak_session_stats_endpoint(&self) -> String {
+        format!(
+            "{}/admin/realms/{}/client-session-stats",
+            self.keycloak_base(),
+            self.keycloak_realm
+        )
+    }
+
     pub fn keycloak_token_endpoint(&self) -> String {
         format!(
             "{}/realms/{}/protocol/openid-connect/token",
@@ -151,11 +1749,90 @@ impl AppConfig {
         )
     }
 
+    /// The `iss` of tokens issued by the realm.
+    pub fn keycloak_issuer(&self) -> String {
+        format!("{}/realms/{}", self.keycloak_base(), self.keycloak_realm)
+    }
+
+    pub fn keycloak_authorization_endpoint(&self) -> String {
+        format!(
+            "{}/realms/{}/protocol/openid-connect/auth",
+            self.keycloak_base(),
+            self.keycloak_realm
+        )
+    }
+
+    pub fn keycloak_par_endpoint(&self) -> String {
+        format!(
+            "{}/realms/{}/protocol/openid-connect/ext/par/request",
+            self.keycloak_base(),
+            self.keycloak_realm
+        )
+    }
+
+    pub fn keycloak_introspect_endpoint(&self) -> String {
+        format!(
+            "{}/realms/{}/protocol/openid-connect/token/introspect",
+            self.keycloak_base(),
+            self.keycloak_realm
+        )
+    }
+
+    /// `KEYCLOAK_HEALTH_URL` when set (e.g. `/health/ready` on the management
+    /// port), otherwise the realm's public metadata endpoint.
+    pub fn keycloak_health_endpoint(&self) -> String {
+        self.keycloak_health_url
+            .clone()
+            .unwrap_or_else(|| self.keycloak_realm_endpoint())
+    }
+
+    pub fn keycloak_realm_endpoint(&self) -> String {
+        format!("{}/realms/{}", self.keycloak_base(), self.keycloak_realm)
+    }
+
     fn keycloak_base(&self) -> String {
         self.keycloak_base_url.trim_end_matches('/').to_owned()
     }
 }
 
+fn keycloak_http_client(config: &AppConfig) -> Client {
+    Client::builder()
+        .danger_accept_invalid_certs(config.keycloak_tls_insecure)
+        .danger_accept_invalid_hostnames(config.keycloak_tls_insecure)
+        .build()
+        .expect("failed to build Keycloak HTTP client")
+}
+
+/// `platform=value` pairs such as `ios=2.3.0,android=2.1.0`, keyed by the
+/// lower-cased platform.
+fn parse_platform_map(value: &str) -> Vec<(String, String)> {
+    split_list(value)
+        .into_iter()
+        .filter_map(|entry| {
+            let (platform, value) = entry.split_once('=')?;
+            Some((
+                platform.trim().to_ascii_lowercase(),
+                value.trim().to_owned(),
+            ))
+        })
+        .filter(|(platform, value)| !platform.is_empty() && !value.is_empty())
+        .collect()
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter_map(|item| {
+            let trimmed = item.trim();
+            if trimmed.is_empty() {
+                None
+            } else {
+                Some(trimmed.to_owned())
+            }
+        })
+        .collect()
+}
+
 fn matches_ignore_ascii_case(value: &str, choices: [&str; 4]) -> bool {
     let lowered = value.trim().to_ascii_lowercase();
     choices.iter().any(|candidate| lowered == *candidate)
@@ -163,19 +1840,96 @@ fn matches_ignore_ascii_case(value: &str, choices: [&str; 4]) -> bool {
 
 #[tokio::main]
 async fn main() {
+    if let Some(command) = cli::Command::from_args() {
+        return command.run().await;
+    }
+
+    reload::remember_environment();
     dotenv().ok();
     init_tracing();
 
     let config = AppConfig::from_env();
+    if let Err(err) = config.validate() {
+        error!("Invalid configuration: {}", err);
+        return;
+    }
     let http_client = Client::new();
-    let keycloak_client = Client::builder()
-        .danger_accept_invalid_certs(config.keycloak_tls_insecure)
-        .danger_accept_invalid_hostnames(config.keycloak_tls_insecure)
-        .build()
-        .expect("failed to build Keycloak HTTP client");
-    let keycloak = KeycloakService::bootstrap(&config, keycloak_client).await;
+    let metrics = Arc::new(Metrics::new());
+    let keycloak =
+        KeycloakService::bootstrap(&config, keycloak_http_client(&config), Arc::clone(&metrics))
+            .await;
+    initial_admin::seed(&config, &keycloak).await;
+    let sessions = session::connect(&config)
+        .await
+        .expect("failed to connect to the session store");
 
-    let app_state = AppState::new(config.clone(), http_client, keycloak);
+    let app_state = AppState::new(config.clone(), http_client, keycloak, metrics, sessions);
+    app_state.ip_filter.spawn_reload_task();
+    app_state.ip_reputation.spawn_reload_task();
+    app_state.webhooks.spawn_dispatcher();
+    app_state.revocations.spawn();
+    event_bridge::spawn(
+        &config,
+        Arc::clone(&app_state.keycloak),
+        Arc::clone(&app_state.webhooks),
+        Arc::clone(&app_state.sessions),
+    );
+    realm_diff::spawn(
+        &config,
+        Arc::clone(&app_state.keycloak),
+        Arc::clone(&app_state.metrics),
+        Arc::clone(&app_state.webhooks),
+    );
+    app_state.keycloak_health.spawn_poller(
+        Arc::clone(&app_state.keycloak),
+        Arc::clone(&app_state.metrics),
+    );
+    metrics_push::spawn(app_state.clone());
+    realm_backup::spawn(app_state.clone());
+    config_snapshot::spawn(app_state.clone());
+    reload::spawn_signal_handler(app_state.clone());
+    let protocols = Protocols::from_config(&config);
+    let lifecycle = Arc::clone(&app_state.lifecycle);
+    lifecycle.spawn_signal_handlers(config.drain_grace);
+
+    if let Some(settings) = InternalTlsSettings::from_config(&config) {
+        let internal_router = create_internal_router(app_state.clone());
+        let lifecycle = Arc::clone(&lifecycle);
+        tokio::spawn(async move {
+            if let Err(err) =
+                serve_internal_tls(internal_router, settings, protocols, lifecycle).await
+            {
+                error!(?err, "Internal mTLS listener crashed");
+            }
+        });
+    }
+
+    if let Some(admin_addr) = config.admin_bind_address {
+        let admin_router = create_admin_router(app_state.clone());
+        let listener = match systemd::activated_listener("admin") {
+            Some(listener) => Ok(listener),
+            None => Listener::bind_tcp(admin_addr).await,
+        };
+        match listener {
+            Ok(listener) => {
+                info!(%admin_addr, "Serving operational routes on the admin listener");
+                let lifecycle = Arc::clone(&lifecycle);
+                tokio::spawn(async move {
+                    if let Err(err) =
+                        serve(admin_router, listener, None, protocols, lifecycle).await
+                    {
+                        error!(?err, "Admin listener crashed");
+                    }
+                });
+            }
+            Err(err) => error!(?err, "Unable to bind admin listener"),
+        }
+    }
+
+    let (audit, webhooks) = (
+        Arc::clone(&app_state.audit),
+        Arc::clone(&app_state.webhooks),
+    );
     let router: Router = create_router(app_state);
     let addr = config.socket_addr();
 
@@ -185,17 +1939,50 @@ async fn main() {
         client_id = %config.keycloak_admin_client_id,
         public_client = %config.keycloak_public_client_id,
         insecure_tls = %config.keycloak_tls_insecure,
+        tenants = config.tenants.len(),
+        captcha = config.captcha_provider.as_str(),
+        tls = config.tls_cert.is_some(),
+        http2 = config.http2_enabled,
+        h2c = config.h2c_enabled,
         "Starting Keycloak backend proxy"
     );
 
-    if let Err(err) = start_server(router, addr).await {
+    let listener = match systemd::activated_listener("public") {
+        Some(listener) => Ok(listener),
+        None => match config.listen_socket_path.as_deref() {
+            Some(path) => Listener::bind_unix(path, config.listen_socket_mode),
+            None => Listener::bind_tcp(addr).await,
+        },
+    };
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(?err, "Unable to bind listener");
+            return;
+        }
+    };
+    let tls = match public_tls(&config, protocols) {
+        Ok(tls) => tls,
+        Err(err) => {
+            error!(?err, "Unable to load TLS certificate");
+            return;
+        }
+    };
+
+    // The Keycloak admin token was acquired during bootstrap, so the service
+    // can take traffic from here on.
+    systemd::notify("READY=1");
+
+    if let Err(err) = serve(router, listener, tls, protocols, Arc::clone(&lifecycle)).await {
         error!(?err, "Server crashed");
     }
-}
 
-async fn start_server(app: Router, addr: SocketAddr) -> Result<(), std::io::Error> {
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await
+    systemd::notify("STOPPING=1");
+    lifecycle
+        .wait_for_connections(config.shutdown_timeout)
+        .await;
+    tokio::join!(audit.flush(), webhooks.flush());
+    info!("Shutdown complete");
 }
 
 fn init_tracing() {
@@ -0,0 +1,408 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use prometheus::{
+    Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+
+const NAMESPACE: &str = "argus_portal";
+const KEYCLOAK_REQUEST_BUCKETS: [f64; 10] =
+    [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// A sampled trace that landed in a histogram bucket.
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    at: f64,
+}
+
+/// The latest exemplar per endpoint, outcome and bucket index.
+type Exemplars = HashMap<(&'static str, &'static str, usize), Exemplar>;
+
+pub struct Metrics {
+    registry: Registry,
+    keycloak_request_exemplars: Mutex<Exemplars>,
+    pub captcha_verifications: IntCounterVec,
+    pub captcha_error_codes: IntCounterVec,
+    pub captcha_warnings: LogSampler,
+    pub bot_trap_rejections: IntCounterVec,
+    pub anomaly_alerts: IntCounterVec,
+    pub keycloak_up: IntGauge,
+    pub keycloak_health_check_seconds: HistogramVec,
+    pub admin_token_remaining_seconds: Gauge,
+    pub admin_token_refresh_failures: IntGauge,
+    pub realm_drift_settings: IntGauge,
+    pub client_upgrade_required: IntCounterVec,
+    pub slo_burn_rate: GaugeVec,
+    pub slo_error_budget_remaining: GaugeVec,
+    pub keycloak_request_seconds: HistogramVec,
+    pub ip_reputation_decisions: IntCounterVec,
+    pub keycloak_admin_queue_depth: IntGaugeVec,
+    pub keycloak_admin_in_flight: IntGauge,
+    pub keycloak_admin_shed: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(NAMESPACE.to_owned()), None)
+            .expect("metrics registry prefix is valid");
+
+        let captcha_verifications = IntCounterVec::new(
+            Opts::new(
+                "captcha_verifications_total",
+                "Captcha verification attempts by provider and outcome",
+            ),
+            &["provider", "outcome"],
+        )
+        .expect("captcha verification metric is valid");
+        let captcha_error_codes = IntCounterVec::new(
+            Opts::new(
+                "captcha_error_codes_total",
+                "Error codes returned by the captcha provider",
+            ),
+            &["code", "class"],
+        )
+        .expect("captcha error code metric is valid");
+
+        let bot_trap_rejections = IntCounterVec::new(
+            Opts::new(
+                "bot_trap_rejections_total",
+                "Registrations rejected by honeypot or time-trap checks",
+            ),
+            &["reason"],
+        )
+        .expect("bot trap metric is valid");
+        let anomaly_alerts = IntCounterVec::new(
+            Opts::new(
+                "anomaly_alerts_total",
+                "Abuse alerts raised by the anomaly detector",
+            ),
+            &["kind", "scope"],
+        )
+        .expect("anomaly alert metric is valid");
+        let keycloak_up = IntGauge::new(
+            "keycloak_up",
+            "Whether the last Keycloak health check succeeded",
+        )
+        .expect("keycloak up metric is valid");
+        let keycloak_health_check_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "keycloak_health_check_seconds",
+                "Latency of Keycloak health checks by outcome",
+            ),
+            &["outcome"],
+        )
+        .expect("keycloak health check metric is valid");
+        let admin_token_remaining_seconds = Gauge::new(
+            "keycloak_admin_token_remaining_seconds",
+            "Seconds until the held Keycloak admin token expires",
+        )
+        .expect("admin token lifetime metric is valid");
+        let admin_token_refresh_failures = IntGauge::new(
+            "keycloak_admin_token_refresh_failures",
+            "Consecutive failed admin token refreshes since the last success",
+        )
+        .expect("admin token refresh failure metric is valid");
+        let realm_drift_settings = IntGauge::new(
+            "realm_drift_settings",
+            "Realm settings that differed from the spec at the last drift check",
+        )
+        .expect("realm drift metric is valid");
+        let client_upgrade_required = IntCounterVec::new(
+            Opts::new(
+                "client_upgrade_required_total",
+                "Auth requests rejected because the client app is below the minimum version",
+            ),
+            &["platform"],
+        )
+        .expect("client upgrade metric is valid");
+        let slo_burn_rate = GaugeVec::new(
+            Opts::new(
+                "slo_burn_rate",
+                "Rate at which each route spends its error budget, by window",
+            ),
+            &["route", "window"],
+        )
+        .expect("slo burn rate metric is valid");
+        let slo_error_budget_remaining = GaugeVec::new(
+            Opts::new(
+                "slo_error_budget_remaining",
+                "Share of each route's error budget left in the SLO window",
+            ),
+            &["route"],
+        )
+        .expect("slo error budget metric is valid");
+        let keycloak_request_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "keycloak_request_seconds",
+                "Latency of outbound Keycloak calls by endpoint and outcome",
+            )
+            .buckets(KEYCLOAK_REQUEST_BUCKETS.to_vec()),
+            &["endpoint", "outcome"],
+        )
+        .expect("keycloak request metric is valid");
+        let ip_reputation_decisions = IntCounterVec::new(
+            Opts::new(
+                "ip_reputation_decisions_total",
+                "Registration sources by IP reputation decision",
+            ),
+            &["decision"],
+        )
+        .expect("ip reputation metric is valid");
+        let keycloak_admin_queue_depth = IntGaugeVec::new(
+            Opts::new(
+                "keycloak_admin_queue_depth",
+                "Keycloak admin API calls waiting for a permit, by queue",
+            ),
+            &["queue"],
+        )
+        .expect("keycloak admin queue metric is valid");
+        let keycloak_admin_in_flight = IntGauge::new(
+            "keycloak_admin_in_flight",
+            "Keycloak admin API calls holding a permit",
+        )
+        .expect("keycloak admin in-flight metric is valid");
+        let keycloak_admin_shed = IntCounterVec::new(
+            Opts::new(
+                "keycloak_admin_shed_total",
+                "Background Keycloak admin API calls shed while Keycloak was saturated",
+            ),
+            &["queue"],
+        )
+        .expect("keycloak admin shed metric is valid");
+
+        registry
+            .register(Box::new(captcha_verifications.clone()))
+            .expect("captcha verification metric registers once");
+        registry
+            .register(Box::new(captcha_error_codes.clone()))
+            .expect("captcha error code metric registers once");
+        registry
+            .register(Box::new(bot_trap_rejections.clone()))
+            .expect("bot trap metric registers once");
+        registry
+            .register(Box::new(anomaly_alerts.clone()))
+            .expect("anomaly alert metric registers once");
+        registry
+            .register(Box::new(keycloak_up.clone()))
+            .expect("keycloak up metric registers once");
+        registry
+            .register(Box::new(keycloak_health_check_seconds.clone()))
+            .expect("keycloak health check metric registers once");
+        registry
+            .register(Box::new(admin_token_remaining_seconds.clone()))
+            .expect("admin token lifetime metric registers once");
+        registry
+            .register(Box::new(admin_token_refresh_failures.clone()))
+            .expect("admin token refresh failure metric registers once");
+        registry
+            .register(Box::new(realm_drift_settings.clone()))
+            .expect("realm drift metric registers once");
+        registry
+            .register(Box::new(client_upgrade_required.clone()))
+            .expect("client upgrade metric registers once");
+        registry
+            .register(Box::new(slo_burn_rate.clone()))
+            .expect("slo burn rate metric registers once");
+        registry
+            .register(Box::new(slo_error_budget_remaining.clone()))
+            .expect("slo error budget metric registers once");
+        registry
+            .register(Box::new(keycloak_request_seconds.clone()))
+            .expect("keycloak request metric registers once");
+        registry
+            .register(Box::new(ip_reputation_decisions.clone()))
+            .expect("ip reputation metric registers once");
+        registry
+            .register(Box::new(keycloak_admin_queue_depth.clone()))
+            .expect("keycloak admin queue metric registers once");
+        registry
+            .register(Box::new(keycloak_admin_in_flight.clone()))
+            .expect("keycloak admin in-flight metric registers once");
+        registry
+            .register(Box::new(keycloak_admin_shed.clone()))
+            .expect("keycloak admin shed metric registers once");
+
+        Self {
+            registry,
+            keycloak_request_exemplars: Mutex::new(HashMap::new()),
+            captcha_verifications,
+            captcha_error_codes,
+            captcha_warnings: LogSampler::new(Duration::from_secs(60)),
+            bot_trap_rejections,
+            anomaly_alerts,
+            keycloak_up,
+            keycloak_health_check_seconds,
+            admin_token_remaining_seconds,
+            admin_token_refresh_failures,
+            realm_drift_settings,
+            client_upgrade_required,
+            slo_burn_rate,
+            slo_error_budget_remaining,
+            keycloak_request_seconds,
+            ip_reputation_decisions,
+            keycloak_admin_queue_depth,
+            keycloak_admin_in_flight,
+            keycloak_admin_shed,
+        }
+    }
+
+    /// Records a Keycloak call in `keycloak_request_seconds`, keeping
+    /// `trace_id` as the exemplar of the bucket it fell into.
+    pub fn observe_keycloak_request(
+        &self,
+        endpoint: &'static str,
+        outcome: &'static str,
+        seconds: f64,
+        trace_id: Option<String>,
+    ) {
+        self.keycloak_request_seconds
+            .with_label_values(&[endpoint, outcome])
+            .observe(seconds);
+        let Some(trace_id) = trace_id else {
+            return;
+        };
+        let bucket = KEYCLOAK_REQUEST_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(KEYCLOAK_REQUEST_BUCKETS.len());
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        self.keycloak_request_exemplars
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .insert(
+                (endpoint, outcome, bucket),
+                Exemplar {
+                    trace_id,
+                    value: seconds,
+                    at,
+                },
+            );
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        let encoder = TextEncoder::new();
+        if let Err(err) = encoder.encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!(?err, "Unable to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    /// Renders every metric in the OpenMetrics format, with the trace
+    /// exemplars of `keycloak_request_seconds` on its buckets. The text
+    /// format has no place for them.
+    pub fn render_openmetrics(&self) -> String {
+        let text = self.render();
+        let counters: HashSet<&str> = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("# TYPE "))
+            .filter_map(|line| line.strip_suffix(" counter"))
+            .collect();
+        let exemplars = self
+            .keycloak_request_exemplars
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let buckets = format!("{NAMESPACE}_keycloak_request_seconds_bucket{{");
+
+        let mut output = String::with_capacity(text.len() + 16);
+        for line in text.lines() {
+            if let Some((kind, rest)) = line
+                .strip_prefix("# HELP ")
+                .map(|rest| ("# HELP ", rest))
+                .or_else(|| line.strip_prefix("# TYPE ").map(|rest| ("# TYPE ", rest)))
+            {
+                // OpenMetrics names a counter family without its `_total`.
+                let (mut name, mut tail) = rest.split_once(' ').unwrap_or((rest, ""));
+                if counters.contains(name) {
+                    name = name.strip_suffix("_total").unwrap_or(name);
+                }
+                if kind == "# TYPE " && tail == "untyped" {
+                    tail = "unknown";
+                }
+                output.push_str(&format!("{kind}{name} {tail}"));
+            } else {
+                output.push_str(line);
+                if line.starts_with(&buckets)
+                    && let Some(exemplar) = bucket_exemplar(line, &exemplars)
+                {
+                    output.push_str(&format!(
+                        " # {{trace_id=\"{}\"}} {} {:.3}",
+                        exemplar.trace_id, exemplar.value, exemplar.at
+                    ));
+                }
+            }
+            output.push('\n');
+        }
+        output.push_str("# EOF\n");
+        output
+    }
+}
+
+/// The exemplar for a `keycloak_request_seconds_bucket` sample line.
+fn bucket_exemplar<'a>(line: &str, exemplars: &'a Exemplars) -> Option<&'a Exemplar> {
+    let labels = line.split_once('{')?.1.split_once('}')?.0;
+    let label = |name: &str| {
+        labels.split("\",").find_map(|pair| {
+            pair.strip_prefix(name)?
+                .strip_prefix("=\"")
+                .map(|value| value.trim_end_matches('"'))
+        })
+    };
+    let (endpoint, outcome) = (label("endpoint")?, label("outcome")?);
+    let bound: f64 = label("le")?.parse().ok()?;
+    let bucket = KEYCLOAK_REQUEST_BUCKETS
+        .iter()
+        .position(|candidate| *candidate == bound)
+        .unwrap_or(KEYCLOAK_REQUEST_BUCKETS.len());
+    exemplars
+        .iter()
+        .find(|((e, o, b), _)| *e == endpoint && *o == outcome && *b == bucket)
+        .map(|(_, exemplar)| exemplar)
+}
+
+/// Lets a caller emit at most one log line per key and interval, reporting how
+/// many occurrences were folded into it.
+pub struct LogSampler {
+    interval: Duration,
+    state: Mutex<HashMap<String, (Instant, u64)>>,
+}
+
+impl LogSampler {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `Some(occurrences)` when the caller should log now.
+    pub fn sample(&self, key: &str) -> Option<u64> {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let now = Instant::now();
+        match state.get_mut(key) {
+            Some((last, suppressed)) if now.duration_since(*last) < self.interval => {
+                *suppressed += 1;
+                None
+            }
+            Some((last, suppressed)) => {
+                let occurrences = *suppressed + 1;
+                *last = now;
+                *suppressed = 0;
+                Some(occurrences)
+            }
+            None => {
+                state.insert(key.to_owned(), (now, 0));
+                Some(1)
+            }
+        }
+    }
+}
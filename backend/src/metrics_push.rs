@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::AppState;
+use crate::handlers::metrics::render;
+
+/// Pushes the portal's metrics to a Prometheus Pushgateway every
+/// `METRICS_PUSH_INTERVAL_SECS`, for deployments Prometheus cannot scrape.
+/// Each push replaces the group `job/<METRICS_PUSH_JOB>/instance/<instance>`,
+/// where the instance is `METRICS_PUSH_INSTANCE` or the host name, so
+/// replicas do not overwrite each other. `/metrics` keeps working alongside.
+pub fn spawn(state: AppState) {
+    let Some(base) = state.config.metrics_push_url.as_deref() else {
+        return;
+    };
+    let Some(url) = group_url(
+        base,
+        &state.config.metrics_push_job,
+        &state.config.metrics_push_instance,
+    ) else {
+        warn!("[MetricsPush] METRICS_PUSH_URL {} is not a valid URL", base);
+        return;
+    };
+    let interval = state.config.metrics_push_interval;
+
+    info!(
+        "[MetricsPush] pushing to {} every {}s",
+        url,
+        interval.as_secs()
+    );
+    tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+            push(&state, &url, interval).await;
+        }
+    });
+}
+
+async fn push(state: &AppState, url: &Url, interval: Duration) {
+    let body = render(state).await;
+    let result = state
+        .http_client
+        .put(url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .timeout(interval)
+        .body(body)
+        .send()
+        .await;
+    match result {
+        Ok(response) if response.status().is_success() => {
+            debug!("[MetricsPush] pushed, status={}", response.status());
+        }
+        Ok(response) => warn!("[MetricsPush] push rejected, status={}", response.status()),
+        Err(err) => warn!("[MetricsPush] push failed: {}", err),
+    }
+}
+
+fn group_url(base: &str, job: &str, instance: &str) -> Option<Url> {
+    let mut url = Url::parse(base).ok()?;
+    url.path_segments_mut()
+        .ok()?
+        .pop_if_empty()
+        .extend(["metrics", "job", job, "instance", instance]);
+    Some(url)
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactivationRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactivateRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotMeRequest {
+    pub token: String,
+}
+
+/// Added by the portal to the `redirect_uri` of verification emails.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct VerifiedCallbackQuery {
+    pub user: Option<String>,
+    pub sig: Option<String>,
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountResponse {
+    pub message: String,
+}
+
+impl AccountResponse {
+    pub fn new(message: &str) -> Self {
+        Self {
+            message: message.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvatarResponse {
+    pub avatar_url: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AvatarQuery {
+    pub v: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Preferences {
+    pub login_alerts: bool,
+    pub marketing_emails: bool,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+/// Only the fields sent are changed; an empty `locale` or `timezone` clears
+/// it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PreferencesUpdate {
+    #[serde(default)]
+    pub login_alerts: Option<bool>,
+    #[serde(default)]
+    pub marketing_emails: Option<bool>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+
+use crate::account_merge::{AttributePolicy, MergeReport};
+use crate::api_keys::{ApiKey, ListedKey};
+use crate::audit::AuditEvent;
+use crate::body_logging::BodyLogSettings;
+use crate::captcha_exemption::Exemption;
+use crate::rate_limit::Override;
+use crate::read_only::ReadOnlyReason;
+use crate::slo::SloReport;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferralStatsResponse {
+    pub since: u64,
+    pub total_conversions: u64,
+    pub codes: Vec<ReferralCodeStats>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferralCodeStats {
+    pub code: String,
+    pub conversions: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub actor: Option<String>,
+    #[serde(default)]
+    pub action: Option<String>,
+    #[serde(default)]
+    pub from: Option<u64>,
+    #[serde(default)]
+    pub to: Option<u64>,
+    #[serde(default)]
+    pub page: Option<usize>,
+    #[serde(default)]
+    pub page_size: Option<usize>,
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditPageResponse {
+    pub page: usize,
+    pub page_size: usize,
+    pub total: usize,
+    pub entries: Vec<AuditEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertsQuery {
+    /// Include acknowledged alerts as well.
+    #[serde(default)]
+    pub all: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DrainResponse {
+    pub status: &'static str,
+    pub grace_secs: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReloadResponse {
+    /// Environment variables of the settings that took new values.
+    pub changed: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverviewResponse {
+    /// `None` when Keycloak could not be asked.
+    pub user_count: Option<u64>,
+    pub active_sessions: Option<u64>,
+    pub registrations_today: usize,
+    pub registrations_this_week: usize,
+    pub failed_logins: FailedLoginStats,
+    pub identity_provider: IdentityProviderStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedLoginStats {
+    pub window_secs: u64,
+    pub attempts: usize,
+    pub failures: usize,
+    pub rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityProviderStatus {
+    pub status: &'static str,
+    pub token_refresh_failures: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptchaExemptionRequest {
+    pub label: String,
+    /// Captcha actions the token covers; all of them when omitted.
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaptchaExemptionResponse {
+    #[serde(flatten)]
+    pub exemption: Exemption,
+    /// Only returned when the exemption is issued.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaptchaExemptionListResponse {
+    pub exemptions: Vec<Exemption>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyRequest {
+    pub label: String,
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    /// Only returned when the key is issued.
+    #[serde(rename = "apiKey", skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyListResponse {
+    pub keys: Vec<ListedKey>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitOverrideRequest {
+    pub label: String,
+    /// Addresses or CIDRs.
+    #[serde(default)]
+    pub networks: Vec<String>,
+    /// Prefix of an issued API key.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub bucket: Option<String>,
+    /// Requests per window; the callers are exempt when omitted.
+    #[serde(default)]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RateLimitOverrideListResponse {
+    pub overrides: Vec<Override>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadOnlyResponse {
+    /// Whether writes are refused right now, by hand or for an outage.
+    pub enabled: bool,
+    /// The manual switch.
+    pub manual: bool,
+    /// Why writes are refused right now, if they are.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<ReadOnlyReason>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReadOnlyRequest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoggingResponse {
+    pub body_logging: BodyLogSettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoggingRequest {
+    #[serde(default)]
+    pub body_logging: Option<BodyLoggingRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BodyLoggingRequest {
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    #[serde(default)]
+    pub sample_rate: Option<f64>,
+    #[serde(default)]
+    pub routes: Option<Vec<String>>,
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MergeUsersRequest {
+    pub primary_id: String,
+    pub duplicate_id: String,
+    #[serde(default)]
+    pub attributes: AttributePolicy,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeUsersResponse {
+    pub primary_id: String,
+    pub duplicate_id: String,
+    #[serde(flatten)]
+    pub report: MergeReport,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RealmExportQuery {
+    /// Adds the realm's users to the export.
+    #[serde(default)]
+    pub users: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SloResponse {
+    pub window_secs: u64,
+    pub routes: Vec<SloReport>,
+}
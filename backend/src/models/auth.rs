@@ -1,11 +1,29 @@
+use std::collections::{BTreeMap, HashMap};
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::login_challenge::ChallengeKind;
+use crate::login_history::LoginAttempt;
+use crate::validation::rfc3339;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LoginRequest {
+    #[serde(alias = "username")]
     pub email: String,
     pub password: String,
+    /// One-time code for accounts with an OTP credential.
+    #[serde(default)]
+    pub otp: Option<String>,
+    #[serde(default, alias = "captcha_token")]
     pub captcha_token: Option<String>,
+    /// Space-separated subset of the allowed scopes; all of them when left
+    /// out.
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
 }
 
 #[derive(Debug, Serialize)]
@@ -13,20 +31,401 @@ pub struct LoginRequest {
 pub struct AuthResponse {
     pub token_type: String,
     pub access_token: String,
+    /// Empty, and left out, when the refresh token was delivered as an
+    /// encrypted cookie instead.
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub refresh_token: String,
     pub expires_in: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub refresh_expires_in: Option<u64>,
+    /// Set when the tokens are bound to the key of the `DPoP` proof sent with
+    /// the request: every use must then carry `Authorization: DPoP <token>`
+    /// and a fresh proof from the same key, including refreshes.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dpop_bound: bool,
+    /// Days left before the password reaches `PASSWORD_MAX_AGE_DAYS`; only
+    /// present when a maximum age is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_expires_in_days: Option<i64>,
+    /// Set under `UNVERIFIED_LOGIN=limited` when the email is not verified
+    /// yet; the client should only offer what an unverified user may do.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub email_unverified: bool,
+    /// Only in `application/vnd.argus.v2+json` answers: what the access
+    /// token says about the user, so clients need not decode the JWT.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claims: Option<Box<TokenClaims>>,
+    /// With `LOGIN_RESPONSE_ROLES`: the access token's realm roles, client
+    /// roles keyed by client id, and groups, for route guards in the SPA.
+    /// Read from the token without checking it, so only good for the UI;
+    /// the API decides on its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub realm_roles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_roles: Option<BTreeMap<String, Vec<String>>>,
+    /// Empty unless the client has a group membership mapper.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+}
+
+/// The access token payload as Keycloak writes it.
+#[derive(Debug, Default, Deserialize)]
+pub struct AccessTokenClaims {
+    #[serde(default)]
+    pub sub: Option<String>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub sid: Option<String>,
+    #[serde(default)]
+    pub iat: Option<u64>,
+    #[serde(default)]
+    pub exp: Option<u64>,
+    #[serde(default)]
+    pub auth_time: Option<u64>,
+    #[serde(default)]
+    pub acr: Option<String>,
+    #[serde(default)]
+    pub realm_access: Option<RealmAccess>,
+    #[serde(default)]
+    pub resource_access: HashMap<String, RealmAccess>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// v2 view of the access token claims. Timestamps are unix seconds on
+/// Keycloak's clock.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenClaims {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Realm roles.
+    pub roles: Vec<String>,
+    /// Client roles, keyed by client id.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub client_roles: BTreeMap<String, Vec<String>>,
+    pub scopes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acr: Option<String>,
+}
+
+impl From<AccessTokenClaims> for TokenClaims {
+    fn from(claims: AccessTokenClaims) -> Self {
+        Self {
+            subject: claims.sub,
+            username: claims.preferred_username,
+            email: claims.email,
+            email_verified: claims.email_verified,
+            name: claims.name,
+            roles: claims
+                .realm_access
+                .map(|access| access.roles)
+                .unwrap_or_default(),
+            client_roles: claims
+                .resource_access
+                .into_iter()
+                .map(|(client, access)| (client, access.roles))
+                .collect(),
+            scopes: claims
+                .scope
+                .as_deref()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect(),
+            session_id: claims.sid,
+            issued_at: claims.iat,
+            expires_at: claims.exp,
+            auth_time: claims.auth_time,
+            acr: claims.acr,
+        }
+    }
+}
+
+/// Answer of `GET /api/auth/check` for a live session.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthCheckResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Seconds the current access token stays valid.
+    pub expires_in: u64,
+    /// New tokens when the check refreshed the session; the refresh token
+    /// itself stays in the cookie.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<AuthResponse>,
+}
+
+/// Outcome of a login: either tokens, or a challenge the client has to
+/// resolve through `/api/auth/login/continue` before it gets them.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginResponse {
+    Tokens(AuthResponse),
+    Challenge(LoginChallenge),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginChallenge {
+    #[serde(rename = "type")]
+    pub kind: ChallengeKind,
+    pub continuation_token: String,
+    pub expires_in: u64,
+    pub message: String,
+    /// Where the user resolves the challenge before continuing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action_url: Option<String>,
+    /// Follow-up requests the client may offer while the challenge is
+    /// pending, e.g. `resend_verification`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<&'static str>,
+    /// The version a `terms_update_required` challenge asks to accept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terms_version: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginContinueRequest {
+    pub continuation_token: String,
+    /// Required when the challenge did not hold on to the tokens, i.e. for
+    /// `mfa_required` and `password_expired`.
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub otp: Option<String>,
+    #[serde(default, alias = "captcha_token")]
+    pub captcha_token: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResendVerificationRequest {
+    pub continuation_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcceptTermsRequest {
+    pub continuation_token: String,
+    /// Must match the version from the challenge, so that terms changed in
+    /// the meantime are not accepted unseen.
+    pub version: String,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RefreshRequest {
+    /// May be omitted in cookie mode, where the refresh cookie is used.
+    #[serde(default, alias = "refresh_token")]
     pub refresh_token: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LogoutRequest {
+    #[serde(default)]
     pub refresh_token: String,
 }
+
+/// Sent by Keycloak on front-channel logout when the client requires the
+/// session to be named.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FrontChannelLogoutQuery {
+    pub iss: Option<String>,
+    pub sid: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TokenIntrospection {
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub sub: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub exp: Option<u64>,
+    #[serde(default)]
+    pub iat: Option<u64>,
+    #[serde(default)]
+    pub auth_time: Option<u64>,
+    #[serde(default)]
+    pub acr: Option<String>,
+    /// The client the token was issued to.
+    #[serde(default)]
+    pub azp: Option<String>,
+    /// Keycloak's user session id.
+    #[serde(default)]
+    pub sid: Option<String>,
+    #[serde(default)]
+    pub realm_access: Option<RealmAccess>,
+    /// Client roles, keyed by client id.
+    #[serde(default)]
+    pub resource_access: HashMap<String, RealmAccess>,
+    #[serde(default)]
+    pub cnf: Option<Confirmation>,
+    /// Every other claim, such as one mapped onto the token by the realm.
+    #[serde(flatten)]
+    pub claims: HashMap<String, Value>,
+}
+
+/// RFC 9449 key confirmation; `jkt` is the thumbprint a DPoP-bound token is
+/// tied to.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Confirmation {
+    #[serde(default)]
+    pub jkt: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RealmAccess {
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl TokenIntrospection {
+    pub fn realm_roles(&self) -> Vec<String> {
+        self.realm_access
+            .as_ref()
+            .map(|access| access.roles.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn client_roles(&self, client: &str) -> &[String] {
+        self.resource_access
+            .get(client)
+            .map(|access| access.roles.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn dpop_thumbprint(&self) -> Option<&str> {
+        self.cnf.as_ref()?.jkt.as_deref()
+    }
+
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.as_deref().unwrap_or_default().split_whitespace()
+    }
+
+    /// Whether the token carries `scope` or the realm role `role`. The two
+    /// are checked separately, so a role named like the scope, or a scope
+    /// named like the role, does not count.
+    pub fn grants(&self, scope: &str, role: &str) -> bool {
+        self.scopes().any(|granted| granted == scope)
+            || self
+                .realm_access
+                .as_ref()
+                .is_some_and(|access| access.roles.iter().any(|granted| granted == role))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PermissionQuery {
+    pub resource: String,
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PermissionResponse {
+    pub resource: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    pub granted: bool,
+}
+
+/// A point in time as Unix seconds and as RFC 3339 in UTC.
+#[derive(Debug, Serialize)]
+pub struct Timestamp {
+    pub epoch: u64,
+    pub rfc3339: String,
+}
+
+impl Timestamp {
+    pub fn new(epoch: u64) -> Self {
+        Self {
+            epoch,
+            rfc3339: rfc3339(epoch),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfoResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticated_at: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<Timestamp>,
+    /// When the access token expires.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<Timestamp>,
+    /// When the refresh token in the cookie expires, i.e. when the user is
+    /// signed out if the session stays idle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_expires_at: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_started_at: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_access_at: Option<Timestamp>,
+    /// The user's preferred time zone, for displaying the times above.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    pub server_time: Timestamp,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserPermissionsResponse {
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginActivityResponse {
+    pub attempts: Vec<LoginAttempt>,
+}
@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+use crate::registration_schema::AttributeSpec;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicConfigResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub captcha_provider: &'static str,
+    pub turnstile_site_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub honeypot_field: Option<String>,
+    /// What a login may name in `scope`.
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationSchemaResponse {
+    pub fields: Vec<AttributeSpec>,
+    /// Whether attributes beyond `fields` are accepted as well.
+    pub additional_attributes: bool,
+}
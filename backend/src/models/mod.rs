@@ -1,2 +1,10 @@
+pub mod account;
+pub mod admin;
 pub mod auth;
+pub mod config;
+pub mod health;
+pub mod internal;
+pub mod oauth;
+pub mod problem;
 pub mod user;
+pub mod webhook;
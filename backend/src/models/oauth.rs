@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::auth::{AuthResponse, LoginChallenge};
+
+#[derive(Debug, Deserialize)]
+pub struct OAuthTokenRequest {
+    pub grant_type: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub captcha_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OAuthTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    pub refresh_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_expires_in: Option<u64>,
+    /// As in `/api/auth/login` with `LOGIN_RESPONSE_ROLES`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub realm_roles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_roles: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+}
+
+impl From<AuthResponse> for OAuthTokenResponse {
+    fn from(response: AuthResponse) -> Self {
+        Self {
+            access_token: response.access_token,
+            token_type: response.token_type,
+            expires_in: response.expires_in,
+            refresh_token: response.refresh_token,
+            refresh_expires_in: response.refresh_expires_in,
+            realm_roles: response.realm_roles,
+            client_roles: response.client_roles,
+            groups: response.groups,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OAuthErrorResponse {
+    pub error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+    /// With `interaction_required`: the login challenge to resolve.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<LoginChallenge>,
+}
+
+impl OAuthErrorResponse {
+    pub fn new(error: &'static str, description: impl Into<String>) -> Self {
+        Self {
+            error,
+            error_description: Some(description.into()),
+            challenge: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeRequest {
+    pub redirect_uri: String,
+    pub state: String,
+    pub code_challenge: String,
+    #[serde(default)]
+    pub code_challenge_method: Option<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default)]
+    pub login_hint: Option<String>,
+    /// The portal's current language, space separated tags as in OIDC
+    /// `ui_locales`; the browser's `Accept-Language` follows it.
+    #[serde(default)]
+    pub ui_locales: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeResponse {
+    pub request_uri: String,
+    pub expires_in: u64,
+    pub authorization_url: String,
+}
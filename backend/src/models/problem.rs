@@ -0,0 +1,17 @@
+use serde::Serialize;
+
+use crate::error_codes::ErrorCode;
+
+/// RFC 9457 `application/problem+json` body.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// The same registry code `ErrorResponse` carries.
+    pub code: ErrorCode,
+}
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
@@ -2,10 +2,46 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 
+use crate::account_merge::{MERGED_AT_ATTRIBUTE, MERGED_INTO_ATTRIBUTE};
+use crate::attributes;
+use crate::avatar::AVATAR_ATTRIBUTE;
+use crate::email::CANONICAL_EMAIL_ATTRIBUTE;
+use crate::error_codes::ErrorCode;
+use crate::login_notification::LOGIN_NOTIFICATIONS_ATTRIBUTE;
+use crate::partner::PARTNER_ID_ATTRIBUTE;
+use crate::reactivation::DEACTIVATED_AT_ATTRIBUTE;
+use crate::referral::REFERRAL_CODE_ATTRIBUTE;
+use crate::terms::{TERMS_ACCEPTED_AT_ATTRIBUTE, TERMS_VERSION_ATTRIBUTE};
+use crate::validation::DATE_OF_BIRTH_ATTRIBUTE;
+use crate::verified_redirect::EMAIL_VERIFIED_AT_ATTRIBUTE;
+
+/// Attributes the portal sets itself and that describe one account only.
+/// The registration schema may not declare them, so a signup cannot arrive
+/// already deactivated, merged, verified, past the terms or vouched for,
+/// and an account merge never copies them to the primary.
+pub const SERVER_ATTRIBUTES: &[&str] = &[
+    PARTNER_ID_ATTRIBUTE,
+    REFERRAL_CODE_ATTRIBUTE,
+    CANONICAL_EMAIL_ATTRIBUTE,
+    DATE_OF_BIRTH_ATTRIBUTE,
+    TERMS_VERSION_ATTRIBUTE,
+    TERMS_ACCEPTED_AT_ATTRIBUTE,
+    DEACTIVATED_AT_ATTRIBUTE,
+    MERGED_INTO_ATTRIBUTE,
+    MERGED_AT_ATTRIBUTE,
+    EMAIL_VERIFIED_AT_ATTRIBUTE,
+    AVATAR_ATTRIBUTE,
+    LOGIN_NOTIFICATIONS_ATTRIBUTE,
+];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RegisterRequest {
     pub email: String,
+    /// Only read under `USERNAME_MODE=optional` or `required`; otherwise the
+    /// email is the username.
+    #[serde(default)]
+    pub username: Option<String>,
     pub password: String,
     #[serde(default)]
     pub first_name: Option<String>,
@@ -13,6 +49,14 @@ pub struct RegisterRequest {
     pub last_name: Option<String>,
     #[serde(default)]
     pub captcha_token: Option<String>,
+    #[serde(default)]
+    pub form_token: Option<String>,
+    #[serde(default)]
+    pub partner_assertion: Option<String>,
+    #[serde(default)]
+    pub referral_code: Option<String>,
+    #[serde(default)]
+    pub date_of_birth: Option<String>,
     #[serde(flatten)]
     pub extra: HashMap<String, Value>,
 }
@@ -31,18 +75,91 @@ impl RegisterResponse {
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UsernameAvailabilityQuery {
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsernameAvailabilityResponse {
+    /// As it would be stored: trimmed and lowercased.
+    pub username: String,
+    pub available: bool,
+}
+
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ErrorResponse {
     pub error: String,
+    pub code: ErrorCode,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<AccountHint>,
 }
 
 impl ErrorResponse {
-    pub fn new(error: String) -> Self {
-        Self { error }
+    pub fn new(code: ErrorCode, error: String) -> Self {
+        Self {
+            error,
+            code,
+            fields: Vec::new(),
+            hint: None,
+        }
+    }
+
+    pub fn with_fields(mut self, fields: Vec<FieldError>) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    pub fn with_hint(mut self, hint: AccountHint) -> Self {
+        self.hint = Some(hint);
+        self
     }
 }
 
+/// What the client can offer a user whose account already exists.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountHint {
+    /// `login_or_reset`: sign in, or reset the password if it is forgotten;
+    /// `use_social_login`: sign in with one of `identity_providers`.
+    pub action: &'static str,
+    /// Whether the account is linked to an identity provider.
+    pub social_login: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub identity_providers: Vec<String>,
+}
+
+impl AccountHint {
+    pub fn login_or_reset(identity_providers: Vec<String>) -> Self {
+        Self {
+            action: "login_or_reset",
+            social_login: !identity_providers.is_empty(),
+            identity_providers,
+        }
+    }
+
+    pub fn use_social_login(identity_providers: Vec<String>) -> Self {
+        Self {
+            action: "use_social_login",
+            social_login: true,
+            identity_providers,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    pub field: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    pub message: String,
+}
+
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct KeycloakUser {
@@ -73,10 +190,13 @@ impl KeycloakUser {
             value: request.password.clone(),
         }];
 
-        let attributes = extract_attributes(&request.extra);
+        let attributes = attributes::extract(&request.extra);
 
         Self {
-            username: request.email.clone(),
+            username: request
+                .username
+                .clone()
+                .unwrap_or_else(|| request.email.clone()),
             email: request.email.clone(),
             first_name: request.first_name.clone(),
             last_name: request.last_name.clone(),
@@ -98,32 +218,23 @@ pub struct KeycloakCredential {
     pub value: String,
 }
 
-fn extract_attributes(extra: &HashMap<String, Value>) -> HashMap<String, Vec<String>> {
-    extra
-        .iter()
-        .filter_map(|(key, value)| {
-            let values = match value {
-                Value::String(text) => vec![text.trim().to_owned()],
-                Value::Bool(flag) => vec![flag.to_string()],
-                Value::Number(num) => vec![num.to_string()],
-                Value::Array(items) => items
-                    .iter()
-                    .filter_map(|item| match item {
-                        Value::String(text) => Some(text.trim().to_owned()),
-                        Value::Bool(flag) => Some(flag.to_string()),
-                        Value::Number(num) => Some(num.to_string()),
-                        _ => None,
-                    })
-                    .filter(|text| !text.is_empty())
-                    .collect::<Vec<_>>(),
-                _ => Vec::new(),
-            };
-
-            if values.is_empty() {
-                None
-            } else {
-                Some((key.clone(), values))
-            }
-        })
-        .collect()
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserRepresentation {
+    pub id: String,
+    pub username: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub first_name: Option<String>,
+    #[serde(default)]
+    pub last_name: Option<String>,
+    #[serde(default)]
+    pub created_timestamp: Option<i64>,
+    #[serde(default)]
+    pub attributes: HashMap<String, Vec<String>>,
 }
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::outbox::DeadLetter;
+use crate::webhooks::{Delivery, WebhookSubscription};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookRequest {
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub events: Option<Vec<String>>,
+    #[serde(default)]
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookResponse {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub created_at: u64,
+    /// Only returned when the subscription is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl From<WebhookSubscription> for WebhookResponse {
+    fn from(subscription: WebhookSubscription) -> Self {
+        Self {
+            id: subscription.id,
+            url: subscription.url,
+            events: subscription.events,
+            enabled: subscription.enabled,
+            created_at: subscription.created_at,
+            secret: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookListResponse {
+    pub webhooks: Vec<WebhookResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryListResponse {
+    pub deliveries: Vec<Delivery>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeadLetterQuery {
+    pub subscription: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterResponse {
+    pub id: String,
+    pub subscription_id: String,
+    pub event: String,
+    pub attempts: u32,
+    pub created_at: u64,
+    pub failed_at: u64,
+    pub last_error: String,
+    /// The body that was sent, as JSON.
+    pub payload: Value,
+}
+
+impl From<DeadLetter> for DeadLetterResponse {
+    fn from(dead: DeadLetter) -> Self {
+        Self {
+            payload: serde_json::from_str(&dead.entry.payload)
+                .unwrap_or(Value::String(dead.entry.payload)),
+            id: dead.entry.id,
+            subscription_id: dead.entry.destination,
+            event: dead.entry.event,
+            attempts: dead.entry.attempts,
+            created_at: dead.entry.created_at,
+            failed_at: dead.failed_at,
+            last_error: dead.last_error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterListResponse {
+    pub dead_letters: Vec<DeadLetterResponse>,
+}
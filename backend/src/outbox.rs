@@ -0,0 +1,369 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::AppConfig;
+use crate::journal::Journal;
+use crate::util::unix_now;
+
+/// Changes appended to `OUTBOX_FILE` before it is compacted again.
+const COMPACT_AFTER: usize = 1000;
+
+/// An event waiting to be delivered. `destination` names the receiver, e.g.
+/// a webhook subscription id, and `payload` is the exact body to send so
+/// that every retry is byte-for-byte identical.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxEntry {
+    pub id: String,
+    pub destination: String,
+    pub event: String,
+    pub payload: String,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub created_at: u64,
+}
+
+/// An entry that used up its attempts. It stays here, payload and all,
+/// until an admin replays or discards it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    #[serde(flatten)]
+    pub entry: OutboxEntry,
+    pub failed_at: u64,
+    pub last_error: String,
+}
+
+/// Durable queue of outgoing events. With `OUTBOX_FILE` set every change is
+/// appended to the file as a JSON line by a background task, so callers
+/// never wait on the disk, and the file is compacted to a snapshot on
+/// startup and every `COMPACT_AFTER` changes. An event that was accepted
+/// survives a restart, short of a crash in the moment before its line is
+/// written, and is picked up again by the dispatcher. Entries are only
+/// removed once delivered or moved to the dead-letter queue, which makes
+/// delivery at-least-once. The dead-letter queue keeps the newest
+/// `OUTBOX_DEAD_LETTER_CAPACITY` entries.
+pub struct Outbox {
+    journal: Option<Journal>,
+    dead_letter_capacity: usize,
+    inner: Mutex<OutboxInner>,
+}
+
+struct OutboxInner {
+    stored: OutboxFile,
+    in_flight: HashSet<String>,
+    /// Changes appended since the file was last compacted.
+    appended: usize,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OutboxFile {
+    #[serde(default)]
+    entries: Vec<OutboxEntry>,
+    #[serde(default)]
+    dead_letters: Vec<DeadLetter>,
+}
+
+/// One line of `OUTBOX_FILE`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Change {
+    Snapshot(OutboxFile),
+    Enqueue(OutboxEntry),
+    #[serde(rename_all = "camelCase")]
+    Complete {
+        id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Reschedule {
+        id: String,
+        attempts: u32,
+        next_attempt_at: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    DeadLetter {
+        id: String,
+        attempts: u32,
+        last_error: String,
+        failed_at: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Replay {
+        id: String,
+        next_attempt_at: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Discard {
+        id: String,
+    },
+}
+
+impl OutboxFile {
+    fn apply(&mut self, change: Change, dead_letter_capacity: usize) {
+        match change {
+            Change::Snapshot(snapshot) => *self = snapshot,
+            Change::Enqueue(entry) => self.entries.push(entry),
+            Change::Complete { id } => self.entries.retain(|entry| entry.id != id),
+            Change::Reschedule {
+                id,
+                attempts,
+                next_attempt_at,
+            } => {
+                if let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) {
+                    entry.attempts = attempts;
+                    entry.next_attempt_at = next_attempt_at;
+                }
+            }
+            Change::DeadLetter {
+                id,
+                attempts,
+                last_error,
+                failed_at,
+            } => {
+                let Some(position) = self.entries.iter().position(|entry| entry.id == id) else {
+                    return;
+                };
+                let mut entry = self.entries.remove(position);
+                entry.attempts = attempts;
+                self.dead_letters.push(DeadLetter {
+                    entry,
+                    failed_at,
+                    last_error,
+                });
+
+                let excess = self.dead_letters.len().saturating_sub(dead_letter_capacity);
+                if excess > 0 {
+                    warn!(
+                        "[Outbox] dead-letter queue full; dropping {} oldest",
+                        excess
+                    );
+                    self.dead_letters.drain(..excess);
+                }
+            }
+            Change::Replay {
+                id,
+                next_attempt_at,
+            } => {
+                let Some(position) = self
+                    .dead_letters
+                    .iter()
+                    .position(|dead| dead.entry.id == id)
+                else {
+                    return;
+                };
+                let mut entry = self.dead_letters.remove(position).entry;
+                entry.attempts = 0;
+                entry.next_attempt_at = next_attempt_at;
+                self.entries.push(entry);
+            }
+            Change::Discard { id } => self.dead_letters.retain(|dead| dead.entry.id != id),
+        }
+    }
+}
+
+impl Outbox {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let capacity = config.outbox_dead_letter_capacity;
+        let stored = config
+            .outbox_file
+            .as_deref()
+            .map(|path| load_file(path, capacity))
+            .unwrap_or_default();
+        if !stored.entries.is_empty() {
+            info!(
+                "[Outbox] resuming {} undelivered events",
+                stored.entries.len()
+            );
+        }
+
+        let journal = config.outbox_file.as_deref().and_then(|path| {
+            Journal::open(path, "Outbox")
+                .inspect_err(|err| warn!("[Outbox] {}; keeping the outbox in memory only", err))
+                .ok()
+        });
+        let outbox = Self {
+            journal,
+            dead_letter_capacity: capacity,
+            inner: Mutex::new(OutboxInner {
+                stored,
+                in_flight: HashSet::new(),
+                appended: 0,
+            }),
+        };
+        outbox.compact(&mut outbox.lock());
+        outbox
+    }
+
+    pub fn enqueue(&self, entry: OutboxEntry) {
+        self.change(&mut self.lock(), Change::Enqueue(entry));
+    }
+
+    /// Every entry in the outbox, in the order it was queued.
+    pub fn entries(&self) -> Vec<OutboxEntry> {
+        self.lock().stored.entries.clone()
+    }
+
+    /// Claims entries whose next attempt is due. A claimed entry is not
+    /// handed out again until it is completed or rescheduled.
+    pub fn claim_due(&self) -> Vec<OutboxEntry> {
+        let now = unix_now();
+        let mut inner = self.lock();
+        let due: Vec<OutboxEntry> = inner
+            .stored
+            .entries
+            .iter()
+            .filter(|entry| entry.next_attempt_at <= now && !inner.in_flight.contains(&entry.id))
+            .cloned()
+            .collect();
+        inner
+            .in_flight
+            .extend(due.iter().map(|entry| entry.id.clone()));
+        due
+    }
+
+    /// Removes an entry once it was delivered or will not be retried.
+    pub fn complete(&self, id: &str) {
+        let mut inner = self.lock();
+        inner.in_flight.remove(id);
+        self.change(&mut inner, Change::Complete { id: id.to_owned() });
+    }
+
+    pub fn reschedule(&self, id: &str, attempts: u32, next_attempt_at: u64) {
+        let mut inner = self.lock();
+        inner.in_flight.remove(id);
+        self.change(
+            &mut inner,
+            Change::Reschedule {
+                id: id.to_owned(),
+                attempts,
+                next_attempt_at,
+            },
+        );
+    }
+
+    /// Moves an entry that will not be retried to the dead-letter queue.
+    pub fn dead_letter(&self, id: &str, attempts: u32, last_error: String) {
+        let mut inner = self.lock();
+        inner.in_flight.remove(id);
+        if !inner.stored.entries.iter().any(|entry| entry.id == id) {
+            return;
+        }
+        self.change(
+            &mut inner,
+            Change::DeadLetter {
+                id: id.to_owned(),
+                attempts,
+                last_error,
+                failed_at: unix_now(),
+            },
+        );
+    }
+
+    /// Dead letters, oldest first.
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.lock().stored.dead_letters.clone()
+    }
+
+    /// Puts a dead letter back in the outbox with a fresh set of attempts.
+    pub fn replay(&self, id: &str) -> Option<OutboxEntry> {
+        let mut inner = self.lock();
+        if !inner
+            .stored
+            .dead_letters
+            .iter()
+            .any(|dead| dead.entry.id == id)
+        {
+            return None;
+        }
+        self.change(
+            &mut inner,
+            Change::Replay {
+                id: id.to_owned(),
+                next_attempt_at: unix_now(),
+            },
+        );
+        inner.stored.entries.last().cloned()
+    }
+
+    pub fn discard(&self, id: &str) -> bool {
+        let mut inner = self.lock();
+        if !inner
+            .stored
+            .dead_letters
+            .iter()
+            .any(|dead| dead.entry.id == id)
+        {
+            return false;
+        }
+        self.change(&mut inner, Change::Discard { id: id.to_owned() });
+        true
+    }
+
+    /// Waits until every change so far is in `OUTBOX_FILE`.
+    pub async fn flush(&self) {
+        if let Some(journal) = &self.journal {
+            journal.flush().await;
+        }
+    }
+
+    /// Applies `change` and queues it for the file.
+    fn change(&self, inner: &mut OutboxInner, change: Change) {
+        if let Some(journal) = &self.journal
+            && let Ok(line) = serde_json::to_string(&change)
+        {
+            journal.append(line);
+            inner.appended += 1;
+        }
+        inner.stored.apply(change, self.dead_letter_capacity);
+        if inner.appended >= COMPACT_AFTER {
+            self.compact(inner);
+        }
+    }
+
+    /// Rewrites the file as a single snapshot of the current state.
+    fn compact(&self, inner: &mut OutboxInner) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        if let Ok(line) = serde_json::to_string(&Change::Snapshot(inner.stored.clone())) {
+            journal.replace(vec![line]);
+            inner.appended = 0;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, OutboxInner> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Replays the changes in `path`. Files written as a single JSON document
+/// by earlier versions are read as a snapshot.
+fn load_file(path: &str, dead_letter_capacity: usize) -> OutboxFile {
+    let Ok(file) = File::open(path) else {
+        return OutboxFile::default();
+    };
+    let mut stored = OutboxFile::default();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let Ok(line) = line else {
+            warn!(%path, "Unable to read outbox file; keeping what was read so far");
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Change>(&line) {
+            Ok(change) => stored.apply(change, dead_letter_capacity),
+            Err(err) => match serde_json::from_str::<OutboxFile>(&line) {
+                Ok(snapshot) if number == 0 => stored = snapshot,
+                _ => warn!(%path, ?err, "Skipping unreadable outbox line"),
+            },
+        }
+    }
+    stored
+}
@@ -0,0 +1,159 @@
+use std::fs;
+use std::time::Duration;
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::AppConfig;
+
+pub const PARTNER_ID_ATTRIBUTE: &str = "partnerId";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PartnerSpec {
+    id: String,
+    algorithm: Algorithm,
+    #[serde(default)]
+    public_key_pem: Option<String>,
+    #[serde(default)]
+    public_key_file: Option<String>,
+    #[serde(default)]
+    secret: Option<String>,
+}
+
+struct PartnerKey {
+    id: String,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+#[derive(Debug, Deserialize)]
+struct PartnerClaims {
+    email: String,
+}
+
+#[derive(Debug)]
+pub enum PartnerError {
+    UnknownPartner,
+    InvalidAssertion,
+    EmailMismatch,
+}
+
+/// Keys of partners allowed to vouch for registrations. Assertions must carry
+/// the partner ID as `iss` (and optionally `kid`), the portal audience, and
+/// the email being registered.
+pub struct PartnerRegistry {
+    partners: Vec<PartnerKey>,
+    audience: String,
+    leeway: Duration,
+}
+
+impl PartnerRegistry {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let partners = config
+            .partners_file
+            .as_deref()
+            .map(load_partners)
+            .unwrap_or_default();
+
+        Self {
+            partners,
+            audience: config.partner_assertion_audience.clone(),
+            leeway: config.clock_skew_leeway,
+        }
+    }
+
+    pub fn verify(&self, assertion: &str, email: &str) -> Result<String, PartnerError> {
+        let header = decode_header(assertion).map_err(|_| PartnerError::InvalidAssertion)?;
+        let candidates: Vec<&PartnerKey> = match header.kid.as_deref() {
+            Some(kid) => self.partners.iter().filter(|p| p.id == kid).collect(),
+            None => self.partners.iter().collect(),
+        };
+        if candidates.is_empty() {
+            return Err(PartnerError::UnknownPartner);
+        }
+
+        for partner in candidates {
+            if partner.algorithm != header.alg {
+                continue;
+            }
+
+            let mut validation = Validation::new(partner.algorithm);
+            validation.set_audience(&[self.audience.as_str()]);
+            validation.set_issuer(&[partner.id.as_str()]);
+            validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+            validation.validate_nbf = true;
+            validation.leeway = self.leeway.as_secs();
+
+            match decode::<PartnerClaims>(assertion, &partner.key, &validation) {
+                Ok(data) => {
+                    if !data.claims.email.trim().eq_ignore_ascii_case(email) {
+                        return Err(PartnerError::EmailMismatch);
+                    }
+                    return Ok(partner.id.clone());
+                }
+                Err(err) => {
+                    debug!(partner = %partner.id, ?err, "Partner assertion did not verify");
+                }
+            }
+        }
+
+        Err(PartnerError::InvalidAssertion)
+    }
+}
+
+fn load_partners(path: &str) -> Vec<PartnerKey> {
+    let specs = match fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<Vec<PartnerSpec>>(&contents).map_err(|err| err.to_string())
+        }) {
+        Ok(specs) => specs,
+        Err(err) => {
+            warn!(%path, %err, "Unable to load partners file; partner registration disabled");
+            return Vec::new();
+        }
+    };
+
+    specs
+        .into_iter()
+        .filter_map(|spec| match decoding_key(&spec) {
+            Ok(key) => Some(PartnerKey {
+                id: spec.id,
+                algorithm: spec.algorithm,
+                key,
+            }),
+            Err(err) => {
+                warn!(partner = %spec.id, %err, "Skipping partner with unusable key");
+                None
+            }
+        })
+        .collect()
+}
+
+fn decoding_key(spec: &PartnerSpec) -> Result<DecodingKey, String> {
+    if let Some(secret) = spec.secret.as_deref() {
+        return Ok(DecodingKey::from_secret(secret.as_bytes()));
+    }
+
+    let pem = match (&spec.public_key_pem, &spec.public_key_file) {
+        (Some(pem), _) => pem.clone(),
+        (None, Some(file)) => fs::read_to_string(file).map_err(|err| err.to_string())?,
+        (None, None) => return Err("no key configured".to_owned()),
+    };
+
+    let key = match spec.algorithm {
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512 => DecodingKey::from_rsa_pem(pem.as_bytes()),
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(pem.as_bytes()),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(pem.as_bytes()),
+        _ => return Err("algorithm requires a shared secret".to_owned()),
+    };
+
+    key.map_err(|err| err.to_string())
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::keycloak::KeycloakService;
+use crate::util::unix_millis;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// Enforces `PASSWORD_MAX_AGE_DAYS` on top of whatever the realm's password
+/// policy does, based on the `createdDate` of the user's password
+/// credential. Lookups that fail are logged and do not block the login.
+pub struct PasswordExpiry {
+    keycloak: Arc<KeycloakService>,
+    max_age_days: u64,
+    change_url: String,
+}
+
+impl PasswordExpiry {
+    pub fn from_config(config: &AppConfig, keycloak: Arc<KeycloakService>) -> Self {
+        Self {
+            keycloak,
+            max_age_days: config.password_max_age_days,
+            change_url: config.password_change_url(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days > 0
+    }
+
+    /// Where users change their password, by default the Keycloak account
+    /// console.
+    pub fn change_url(&self) -> &str {
+        &self.change_url
+    }
+
+    /// Whole days until the password expires; zero or less once it has.
+    pub async fn days_remaining(&self, email: &str) -> Option<i64> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let users = self
+            .keycloak
+            .find_users_by_email(email)
+            .await
+            .inspect_err(|err| warn!("[Password] user lookup failed for {}: {}", email, err))
+            .ok()?;
+        let [user] = users.as_slice() else {
+            return None;
+        };
+        let credentials = self
+            .keycloak
+            .user_credentials(&user.id)
+            .await
+            .inspect_err(|err| warn!("[Password] credential lookup failed for {}: {}", email, err))
+            .ok()?;
+        let created = credentials
+            .iter()
+            .filter(|credential| credential.kind == "password")
+            .find_map(|credential| credential.created_date)?;
+
+        let age_days = unix_millis().saturating_sub(created) / DAY_MILLIS;
+        Some(self.max_age_days as i64 - age_days as i64)
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::AppConfig;
+use crate::cache::TtlLruCache;
+use crate::keycloak::{KeycloakError, KeycloakService};
+use crate::revocation::token_hash;
+
+type DecisionKey = (String, String, Option<String>);
+
+/// Resource-level checks against Keycloak Authorization Services: "may the
+/// holder of this token do `scope` on `resource`?". Decisions are cached per
+/// token (by hash), resource and scope for `UMA_DECISION_CACHE_TTL_SECS`.
+pub struct PermissionService {
+    keycloak: Arc<KeycloakService>,
+    resource_server: String,
+    decisions: Mutex<TtlLruCache<DecisionKey, bool>>,
+}
+
+impl PermissionService {
+    pub fn from_config(config: &AppConfig, keycloak: Arc<KeycloakService>) -> Self {
+        Self {
+            keycloak,
+            resource_server: config.uma_resource_server.clone(),
+            decisions: Mutex::new(TtlLruCache::new(
+                config.uma_decision_cache_capacity,
+                config.uma_decision_cache_ttl,
+            )),
+        }
+    }
+
+    pub async fn is_permitted(
+        &self,
+        access_token: &str,
+        resource: &str,
+        scope: Option<&str>,
+    ) -> Result<bool, KeycloakError> {
+        let key = (
+            token_hash(access_token),
+            resource.to_owned(),
+            scope.map(str::to_owned),
+        );
+        if let Some(granted) = self.decisions.lock().await.get(&key) {
+            return Ok(granted);
+        }
+
+        let granted = self
+            .keycloak
+            .uma_decision(access_token, &self.resource_server, resource, scope)
+            .await?;
+        self.decisions.lock().await.insert(key, granted);
+        Ok(granted)
+    }
+
+    /// Forgets the cached decisions of a revoked token.
+    pub async fn forget_token(&self, token_hash: &str) {
+        self.decisions
+            .lock()
+            .await
+            .retain(|(hash, _, _)| hash != token_hash);
+    }
+}
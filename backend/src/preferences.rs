@@ -0,0 +1,111 @@
+use crate::login_notification::LOGIN_NOTIFICATIONS_ATTRIBUTE;
+use crate::models::account::{Preferences, PreferencesUpdate};
+use crate::models::user::{FieldError, UserRepresentation};
+
+pub const MARKETING_EMAILS_ATTRIBUTE: &str = "marketingEmails";
+/// Also Keycloak's own attribute for the account console and its emails.
+pub const LOCALE_ATTRIBUTE: &str = "locale";
+pub const TIMEZONE_ATTRIBUTE: &str = "timezone";
+
+/// The user's notification and privacy preferences as stored in their
+/// Keycloak attributes. Login alerts follow `LOGIN_NOTIFICATIONS_DEFAULT`
+/// until the user chooses; marketing emails are off unless opted into.
+pub fn read(user: &UserRepresentation, login_alerts_default: bool) -> Preferences {
+    let flag = |name: &str| attribute(user, name).map(|value| value.eq_ignore_ascii_case("true"));
+    Preferences {
+        login_alerts: flag(LOGIN_NOTIFICATIONS_ATTRIBUTE).unwrap_or(login_alerts_default),
+        marketing_emails: flag(MARKETING_EMAILS_ATTRIBUTE).unwrap_or(false),
+        locale: attribute(user, LOCALE_ATTRIBUTE).map(str::to_owned),
+        timezone: attribute(user, TIMEZONE_ATTRIBUTE).map(str::to_owned),
+    }
+}
+
+pub fn attribute<'a>(user: &'a UserRepresentation, name: &str) -> Option<&'a str> {
+    user.attributes
+        .get(name)
+        .and_then(|values| values.first())
+        .map(String::as_str)
+        .filter(|value| !value.is_empty())
+}
+
+/// Writes the update into the user's attributes, or reports every invalid
+/// field without changing anything.
+pub fn apply(
+    user: &mut UserRepresentation,
+    update: PreferencesUpdate,
+) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let locale = update.locale.map(|value| value.trim().replace('_', "-"));
+    if let Some(locale) = locale.as_deref().filter(|value| !value.is_empty())
+        && !is_language_tag(locale)
+    {
+        errors.push(FieldError {
+            field: "locale".to_owned(),
+            expected: Some("language tag such as en or pt-BR".to_owned()),
+            message: "invalid locale".to_owned(),
+        });
+    }
+    let timezone = update.timezone.map(|value| value.trim().to_owned());
+    if let Some(timezone) = timezone.as_deref().filter(|value| !value.is_empty())
+        && !is_timezone_name(timezone)
+    {
+        errors.push(FieldError {
+            field: "timezone".to_owned(),
+            expected: Some("IANA time zone such as Europe/Kyiv or UTC".to_owned()),
+            message: "invalid timezone".to_owned(),
+        });
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    for (name, value) in [
+        (
+            LOGIN_NOTIFICATIONS_ATTRIBUTE,
+            update.login_alerts.map(|flag| flag.to_string()),
+        ),
+        (
+            MARKETING_EMAILS_ATTRIBUTE,
+            update.marketing_emails.map(|flag| flag.to_string()),
+        ),
+        (LOCALE_ATTRIBUTE, locale),
+        (TIMEZONE_ATTRIBUTE, timezone),
+    ] {
+        match value {
+            Some(value) if value.is_empty() => {
+                user.attributes.remove(name);
+            }
+            Some(value) => {
+                user.attributes.insert(name.to_owned(), vec![value]);
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// `en`, `pt-BR`, `zh-Hant-TW`: a 2–3 letter language and optional
+/// alphanumeric subtags.
+pub fn is_language_tag(value: &str) -> bool {
+    let mut parts = value.split('-');
+    let language = parts.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| {
+            (2..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+/// Shape of an IANA zone name; the portal carries no zone database, so a
+/// well-formed but unknown name is accepted.
+fn is_timezone_name(value: &str) -> bool {
+    value.len() <= 64
+        && (value == "UTC"
+            || value.split('/').count() >= 2
+                && value.split('/').all(|part| {
+                    !part.is_empty()
+                        && part
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+                }))
+}
@@ -0,0 +1,333 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ipnet::IpNet;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::api_keys::{ApiKeys, api_key_prefix};
+use crate::session::SessionStore;
+use crate::util::unix_now;
+
+/// Whether another request from `subject` fits in `limit` requests per
+/// `window` for `bucket`. Counters live in the session store, so every
+/// replica spends the same budget. A limit of zero disables the check; a
+/// store failure denies the request.
+pub async fn allow(
+    store: &dyn SessionStore,
+    bucket: &str,
+    subject: &str,
+    limit: u32,
+    window: Duration,
+) -> bool {
+    if limit == 0 {
+        return true;
+    }
+    let window_secs = window.as_secs().max(1);
+    let key = format!(
+        "rate:{bucket}:{}:{}",
+        hex::encode(Sha256::digest(subject.as_bytes())),
+        unix_now() / window_secs
+    );
+    match store
+        .increment(&key, Duration::from_secs(window_secs))
+        .await
+    {
+        Ok(count) => count <= u64::from(limit),
+        Err(err) => {
+            warn!("[RateLimit] unable to count {} request: {}", bucket, err);
+            false
+        }
+    }
+}
+
+const OVERRIDES_KEY: &str = "rate-limit-overrides";
+/// How long the override list outlives its last change.
+const OVERRIDES_TTL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Buckets that can be given overrides.
+pub const BUCKETS: &[&str] = &["username-check", "duplicate-check"];
+
+/// A change to the configured limit for some callers: a higher quota for a
+/// partner's API key, an exemption for health-check addresses, or a lower
+/// limit for networks such as an abusive ASN's prefixes or anonymizer exit
+/// nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Override {
+    pub id: String,
+    pub label: String,
+    /// Addresses the override covers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub networks: Vec<IpNet>,
+    /// Prefix of the API key the override covers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// The one bucket it applies to; all of them when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// Requests per window in place of the configured limit; the callers
+    /// are exempt when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    pub created_by: String,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// Who a rate-limited request comes from.
+pub struct Caller<'a> {
+    pub ip: IpAddr,
+    /// The `X-Api-Key` it was sent with, not yet verified.
+    pub api_key: Option<&'a str>,
+}
+
+pub struct NewOverride {
+    pub label: String,
+    pub networks: Vec<IpNet>,
+    pub api_key: Option<String>,
+    pub bucket: Option<String>,
+    pub limit: Option<u32>,
+    pub ttl: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum OverrideError {
+    NotFound,
+    Invalid(&'static str),
+    Unavailable,
+}
+
+/// Rate-limit overrides managed through the admin API, kept in the session
+/// store so that every replica applies them. An override for the caller's
+/// API key is preferred to one for its address, one for the bucket to one
+/// for every bucket, and of several matching networks the narrowest wins.
+/// An API key only counts once it verifies, and a keyed caller is counted
+/// per key rather than per address.
+pub struct RateLimits {
+    store: Arc<dyn SessionStore>,
+    api_keys: Arc<ApiKeys>,
+    write_lock: Mutex<()>,
+}
+
+impl RateLimits {
+    pub fn new(store: Arc<dyn SessionStore>, api_keys: Arc<ApiKeys>) -> Self {
+        Self {
+            store,
+            api_keys,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// [`allow`], with the overrides that cover `caller` applied. The
+    /// overrides cannot be read when the store is down, and neither can the
+    /// counters, so the request is denied then. A key override is only used
+    /// once the key checks out; until the key has been verified recently,
+    /// the request first counts against the caller's network, so a wrong
+    /// secret costs an Argon2 hash only within that limit.
+    pub async fn allow(
+        &self,
+        bucket: &str,
+        caller: &Caller<'_>,
+        limit: u32,
+        window: Duration,
+    ) -> bool {
+        let Ok(overrides) = self.load().await else {
+            return false;
+        };
+
+        let keyed = caller.api_key.and_then(|raw| {
+            let prefix = api_key_prefix(raw)?;
+            let keyed: Vec<&Override> = overrides
+                .iter()
+                .filter(|entry| entry.api_key.as_deref() == Some(prefix))
+                .filter(|entry| applies(entry, bucket))
+                .collect();
+            let entry = keyed
+                .iter()
+                .find(|entry| entry.bucket.is_some())
+                .or(keyed.first())?;
+            Some((raw, prefix, *entry))
+        });
+        let Some((raw, prefix, entry)) = keyed else {
+            return self
+                .allow_network(&overrides, bucket, caller, limit, window)
+                .await;
+        };
+
+        if self.api_keys.verify_cached(raw).await.is_none() {
+            if !self
+                .allow_network(&overrides, bucket, caller, limit, window)
+                .await
+            {
+                return false;
+            }
+            if self.api_keys.verify(raw).await.is_none() {
+                return true;
+            }
+        }
+        match entry.limit {
+            None => true,
+            Some(limit) => {
+                let subject = format!("key:{prefix}");
+                allow(self.store.as_ref(), bucket, &subject, limit, window).await
+            }
+        }
+    }
+
+    /// The most specific network override covering the caller, or `limit`.
+    async fn allow_network(
+        &self,
+        overrides: &[Override],
+        bucket: &str,
+        caller: &Caller<'_>,
+        limit: u32,
+        window: Duration,
+    ) -> bool {
+        let entry = overrides
+            .iter()
+            .filter(|entry| applies(entry, bucket))
+            .filter_map(|entry| {
+                entry
+                    .networks
+                    .iter()
+                    .filter(|network| network.contains(&caller.ip))
+                    .map(IpNet::prefix_len)
+                    .max()
+                    .map(|prefix_len| (entry.bucket.is_some(), prefix_len, entry))
+            })
+            .max_by_key(|(specific, prefix_len, _)| (*specific, *prefix_len))
+            .map(|(_, _, entry)| entry);
+        let limit = match entry {
+            Some(Override { limit: None, .. }) => return true,
+            Some(Override {
+                limit: Some(limit), ..
+            }) => *limit,
+            None => limit,
+        };
+        allow(
+            self.store.as_ref(),
+            bucket,
+            &caller.ip.to_string(),
+            limit,
+            window,
+        )
+        .await
+    }
+
+    pub async fn list(&self) -> Result<Vec<Override>, OverrideError> {
+        self.load().await
+    }
+
+    /// Adds an override. It needs either networks or an API key prefix,
+    /// and a limit of zero is refused; leave the limit out to exempt.
+    pub async fn add(
+        &self,
+        request: NewOverride,
+        created_by: &str,
+    ) -> Result<Override, OverrideError> {
+        let NewOverride {
+            label,
+            networks,
+            api_key,
+            bucket,
+            limit,
+            ttl,
+        } = request;
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(OverrideError::Invalid("label is required"));
+        }
+        let api_key = api_key
+            .map(|prefix| prefix.trim().to_owned())
+            .filter(|prefix| !prefix.is_empty());
+        if networks.is_empty() == api_key.is_none() {
+            return Err(OverrideError::Invalid(
+                "give either networks or an apiKey prefix",
+            ));
+        }
+        if bucket
+            .as_deref()
+            .is_some_and(|bucket| !BUCKETS.contains(&bucket))
+        {
+            return Err(OverrideError::Invalid("unknown bucket"));
+        }
+        if limit == Some(0) {
+            return Err(OverrideError::Invalid(
+                "limit must be positive; leave it out to exempt",
+            ));
+        }
+        if ttl.is_some_and(|ttl| ttl.is_zero()) {
+            return Err(OverrideError::Invalid("ttlSecs must be positive"));
+        }
+
+        let mut id = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut id);
+        let now = unix_now();
+        let entry = Override {
+            id: hex::encode(id),
+            label: label.to_owned(),
+            networks,
+            api_key,
+            bucket,
+            limit,
+            created_by: created_by.to_owned(),
+            created_at: now,
+            expires_at: ttl.map(|ttl| now + ttl.as_secs()),
+        };
+        let _guard = self.write_lock.lock().await;
+        let mut overrides = self.load().await?;
+        overrides.push(entry.clone());
+        self.save(&overrides).await?;
+        Ok(entry)
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<Override, OverrideError> {
+        let _guard = self.write_lock.lock().await;
+        let mut overrides = self.load().await?;
+        let position = overrides
+            .iter()
+            .position(|entry| entry.id == id)
+            .ok_or(OverrideError::NotFound)?;
+        let entry = overrides.remove(position);
+        self.save(&overrides).await?;
+        Ok(entry)
+    }
+
+    async fn load(&self) -> Result<Vec<Override>, OverrideError> {
+        let stored = self.store.get(OVERRIDES_KEY).await.map_err(|err| {
+            warn!("[RateLimit] unable to load overrides: {}", err);
+            OverrideError::Unavailable
+        })?;
+        let now = unix_now();
+        let mut overrides: Vec<Override> = stored
+            .and_then(|value| serde_json::from_str(&value).ok())
+            .unwrap_or_default();
+        overrides.retain(|entry| entry.expires_at.is_none_or(|expires_at| expires_at >= now));
+        Ok(overrides)
+    }
+
+    async fn save(&self, overrides: &[Override]) -> Result<(), OverrideError> {
+        let encoded = serde_json::to_string(overrides).unwrap_or_default();
+        self.store
+            .set(OVERRIDES_KEY, &encoded, OVERRIDES_TTL)
+            .await
+            .map_err(|err| {
+                warn!("[RateLimit] unable to store overrides: {}", err);
+                OverrideError::Unavailable
+            })
+    }
+}
+
+/// Whether `entry` covers `bucket`; an override without a bucket covers all.
+fn applies(entry: &Override, bucket: &str) -> bool {
+    entry
+        .bucket
+        .as_deref()
+        .is_none_or(|scoped| scoped == bucket)
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::Mac;
+use rand::RngCore;
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::models::user::UserRepresentation;
+use crate::util::{HmacSha256, hmac_sha256, unix_now};
+
+/// Set on accounts the owner deactivated. Only those may be reactivated
+/// with a link; accounts an administrator disabled stay disabled.
+pub const DEACTIVATED_AT_ATTRIBUTE: &str = "deactivatedAt";
+
+/// Signed, expiring reactivation links. A token is
+/// `<b64url user id>.<expiry>.<deactivatedAt>.<b64url HMAC>`. The MAC is
+/// checked before the account is loaded, and the signed `deactivatedAt`
+/// value must still match the account's, so a link stops working once the
+/// account has been reactivated.
+pub struct ReactivationLinks {
+    secret: Vec<u8>,
+    ttl: Duration,
+    url: Option<String>,
+}
+
+pub struct ReactivationLink {
+    pub url: String,
+    pub expires_at: u64,
+}
+
+/// What a verified token claims; the caller still has to compare it with
+/// the account.
+pub struct ReactivationClaim {
+    pub user_id: String,
+    pub deactivated_at: String,
+}
+
+impl ReactivationLinks {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let secret = match config.reactivation_secret.as_deref().map(str::trim) {
+            Some(secret) if !secret.is_empty() => secret.as_bytes().to_vec(),
+            _ => {
+                if config.reactivation_url.is_some() {
+                    warn!(
+                        "[Reactivation] REACTIVATION_SECRET is not set; links stop working on restart"
+                    );
+                }
+                let mut secret = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                secret
+            }
+        };
+
+        Self {
+            secret,
+            ttl: config.reactivation_link_ttl,
+            url: config.reactivation_url.clone(),
+        }
+    }
+
+    /// `None` when `REACTIVATION_URL` is not configured or the account was
+    /// not deactivated by its owner.
+    pub fn issue(&self, user: &UserRepresentation) -> Option<ReactivationLink> {
+        let Some(base) = self.url.as_deref() else {
+            warn!("[Reactivation] REACTIVATION_URL is not set; no link issued");
+            return None;
+        };
+        let deactivated_at = deactivated_at(user)?;
+
+        let expires_at = unix_now() + self.ttl.as_secs();
+        let user_part = URL_SAFE_NO_PAD.encode(user.id.as_bytes());
+        let mac = URL_SAFE_NO_PAD.encode(self.sign(&user.id, expires_at, deactivated_at));
+        let separator = if base.contains('?') { '&' } else { '?' };
+
+        Some(ReactivationLink {
+            url: format!("{base}{separator}token={user_part}.{expires_at}.{deactivated_at}.{mac}"),
+            expires_at,
+        })
+    }
+
+    /// The claim of a well-formed, unexpired token whose MAC verifies.
+    pub fn verify(&self, token: &str) -> Option<ReactivationClaim> {
+        let mut parts = token.trim().splitn(4, '.');
+        let user_part = parts.next()?;
+        let expires_at: u64 = parts.next()?.parse().ok()?;
+        let deactivated_at = parts.next()?;
+        let signature = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+        if expires_at < unix_now() || !deactivated_at.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let user_id = String::from_utf8(URL_SAFE_NO_PAD.decode(user_part).ok()?).ok()?;
+
+        self.mac(&user_id, expires_at, deactivated_at)
+            .verify_slice(&signature)
+            .ok()?;
+        Some(ReactivationClaim {
+            user_id,
+            deactivated_at: deactivated_at.to_owned(),
+        })
+    }
+
+    fn sign(&self, user_id: &str, expires_at: u64, deactivated_at: &str) -> Vec<u8> {
+        self.mac(user_id, expires_at, deactivated_at)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    fn mac(&self, user_id: &str, expires_at: u64, deactivated_at: &str) -> HmacSha256 {
+        let mut mac = hmac_sha256(&self.secret);
+        mac.update(format!("{user_id}\n{expires_at}\n{deactivated_at}").as_bytes());
+        mac
+    }
+}
+
+pub fn deactivated_at(user: &UserRepresentation) -> Option<&str> {
+    user.attributes
+        .get(DEACTIVATED_AT_ATTRIBUTE)
+        .and_then(|values| values.first())
+        .map(String::as_str)
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{HeaderValue, Method, StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::error_codes::ErrorCode;
+use crate::keycloak_health::KeycloakHealth;
+use crate::models::user::ErrorResponse;
+use crate::session::{SessionStore, StoreError};
+use crate::{AppConfig, AppState};
+
+/// Seconds a client is asked to wait before retrying a refused change.
+const RETRY_AFTER_SECS: &str = "30";
+
+const SWITCH_KEY: &str = "read-only:manual";
+/// Store entries need an expiry; a switch is not expected to outlive this.
+const SWITCH_TTL: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// Why writes are refused.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadOnlyReason {
+    /// Switched on with `READ_ONLY_MODE` or `PUT /api/admin/read-only`.
+    Manual,
+    /// Keycloak has been unreachable for `KEYCLOAK_UNAVAILABLE_AFTER_SECS`
+    /// and `READ_ONLY_ON_OUTAGE` is set.
+    Outage,
+}
+
+/// Degraded operation during partial outages or maintenance: routes that
+/// change state (registration and the sign-in steps that record consent or
+/// send mail, profile and preference updates, avatar uploads, deactivation
+/// and reactivation, admin changes) answer 503 with `read_only`, while
+/// reads, sign-in and token refreshes go on, served from the introspection
+/// and lookup caches where Keycloak cannot answer.
+///
+/// The manual switch lives in the session store so that it reaches every
+/// replica; `READ_ONLY_MODE` applies until it is first flipped. Should the
+/// store be unreachable, the last value seen is used.
+pub struct ReadOnlyMode {
+    default: bool,
+    last_seen: AtomicBool,
+    on_outage: bool,
+    health: Arc<KeycloakHealth>,
+    store: Arc<dyn SessionStore>,
+}
+
+impl ReadOnlyMode {
+    pub fn from_config(
+        config: &AppConfig,
+        health: Arc<KeycloakHealth>,
+        store: Arc<dyn SessionStore>,
+    ) -> Self {
+        Self {
+            default: config.read_only_mode,
+            last_seen: AtomicBool::new(config.read_only_mode),
+            on_outage: config.read_only_on_outage,
+            health,
+            store,
+        }
+    }
+
+    pub async fn reason(&self) -> Option<ReadOnlyReason> {
+        if self.is_manual().await {
+            Some(ReadOnlyReason::Manual)
+        } else if self.on_outage && self.health.is_unavailable() {
+            Some(ReadOnlyReason::Outage)
+        } else {
+            None
+        }
+    }
+
+    pub async fn is_manual(&self) -> bool {
+        match self.store.get(SWITCH_KEY).await {
+            Ok(value) => {
+                let enabled = value.map_or(self.default, |value| value == "on");
+                self.last_seen.store(enabled, Ordering::Relaxed);
+                enabled
+            }
+            Err(err) => {
+                warn!("[ReadOnly] unable to read the switch: {}", err);
+                self.last_seen.load(Ordering::Relaxed)
+            }
+        }
+    }
+
+    pub async fn set_manual(&self, enabled: bool) -> Result<(), StoreError> {
+        let value = if enabled { "on" } else { "off" };
+        self.store.set(SWITCH_KEY, value, SWITCH_TTL).await?;
+        self.last_seen.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Route layer for routes that change state; safe methods pass.
+pub async fn enforce(state: State<AppState>, request: Request, next: Next) -> Response {
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if safe {
+        return next.run(request).await;
+    }
+    enforce_any_method(state, request, next).await
+}
+
+/// Route layer for GET routes that change state anyway, such as the
+/// email verification callback.
+pub async fn enforce_any_method(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(reason) = state.read_only.reason().await else {
+        return next.run(request).await;
+    };
+
+    info!(
+        "[ReadOnly] refused {} {} ({:?})",
+        request.method(),
+        request.uri().path(),
+        reason
+    );
+    let mut response = (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ErrorResponse::new(
+            ErrorCode::ReadOnly,
+            "Changes cannot be saved right now; you can still sign in and view your account. Please try again later".to_owned(),
+        )),
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+    response
+}
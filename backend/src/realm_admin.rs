@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+
+use crate::AppConfig;
+use crate::keycloak::KeycloakService;
+
+/// Admin REST client for realm provisioning. With `KEYCLOAK_ADMIN` set it
+/// signs in to the master realm through `admin-cli`, because the realm and
+/// the backend's own admin client may not exist yet. Otherwise it uses the
+/// backend's admin client, which is enough to inspect or update an existing
+/// realm. Either way its calls go through [`KeycloakService`], so they are
+/// limited, metered and recorded like the portal's own.
+pub struct RealmAdmin {
+    keycloak: Arc<KeycloakService>,
+    base_url: String,
+    /// The master realm token; `None` uses the backend's admin token.
+    token: Option<String>,
+}
+
+impl RealmAdmin {
+    /// Uses the backend's admin client only, for checks that run inside the
+    /// service and should not hold master realm credentials.
+    pub fn backend(config: &AppConfig, keycloak: Arc<KeycloakService>) -> Self {
+        Self {
+            keycloak,
+            base_url: config.keycloak_base_url.trim_end_matches('/').to_owned(),
+            token: None,
+        }
+    }
+
+    pub async fn sign_in(
+        config: &AppConfig,
+        keycloak: Arc<KeycloakService>,
+    ) -> Result<Self, String> {
+        let base_url = config.keycloak_base_url.trim_end_matches('/').to_owned();
+        let token = match (
+            config.keycloak_master_admin.as_deref(),
+            config.keycloak_master_password.as_deref(),
+        ) {
+            (Some(username), Some(password)) => Some(
+                keycloak
+                    .admin_cli_token(
+                        &format!("{base_url}/realms/master/protocol/openid-connect/token"),
+                        username,
+                        password,
+                    )
+                    .await
+                    .map_err(|err| format!("sign-in to realm master failed: {err}"))?,
+            ),
+            _ => None,
+        };
+
+        Ok(Self {
+            keycloak,
+            base_url,
+            token,
+        })
+    }
+
+    /// `path` is relative to `/admin/realms`. `None` when it does not exist.
+    pub async fn get(&self, path: &str) -> Result<Option<Value>, String> {
+        let response = self
+            .keycloak
+            .admin_request(
+                Method::GET,
+                &self.url(path),
+                &[],
+                None,
+                self.token.as_deref(),
+            )
+            .await
+            .map_err(|err| format!("GET {path}: {err}"))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => response
+                .json()
+                .await
+                .map(Some)
+                .map_err(|err| format!("GET {path}: {err}")),
+            status => Err(format!("GET {path}: {status}")),
+        }
+    }
+
+    pub async fn post(&self, path: &str, body: &Value) -> Result<(), String> {
+        self.send(Method::POST, path, body).await
+    }
+
+    pub async fn put(&self, path: &str, body: &Value) -> Result<(), String> {
+        self.send(Method::PUT, path, body).await
+    }
+
+    /// The internal id of a client in `realm`, looked up by `clientId`.
+    pub async fn client_uuid(
+        &self,
+        realm: &str,
+        client_id: &str,
+    ) -> Result<Option<String>, String> {
+        let clients = self
+            .get(&format!("{realm}/clients?clientId={}", encode(client_id)))
+            .await?;
+        Ok(clients
+            .as_ref()
+            .and_then(Value::as_array)
+            .and_then(|clients| clients.first())
+            .and_then(|client| client["id"].as_str())
+            .map(str::to_owned))
+    }
+
+    /// The id of the top-level group `name` in `realm`.
+    pub async fn group_id(&self, realm: &str, name: &str) -> Result<Option<String>, String> {
+        let groups = self
+            .get(&format!(
+                "{realm}/groups?search={}&exact=true",
+                encode(name)
+            ))
+            .await?;
+        Ok(groups
+            .as_ref()
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .find(|group| group["name"] == name)
+            .and_then(|group| group["id"].as_str())
+            .map(str::to_owned))
+    }
+
+    async fn send(&self, method: Method, path: &str, body: &Value) -> Result<(), String> {
+        let response = self
+            .keycloak
+            .admin_request(
+                method.clone(),
+                &self.url(path),
+                &[],
+                Some(body),
+                self.token.as_deref(),
+            )
+            .await
+            .map_err(|err| format!("{method} {path}: {err}"))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let message = response.text().await.unwrap_or_default();
+        Err(format!("{method} {path}: {status} {message}"))
+    }
+
+    fn url(&self, path: &str) -> String {
+        if path.is_empty() {
+            format!("{}/admin/realms", self.base_url)
+        } else {
+            format!("{}/admin/realms/{path}", self.base_url)
+        }
+    }
+}
+
+/// Percent-encodes a query parameter value.
+pub fn encode(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
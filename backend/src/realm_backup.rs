@@ -0,0 +1,619 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures_util::{Stream, TryStreamExt, stream};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::{Value, json};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+use crate::AppConfig;
+use crate::AppState;
+use crate::keycloak::{KeycloakError, KeycloakService};
+use crate::keycloak_limiter::{Priority, prioritized};
+use crate::s3::S3Target;
+use crate::util::unix_now;
+use crate::validation::rfc3339;
+
+/// Start of snapshot files sealed in one piece, as earlier versions wrote
+/// them; also their AES-GCM associated data.
+const MAGIC: &[u8] = b"ARGUSRB1";
+/// Start of snapshot files sealed in segments; also their associated data.
+const MAGIC_SEGMENTED: &[u8] = b"ARGUSRB2";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+/// Random part of a segment nonce; the rest is the segment number and a
+/// flag marking the last segment, so segments cannot be reordered or the
+/// snapshot cut short unnoticed.
+const NONCE_PREFIX_LEN: usize = 7;
+const SEGMENT_LEN: usize = 64 * 1024;
+const USER_PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error("realm backups are not configured")]
+    Disabled,
+    #[error(transparent)]
+    Keycloak(#[from] KeycloakError),
+    #[error("{0}")]
+    Storage(String),
+}
+
+/// A snapshot as written to the configured targets.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub name: String,
+    pub created_at: u64,
+    pub include_users: bool,
+    pub users: usize,
+    /// Encrypted size.
+    pub bytes: usize,
+    /// `file://` paths and `s3://` URLs the snapshot was stored at.
+    pub locations: Vec<String>,
+}
+
+/// Disaster-recovery copies of the realm configuration. An export is
+/// Keycloak's partial export, optionally with the realm's users added under
+/// `users` (without credentials, which the admin API never returns) along
+/// with their `realmRoles`, `clientRoles` and `groups`, as in Keycloak's own
+/// exports. Exports are produced piece by piece, a page of users at a time,
+/// so the realm is never held in memory whole.
+///
+/// Snapshots are sealed with AES-256-GCM under `REALM_BACKUP_KEY` (32 base64
+/// bytes, e.g. from `backend generate-cookie-key`) in 64 KiB segments as
+/// `ARGUSRB2 || nonce prefix || (length || ciphertext)*`, written to
+/// `REALM_BACKUP_DIR` and/or uploaded from there to
+/// `REALM_BACKUP_S3_BUCKET`, and pruned to the newest `REALM_BACKUP_RETAIN`
+/// on each target. With `REALM_BACKUP_INTERVAL_SECS` they are taken on a
+/// schedule as well as on request. `backend decrypt-backup <file>` turns one
+/// back into JSON.
+pub struct RealmBackups {
+    keycloak: Arc<KeycloakService>,
+    client: Client,
+    realm: String,
+    cipher: Option<Aes256Gcm>,
+    dir: Option<PathBuf>,
+    s3: Option<S3Target>,
+    s3_prefix: String,
+    retain: usize,
+}
+
+/// Part of an export, and whether it is one user.
+struct Piece {
+    bytes: Vec<u8>,
+    user: bool,
+}
+
+/// How far an export has got.
+enum Cursor {
+    Start,
+    Users {
+        next_page: Option<u32>,
+        page: VecDeque<Value>,
+        started: bool,
+    },
+    Done,
+}
+
+impl RealmBackups {
+    pub fn from_config(config: &AppConfig, keycloak: Arc<KeycloakService>, client: Client) -> Self {
+        let cipher = config.realm_backup_key.as_deref().and_then(|key| {
+            let cipher = cipher(key);
+            if cipher.is_none() {
+                warn!("[RealmBackup] REALM_BACKUP_KEY is not 32 base64 bytes; backups are off");
+            }
+            cipher
+        });
+        let s3 = config.realm_backup_s3_bucket.clone().and_then(|bucket| {
+            match (
+                config.aws_access_key_id.clone(),
+                config.aws_secret_access_key.clone(),
+            ) {
+                (Some(access_key), Some(secret_key)) => Some(S3Target {
+                    bucket,
+                    region: config.realm_backup_s3_region.clone(),
+                    endpoint: config.realm_backup_s3_endpoint.clone(),
+                    access_key,
+                    secret_key,
+                    session_token: config.aws_session_token.clone(),
+                }),
+                _ => {
+                    warn!(
+                        "[RealmBackup] REALM_BACKUP_S3_BUCKET needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+                    );
+                    None
+                }
+            }
+        });
+
+        Self {
+            keycloak,
+            client,
+            realm: config.keycloak_realm.clone(),
+            cipher,
+            dir: config.realm_backup_dir.clone().map(PathBuf::from),
+            s3,
+            s3_prefix: config.realm_backup_s3_prefix.clone(),
+            retain: config.realm_backup_retain,
+        }
+    }
+
+    /// Whether snapshots can be taken: a key and at least one target.
+    pub fn is_enabled(&self) -> bool {
+        self.cipher.is_some() && (self.dir.is_some() || self.s3.is_some())
+    }
+
+    /// The realm as JSON, with its users when `include_users` is set, in
+    /// pieces that concatenate to one document.
+    pub fn export(
+        self: &Arc<Self>,
+        include_users: bool,
+    ) -> impl Stream<Item = Result<Vec<u8>, KeycloakError>> + Send + 'static {
+        self.pieces(include_users).map_ok(|piece| piece.bytes)
+    }
+
+    fn pieces(
+        self: &Arc<Self>,
+        include_users: bool,
+    ) -> impl Stream<Item = Result<Piece, KeycloakError>> + Send + 'static {
+        let backups = Arc::clone(self);
+        // Polled as a response body too, after the route's priority scope.
+        stream::try_unfold(Cursor::Start, move |cursor| {
+            let backups = Arc::clone(&backups);
+            let next = async move { backups.next_piece(cursor, include_users).await };
+            prioritized(Priority::Background, next)
+        })
+    }
+
+    async fn next_piece(
+        &self,
+        cursor: Cursor,
+        include_users: bool,
+    ) -> Result<Option<(Piece, Cursor)>, KeycloakError> {
+        match cursor {
+            Cursor::Start => {
+                let realm: Value = self.keycloak.partial_export().await?;
+                let mut bytes = serde_json::to_vec(&realm).unwrap_or_default();
+                let Value::Object(fields) = realm else {
+                    return Ok(Some((Piece { bytes, user: false }, Cursor::Done)));
+                };
+                if !include_users {
+                    return Ok(Some((Piece { bytes, user: false }, Cursor::Done)));
+                }
+                // Reopen the realm object to add its users after the rest.
+                bytes.pop();
+                if !fields.is_empty() {
+                    bytes.push(b',');
+                }
+                bytes.extend_from_slice(br#""users":["#);
+                let cursor = Cursor::Users {
+                    next_page: Some(0),
+                    page: VecDeque::new(),
+                    started: false,
+                };
+                Ok(Some((Piece { bytes, user: false }, cursor)))
+            }
+            Cursor::Users {
+                mut next_page,
+                mut page,
+                started,
+            } => {
+                let user = loop {
+                    if let Some(user) = page.pop_front() {
+                        break user;
+                    }
+                    let Some(first) = next_page else {
+                        let end = Piece {
+                            bytes: b"]}".to_vec(),
+                            user: false,
+                        };
+                        return Ok(Some((end, Cursor::Done)));
+                    };
+                    let users: Vec<Value> = self.keycloak.users_page(first, USER_PAGE_SIZE).await?;
+                    next_page =
+                        (users.len() >= USER_PAGE_SIZE as usize).then(|| first + USER_PAGE_SIZE);
+                    page = users.into();
+                };
+
+                let user = self.with_access(user).await?;
+                let mut bytes = if started { vec![b','] } else { Vec::new() };
+                bytes.extend(serde_json::to_vec(&user).unwrap_or_default());
+                let cursor = Cursor::Users {
+                    next_page,
+                    page,
+                    started: true,
+                };
+                Ok(Some((Piece { bytes, user: true }, cursor)))
+            }
+            Cursor::Done => Ok(None),
+        }
+    }
+
+    /// Adds the user's directly assigned roles and groups, which the users
+    /// listing leaves out, in the shape Keycloak's own exports use.
+    async fn with_access(&self, mut user: Value) -> Result<Value, KeycloakError> {
+        let Some(id) = user.get("id").and_then(Value::as_str).map(str::to_owned) else {
+            return Ok(user);
+        };
+        let mappings = self.keycloak.role_mappings(&id).await?;
+        let groups = self.keycloak.user_groups(&id).await?;
+        if let Some(fields) = user.as_object_mut() {
+            let realm_roles: Vec<&str> = mappings
+                .realm_mappings
+                .iter()
+                .map(|role| role.name.as_str())
+                .collect();
+            let client_roles: serde_json::Map<String, Value> = mappings
+                .client_mappings
+                .iter()
+                .map(|(client, roles)| {
+                    let names: Vec<&str> = roles
+                        .mappings
+                        .iter()
+                        .map(|role| role.name.as_str())
+                        .collect();
+                    (client.clone(), json!(names))
+                })
+                .collect();
+            let groups: Vec<&str> = groups.iter().map(|group| group.path.as_str()).collect();
+            fields.insert("realmRoles".to_owned(), json!(realm_roles));
+            fields.insert("clientRoles".to_owned(), Value::Object(client_roles));
+            fields.insert("groups".to_owned(), json!(groups));
+        }
+        Ok(user)
+    }
+
+    /// Exports, seals and stores a snapshot on every target, then prunes
+    /// old ones. The snapshot is sealed into a file as the export comes in,
+    /// in `REALM_BACKUP_DIR` or a temporary directory, and uploaded from
+    /// there. A target that fails fails the snapshot, though others may
+    /// already hold it.
+    pub async fn snapshot(self: &Arc<Self>, include_users: bool) -> Result<Snapshot, BackupError> {
+        let Some(cipher) = self.cipher.as_ref().filter(|_| self.is_enabled()) else {
+            return Err(BackupError::Disabled);
+        };
+
+        let created_at = unix_now();
+        let name = format!(
+            "{}{}{}.json.enc",
+            self.name_prefix(),
+            rfc3339(created_at).replace(['-', ':'], ""),
+            if include_users { "-users" } else { "" }
+        );
+        let dir = self.dir.clone().unwrap_or_else(std::env::temp_dir);
+        let path = dir.join(&name);
+        let staging = dir.join(format!("{name}.partial"));
+        let storage = |err: std::io::Error, path: &Path| {
+            BackupError::Storage(format!("{}: {err}", path.display()))
+        };
+
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|err| storage(err, &dir))?;
+        let written = self.write_sealed(cipher, include_users, &staging).await;
+        let (users, bytes) = match written {
+            Ok(written) => written,
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&staging).await;
+                return Err(err);
+            }
+        };
+        tokio::fs::rename(&staging, &path)
+            .await
+            .map_err(|err| storage(err, &path))?;
+
+        let mut locations = Vec::new();
+        if self.dir.is_some() {
+            locations.push(format!("file://{}", path.display()));
+        }
+        if let Some(s3) = &self.s3 {
+            let key = format!("{}{name}", self.s3_prefix);
+            let uploaded = s3.put_file(&self.client, &key, &path).await;
+            if self.dir.is_none() {
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+            uploaded.map_err(BackupError::Storage)?;
+            locations.push(format!("s3://{}/{key}", s3.bucket));
+        }
+
+        info!(
+            "[RealmBackup] stored {} ({} bytes, {} users) at {}",
+            name,
+            bytes,
+            users,
+            locations.join(", ")
+        );
+        self.prune().await;
+        Ok(Snapshot {
+            name,
+            created_at,
+            include_users,
+            users,
+            bytes,
+            locations,
+        })
+    }
+
+    /// Streams the export into `path`, sealed; returns how many users it
+    /// holds and its size.
+    async fn write_sealed(
+        self: &Arc<Self>,
+        cipher: &Aes256Gcm,
+        include_users: bool,
+        path: &Path,
+    ) -> Result<(usize, usize), BackupError> {
+        let io = |err: std::io::Error| BackupError::Storage(format!("{}: {err}", path.display()));
+        let mut file = tokio::fs::File::create(path).await.map_err(io)?;
+        let mut sealer = Sealer::new(cipher);
+        let mut sealed = sealer.header();
+        let mut bytes = 0;
+        let mut users = 0;
+
+        let pieces = self.pieces(include_users);
+        futures_util::pin_mut!(pieces);
+        while let Some(piece) = pieces.try_next().await? {
+            users += usize::from(piece.user);
+            sealer.push(&piece.bytes, &mut sealed)?;
+            if !sealed.is_empty() {
+                file.write_all(&sealed).await.map_err(io)?;
+                bytes += sealed.len();
+                sealed.clear();
+            }
+        }
+        sealer.finish(&mut sealed)?;
+        file.write_all(&sealed).await.map_err(io)?;
+        file.sync_all().await.map_err(io)?;
+        bytes += sealed.len();
+        Ok((users, bytes))
+    }
+
+    /// `{realm}-`, which starts every snapshot name; names then sort by
+    /// when they were taken.
+    fn name_prefix(&self) -> String {
+        format!("{}-", self.realm)
+    }
+
+    /// Deletes all but the newest `REALM_BACKUP_RETAIN` snapshots on each
+    /// target; zero keeps them all.
+    async fn prune(&self) {
+        if self.retain == 0 {
+            return;
+        }
+        let prefix = self.name_prefix();
+        if let Some(dir) = &self.dir
+            && let Err(err) = prune_dir(dir, &prefix, self.retain).await
+        {
+            warn!("[RealmBackup] pruning {} failed: {}", dir.display(), err);
+        }
+        if let Some(s3) = &self.s3 {
+            let prefix = format!("{}{prefix}", self.s3_prefix);
+            let result = match s3.list(&self.client, &prefix).await {
+                Ok(keys) => {
+                    let mut result = Ok(());
+                    for key in outdated(keys, self.retain) {
+                        result = result.and(s3.delete(&self.client, &key).await);
+                    }
+                    result
+                }
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                warn!("[RealmBackup] pruning s3://{} failed: {}", s3.bucket, err);
+            }
+        }
+    }
+}
+
+async fn prune_dir(dir: &Path, prefix: &str, retain: usize) -> std::io::Result<()> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut names = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(prefix) {
+            names.push(name);
+        }
+    }
+    for name in outdated(names, retain) {
+        tokio::fs::remove_file(dir.join(name)).await?;
+    }
+    Ok(())
+}
+
+/// Snapshot names (or keys) beyond the newest `retain`.
+fn outdated(mut names: Vec<String>, retain: usize) -> Vec<String> {
+    names.retain(|name| name.ends_with(".json.enc"));
+    names.sort();
+    let excess = names.len().saturating_sub(retain);
+    names.truncate(excess);
+    names
+}
+
+/// Takes a snapshot every `REALM_BACKUP_INTERVAL_SECS`, with users when
+/// `REALM_BACKUP_INCLUDE_USERS` is set. Every replica runs the timer, but
+/// only the one that claims the interval in the session store takes the
+/// snapshot.
+pub fn spawn(state: AppState) {
+    let Some(interval) = state.config.realm_backup_interval else {
+        return;
+    };
+    if !state.realm_backups.is_enabled() {
+        warn!("[RealmBackup] REALM_BACKUP_INTERVAL_SECS is set but backups are not configured");
+        return;
+    }
+    let include_users = state.config.realm_backup_include_users;
+    info!(
+        "[RealmBackup] taking a snapshot every {}s",
+        interval.as_secs()
+    );
+    tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+            if !claim_interval(&state, interval).await {
+                continue;
+            }
+            if let Err(err) = state.realm_backups.snapshot(include_users).await {
+                error!("[RealmBackup] scheduled snapshot failed: {}", err);
+            }
+        }
+    });
+}
+
+async fn claim_interval(state: &AppState, interval: Duration) -> bool {
+    let slot = unix_now() / interval.as_secs().max(1);
+    let key = format!("realm-backup:{slot}");
+    match state.sessions.set_if_absent(&key, "1", interval).await {
+        Ok(claimed) => claimed,
+        Err(err) => {
+            warn!("[RealmBackup] unable to claim scheduled snapshot: {}", err);
+            false
+        }
+    }
+}
+
+fn cipher(key: &str) -> Option<Aes256Gcm> {
+    let bytes = STANDARD.decode(key.trim()).ok()?;
+    (bytes.len() == KEY_LEN).then(|| Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)))
+}
+
+/// Seals a snapshot segment by segment as its plaintext comes in.
+struct Sealer<'a> {
+    cipher: &'a Aes256Gcm,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<'a> Sealer<'a> {
+    fn new(cipher: &'a Aes256Gcm) -> Self {
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        prefix.copy_from_slice(&Aes256Gcm::generate_nonce(&mut OsRng)[..NONCE_PREFIX_LEN]);
+        Self {
+            cipher,
+            prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(SEGMENT_LEN),
+        }
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = MAGIC_SEGMENTED.to_vec();
+        header.extend_from_slice(&self.prefix);
+        header
+    }
+
+    /// Adds plaintext, appending every segment it completes to `out`.
+    fn push(&mut self, mut plaintext: &[u8], out: &mut Vec<u8>) -> Result<(), BackupError> {
+        while !plaintext.is_empty() {
+            let take = (SEGMENT_LEN - self.buffer.len()).min(plaintext.len());
+            self.buffer.extend_from_slice(&plaintext[..take]);
+            plaintext = &plaintext[take..];
+            if self.buffer.len() == SEGMENT_LEN {
+                self.seal_segment(false, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Seals what is left as the last segment.
+    fn finish(mut self, out: &mut Vec<u8>) -> Result<(), BackupError> {
+        self.seal_segment(true, out)
+    }
+
+    fn seal_segment(&mut self, last: bool, out: &mut Vec<u8>) -> Result<(), BackupError> {
+        let nonce = segment_nonce(&self.prefix, self.counter, last);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &self.buffer,
+                    aad: MAGIC_SEGMENTED,
+                },
+            )
+            .map_err(|_| BackupError::Storage("encryption failed".to_owned()))?;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| BackupError::Storage("snapshot too large".to_owned()))?;
+        self.buffer.clear();
+        out.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        out.extend_from_slice(&ciphertext);
+        Ok(())
+    }
+}
+
+fn segment_nonce(prefix: &[u8], counter: u32, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_LEN - 1] = u8::from(last);
+    nonce
+}
+
+/// The JSON inside a snapshot, for `backend decrypt-backup`.
+pub fn open(key: &str, sealed: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = cipher(key).ok_or("REALM_BACKUP_KEY is not 32 base64 bytes")?;
+    if let Some(rest) = sealed.strip_prefix(MAGIC_SEGMENTED) {
+        return open_segments(&cipher, rest);
+    }
+    let rest = sealed
+        .strip_prefix(MAGIC)
+        .filter(|rest| rest.len() > NONCE_LEN)
+        .ok_or("not a realm backup")?;
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: MAGIC,
+            },
+        )
+        .map_err(|_| "the key does not open this backup".to_owned())
+}
+
+fn open_segments(cipher: &Aes256Gcm, sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_PREFIX_LEN {
+        return Err("not a realm backup".to_owned());
+    }
+    let (prefix, mut rest) = sealed.split_at(NONCE_PREFIX_LEN);
+    let mut plaintext = Vec::new();
+    let mut counter = 0u32;
+    loop {
+        let Some((length, after)) = rest.split_first_chunk::<4>() else {
+            return Err("the backup is truncated".to_owned());
+        };
+        let length = u32::from_be_bytes(*length) as usize;
+        if after.len() < length {
+            return Err("the backup is truncated".to_owned());
+        }
+        let (ciphertext, after) = after.split_at(length);
+        let last = after.is_empty();
+        let nonce = segment_nonce(prefix, counter, last);
+        let segment = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: MAGIC_SEGMENTED,
+                },
+            )
+            .map_err(|_| "the key does not open this backup, or it is truncated".to_owned())?;
+        plaintext.extend_from_slice(&segment);
+        if last {
+            return Ok(plaintext);
+        }
+        rest = after;
+        counter += 1;
+    }
+}
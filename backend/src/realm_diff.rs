@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+use crate::AppConfig;
+use crate::keycloak::KeycloakService;
+use crate::keycloak_limiter::{Priority, prioritized};
+use crate::metrics::Metrics;
+use crate::realm_admin::RealmAdmin;
+use crate::realm_spec::RealmSpec;
+use crate::webhooks::WebhookService;
+
+/// Realm representation fields that name an authentication flow.
+const FLOW_BINDINGS: [&str; 4] = [
+    "browserFlow",
+    "directGrantFlow",
+    "registrationFlow",
+    "resetCredentialsFlow",
+];
+
+/// One setting whose live value differs from the spec. `actual` is `null`
+/// when the setting, or the whole object, is missing.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Drift {
+    pub object: String,
+    pub field: String,
+    pub expected: Value,
+    pub actual: Value,
+}
+
+impl Drift {
+    fn missing(object: String) -> Self {
+        Self {
+            object,
+            field: "exists".to_owned(),
+            expected: json!(true),
+            actual: json!(false),
+        }
+    }
+}
+
+/// Compares the live realm with the spec: realm settings including the
+/// password policy and flow bindings, that bound flows exist, both clients,
+/// roles, groups with their realm roles and default membership, and
+/// required actions. Client secrets are never compared. Roles a group has
+/// beyond the spec are not drift, since provisioning only ever adds them.
+pub async fn diff(
+    admin: &RealmAdmin,
+    config: &AppConfig,
+    spec: &RealmSpec,
+) -> Result<Vec<Drift>, String> {
+    let realm = spec.realm_name(config);
+    let mut drift = Vec::new();
+
+    let expected = spec.realm_representation(config);
+    let Some(live) = admin.get(&realm).await? else {
+        return Ok(vec![Drift::missing(format!("realm {realm}"))]);
+    };
+    compare(&format!("realm {realm}"), "", &expected, &live, &mut drift);
+
+    let flows = admin
+        .get(&format!("{realm}/authentication/flows"))
+        .await?
+        .unwrap_or_default();
+    for binding in FLOW_BINDINGS {
+        let Some(alias) = expected[binding].as_str() else {
+            continue;
+        };
+        let exists = flows
+            .as_array()
+            .is_some_and(|flows| flows.iter().any(|flow| flow["alias"] == alias));
+        if !exists {
+            drift.push(Drift::missing(format!("flow {alias}")));
+        }
+    }
+
+    for expected in [
+        spec.admin_client_representation(config),
+        spec.public_client_representation(config),
+    ] {
+        let client_id = expected["clientId"].as_str().unwrap_or_default();
+        let object = format!("client {client_id}");
+        match admin.client_uuid(&realm, client_id).await? {
+            Some(id) => {
+                let live = admin
+                    .get(&format!("{realm}/clients/{id}"))
+                    .await?
+                    .unwrap_or_default();
+                compare(&object, "", &expected, &live, &mut drift);
+            }
+            None => drift.push(Drift::missing(object)),
+        }
+    }
+
+    for role in &spec.roles {
+        if admin
+            .get(&format!("{realm}/roles/{}", role.name))
+            .await?
+            .is_none()
+        {
+            drift.push(Drift::missing(format!("role {}", role.name)));
+        }
+    }
+
+    let default_groups = admin
+        .get(&format!("{realm}/default-groups"))
+        .await?
+        .unwrap_or_default();
+    for group in &spec.groups {
+        let object = format!("group {}", group.name);
+        let Some(id) = admin.group_id(&realm, &group.name).await? else {
+            drift.push(Drift::missing(object));
+            continue;
+        };
+        let mappings = admin
+            .get(&format!("{realm}/groups/{id}/role-mappings/realm"))
+            .await?
+            .unwrap_or_default();
+        let roles = names(&mappings);
+        if !group.realm_roles.iter().all(|role| roles.contains(role)) {
+            drift.push(Drift {
+                object: object.clone(),
+                field: "realmRoles".to_owned(),
+                expected: json!(group.realm_roles),
+                actual: json!(roles),
+            });
+        }
+        let default = names(&default_groups).contains(&group.name);
+        if default != group.default {
+            drift.push(Drift {
+                object,
+                field: "default".to_owned(),
+                expected: json!(group.default),
+                actual: json!(default),
+            });
+        }
+    }
+
+    for action in &spec.required_actions {
+        let object = format!("required action {}", action.alias);
+        let path = format!("{realm}/authentication/required-actions/{}", action.alias);
+        match admin.get(&path).await? {
+            Some(live) => {
+                let expected = json!({
+                    "enabled": action.enabled,
+                    "defaultAction": action.default_action,
+                });
+                compare(&object, "", &expected, &live, &mut drift);
+            }
+            None => drift.push(Drift::missing(object)),
+        }
+    }
+
+    Ok(drift)
+}
+
+/// The `name` of every representation in a list.
+fn names(list: &Value) -> Vec<String> {
+    list.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item["name"].as_str())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Compares every field of `expected` with `live`, descending into nested
+/// objects. Lists are compared as sets since Keycloak does not keep their
+/// order.
+fn compare(object: &str, prefix: &str, expected: &Value, live: &Value, drift: &mut Vec<Drift>) {
+    let Some(fields) = expected.as_object() else {
+        return;
+    };
+    for (key, expected) in fields {
+        if key == "secret" {
+            continue;
+        }
+        let field = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        let actual = live.get(key).cloned().unwrap_or(Value::Null);
+        if expected.is_object() {
+            compare(object, &field, expected, &actual, drift);
+        } else if !same(expected, &actual) {
+            drift.push(Drift {
+                object: object.to_owned(),
+                field,
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+}
+
+fn same(expected: &Value, actual: &Value) -> bool {
+    match (expected.as_array(), actual.as_array()) {
+        (Some(expected), Some(actual)) => {
+            expected.len() == actual.len() && expected.iter().all(|item| actual.contains(item))
+        }
+        _ => expected == actual,
+    }
+}
+
+pub fn print(drift: &[Drift]) {
+    for item in drift {
+        println!(
+            "[DRIFT] {} {}: expected {}, found {}",
+            item.object, item.field, item.expected, item.actual
+        );
+    }
+    println!("{} drifted settings", drift.len());
+}
+
+/// Every `REALM_DRIFT_CHECK_SECS`, compares the realm with
+/// `REALM_SPEC_FILE`. Drift is logged on every check, exported as
+/// `realm_drift_settings` and published as a `realm.drift_detected` webhook
+/// whenever it changes, so a console edit is reported once rather than on
+/// every check. It reads the realm with the backend's own admin client,
+/// never with `KEYCLOAK_ADMIN`.
+pub fn spawn(
+    config: &AppConfig,
+    keycloak: Arc<KeycloakService>,
+    metrics: Arc<Metrics>,
+    webhooks: Arc<WebhookService>,
+) {
+    let Some(path) = config.realm_spec_file.clone() else {
+        return;
+    };
+    let interval = config.realm_drift_check_interval;
+    if interval.is_zero() {
+        return;
+    }
+    let spec = match RealmSpec::load(&path) {
+        Ok(spec) => spec,
+        Err(err) => {
+            error!("[Realm] drift check disabled, unable to load spec: {}", err);
+            return;
+        }
+    };
+
+    info!(
+        "[Realm] checking for drift from {} every {}s",
+        path,
+        interval.as_secs()
+    );
+    let config = config.clone();
+    tokio::spawn(async move {
+        let mut reported: Vec<Drift> = Vec::new();
+        loop {
+            sleep(interval).await;
+            let result = prioritized(Priority::Background, async {
+                let admin = RealmAdmin::backend(&config, keycloak.clone());
+                diff(&admin, &config, &spec).await
+            })
+            .await;
+            let drift = match result {
+                Ok(drift) => drift,
+                Err(err) => {
+                    warn!("[Realm] drift check failed: {}", err);
+                    continue;
+                }
+            };
+
+            metrics.realm_drift_settings.set(drift.len() as i64);
+            for item in &drift {
+                warn!(
+                    "[Realm] drift in {} {}: expected {}, found {}",
+                    item.object, item.field, item.expected, item.actual
+                );
+            }
+            if drift == reported {
+                continue;
+            }
+            if drift.is_empty() {
+                info!("[Realm] realm matches the spec again");
+            } else {
+                webhooks.publish(
+                    "realm.drift_detected",
+                    json!({ "realm": spec.realm_name(&config), "drift": drift }),
+                );
+            }
+            reported = drift;
+        }
+    });
+}
@@ -0,0 +1,291 @@
+use std::fs;
+
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+use crate::AppConfig;
+
+/// Declarative description of the realm the portal runs against, read from
+/// a TOML file by `bootstrap-realm`. Client ids default to the ones the
+/// backend is configured with, so the same environment variables drive
+/// both; everything left out of `[realm]` is not touched.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RealmSpec {
+    pub realm: RealmSettings,
+    pub admin_client: AdminClientSpec,
+    pub public_client: PublicClientSpec,
+    pub roles: Vec<RoleSpec>,
+    /// Realm roles every new user gets, through the realm's default role.
+    pub default_roles: Vec<String>,
+    pub groups: Vec<GroupSpec>,
+    pub required_actions: Vec<RequiredActionSpec>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RealmSettings {
+    /// Defaults to `KEYCLOAK_REALM`.
+    pub name: Option<String>,
+    pub display_name: Option<String>,
+    pub registration_allowed: Option<bool>,
+    pub verify_email: Option<bool>,
+    pub registration_email_as_username: Option<bool>,
+    pub login_with_email_allowed: Option<bool>,
+    pub duplicate_emails_allowed: Option<bool>,
+    pub reset_password_allowed: Option<bool>,
+    /// Keycloak policy string, e.g. `length(12) and notUsername`.
+    pub password_policy: Option<String>,
+    pub browser_flow: Option<String>,
+    pub direct_grant_flow: Option<String>,
+    pub registration_flow: Option<String>,
+    pub reset_credentials_flow: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminClientSpec {
+    /// Defaults to `KEYCLOAK_ADMIN_CLIENT_ID`.
+    pub client_id: Option<String>,
+    /// `realm-management` roles granted to the client's service account.
+    /// Defaults to what the backend calls: users, groups, sessions, roles
+    /// and events. `manage-realm` (realm backups, creating `ADMIN_ROLE`)
+    /// and `manage-clients` have to be listed to be granted.
+    pub service_account_roles: Vec<String>,
+}
+
+impl Default for AdminClientSpec {
+    fn default() -> Self {
+        Self {
+            client_id: None,
+            service_account_roles: [
+                "manage-users",
+                "view-users",
+                "query-users",
+                "query-groups",
+                "view-realm",
+                "view-clients",
+                "view-events",
+            ]
+            .map(str::to_owned)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PublicClientSpec {
+    /// Defaults to `KEYCLOAK_PUBLIC_CLIENT_ID`.
+    pub client_id: Option<String>,
+    /// Required, like `web_origins`: there is no default that would not let
+    /// any site receive the client's codes or call Keycloak with its tokens.
+    pub redirect_uris: Vec<String>,
+    pub web_origins: Vec<String>,
+    pub direct_access_grants: bool,
+    pub pkce: bool,
+}
+
+impl Default for PublicClientSpec {
+    fn default() -> Self {
+        Self {
+            client_id: None,
+            redirect_uris: Vec::new(),
+            web_origins: Vec::new(),
+            direct_access_grants: true,
+            pkce: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleSpec {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupSpec {
+    pub name: String,
+    #[serde(default)]
+    pub realm_roles: Vec<String>,
+    /// Whether new users join the group automatically.
+    #[serde(default)]
+    pub default: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequiredActionSpec {
+    pub alias: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// Whether the action is added to every new user.
+    #[serde(default)]
+    pub default_action: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl RealmSpec {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+        let spec: Self = toml::from_str(&contents).map_err(|err| format!("{path}: {err}"))?;
+        if spec.public_client.redirect_uris.is_empty() {
+            return Err(format!("{path}: public_client.redirect_uris is required"));
+        }
+        if spec.public_client.web_origins.is_empty() {
+            return Err(format!("{path}: public_client.web_origins is required"));
+        }
+        Ok(spec)
+    }
+
+    pub fn realm_name(&self, config: &AppConfig) -> String {
+        self.realm
+            .name
+            .clone()
+            .unwrap_or_else(|| config.keycloak_realm.clone())
+    }
+
+    pub fn admin_client_id(&self, config: &AppConfig) -> String {
+        self.admin_client
+            .client_id
+            .clone()
+            .unwrap_or_else(|| config.keycloak_admin_client_id.clone())
+    }
+
+    pub fn public_client_id(&self, config: &AppConfig) -> String {
+        self.public_client
+            .client_id
+            .clone()
+            .unwrap_or_else(|| config.keycloak_public_client_id.clone())
+    }
+
+    /// The realm settings the spec pins, as a Keycloak realm representation.
+    pub fn realm_representation(&self, config: &AppConfig) -> Value {
+        let settings = &self.realm;
+        let mut realm = Map::new();
+        realm.insert("realm".to_owned(), json!(self.realm_name(config)));
+        realm.insert("enabled".to_owned(), json!(true));
+        let fields = [
+            (
+                "displayName",
+                settings.display_name.as_ref().map(|value| json!(value)),
+            ),
+            (
+                "registrationAllowed",
+                settings.registration_allowed.map(Value::Bool),
+            ),
+            ("verifyEmail", settings.verify_email.map(Value::Bool)),
+            (
+                "registrationEmailAsUsername",
+                settings.registration_email_as_username.map(Value::Bool),
+            ),
+            (
+                "loginWithEmailAllowed",
+                settings.login_with_email_allowed.map(Value::Bool),
+            ),
+            (
+                "duplicateEmailsAllowed",
+                settings.duplicate_emails_allowed.map(Value::Bool),
+            ),
+            (
+                "resetPasswordAllowed",
+                settings.reset_password_allowed.map(Value::Bool),
+            ),
+            (
+                "passwordPolicy",
+                settings.password_policy.as_ref().map(|value| json!(value)),
+            ),
+            (
+                "browserFlow",
+                settings.browser_flow.as_ref().map(|value| json!(value)),
+            ),
+            (
+                "directGrantFlow",
+                settings
+                    .direct_grant_flow
+                    .as_ref()
+                    .map(|value| json!(value)),
+            ),
+            (
+                "registrationFlow",
+                settings
+                    .registration_flow
+                    .as_ref()
+                    .map(|value| json!(value)),
+            ),
+            (
+                "resetCredentialsFlow",
+                settings
+                    .reset_credentials_flow
+                    .as_ref()
+                    .map(|value| json!(value)),
+            ),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                realm.insert(key.to_owned(), value);
+            }
+        }
+        Value::Object(realm)
+    }
+
+    /// The confidential client the backend authenticates as. The secret is
+    /// `KEYCLOAK_ADMIN_CLIENT_SECRET` so that it matches the backend's
+    /// configuration.
+    pub fn admin_client_representation(&self, config: &AppConfig) -> Value {
+        let mut client = json!({
+            "clientId": self.admin_client_id(config),
+            "protocol": "openid-connect",
+            "enabled": true,
+            "publicClient": false,
+            "clientAuthenticatorType": "client-secret",
+            "serviceAccountsEnabled": true,
+            "standardFlowEnabled": false,
+            "directAccessGrantsEnabled": false,
+            "implicitFlowEnabled": false,
+        });
+        if !config.keycloak_admin_client_secret.is_empty() {
+            client["secret"] = json!(config.keycloak_admin_client_secret);
+        }
+        client
+    }
+
+    /// The client the SPA signs in with; confidential only when
+    /// `KEYCLOAK_PUBLIC_CLIENT_SECRET` is set.
+    pub fn public_client_representation(&self, config: &AppConfig) -> Value {
+        let spec = &self.public_client;
+        let secret = config.keycloak_public_client_secret.as_deref();
+        let mut client = json!({
+            "clientId": self.public_client_id(config),
+            "protocol": "openid-connect",
+            "enabled": true,
+            "publicClient": secret.is_none(),
+            "standardFlowEnabled": true,
+            "directAccessGrantsEnabled": spec.direct_access_grants,
+            "redirectUris": spec.redirect_uris,
+            "webOrigins": spec.web_origins,
+        });
+        if let Some(secret) = secret {
+            client["secret"] = json!(secret);
+        }
+        if spec.pkce {
+            client["attributes"] = json!({ "pkce.code.challenge.method": "S256" });
+        }
+        if let Some(url) = &config.frontchannel_logout_url {
+            client["frontchannelLogout"] = json!(true);
+            if !client["attributes"].is_object() {
+                client["attributes"] = json!({});
+            }
+            client["attributes"]["frontchannel.logout.url"] = json!(url);
+            client["attributes"]["frontchannel.logout.session.required"] = json!("true");
+        }
+        client
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::SystemTime;
+
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::AppConfig;
+
+pub const REFERRAL_CODE_ATTRIBUTE: &str = "referralCode";
+
+/// Codes counted one by one; the rest are counted together under
+/// [`OTHER_CODES`].
+const MAX_TRACKED_CODES: usize = 1_000;
+const OTHER_CODES: &str = "(other)";
+
+/// Where referral codes are checked. A static list wins over a remote
+/// endpoint when both are configured.
+pub enum ReferralSource {
+    Disabled,
+    Static(HashSet<String>),
+    Remote(String),
+}
+
+#[derive(Debug)]
+pub enum ReferralError {
+    Disabled,
+    Unknown,
+    Unavailable(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReferralCounts {
+    pub conversions: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoteVerdict {
+    #[serde(default = "default_valid")]
+    valid: bool,
+}
+
+fn default_valid() -> bool {
+    true
+}
+
+pub struct ReferralService {
+    source: ReferralSource,
+    started_at: SystemTime,
+    counts: Mutex<BTreeMap<String, ReferralCounts>>,
+}
+
+impl ReferralService {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let source = if !config.referral_codes.is_empty() {
+            ReferralSource::Static(
+                config
+                    .referral_codes
+                    .iter()
+                    .map(|code| normalize(code))
+                    .collect(),
+            )
+        } else if let Some(url) = config.referral_validate_url.clone() {
+            ReferralSource::Remote(url)
+        } else {
+            ReferralSource::Disabled
+        };
+
+        Self {
+            source,
+            started_at: SystemTime::now(),
+            counts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+
+    /// Checks `code` against the configured source and returns it in the
+    /// canonical form stored on the user.
+    pub async fn validate(&self, client: &Client, code: &str) -> Result<String, ReferralError> {
+        let code = normalize(code);
+        let verdict = match &self.source {
+            ReferralSource::Disabled => return Err(ReferralError::Disabled),
+            ReferralSource::Static(codes) => codes.contains(&code),
+            ReferralSource::Remote(url) => check_remote(client, url, &code).await?,
+        };
+
+        if verdict {
+            Ok(code)
+        } else {
+            tracked(&mut *self.counts.lock().await, code, false).rejected += 1;
+            Err(ReferralError::Unknown)
+        }
+    }
+
+    pub async fn record_conversion(&self, code: &str) {
+        tracked(&mut *self.counts.lock().await, code.to_owned(), true).conversions += 1;
+    }
+
+    /// Per-code counters since startup. Codes from a static list are listed
+    /// even before their first use.
+    pub async fn snapshot(&self) -> BTreeMap<String, ReferralCounts> {
+        let mut counts = self.counts.lock().await.clone();
+        if let ReferralSource::Static(codes) = &self.source {
+            for code in codes {
+                counts.entry(code.clone()).or_default();
+            }
+        }
+        counts
+    }
+}
+
+/// The counters for `code`. Once `MAX_TRACKED_CODES` are counted, a code
+/// that converts takes the place of the least rejected code that never
+/// has, and other codes are counted under `OTHER_CODES`, so made-up codes
+/// cannot grow the map without bound.
+fn tracked(
+    counts: &mut BTreeMap<String, ReferralCounts>,
+    code: String,
+    converting: bool,
+) -> &mut ReferralCounts {
+    if counts.contains_key(&code) || counts.len() < MAX_TRACKED_CODES {
+        return counts.entry(code).or_default();
+    }
+    let unconverted = counts
+        .iter()
+        .filter(|(code, counts)| code.as_str() != OTHER_CODES && counts.conversions == 0)
+        .min_by_key(|(_, counts)| counts.rejected)
+        .map(|(code, _)| code.clone());
+    match unconverted.filter(|_| converting) {
+        Some(evicted) => {
+            let evicted = counts.remove(&evicted).unwrap_or_default();
+            counts.entry(OTHER_CODES.to_owned()).or_default().rejected += evicted.rejected;
+            counts.entry(code).or_default()
+        }
+        None => counts.entry(OTHER_CODES.to_owned()).or_default(),
+    }
+}
+
+async fn check_remote(client: &Client, url: &str, code: &str) -> Result<bool, ReferralError> {
+    let response = client
+        .get(url)
+        .query(&[("code", code)])
+        .send()
+        .await
+        .map_err(|err| ReferralError::Unavailable(err.to_string()))?;
+
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(false),
+        status if status.is_success() => {
+            let body = response.text().await.unwrap_or_default();
+            if body.trim().is_empty() {
+                return Ok(true);
+            }
+            serde_json::from_str::<RemoteVerdict>(&body)
+                .map(|verdict| verdict.valid)
+                .map_err(|err| {
+                    warn!(?err, "[Referral] unexpected validation response");
+                    ReferralError::Unavailable(err.to_string())
+                })
+        }
+        status => Err(ReferralError::Unavailable(format!(
+            "unexpected status {status}"
+        ))),
+    }
+}
+
+fn normalize(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
@@ -0,0 +1,38 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use rand::Rng;
+
+use crate::AppConfig;
+use crate::clock_skew::usable_lifetime;
+use crate::util::unix_now;
+
+pub const EXPIRES_IN_HEADER: &str = "x-access-token-expires-in";
+pub const REFRESH_AT_HEADER: &str = "x-refresh-recommended-at";
+
+/// Adds `X-Access-Token-Expires-In` and `X-Refresh-Recommended-At` (unix
+/// seconds) to a token response. The recommended time lands at
+/// `REFRESH_HINT_RATIO` of the access token's lifetime, moved by up to
+/// `REFRESH_HINT_JITTER` of that lifetime either way, so clients that logged
+/// in together do not all refresh in the same second. The ratio applies to
+/// the lifetime left after `CLOCK_SKEW_LEEWAY_SECS`, so a client whose clock
+/// runs behind still refreshes before its token is refused.
+pub fn apply(config: &AppConfig, headers: &mut HeaderMap, expires_in: u64) {
+    if expires_in == 0 {
+        return;
+    }
+    let usable = usable_lifetime(expires_in, config.clock_skew_leeway);
+
+    let jitter = config.refresh_hint_jitter;
+    let ratio = if jitter > 0.0 {
+        config.refresh_hint_ratio + rand::thread_rng().gen_range(-jitter..=jitter)
+    } else {
+        config.refresh_hint_ratio
+    };
+    let refresh_in = (usable as f64 * ratio.clamp(0.0, 1.0)).round() as u64;
+
+    for (name, value) in [
+        (EXPIRES_IN_HEADER, expires_in),
+        (REFRESH_AT_HEADER, unix_now() + refresh_in),
+    ] {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::models::user::SERVER_ATTRIBUTES;
+use crate::username::{self, UsernameMode};
+use crate::validation::DATE_OF_BIRTH_ATTRIBUTE;
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeKind {
+    #[default]
+    String,
+    Email,
+    Password,
+    Number,
+    Boolean,
+    Date,
+}
+
+/// One field the registration form may send. Extra attributes are read from
+/// `REGISTRATION_ATTRIBUTES_FILE`, which is also the allowlist: attributes
+/// not named there are rejected, and without the file none are stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttributeSpec {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: AttributeKind,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// Accepted values, for fields rendered as a choice.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+impl AttributeSpec {
+    fn builtin(name: &str, kind: AttributeKind, required: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind,
+            required,
+            max_length: None,
+            options: Vec::new(),
+        }
+    }
+}
+
+/// Every field registration accepts: the built-in ones followed by the
+/// configured attributes.
+pub fn registration_fields(config: &AppConfig) -> Vec<AttributeSpec> {
+    let mut fields = vec![
+        AttributeSpec::builtin("email", AttributeKind::Email, true),
+        AttributeSpec::builtin("password", AttributeKind::Password, true),
+        AttributeSpec::builtin("firstName", AttributeKind::String, false),
+        AttributeSpec::builtin("lastName", AttributeKind::String, false),
+        AttributeSpec::builtin(
+            DATE_OF_BIRTH_ATTRIBUTE,
+            AttributeKind::Date,
+            config.register_min_age > 0,
+        ),
+        AttributeSpec::builtin("referralCode", AttributeKind::String, false),
+    ];
+    if config.username_mode.distinct() {
+        let required = config.username_mode == UsernameMode::Required;
+        fields.insert(
+            1,
+            AttributeSpec {
+                max_length: Some(username::MAX_LENGTH),
+                ..AttributeSpec::builtin("username", AttributeKind::String, required)
+            },
+        );
+    }
+    fields.extend(config.registration_attributes.iter().cloned());
+    fields
+}
+
+/// Keeps only the extras the schema declares, so that nothing else a signup
+/// sends becomes a Keycloak attribute.
+pub fn retain_declared(config: &AppConfig, extra: &mut HashMap<String, Value>) {
+    extra.retain(|key, _| {
+        config
+            .registration_attributes
+            .iter()
+            .any(|spec| spec.name == *key)
+    });
+}
+
+/// Refuses a schema that declares an attribute the portal sets itself,
+/// which would let a signup set it too.
+pub fn check(config: &AppConfig) -> Result<(), String> {
+    let owned = config
+        .registration_attributes
+        .iter()
+        .map(|spec| spec.name.as_str())
+        .filter(|name| SERVER_ATTRIBUTES.contains(name) || *name == config.admin_tenant_attribute)
+        .collect::<Vec<_>>();
+    if owned.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "REGISTRATION_ATTRIBUTES_FILE declares server attributes {}",
+            owned.join(", ")
+        ))
+    }
+}
+
+pub fn load_attributes(path: Option<&str>) -> Vec<AttributeSpec> {
+    let Some(path) = path.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Vec::new();
+    };
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!(%path, ?err, "Unable to read registration attributes file; extra attributes are dropped");
+            return Vec::new();
+        }
+    };
+
+    match serde_json::from_str::<Vec<AttributeSpec>>(&contents) {
+        Ok(attributes) => attributes,
+        Err(err) => {
+            warn!(%path, ?err, "Unable to parse registration attributes file; extra attributes are dropped");
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{error, info, warn};
+
+use crate::audit::AuditOutcome;
+use crate::{AppConfig, AppState, config_snapshot};
+
+/// Names of the variables the process was started with, before `.env` was
+/// loaded into the environment.
+static PROCESS_ENVIRONMENT: OnceLock<HashSet<String>> = OnceLock::new();
+
+/// Copies each listed setting that differs in `$fresh` into `$next` and
+/// notes its environment variable in `$changed`.
+macro_rules! take_changed {
+    ($next:ident, $fresh:ident, $changed:ident; $($field:ident => $name:literal),+ $(,)?) => {
+        $(
+            if $next.$field != $fresh.$field {
+                $next.$field = $fresh.$field.clone();
+                $changed.push($name);
+            }
+        )+
+    };
+}
+
+/// The configuration as last reloaded. Reloads only change the settings
+/// that are safe to switch under running requests: CORS origins
+/// (`BACKEND_ALLOWED_ORIGINS`), the IP allow and deny lists, the anomaly
+/// alert thresholds, the username and duplicate check limits
+/// (`USERNAME_CHECK_LIMIT`, `REGISTER_DUPLICATE_CHECK_LIMIT`) and the
+/// feature flags `STRICT_REQUEST_BODIES`, `TURNSTILE_VALIDATE_ACTION`,
+/// `LOGIN_RESPONSE_ROLES`, `REGISTER_DUPLICATE_CHECK`, `REGISTER_STORE_DOB`
+/// and `READ_ONLY_MODE`. Everything else, from listeners to Keycloak
+/// clients and the admin token, keeps its startup value until a restart,
+/// and so does `AppState::config`; code reading a reload-safe setting asks
+/// [`LiveConfig::current`].
+///
+/// A reload only applies to the replica that receives it: send SIGHUP or
+/// `POST /api/admin/reload` to every replica.
+pub struct LiveConfig {
+    current: RwLock<Arc<AppConfig>>,
+}
+
+impl LiveConfig {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(config.clone())),
+        }
+    }
+
+    pub fn current(&self) -> Arc<AppConfig> {
+        Arc::clone(&self.current.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Takes the reload-safe settings from `fresh` in one swap and names
+    /// the ones that changed.
+    fn update(&self, fresh: &AppConfig) -> Vec<&'static str> {
+        let mut current = self.current.write().unwrap_or_else(|err| err.into_inner());
+        let mut next = AppConfig::clone(&current);
+        let mut changed = Vec::new();
+        take_changed!(next, fresh, changed;
+            cors_allowed_origins => "BACKEND_ALLOWED_ORIGINS",
+            ip_allowlist => "IP_ALLOWLIST",
+            ip_denylist => "IP_DENYLIST",
+            admin_ip_allowlist => "ADMIN_IP_ALLOWLIST",
+            admin_ip_denylist => "ADMIN_IP_DENYLIST",
+            anomaly_failed_login_threshold => "ANOMALY_FAILED_LOGIN_THRESHOLD",
+            anomaly_captcha_rejection_threshold => "ANOMALY_CAPTCHA_REJECTION_THRESHOLD",
+            anomaly_registration_threshold => "ANOMALY_REGISTRATION_THRESHOLD",
+            strict_request_bodies => "STRICT_REQUEST_BODIES",
+            turnstile_validate_action => "TURNSTILE_VALIDATE_ACTION",
+            login_response_roles => "LOGIN_RESPONSE_ROLES",
+            register_duplicate_check => "REGISTER_DUPLICATE_CHECK",
+            store_date_of_birth => "REGISTER_STORE_DOB",
+            read_only_mode => "READ_ONLY_MODE",
+            username_check_limit => "USERNAME_CHECK_LIMIT",
+            register_duplicate_check_limit => "REGISTER_DUPLICATE_CHECK_LIMIT",
+        );
+        if !changed.is_empty() {
+            *current = Arc::new(next);
+        }
+        changed
+    }
+}
+
+/// Records which variables came from the process environment. Call once at
+/// startup, before `.env` is loaded.
+pub fn remember_environment() {
+    PROCESS_ENVIRONMENT.get_or_init(|| {
+        env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .collect()
+    });
+}
+
+/// Re-reads `.env`, applies the reload-safe settings and re-reads
+/// `IP_FILTER_FILE`. The process environment is never modified: variables
+/// the process was started with keep precedence over `.env`, and variables
+/// removed from `.env` keep the value they were given. Returns the settings
+/// that changed.
+pub fn reload(state: &AppState, actor: &str) -> Vec<&'static str> {
+    let dotenv = read_dotenv();
+    let process = PROCESS_ENVIRONMENT.get();
+    let fresh = AppConfig::from_source(|name| {
+        let from_process = process.is_none_or(|names| names.contains(name));
+        match dotenv.get(name) {
+            Some(value) if !from_process => Ok(value.clone()),
+            _ => env::var(name),
+        }
+    });
+    let changed = state.live.update(&fresh);
+    let config = state.live.current();
+    state.ip_filter.reload(&config);
+    state.anomalies.set_thresholds(&config);
+    if changed.contains(&"READ_ONLY_MODE") {
+        let read_only = Arc::clone(&state.read_only);
+        let enabled = config.read_only_mode;
+        tokio::spawn(async move {
+            if let Err(err) = read_only.set_manual(enabled).await {
+                warn!("[Reload] unable to apply READ_ONLY_MODE: {}", err);
+            }
+        });
+    }
+
+    if changed.is_empty() {
+        info!(
+            "[Reload] configuration re-read by {}, nothing changed",
+            actor
+        );
+    } else {
+        info!(
+            "[Reload] configuration re-read by {}, changed {}",
+            actor,
+            changed.join(", ")
+        );
+    }
+    let target = changed.join(",");
+    state.audit.record(
+        actor,
+        "config.reload",
+        AuditOutcome::Success,
+        (!changed.is_empty()).then_some(target.as_str()),
+        None,
+    );
+    config_snapshot::record(state);
+    changed
+}
+
+fn read_dotenv() -> HashMap<String, String> {
+    let entries = match dotenvy::dotenv_iter() {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("[Reload] .env not re-read: {}", err);
+            return HashMap::new();
+        }
+    };
+    let mut variables = HashMap::new();
+    for entry in entries {
+        match entry {
+            Ok((name, value)) => {
+                variables.insert(name, value);
+            }
+            Err(err) => {
+                warn!("[Reload] .env not re-read: {}", err);
+                return HashMap::new();
+            }
+        }
+    }
+    variables
+}
+
+/// Reloads on SIGHUP.
+pub fn spawn_signal_handler(state: AppState) {
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                error!("Unable to install the SIGHUP handler: {}", err);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            reload(&state, "sighup");
+        }
+    });
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+    extract::{FromRequestParts, Request, State},
+    http::{HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::Mac;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::audit::AuditOutcome;
+use crate::error_codes::ErrorCode;
+use crate::internal::ServiceIdentity;
+use crate::models::user::ErrorResponse;
+use crate::session::SessionStore;
+use crate::util::{hmac_sha256, unix_now};
+use crate::{AppConfig, AppState};
+
+pub const TIMESTAMP_HEADER: &str = "x-request-timestamp";
+pub const NONCE_HEADER: &str = "x-request-nonce";
+pub const SIGNATURE_HEADER: &str = "x-request-signature";
+const REPLAY_KEY_PREFIX: &str = "request-nonce:";
+/// Signed machine-to-machine requests carry small JSON bodies.
+const MAX_SIGNED_BODY: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum SigningError {
+    #[error("Request signature is required")]
+    Missing,
+    #[error("Request signature is invalid")]
+    InvalidSignature,
+    #[error("Request timestamp is outside the accepted window")]
+    Stale,
+    #[error("Request was already received")]
+    Replayed,
+    #[error("Request body is too large to verify")]
+    BodyTooLarge,
+    #[error("Replay check is unavailable")]
+    ReplayCheckUnavailable,
+}
+
+impl SigningError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Missing | Self::InvalidSignature | Self::Stale => StatusCode::UNAUTHORIZED,
+            Self::Replayed => StatusCode::CONFLICT,
+            Self::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::ReplayCheckUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn code(&self) -> ErrorCode {
+        match self {
+            Self::Missing => ErrorCode::SignatureRequired,
+            Self::InvalidSignature => ErrorCode::SignatureInvalid,
+            Self::Stale => ErrorCode::SignatureStale,
+            Self::Replayed => ErrorCode::RequestReplayed,
+            Self::BodyTooLarge => ErrorCode::PayloadTooLarge,
+            Self::ReplayCheckUnavailable => ErrorCode::ServiceUnavailable,
+        }
+    }
+}
+
+/// HMAC request signing for internal callers listed in
+/// `REQUEST_SIGNING_KEYS`. A signed request sends `X-Request-Timestamp`
+/// (unix seconds), a unique `X-Request-Nonce` and `X-Request-Signature`, the
+/// hex HMAC-SHA256 of
+/// `<METHOD>\n<path and query>\n<timestamp>\n<nonce>\n<hex SHA-256 of body>`.
+/// Nonces are remembered in the session store so a captured request cannot be
+/// replayed on any replica while its timestamp is still accepted.
+pub struct RequestSigner {
+    keys: Vec<(String, String)>,
+    window: Duration,
+    sessions: Arc<dyn SessionStore>,
+}
+
+impl RequestSigner {
+    pub fn from_config(config: &AppConfig, sessions: Arc<dyn SessionStore>) -> Self {
+        Self {
+            keys: config.request_signing_keys.clone(),
+            window: config.request_signature_window,
+            sessions,
+        }
+    }
+
+    /// The key a service must sign with; services without one are not
+    /// required to sign.
+    fn key(&self, service: &str) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(name, _)| name == service)
+            .map(|(_, key)| key.as_str())
+    }
+
+    pub async fn verify(
+        &self,
+        service: &str,
+        method: &Method,
+        target: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<(), SigningError> {
+        let Some(key) = self.key(service) else {
+            return Ok(());
+        };
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let (Some(timestamp), Some(nonce), Some(signature)) = (
+            header(TIMESTAMP_HEADER),
+            header(NONCE_HEADER),
+            header(SIGNATURE_HEADER),
+        ) else {
+            return Err(SigningError::Missing);
+        };
+
+        let signature = hex::decode(signature).map_err(|_| SigningError::InvalidSignature)?;
+        let payload = format!(
+            "{method}\n{target}\n{timestamp}\n{nonce}\n{}",
+            hex::encode(Sha256::digest(body))
+        );
+        let mut mac = hmac_sha256(key.as_bytes());
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| SigningError::InvalidSignature)?;
+
+        let timestamp: u64 = timestamp.parse().map_err(|_| SigningError::Stale)?;
+        if timestamp.abs_diff(unix_now()) > self.window.as_secs() {
+            return Err(SigningError::Stale);
+        }
+
+        // A timestamp stays acceptable for `window` either side of now, so
+        // its nonce is remembered for twice that.
+        let fresh = self
+            .sessions
+            .set_if_absent(
+                &format!("{REPLAY_KEY_PREFIX}{service}:{nonce}"),
+                "1",
+                self.window * 2,
+            )
+            .await
+            .map_err(|_| SigningError::ReplayCheckUnavailable)?;
+        if !fresh {
+            return Err(SigningError::Replayed);
+        }
+        Ok(())
+    }
+}
+
+/// Checks the signature of requests from services that have a signing key.
+/// Requests without a recognised service identity pass through; the route's
+/// own `ServiceIdentity` extractor rejects them.
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let Ok(identity) = ServiceIdentity::from_request_parts(&mut parts, &state).await else {
+        return next.run(Request::from_parts(parts, body)).await;
+    };
+    if state.request_signing.key(&identity.name).is_none() {
+        return next.run(Request::from_parts(parts, body)).await;
+    }
+
+    let result = match to_bytes(body, MAX_SIGNED_BODY).await {
+        Ok(bytes) => {
+            let target = parts
+                .uri
+                .path_and_query()
+                .map(|target| target.as_str())
+                .unwrap_or_else(|| parts.uri.path());
+            state
+                .request_signing
+                .verify(
+                    &identity.name,
+                    &parts.method,
+                    target,
+                    &parts.headers,
+                    &bytes,
+                )
+                .await
+                .map(|_| bytes)
+        }
+        Err(_) => Err(SigningError::BodyTooLarge),
+    };
+
+    match result {
+        Ok(bytes) => {
+            next.run(Request::from_parts(parts, Body::from(bytes)))
+                .await
+        }
+        Err(err) => {
+            warn!(
+                "[Internal] rejected signed request service={} path={}: {}",
+                identity.name,
+                parts.uri.path(),
+                err
+            );
+            state.audit.record(
+                &identity.name,
+                "internal.request.rejected",
+                AuditOutcome::Failure,
+                Some(parts.uri.path()),
+                None,
+            );
+            (
+                err.status(),
+                Json(ErrorResponse::new(err.code(), err.to_string())),
+            )
+                .into_response()
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use axum::{
+    Json,
+    body::{Body, Bytes, to_bytes},
+    extract::{Request, State},
+    http::{
+        HeaderValue, Method, StatusCode,
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH, ORIGIN, VARY},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::cache::TtlLruCache;
+use crate::error_codes::ErrorCode;
+use crate::models::user::ErrorResponse;
+use crate::tenant::TENANT_HEADER;
+use crate::{AppConfig, AppState};
+
+#[derive(Clone)]
+struct CachedResponse {
+    body: Bytes,
+    content_type: Option<HeaderValue>,
+    etag: HeaderValue,
+}
+
+/// Keeps successful GET responses of the public, cacheable routes for
+/// `RESPONSE_CACHE_TTL_SECS`, keyed by path, query and the headers that pick
+/// the tenant. A handler that sets its own `Cache-Control` (for example
+/// because the body carries a per-request form token) is never cached.
+pub struct ResponseCache {
+    enabled: bool,
+    entries: Mutex<TtlLruCache<String, CachedResponse>>,
+    cache_control: HeaderValue,
+}
+
+impl ResponseCache {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let max_age = config.response_cache_ttl.as_secs();
+        let entries = TtlLruCache::new(config.response_cache_capacity, config.response_cache_ttl);
+        Self {
+            enabled: entries.is_enabled(),
+            entries: Mutex::new(entries),
+            cache_control: HeaderValue::from_str(&format!("public, max-age={max_age}"))
+                .unwrap_or(HeaderValue::from_static("no-cache")),
+        }
+    }
+
+    fn conditional(&self, cached: CachedResponse, if_none_match: Option<HeaderValue>) -> Response {
+        let headers = [
+            (CACHE_CONTROL, self.cache_control.clone()),
+            (ETAG, cached.etag.clone()),
+            (VARY, HeaderValue::from_static("origin, x-argus-tenant")),
+        ];
+        if if_none_match.is_some_and(|tags| matches_etag(&tags, &cached.etag)) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+
+        let mut response = (headers, Body::from(cached.body)).into_response();
+        if let Some(content_type) = cached.content_type {
+            response.headers_mut().insert(CONTENT_TYPE, content_type);
+        }
+        response
+    }
+}
+
+pub async fn serve(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let cache = &state.response_cache;
+    if request.method() != Method::GET || !cache.enabled {
+        return next.run(request).await;
+    }
+
+    let key = cache_key(&request);
+    let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+    if let Some(cached) = cache.entries.lock().await.get(&key) {
+        return cache.conditional(cached, if_none_match);
+    }
+
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK || response.headers().contains_key(CACHE_CONTROL) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            warn!("[Cache] unable to buffer response for {}: {}", key, err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(
+                    ErrorCode::InternalError,
+                    "Unable to serve the response".to_owned(),
+                )),
+            )
+                .into_response();
+        }
+    };
+
+    let digest = hex::encode(&Sha256::digest(&body)[..8]);
+    let cached = CachedResponse {
+        body,
+        content_type: parts.headers.get(CONTENT_TYPE).cloned(),
+        etag: HeaderValue::from_str(&format!("\"{digest}\""))
+            .expect("hex digest is a valid header"),
+    };
+    cache.entries.lock().await.insert(key, cached.clone());
+    cache.conditional(cached, if_none_match)
+}
+
+fn cache_key(request: &Request) -> String {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+    };
+    format!(
+        "{}|{}|{}",
+        request.uri(),
+        header(TENANT_HEADER),
+        header(ORIGIN.as_str())
+    )
+}
+
+fn matches_etag(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(tags) = if_none_match.to_str() else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap_or_default();
+    tags.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use rand::RngCore;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::AppConfig;
+use crate::permissions::PermissionService;
+use crate::session::{SessionBackend, SessionStore};
+use crate::util::{unix_now, unverified_claims};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const REVOKED_KEY_PREFIX: &str = "revoked:";
+
+/// Hex SHA-256 of a token; tokens themselves never leave the replica.
+pub fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Revocation {
+    replica: String,
+    token_hash: String,
+}
+
+#[derive(Deserialize)]
+struct Expiry {
+    exp: Option<u64>,
+}
+
+/// Tokens ended by a logout. Revocations are kept in the session store
+/// until the token expires, read from its `exp` claim and capped at
+/// `REVOCATION_TTL_SECS` (the whole period for tokens without one), so they
+/// survive restarts and reach every replica. Each replica also keeps the
+/// revocations it has seen in a local cache and drops any state it cached
+/// for those tokens. With the Redis session store the revocation is
+/// published on `<SESSION_STORE_PREFIX>revocations` as well, so every other
+/// replica does that straight away instead of when its caches expire.
+pub struct RevocationBus {
+    replica: String,
+    channel: String,
+    client: Option<redis::Client>,
+    sessions: Arc<dyn SessionStore>,
+    ttl: Duration,
+    /// Token hash to the time its cached revocation may be forgotten.
+    revoked: Mutex<HashMap<String, Instant>>,
+    permissions: Arc<PermissionService>,
+    outgoing: mpsc::UnboundedSender<String>,
+    pending: Mutex<Option<mpsc::UnboundedReceiver<String>>>,
+}
+
+impl RevocationBus {
+    pub fn from_config(
+        config: &AppConfig,
+        sessions: Arc<dyn SessionStore>,
+        permissions: Arc<PermissionService>,
+    ) -> Self {
+        let client = match config.session_backend {
+            SessionBackend::Memory => None,
+            SessionBackend::Redis => redis::Client::open(config.redis_url.as_str())
+                .inspect_err(|err| warn!(?err, "[Revocation] invalid Redis URL; not broadcasting"))
+                .ok(),
+        };
+        let mut replica = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut replica);
+        let (outgoing, pending) = mpsc::unbounded_channel();
+
+        Self {
+            replica: hex::encode(replica),
+            channel: format!("{}revocations", config.session_store_prefix),
+            client,
+            sessions,
+            ttl: config.revocation_ttl,
+            revoked: Mutex::new(HashMap::new()),
+            permissions,
+            outgoing,
+            pending: Mutex::new(Some(pending)),
+        }
+    }
+
+    /// Ends `token` on this replica and tells the others.
+    pub async fn revoke(&self, token: &str) {
+        let token_hash = token_hash(token);
+        let ttl = self.remaining_lifetime(token);
+        if let Err(err) = self.sessions.set(&revoked_key(&token_hash), "1", ttl).await {
+            warn!("[Revocation] unable to store revocation: {}", err);
+        }
+        self.apply(&token_hash, ttl).await;
+
+        if self.client.is_some() {
+            let message = Revocation {
+                replica: self.replica.clone(),
+                token_hash,
+            };
+            if let Ok(payload) = serde_json::to_string(&message) {
+                let _ = self.outgoing.send(payload);
+            }
+        }
+    }
+
+    /// Whether `token` was revoked on any replica. A store failure counts
+    /// as revoked.
+    pub async fn is_revoked(&self, token: &str) -> bool {
+        let token_hash = token_hash(token);
+        let cached = self
+            .lock()
+            .get(&token_hash)
+            .is_some_and(|expires_at| *expires_at > Instant::now());
+        if cached {
+            return true;
+        }
+
+        match self.sessions.get(&revoked_key(&token_hash)).await {
+            Ok(None) => false,
+            Ok(Some(_)) => {
+                self.cache(&token_hash, self.remaining_lifetime(token));
+                true
+            }
+            Err(err) => {
+                warn!("[Revocation] unable to check revocation: {}", err);
+                true
+            }
+        }
+    }
+
+    /// Starts publishing local revocations and applying remote ones. Both
+    /// reconnect on their own after Redis errors.
+    pub fn spawn(self: &Arc<Self>) {
+        let Some(client) = self.client.clone() else {
+            return;
+        };
+        let Some(pending) = self
+            .pending
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .take()
+        else {
+            return;
+        };
+
+        info!("[Revocation] broadcasting on channel {}", self.channel);
+        tokio::spawn(publish_loop(client.clone(), self.channel.clone(), pending));
+
+        let bus = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = bus.listen(&client).await {
+                    warn!(?err, "[Revocation] subscription lost; reconnecting");
+                }
+                sleep(RECONNECT_DELAY).await;
+            }
+        });
+    }
+
+    async fn listen(&self, client: &redis::Client) -> redis::RedisResult<()> {
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(&self.channel).await?;
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let Ok(payload) = message.get_payload::<String>() else {
+                continue;
+            };
+            match serde_json::from_str::<Revocation>(&payload) {
+                Ok(revocation) if revocation.replica != self.replica => {
+                    debug!("[Revocation] received from replica {}", revocation.replica);
+                    self.apply(&revocation.token_hash, self.ttl).await;
+                }
+                Ok(_) => {}
+                Err(err) => warn!(?err, "[Revocation] ignoring malformed message"),
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply(&self, token_hash: &str, ttl: Duration) {
+        self.cache(token_hash, ttl);
+        self.permissions.forget_token(token_hash).await;
+    }
+
+    fn cache(&self, token_hash: &str, ttl: Duration) {
+        let now = Instant::now();
+        let mut revoked = self.lock();
+        revoked.retain(|_, expires_at| *expires_at > now);
+        revoked.insert(token_hash.to_owned(), now + ttl);
+    }
+
+    /// How long until `token` expires, at most `REVOCATION_TTL_SECS`.
+    fn remaining_lifetime(&self, token: &str) -> Duration {
+        unverified_claims::<Expiry>(token)
+            .and_then(|claims| claims.exp)
+            .map(|exp| Duration::from_secs(exp.saturating_sub(unix_now()).max(1)))
+            .map_or(self.ttl, |remaining| remaining.min(self.ttl))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Instant>> {
+        self.revoked.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+fn revoked_key(token_hash: &str) -> String {
+    format!("{REVOKED_KEY_PREFIX}{token_hash}")
+}
+
+async fn publish_loop(
+    client: redis::Client,
+    channel: String,
+    mut pending: mpsc::UnboundedReceiver<String>,
+) {
+    let mut connection: Option<ConnectionManager> = None;
+    while let Some(payload) = pending.recv().await {
+        if connection.is_none() {
+            match ConnectionManager::new(client.clone()).await {
+                Ok(manager) => connection = Some(manager),
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        "[Revocation] unable to connect to Redis; revocation not broadcast"
+                    );
+                    continue;
+                }
+            }
+        }
+        if let Some(manager) = connection.as_mut()
+            && let Err(err) = manager.publish::<_, _, ()>(&channel, payload).await
+        {
+            warn!(?err, "[Revocation] publish failed");
+        }
+    }
+}
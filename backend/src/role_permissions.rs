@@ -0,0 +1,65 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::AppConfig;
+use crate::models::auth::TokenIntrospection;
+
+/// `PERMISSIONS_FILE`: the portal permissions each Keycloak client role
+/// grants, keyed by client id and then role:
+///
+/// ```toml
+/// [clients.argus-portal]
+/// viewer = ["reports.read"]
+/// editor = ["reports.read", "reports.write"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermissionMap {
+    pub clients: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
+/// Translates a token's client roles into portal permission names, so the
+/// SPA can gate features on `reports.write` rather than on how roles happen
+/// to be named in Keycloak. Roles without a mapping grant nothing.
+pub struct RolePermissions {
+    map: PermissionMap,
+}
+
+impl RolePermissions {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let map = match config.permissions_file.as_deref() {
+            Some(path) => load(path).unwrap_or_else(|err| {
+                warn!("[Permissions] {}; no roles are mapped", err);
+                PermissionMap::default()
+            }),
+            None => PermissionMap::default(),
+        };
+        if !map.clients.is_empty() {
+            info!(
+                "[Permissions] mapping roles of {} client(s)",
+                map.clients.len()
+            );
+        }
+        Self { map }
+    }
+
+    pub fn permissions(&self, introspection: &TokenIntrospection) -> BTreeSet<String> {
+        let mut permissions = BTreeSet::new();
+        for (client, roles) in &self.map.clients {
+            for role in introspection.client_roles(client) {
+                if let Some(granted) = roles.get(role) {
+                    permissions.extend(granted.iter().cloned());
+                }
+            }
+        }
+        permissions
+    }
+}
+
+fn load(path: &str) -> Result<PermissionMap, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+    toml::from_str(&contents).map_err(|err| format!("{path}: {err}"))
+}
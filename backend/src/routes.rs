@@ -1,40 +1,392 @@
-use axum::{Router, http::HeaderValue, http::Method, routing::post};
-use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use std::sync::Arc;
 
-use crate::handlers::auth::{login_handler, logout_handler, refresh_handler};
-use crate::handlers::register::register_handler;
-use crate::{AppConfig, AppState};
+use axum::{
+    Router,
+    http::HeaderName,
+    http::HeaderValue,
+    http::Method,
+    middleware,
+    routing::{delete, get, post, put},
+};
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
 
+use crate::AppState;
+use crate::handlers::account::{
+    avatar_handler, deactivate_handler, not_me_handler, preferences_handler, reactivate_handler,
+    request_reactivation_handler, update_preferences_handler, upload_avatar_handler,
+    verified_callback_handler,
+};
+use crate::handlers::activity::activity_handler;
+use crate::handlers::admin::{
+    acknowledge_alert_handler, alerts_handler, audit_handler, create_api_key_handler,
+    create_captcha_exemption_handler, create_rate_limit_override_handler,
+    create_realm_backup_handler, delete_rate_limit_override_handler, drain_handler,
+    list_api_keys_handler, list_captcha_exemptions_handler, list_rate_limit_overrides_handler,
+    logging_handler, merge_users_handler, overview_handler, read_only_handler,
+    realm_export_handler, referral_stats_handler, reload_handler, revoke_api_key_handler,
+    revoke_captcha_exemption_handler, slo_handler, update_logging_handler,
+    update_read_only_handler,
+};
+use crate::handlers::auth::{
+    accept_terms_handler, check_handler, frontchannel_logout_handler, login_continue_handler,
+    login_handler, logout_handler, refresh_handler, resend_verification_handler, session_handler,
+};
+use crate::handlers::authorize::authorize_handler;
+use crate::handlers::captcha::challenge_handler;
+use crate::handlers::config::{config_handler, error_codes_handler, registration_schema_handler};
+use crate::handlers::health::{liveness_handler, readiness_handler};
+use crate::handlers::internal::{identity_handler, service_token_handler};
+use crate::handlers::metrics::metrics_handler;
+use crate::handlers::oauth::token_handler;
+use crate::handlers::permissions::{my_permissions_handler, permission_handler};
+use crate::handlers::register::{register_handler, username_availability_handler};
+use crate::handlers::webhooks::{
+    create_webhook_handler, dead_letters_handler, delete_webhook_handler,
+    discard_dead_letter_handler, get_webhook_handler, list_webhooks_handler,
+    replay_dead_letter_handler, replay_webhook_handler, test_webhook_handler,
+    update_webhook_handler, webhook_deliveries_handler,
+};
+use crate::scope::{ADMIN_SCOPE, AUDIT_SCOPE, RequireScope};
+use crate::{
+    api_version, body_logging, client_version, correlation, deadline, ip_filter, keycloak_limiter,
+    read_only, refresh_hint, request_signing, response_cache, scope, slo,
+};
+
+/// Public API. Operational routes are included too unless a separate admin
+/// listener is configured, in which case only that listener serves them.
 pub fn create_router(state: AppState) -> Router {
-    let cors = build_cors_layer(&state.config);
+    let cors = build_cors_layer(&state);
+
+    let mut router = public_routes(&state);
+    if state.config.admin_bind_address.is_none() {
+        router = router.merge(operational_routes(&state));
+    }
 
+    router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            body_logging::capture,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            deadline::enforce,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), slo::track))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            correlation::capture,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ip_filter::enforce,
+        ))
+        .with_state(state)
+        .layer(cors)
+}
+
+pub fn create_admin_router(state: AppState) -> Router {
+    operational_routes(&state)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            deadline::enforce,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            correlation::capture,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ip_filter::enforce,
+        ))
+        .with_state(state)
+}
+
+fn public_routes(state: &AppState) -> Router<AppState> {
+    let read_only = middleware::from_fn_with_state(state.clone(), read_only::enforce);
+    Router::new()
+        .merge(cacheable_routes(state))
+        .merge(auth_routes(state))
+        .route(
+            "/api/auth/frontchannel-logout",
+            get(frontchannel_logout_handler),
+        )
+        .route(
+            "/api/auth/verified-callback",
+            get(verified_callback_handler).layer(middleware::from_fn_with_state(
+                state.clone(),
+                read_only::enforce_any_method,
+            )),
+        )
+        .route(
+            "/api/auth/username-available",
+            get(username_availability_handler),
+        )
+        .route("/api/captcha/challenge", get(challenge_handler))
+        .route("/api/auth/authorize", post(authorize_handler))
+        .route("/api/auth/permissions", get(permission_handler))
+        .route("/api/users/me/permissions", get(my_permissions_handler))
+        .route("/api/auth/activity", get(activity_handler))
+        .route(
+            "/api/users/me/deactivate",
+            post(deactivate_handler).layer(read_only.clone()),
+        )
+        .route(
+            "/api/users/me/preferences",
+            get(preferences_handler)
+                .patch(update_preferences_handler)
+                .layer(read_only.clone()),
+        )
+        .route(
+            "/api/users/me/avatar",
+            put(upload_avatar_handler).layer(read_only.clone()),
+        )
+        .route("/api/users/:id/avatar", get(avatar_handler))
+        .route(
+            "/api/users/reactivate/request",
+            post(request_reactivation_handler).layer(read_only.clone()),
+        )
+        .route(
+            "/api/users/reactivate",
+            post(reactivate_handler).layer(read_only.clone()),
+        )
+        .route("/api/users/not-me", post(not_me_handler).layer(read_only))
+        .merge(internal_routes(state))
+}
+
+/// Routes that sign users in or out, fenced off for app builds below
+/// `MIN_CLIENT_VERSIONS` except for logout, so an outdated app can still
+/// sign out. Token answers follow the version in `Accept`.
+fn auth_routes(state: &AppState) -> Router<AppState> {
+    let read_only = middleware::from_fn_with_state(state.clone(), read_only::enforce);
     Router::new()
-        .route("/api/auth/register", post(register_handler))
+        .route(
+            "/api/auth/register",
+            post(register_handler).layer(read_only.clone()),
+        )
         .route("/api/auth/login", post(login_handler))
+        .route(
+            "/api/auth/login/continue",
+            post(login_continue_handler).layer(read_only.clone()),
+        )
+        .route(
+            "/api/auth/login/accept-terms",
+            post(accept_terms_handler).layer(read_only.clone()),
+        )
+        .route(
+            "/api/auth/login/resend-verification",
+            post(resend_verification_handler).layer(read_only),
+        )
         .route("/api/auth/refresh", post(refresh_handler))
-        .route("/api/auth/logout", post(logout_handler))
-        .with_state(state)
-        .layer(cors)
+        .route("/api/auth/check", get(check_handler))
+        .route("/api/auth/session", get(session_handler))
+        .route("/oauth/token", post(token_handler))
+        .route_layer(middleware::from_fn(keycloak_limiter::interactive))
+        .route_layer(middleware::from_fn(api_version::enforce))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            client_version::enforce,
+        ))
+        .route(
+            "/api/auth/logout",
+            post(logout_handler)
+                .layer(middleware::from_fn(keycloak_limiter::interactive))
+                .layer(middleware::from_fn(api_version::enforce)),
+        )
+}
+
+/// Public GET routes whose answers only change with configuration.
+fn cacheable_routes(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .route("/api/config", get(config_handler))
+        .route(
+            "/api/config/registration-schema",
+            get(registration_schema_handler),
+        )
+        .route("/api/error-codes", get(error_codes_handler))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            response_cache::serve,
+        ))
 }
 
-fn build_cors_layer(config: &AppConfig) -> CorsLayer {
-    let base = CorsLayer::new()
-        .allow_methods([Method::POST, Method::OPTIONS])
-        .allow_headers(Any);
+fn operational_routes(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(liveness_handler))
+        .route("/readyz", get(readiness_handler))
+        .merge(admin_routes(state))
+        .merge(audit_routes(state))
+}
 
-    if config.cors_allowed_origins.is_empty() {
-        return base.allow_origin(Any);
-    }
+/// Each group accepts its scope or the equivalent configured realm role.
+fn admin_routes(state: &AppState) -> Router<AppState> {
+    let guard = RequireScope::new(state, ADMIN_SCOPE, &state.config.admin_role);
+    Router::new()
+        .route("/api/admin/overview", get(overview_handler))
+        .route("/api/admin/stats/referrals", get(referral_stats_handler))
+        .route("/api/admin/alerts", get(alerts_handler))
+        .route("/api/admin/drain", post(drain_handler))
+        .route("/api/admin/reload", post(reload_handler))
+        .route("/api/admin/slo", get(slo_handler))
+        .route(
+            "/api/admin/read-only",
+            get(read_only_handler).put(update_read_only_handler),
+        )
+        .route(
+            "/api/admin/realm/export",
+            get(realm_export_handler).layer(middleware::from_fn(keycloak_limiter::background)),
+        )
+        .route(
+            "/api/admin/webhooks/dead-letters",
+            get(dead_letters_handler),
+        )
+        .route(
+            "/api/admin/webhooks/:id/deliveries",
+            get(webhook_deliveries_handler),
+        )
+        .merge(admin_change_routes(state))
+        .route_layer(middleware::from_fn_with_state(guard, scope::enforce))
+}
+
+/// Admin routes that change state, refused in read-only mode. Operational
+/// switches (drain, reload, read-only itself) stay outside.
+fn admin_change_routes(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .route("/api/admin/alerts/:id/ack", post(acknowledge_alert_handler))
+        .route("/api/admin/users/merge", post(merge_users_handler))
+        .route(
+            "/api/admin/realm/backups",
+            post(create_realm_backup_handler)
+                .layer(middleware::from_fn(keycloak_limiter::background)),
+        )
+        .route(
+            "/api/admin/logging",
+            get(logging_handler).patch(update_logging_handler),
+        )
+        .route(
+            "/api/admin/captcha-exemptions",
+            get(list_captcha_exemptions_handler).post(create_captcha_exemption_handler),
+        )
+        .route(
+            "/api/admin/captcha-exemptions/:id",
+            delete(revoke_captcha_exemption_handler),
+        )
+        .route(
+            "/api/admin/api-keys",
+            get(list_api_keys_handler).post(create_api_key_handler),
+        )
+        .route(
+            "/api/admin/api-keys/:prefix",
+            delete(revoke_api_key_handler),
+        )
+        .route(
+            "/api/admin/rate-limit-overrides",
+            get(list_rate_limit_overrides_handler).post(create_rate_limit_override_handler),
+        )
+        .route(
+            "/api/admin/rate-limit-overrides/:id",
+            delete(delete_rate_limit_override_handler),
+        )
+        .route(
+            "/api/admin/webhooks",
+            get(list_webhooks_handler).post(create_webhook_handler),
+        )
+        .route(
+            "/api/admin/webhooks/:id",
+            get(get_webhook_handler)
+                .patch(update_webhook_handler)
+                .delete(delete_webhook_handler),
+        )
+        .route("/api/admin/webhooks/:id/test", post(test_webhook_handler))
+        .route(
+            "/api/admin/webhooks/:id/replay",
+            post(replay_webhook_handler),
+        )
+        .route(
+            "/api/admin/webhooks/dead-letters/:id",
+            delete(discard_dead_letter_handler),
+        )
+        .route(
+            "/api/admin/webhooks/dead-letters/:id/replay",
+            post(replay_dead_letter_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            read_only::enforce,
+        ))
+}
 
-    let origins: Vec<HeaderValue> = config
-        .cors_allowed_origins
-        .iter()
-        .filter_map(|origin| HeaderValue::from_str(origin).ok())
-        .collect();
+fn audit_routes(state: &AppState) -> Router<AppState> {
+    let guard = RequireScope::new(state, AUDIT_SCOPE, &state.config.auditor_role);
+    Router::new()
+        .route("/api/admin/audit", get(audit_handler))
+        .route_layer(middleware::from_fn_with_state(guard, scope::enforce))
+}
+
+/// Routes served by the internal mTLS listener; also reachable on the public
+/// listener for callers whose certificate is forwarded by a trusted proxy.
+pub fn create_internal_router(state: AppState) -> Router {
+    internal_routes(&state)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            deadline::enforce,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            correlation::capture,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ip_filter::enforce,
+        ))
+        .with_state(state)
+}
+
+/// Machine-to-machine routes; callers with a `REQUEST_SIGNING_KEYS` entry
+/// must sign every request.
+fn internal_routes(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .route("/api/internal/identity", get(identity_handler))
+        .route("/api/internal/token", post(service_token_handler))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_signing::enforce,
+        ))
+}
 
-    if origins.is_empty() {
-        base.allow_origin(Any)
+/// Origins come from the live configuration, so a reload applies to the
+/// next preflight; an empty `BACKEND_ALLOWED_ORIGINS` admits any origin.
+/// With `COOKIE_KEYS` the refresh token travels in a cookie, so credentials
+/// are allowed and only the listed origins are admitted, never any.
+fn build_cors_layer(state: &AppState) -> CorsLayer {
+    let live = Arc::clone(&state.live);
+    let credentials = state.cookies.is_enabled();
+    let layer = CorsLayer::new()
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .expose_headers([
+            HeaderName::from_static(refresh_hint::EXPIRES_IN_HEADER),
+            HeaderName::from_static(refresh_hint::REFRESH_AT_HEADER),
+        ])
+        .allow_origin(AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            let config = live.current();
+            (config.cors_allowed_origins.is_empty() && !credentials)
+                || config
+                    .cors_allowed_origins
+                    .iter()
+                    .any(|allowed| allowed.as_bytes() == origin.as_bytes())
+        }));
+    if credentials {
+        layer
+            .allow_credentials(true)
+            .allow_headers(AllowHeaders::mirror_request())
     } else {
-        base.allow_origin(AllowOrigin::list(origins))
+        layer.allow_headers(Any)
     }
 }
@@ -0,0 +1,268 @@
+use std::path::Path;
+use std::time::Duration;
+
+use hmac::Mac;
+use regex::Regex;
+use reqwest::{Client, Method, Response, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use crate::util::{hmac_sha256, unix_now};
+use crate::validation::rfc3339;
+
+const FILE_UPLOAD_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Where and as whom objects are uploaded. `endpoint` defaults to AWS;
+/// MinIO and other S3-compatible stores work through a custom one, which is
+/// why requests use path-style addressing.
+pub struct S3Target {
+    pub bucket: String,
+    pub region: String,
+    pub endpoint: Option<String>,
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+/// What a request sends. Files are streamed with an unsigned payload, which
+/// S3 accepts over TLS, so they never have to fit in memory.
+enum Upload {
+    Bytes(Vec<u8>),
+    File(tokio::fs::File, u64),
+}
+
+impl S3Target {
+    /// Uploads `body` as `key`.
+    pub async fn put(&self, client: &Client, key: &str, body: Vec<u8>) -> Result<(), String> {
+        let response = self
+            .send(client, Method::PUT, key, &[], Upload::Bytes(body))
+            .await?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        Err(failure(response).await)
+    }
+
+    /// Uploads the file at `path` as `key`, streaming it from disk.
+    pub async fn put_file(&self, client: &Client, key: &str, path: &Path) -> Result<(), String> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        let length = file
+            .metadata()
+            .await
+            .map_err(|err| format!("{}: {err}", path.display()))?
+            .len();
+        let response = self
+            .send(client, Method::PUT, key, &[], Upload::File(file, length))
+            .await?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        Err(failure(response).await)
+    }
+
+    /// Keys that start with `prefix`, in S3's (lexicographic) order.
+    pub async fn list(&self, client: &Client, prefix: &str) -> Result<Vec<String>, String> {
+        let key_pattern = Regex::new("<Key>([^<]*)</Key>").expect("valid pattern");
+        let token_pattern = Regex::new("<NextContinuationToken>([^<]*)</NextContinuationToken>")
+            .expect("valid pattern");
+        let mut keys = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token));
+            }
+            let response = self
+                .send(client, Method::GET, "", &query, Upload::Bytes(Vec::new()))
+                .await?;
+            if !response.status().is_success() {
+                return Err(failure(response).await);
+            }
+            let body = response.text().await.map_err(|err| err.to_string())?;
+            keys.extend(
+                key_pattern
+                    .captures_iter(&body)
+                    .map(|captures| xml_unescape(&captures[1])),
+            );
+            match token_pattern.captures(&body) {
+                Some(captures) => continuation = Some(xml_unescape(&captures[1])),
+                None => return Ok(keys),
+            }
+        }
+    }
+
+    pub async fn delete(&self, client: &Client, key: &str) -> Result<(), String> {
+        let response = self
+            .send(client, Method::DELETE, key, &[], Upload::Bytes(Vec::new()))
+            .await?;
+        if response.status().is_success() || response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        Err(failure(response).await)
+    }
+
+    /// The object stored as `key`, or `None` if there is none.
+    pub async fn get(&self, client: &Client, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let response = self
+            .send(client, Method::GET, key, &[], Upload::Bytes(Vec::new()))
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => response
+                .bytes()
+                .await
+                .map(|body| Some(body.to_vec()))
+                .map_err(|err| err.to_string()),
+            _ => Err(failure(response).await),
+        }
+    }
+
+    /// Sends a Signature Version 4 request for `key`.
+    async fn send(
+        &self,
+        client: &Client,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Upload,
+    ) -> Result<Response, String> {
+        let base = self
+            .endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", self.region));
+        let path = format!("/{}/{}", uri_encode(&self.bucket), uri_encode(key));
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (query_encode(name), query_encode(value)))
+            .collect();
+        query.sort();
+        let canonical_query = query
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+        let separator = if canonical_query.is_empty() { "" } else { "?" };
+        let url = Url::parse(&format!(
+            "{}{path}{separator}{canonical_query}",
+            base.trim_end_matches('/')
+        ))
+        .map_err(|err| format!("invalid S3 endpoint {base}: {err}"))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => return Err(format!("invalid S3 endpoint {base}: no host")),
+        };
+
+        let timestamp = rfc3339(unix_now()).replace(['-', ':'], "");
+        let date = &timestamp[..8];
+        let payload_hash = match &body {
+            Upload::Bytes(bytes) => hex::encode(Sha256::digest(bytes)),
+            Upload::File(..) => "UNSIGNED-PAYLOAD".to_owned(),
+        };
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", timestamp.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{method}\n{path}\n{canonical_query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}"
+        );
+
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = [self.region.as_str(), "s3", "aws4_request"]
+            .into_iter()
+            .fold(
+                hmac(
+                    format!("AWS4{}", self.secret_key).as_bytes(),
+                    date.as_bytes(),
+                ),
+                |key, part| hmac(&key, part.as_bytes()),
+            );
+        let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+
+        let timeout = match &body {
+            Upload::Bytes(_) => Duration::from_secs(60),
+            Upload::File(..) => FILE_UPLOAD_TIMEOUT,
+        };
+        let mut request = client
+            .request(method, url)
+            .timeout(timeout)
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                    self.access_key
+                ),
+            );
+        request = match body {
+            Upload::Bytes(bytes) => request.body(bytes),
+            Upload::File(file, length) => request
+                .header(reqwest::header::CONTENT_LENGTH, length)
+                .body(reqwest::Body::from(file)),
+        };
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+
+        request.send().await.map_err(|err| err.to_string())
+    }
+}
+
+async fn failure(response: Response) -> String {
+    let status = response.status();
+    let message = response.text().await.unwrap_or_default();
+    format!("S3 answered {status}: {message}")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = hmac_sha256(key);
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters, as SigV4 canonical
+/// query strings require.
+fn query_encode(value: &str) -> String {
+    uri_encode(value).replace('/', "%2F")
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Percent-encodes everything but unreserved characters and `/`, as SigV4
+/// canonical paths require.
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
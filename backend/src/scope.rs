@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::AppState;
+use crate::admin::introspect;
+use crate::error_codes::ErrorCode;
+use crate::models::problem::{PROBLEM_JSON, ProblemDetails};
+
+pub const ADMIN_SCOPE: &str = "argus:admin";
+pub const AUDIT_SCOPE: &str = "argus:audit";
+
+/// Route-group guard: the validated bearer token must carry `scope` as an
+/// OAuth scope or `role` as a realm role.
+#[derive(Clone)]
+pub struct RequireScope {
+    state: AppState,
+    scope: &'static str,
+    role: Arc<str>,
+}
+
+impl RequireScope {
+    pub fn new(state: &AppState, scope: &'static str, role: &str) -> Self {
+        Self {
+            state: state.clone(),
+            scope,
+            role: role.into(),
+        }
+    }
+}
+
+pub async fn enforce(State(guard): State<RequireScope>, request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let introspection = match introspect(&mut parts, &guard.state).await {
+        Ok(introspection) => introspection,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    if introspection.grants(guard.scope, &guard.role) {
+        return next.run(Request::from_parts(parts, body)).await;
+    }
+
+    warn!(
+        "[Admin] sub={} denied path={}: missing scope {}",
+        introspection.sub.as_deref().unwrap_or_default(),
+        parts.uri.path(),
+        guard.scope
+    );
+    (
+        StatusCode::FORBIDDEN,
+        [(header::CONTENT_TYPE, PROBLEM_JSON)],
+        Json(ProblemDetails {
+            problem_type: "about:blank".to_owned(),
+            title: "Forbidden".to_owned(),
+            status: StatusCode::FORBIDDEN.as_u16(),
+            detail: format!("Missing required scope: {}", guard.scope),
+            code: ErrorCode::Forbidden,
+        }),
+    )
+        .into_response()
+}
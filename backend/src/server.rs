@@ -0,0 +1,305 @@
+use std::fs::{self, File, Permissions};
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+
+use axum::{Router, extract::ConnectInfo, http::Request};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{
+    RootCertStore, ServerConfig, crypto::ring, pki_types::CertificateDer,
+    server::WebPkiClientVerifier,
+};
+use tower::ServiceExt;
+use tracing::{debug, info};
+
+use crate::AppConfig;
+use crate::client_ip::LocalSocketPeer;
+use crate::internal::{ClientCertificate, certificate_sans};
+use crate::lifecycle::{ConnectionGuard, Lifecycle};
+
+/// Which HTTP versions a listener speaks. Over TLS HTTP/2 is negotiated with
+/// ALPN; on plaintext listeners it is only accepted as prior-knowledge h2c.
+#[derive(Debug, Clone, Copy)]
+pub struct Protocols {
+    pub http2: bool,
+    pub h2c: bool,
+}
+
+impl Protocols {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            http2: config.http2_enabled,
+            h2c: config.h2c_enabled,
+        }
+    }
+
+    fn builder(self, tls: bool) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        let http2 = if tls { self.http2 } else { self.h2c };
+        if !http2 {
+            builder = builder.http1_only();
+        }
+        builder
+    }
+
+    fn alpn(self) -> Vec<Vec<u8>> {
+        if self.http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        }
+    }
+}
+
+/// Settings for the internal listener that only accepts clients presenting a
+/// certificate signed by `INTERNAL_TLS_CLIENT_CA`.
+pub struct InternalTlsSettings {
+    pub addr: SocketAddr,
+    pub cert_path: String,
+    pub key_path: String,
+    pub client_ca_path: String,
+}
+
+impl InternalTlsSettings {
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        let addr = config.internal_tls_bind.as_deref()?.parse().ok()?;
+        Some(Self {
+            addr,
+            cert_path: config.internal_tls_cert.clone()?,
+            key_path: config.internal_tls_key.clone()?,
+            client_ca_path: config.internal_tls_client_ca.clone()?,
+        })
+    }
+}
+
+pub async fn serve_internal_tls(
+    app: Router,
+    settings: InternalTlsSettings,
+    protocols: Protocols,
+    lifecycle: Arc<Lifecycle>,
+) -> io::Result<()> {
+    let acceptor = TlsAcceptor::from(Arc::new(mtls_config(&settings, protocols)?));
+    let listener = TcpListener::bind(settings.addr).await?;
+    info!(addr = %settings.addr, "Internal mTLS listener ready");
+
+    serve_tcp(app, listener, Some(acceptor), protocols, lifecycle).await
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub async fn bind_tcp(addr: SocketAddr) -> io::Result<Self> {
+        TcpListener::bind(addr).await.map(Self::Tcp)
+    }
+
+    pub fn bind_unix(path: &str, mode: Option<u32>) -> io::Result<Self> {
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+        info!(%path, "Listening on Unix socket");
+        Ok(Self::Unix(listener))
+    }
+}
+
+/// Serves `app` on `listener`, terminating TLS there when `tls` is given.
+pub async fn serve(
+    app: Router,
+    listener: Listener,
+    tls: Option<TlsAcceptor>,
+    protocols: Protocols,
+    lifecycle: Arc<Lifecycle>,
+) -> io::Result<()> {
+    match listener {
+        Listener::Tcp(listener) => serve_tcp(app, listener, tls, protocols, lifecycle).await,
+        Listener::Unix(listener) => serve_unix(app, listener, protocols, lifecycle).await,
+    }
+}
+
+async fn serve_tcp(
+    app: Router,
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    protocols: Protocols,
+    lifecycle: Arc<Lifecycle>,
+) -> io::Result<()> {
+    let mut shutdown = lifecycle.subscribe();
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.wait_for(|stop| *stop) => return Ok(()),
+        };
+        if let Err(err) = stream.set_nodelay(true) {
+            debug!(%peer, ?err, "Unable to set TCP_NODELAY");
+        }
+        let app = app.clone();
+        let tls = tls.clone();
+        let lifecycle = Arc::clone(&lifecycle);
+        let guard = lifecycle.track_connection();
+
+        tokio::spawn(async move {
+            let Some(acceptor) = tls else {
+                let builder = protocols.builder(false);
+                serve_connection(stream, app, builder, &lifecycle, guard, move |request| {
+                    request.extensions_mut().insert(ConnectInfo(peer));
+                })
+                .await;
+                return;
+            };
+
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!(%peer, ?err, "TLS handshake failed");
+                    return;
+                }
+            };
+
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .map(|leaf| ClientCertificate {
+                    sans: certificate_sans(leaf.as_ref()),
+                });
+
+            let builder = protocols.builder(true);
+            serve_connection(stream, app, builder, &lifecycle, guard, move |request| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                if let Some(certificate) = &certificate {
+                    request.extensions_mut().insert(certificate.clone());
+                }
+            })
+            .await;
+        });
+    }
+}
+
+/// Serves `app` on a Unix domain socket for a co-located reverse proxy. The
+/// proxy is treated as a trusted hop, so its `X-Forwarded-For` is honoured.
+async fn serve_unix(
+    app: Router,
+    listener: UnixListener,
+    protocols: Protocols,
+    lifecycle: Arc<Lifecycle>,
+) -> io::Result<()> {
+    let mut shutdown = lifecycle.subscribe();
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.wait_for(|stop| *stop) => return Ok(()),
+        };
+        let app = app.clone();
+        let lifecycle = Arc::clone(&lifecycle);
+        let guard = lifecycle.track_connection();
+        tokio::spawn(async move {
+            let builder = protocols.builder(false);
+            serve_connection(stream, app, builder, &lifecycle, guard, |request| {
+                request.extensions_mut().insert(LocalSocketPeer);
+            })
+            .await;
+        });
+    }
+}
+
+async fn serve_connection<I, F>(
+    io: I,
+    app: Router,
+    builder: auto::Builder<TokioExecutor>,
+    lifecycle: &Lifecycle,
+    _guard: ConnectionGuard,
+    decorate: F,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: Fn(&mut Request<Incoming>) + Clone + Send + Sync + 'static,
+{
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        decorate(&mut request);
+        app.clone().oneshot(request)
+    });
+
+    let mut shutdown = lifecycle.subscribe();
+    let connection = builder.serve_connection(TokioIo::new(io), service);
+    tokio::pin!(connection);
+
+    // On shutdown, finish the requests already in flight and then close.
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = async { shutdown.wait_for(|stop| *stop).await.is_ok() } => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+
+    if let Err(err) = result {
+        debug!(?err, "Connection closed with error");
+    }
+}
+
+/// TLS for the public listener from `BACKEND_TLS_CERT`/`BACKEND_TLS_KEY`.
+pub fn public_tls(config: &AppConfig, protocols: Protocols) -> io::Result<Option<TlsAcceptor>> {
+    let (Some(cert_path), Some(key_path)) = (&config.tls_cert, &config.tls_key) else {
+        return Ok(None);
+    };
+
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?
+        .with_no_client_auth()
+        .with_single_cert(load_certs(cert_path)?, load_key(key_path)?)
+        .map_err(invalid_data)?;
+    server_config.alpn_protocols = protocols.alpn();
+
+    Ok(Some(TlsAcceptor::from(Arc::new(server_config))))
+}
+
+fn mtls_config(settings: &InternalTlsSettings, protocols: Protocols) -> io::Result<ServerConfig> {
+    let certs = load_certs(&settings.cert_path)?;
+    let key = load_key(&settings.key_path)?;
+
+    let mut roots = RootCertStore::empty();
+    for ca in load_certs(&settings.client_ca_path)? {
+        roots.add(ca).map_err(invalid_data)?;
+    }
+
+    let provider = Arc::new(ring::default_provider());
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(invalid_data)?;
+
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)
+        .map_err(invalid_data)?;
+    config.alpn_protocols = protocols.alpn();
+
+    Ok(config)
+}
+
+fn load_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+    rustls_pemfile::certs(&mut BufReader::new(File::open(path)?)).collect()
+}
+
+fn load_key(path: &str) -> io::Result<tokio_rustls::rustls::pki_types::PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut BufReader::new(File::open(path)?))?
+        .ok_or_else(|| invalid_data("no private key found"))
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::AppConfig;
+
+/// Expired entries are swept from the memory store once it grows past
+/// this, at most once per `MEMORY_SWEEP_INTERVAL`.
+const MEMORY_SWEEP_THRESHOLD: usize = 10_000;
+const MEMORY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// Entries the memory store holds at most; past it, those closest to
+/// expiring are dropped first.
+const MEMORY_MAX_ENTRIES: usize = 200_000;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("session store request failed: {0}")]
+    Redis(#[from] redis::RedisError),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionBackend {
+    Memory,
+    Redis,
+}
+
+impl SessionBackend {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "memory" => Some(Self::Memory),
+            "redis" => Some(Self::Redis),
+            _ => None,
+        }
+    }
+}
+
+/// Short-lived key/value state (sessions, one-time codes, replay and
+/// idempotency markers) that must be shared by every replica when more than
+/// one runs. Every entry carries a TTL.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, StoreError>;
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), StoreError>;
+
+    /// Stores `value` only when `key` is absent; returns whether it did.
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError>;
+
+    /// Reads and removes `key` in one step, for one-time values.
+    async fn take(&self, key: &str) -> Result<Option<String>, StoreError>;
+
+    async fn delete(&self, key: &str) -> Result<(), StoreError>;
+
+    /// The live keys starting with `prefix`, for registries kept as one
+    /// entry per item. Not for hot paths: Redis has to scan for them.
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>, StoreError>;
+
+    /// Adds one to the counter at `key` and returns the new count. The TTL
+    /// is set by the increment that creates the counter.
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, StoreError>;
+}
+
+/// Builds the store chosen by `SESSION_STORE`. Redis keys are namespaced with
+/// `SESSION_STORE_PREFIX`.
+pub async fn connect(config: &AppConfig) -> Result<Arc<dyn SessionStore>, StoreError> {
+    match config.session_backend {
+        SessionBackend::Memory => Ok(Arc::new(MemoryStore::new())),
+        SessionBackend::Redis => {
+            let client = redis::Client::open(config.redis_url.as_str())?;
+            let connection = ConnectionManager::new(client).await?;
+            info!("[Session] using Redis session store");
+            Ok(Arc::new(RedisStore {
+                connection,
+                prefix: config.session_store_prefix.clone(),
+            }))
+        }
+    }
+}
+
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+struct MemoryState {
+    entries: HashMap<String, (String, Instant)>,
+    swept_at: Instant,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(MemoryState {
+                entries: HashMap::new(),
+                swept_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn with_entries<T>(&self, f: impl FnOnce(&mut HashMap<String, (String, Instant)>) -> T) -> T {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+        let full = state.entries.len() > MEMORY_MAX_ENTRIES;
+        if full
+            || (state.entries.len() > MEMORY_SWEEP_THRESHOLD
+                && now.duration_since(state.swept_at) >= MEMORY_SWEEP_INTERVAL)
+        {
+            state.entries.retain(|_, (_, expires_at)| *expires_at > now);
+            state.swept_at = now;
+        }
+        if state.entries.len() > MEMORY_MAX_ENTRIES {
+            evict_soonest(&mut state.entries);
+        }
+        f(&mut state.entries)
+    }
+}
+
+/// Drops the tenth of the entries closest to expiring.
+fn evict_soonest(entries: &mut HashMap<String, (String, Instant)>) {
+    let mut expiries: Vec<Instant> = entries
+        .values()
+        .map(|(_, expires_at)| *expires_at)
+        .collect();
+    let keep = MEMORY_MAX_ENTRIES - MEMORY_MAX_ENTRIES / 10;
+    let cut = expiries.len() - keep;
+    let (_, &mut cutoff, _) = expiries.select_nth_unstable(cut);
+    let before = entries.len();
+    entries.retain(|_, (_, expires_at)| *expires_at >= cutoff);
+    warn!(
+        "[Session] memory store full, dropped {} entries closest to expiring",
+        before - entries.len()
+    );
+}
+
+fn live(entry: Option<&(String, Instant)>) -> Option<&String> {
+    entry
+        .filter(|(_, expires_at)| *expires_at > Instant::now())
+        .map(|(value, _)| value)
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.with_entries(|entries| live(entries.get(key)).cloned()))
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), StoreError> {
+        self.with_entries(|entries| {
+            entries.insert(key.to_owned(), (value.to_owned(), Instant::now() + ttl));
+        });
+        Ok(())
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError> {
+        Ok(self.with_entries(|entries| {
+            if live(entries.get(key)).is_some() {
+                return false;
+            }
+            entries.insert(key.to_owned(), (value.to_owned(), Instant::now() + ttl));
+            true
+        }))
+    }
+
+    async fn take(&self, key: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.with_entries(|entries| {
+            let (value, expires_at) = entries.remove(key)?;
+            (expires_at > Instant::now()).then_some(value)
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StoreError> {
+        self.with_entries(|entries| entries.remove(key));
+        Ok(())
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>, StoreError> {
+        Ok(self.with_entries(|entries| {
+            entries
+                .iter()
+                .filter(|(key, entry)| key.starts_with(prefix) && live(Some(*entry)).is_some())
+                .map(|(key, _)| key.clone())
+                .collect()
+        }))
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, StoreError> {
+        Ok(self.with_entries(|entries| {
+            let count = live(entries.get(key))
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(0)
+                + 1;
+            let expires_at = match entries.get(key) {
+                Some((_, expires_at)) if count > 1 => *expires_at,
+                _ => Instant::now() + ttl,
+            };
+            entries.insert(key.to_owned(), (count.to_string(), expires_at));
+            count
+        }))
+    }
+}
+
+pub struct RedisStore {
+    connection: ConnectionManager,
+    prefix: String,
+}
+
+impl RedisStore {
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+/// Escapes the characters Redis `MATCH` patterns treat specially.
+fn glob_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn ttl_millis(ttl: Duration) -> u64 {
+    (ttl.as_millis() as u64).max(1)
+}
+
+#[async_trait]
+impl SessionStore for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, StoreError> {
+        let mut connection = self.connection.clone();
+        Ok(connection.get(self.key(key)).await?)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), StoreError> {
+        let mut connection = self.connection.clone();
+        let _: () = redis::cmd("SET")
+            .arg(self.key(key))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl_millis(ttl))
+            .query_async(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError> {
+        let mut connection = self.connection.clone();
+        let stored: Option<String> = redis::cmd("SET")
+            .arg(self.key(key))
+            .arg(value)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_millis(ttl))
+            .query_async(&mut connection)
+            .await?;
+        Ok(stored.is_some())
+    }
+
+    async fn take(&self, key: &str) -> Result<Option<String>, StoreError> {
+        let mut connection = self.connection.clone();
+        Ok(redis::cmd("GETDEL")
+            .arg(self.key(key))
+            .query_async(&mut connection)
+            .await?)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StoreError> {
+        let mut connection = self.connection.clone();
+        let _: () = connection.del(self.key(key)).await?;
+        Ok(())
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>, StoreError> {
+        let mut connection = self.connection.clone();
+        let pattern = format!("{}*", glob_escape(&self.key(prefix)));
+        let mut found = Vec::new();
+        let mut iter = connection.scan_match::<_, String>(pattern).await?;
+        while let Some(key) = iter.next_item().await {
+            if let Some(key) = key.strip_prefix(self.prefix.as_str()) {
+                found.push(key.to_owned());
+            }
+        }
+        Ok(found)
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, StoreError> {
+        let mut connection = self.connection.clone();
+        let key = self.key(key);
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_millis(ttl))
+            .ignore()
+            .cmd("INCR")
+            .arg(&key)
+            .query_async(&mut connection)
+            .await?;
+        Ok(count)
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::AppConfig;
+use crate::keycloak::KeycloakService;
+use crate::tenant::TenantConfig;
+
+/// What happens when a login would take a user past their session cap:
+/// `reject` turns the new login away with `too_many_sessions`, and
+/// `evict_oldest` ends the user's oldest sessions to make room for it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLimitPolicy {
+    Reject,
+    EvictOldest,
+}
+
+impl SessionLimitPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "reject" => Some(Self::Reject),
+            "evict_oldest" | "evict" => Some(Self::EvictOldest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SessionCheck {
+    Within,
+    /// This many older sessions were ended.
+    Evicted(usize),
+    /// The user has `active` sessions, including the new one, over a cap of
+    /// `max`.
+    Exceeded {
+        active: usize,
+        max: usize,
+    },
+    /// The sessions could not be counted, so the login cannot be allowed.
+    Unknown,
+}
+
+/// Caps how many Keycloak sessions a user may hold at once, for deployments
+/// licensed per concurrent session. `MAX_SESSIONS_PER_USER` and
+/// `SESSION_LIMIT_POLICY` set the default, which tenants can override with
+/// `maxSessions` and `sessionLimitPolicy`. The tenant is the one whose
+/// Keycloak client the new tokens were issued to, never a request header.
+/// The check runs after Keycloak has created the new session, so it counts
+/// towards the cap. When the sessions cannot be counted the login is
+/// refused rather than let past the cap.
+pub struct SessionLimits {
+    keycloak: Arc<KeycloakService>,
+    tenants: Vec<TenantConfig>,
+    max_sessions: usize,
+    policy: SessionLimitPolicy,
+}
+
+impl SessionLimits {
+    pub fn from_config(config: &AppConfig, keycloak: Arc<KeycloakService>) -> Self {
+        Self {
+            keycloak,
+            tenants: config.tenants.clone(),
+            max_sessions: config.max_sessions_per_user,
+            policy: config.session_limit_policy,
+        }
+    }
+
+    /// The cap and policy for the Keycloak client a session belongs to; a
+    /// cap of zero means unlimited.
+    fn limits(&self, client_id: Option<&str>) -> (usize, SessionLimitPolicy) {
+        let tenant = client_id.and_then(|client_id| {
+            self.tenants
+                .iter()
+                .find(|tenant| tenant.keycloak_client_id.as_deref() == Some(client_id))
+        });
+        (
+            tenant
+                .and_then(|tenant| tenant.max_sessions)
+                .unwrap_or(self.max_sessions),
+            tenant
+                .and_then(|tenant| tenant.session_limit_policy)
+                .unwrap_or(self.policy),
+        )
+    }
+
+    /// `user_id` and `client_id` are the `sub` and `azp` of the tokens
+    /// Keycloak just issued.
+    pub async fn enforce(
+        &self,
+        email: &str,
+        user_id: Option<&str>,
+        client_id: Option<&str>,
+    ) -> SessionCheck {
+        let (max, policy) = self.limits(client_id);
+        if max == 0 {
+            return SessionCheck::Within;
+        }
+
+        let Some(user_id) = user_id else {
+            warn!("[Sessions] token for {} names no user", email);
+            return SessionCheck::Unknown;
+        };
+        let mut sessions = match self.keycloak.user_sessions(user_id).await {
+            Ok(sessions) => sessions,
+            Err(err) => {
+                warn!("[Sessions] session lookup failed for {}: {}", email, err);
+                return SessionCheck::Unknown;
+            }
+        };
+        if sessions.len() <= max {
+            return SessionCheck::Within;
+        }
+        if policy == SessionLimitPolicy::Reject {
+            return SessionCheck::Exceeded {
+                active: sessions.len(),
+                max,
+            };
+        }
+
+        sessions.sort_by_key(|session| session.start);
+        let excess = sessions.len() - max;
+        let mut evicted = 0;
+        for session in &sessions[..excess] {
+            match self.keycloak.delete_session(&session.id).await {
+                Ok(()) => evicted += 1,
+                Err(err) => warn!(
+                    "[Sessions] unable to end session {} of {}: {}",
+                    session.id, email, err
+                ),
+            }
+        }
+        info!(
+            "[Sessions] user={} ended {} of {} oldest sessions (max {})",
+            email, evicted, excess, max
+        );
+        SessionCheck::Evicted(evicted)
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+
+use crate::metrics::Metrics;
+use crate::util::unix_now;
+use crate::{AppConfig, AppState};
+
+const BUCKET_SECS: u64 = 60;
+
+/// Windows burn rates are reported over, besides the whole SLO window.
+const BURN_RATE_WINDOWS: [(&str, u64); 3] = [("5m", 300), ("1h", 3600), ("6h", 6 * 3600)];
+
+/// One `SLO_TARGETS` entry: requests to paths starting with `route` are
+/// good when they do not fail with a 5xx and finish within `latency`, and
+/// `objective` is the fraction of requests that must be good.
+#[derive(Debug, Clone, Serialize)]
+pub struct SloTarget {
+    pub route: String,
+    pub objective: f64,
+    pub latency: Duration,
+}
+
+impl SloTarget {
+    /// `<route>=<objective percent>[:<latency ms>]`, e.g.
+    /// `/api/auth/login=99.9:500`. Latency defaults to one second.
+    pub fn parse(entry: &str) -> Option<Self> {
+        let (route, spec) = entry.rsplit_once('=')?;
+        let (objective, latency) = match spec.split_once(':') {
+            Some((objective, latency)) => (objective, latency.trim().parse::<u64>().ok()?),
+            None => (spec, 1000),
+        };
+        let objective = objective.trim().parse::<f64>().ok()? / 100.0;
+        let route = route.trim();
+        if route.is_empty() || !(0.0..1.0).contains(&objective) {
+            return None;
+        }
+        Some(Self {
+            route: route.to_owned(),
+            objective,
+            latency: Duration::from_millis(latency),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    start: u64,
+    requests: u64,
+    errors: u64,
+    slow: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SloReport {
+    pub route: String,
+    pub objective: f64,
+    pub latency_threshold_ms: u64,
+    pub requests: u64,
+    /// Requests that failed with a 5xx.
+    pub errors: u64,
+    /// Requests that succeeded but took longer than the threshold.
+    pub slow: u64,
+    /// Fraction of good requests; `None` before the first request.
+    pub sli: Option<f64>,
+    /// Share of the window's error budget still unspent; negative once it
+    /// is exhausted.
+    pub error_budget_remaining: f64,
+    /// How fast the budget is being spent in each window; 1 spends exactly
+    /// the whole budget over the SLO window.
+    pub burn_rates: BTreeMap<&'static str, f64>,
+}
+
+/// Per-route availability and latency against `SLO_TARGETS`, kept in
+/// one-minute buckets for `SLO_WINDOW_SECS`. Counts are per process, so with
+/// several replicas each reports its own share of the traffic.
+pub struct SloTracker {
+    targets: Vec<SloTarget>,
+    window: Duration,
+    buckets: Mutex<HashMap<String, VecDeque<Bucket>>>,
+}
+
+impl SloTracker {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            targets: config.slo_targets.clone(),
+            window: config.slo_window,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The longest matching target, as for `ROUTE_TIMEOUTS`.
+    fn target(&self, path: &str) -> Option<&SloTarget> {
+        self.targets
+            .iter()
+            .filter(|target| path.starts_with(target.route.as_str()))
+            .max_by_key(|target| target.route.len())
+    }
+
+    fn record(&self, target: &SloTarget, failed: bool, elapsed: Duration) {
+        let now = unix_now();
+        let start = now - now % BUCKET_SECS;
+        let cutoff = now.saturating_sub(self.window.as_secs());
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        let series = buckets.entry(target.route.clone()).or_default();
+        if series.back().is_none_or(|bucket| bucket.start != start) {
+            series.push_back(Bucket {
+                start,
+                ..Bucket::default()
+            });
+        }
+        while series.front().is_some_and(|bucket| bucket.start < cutoff) {
+            series.pop_front();
+        }
+
+        let bucket = series.back_mut().expect("current bucket was just pushed");
+        bucket.requests += 1;
+        if failed {
+            bucket.errors += 1;
+        } else if elapsed > target.latency {
+            bucket.slow += 1;
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn report(&self) -> Vec<SloReport> {
+        let now = unix_now();
+        let buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        self.targets
+            .iter()
+            .map(|target| {
+                let series = buckets.get(&target.route);
+                let since = |secs: u64| {
+                    let cutoff = now.saturating_sub(secs);
+                    series
+                        .into_iter()
+                        .flatten()
+                        .filter(|bucket| bucket.start + BUCKET_SECS > cutoff)
+                        .fold(Bucket::default(), |mut total, bucket| {
+                            total.requests += bucket.requests;
+                            total.errors += bucket.errors;
+                            total.slow += bucket.slow;
+                            total
+                        })
+                };
+                let budget = 1.0 - target.objective;
+                let burn_rate = |total: &Bucket| {
+                    if total.requests == 0 {
+                        0.0
+                    } else {
+                        (total.errors + total.slow) as f64 / total.requests as f64 / budget
+                    }
+                };
+
+                let all = since(self.window.as_secs());
+                let mut burn_rates: BTreeMap<&'static str, f64> = BURN_RATE_WINDOWS
+                    .iter()
+                    .map(|(name, secs)| (*name, burn_rate(&since(*secs))))
+                    .collect();
+                burn_rates.insert("window", burn_rate(&all));
+
+                SloReport {
+                    route: target.route.clone(),
+                    objective: target.objective,
+                    latency_threshold_ms: target.latency.as_millis() as u64,
+                    requests: all.requests,
+                    errors: all.errors,
+                    slow: all.slow,
+                    sli: (all.requests > 0).then(|| {
+                        (all.requests - all.errors - all.slow) as f64 / all.requests as f64
+                    }),
+                    error_budget_remaining: 1.0 - burn_rate(&all),
+                    burn_rates,
+                }
+            })
+            .collect()
+    }
+
+    /// Copies the current report into the burn-rate and budget gauges; called
+    /// on every metrics scrape.
+    pub fn export(&self, metrics: &Metrics) {
+        for report in self.report() {
+            metrics
+                .slo_error_budget_remaining
+                .with_label_values(&[&report.route])
+                .set(report.error_budget_remaining);
+            for (window, rate) in &report.burn_rates {
+                metrics
+                    .slo_burn_rate
+                    .with_label_values(&[report.route.as_str(), window])
+                    .set(*rate);
+            }
+        }
+    }
+}
+
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(target) = state.slo.target(request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    state.slo.record(
+        target,
+        response.status().is_server_error(),
+        started.elapsed(),
+    );
+    response
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use reqwest::Client;
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::s3::S3Target;
+
+/// Where user-supplied files such as avatars are kept: an S3 bucket when
+/// `STORAGE_S3_BUCKET` is set (with the `AWS_*` credentials), otherwise the
+/// directory `STORAGE_DIR`. Keys are `/`-separated relative paths; the
+/// portal never lists or deletes, so replaced objects stay until a bucket
+/// lifecycle rule or housekeeping removes them.
+pub struct Storage {
+    client: Client,
+    backend: Option<Backend>,
+}
+
+enum Backend {
+    Dir(PathBuf),
+    S3 { target: S3Target, prefix: String },
+}
+
+impl Storage {
+    pub fn from_config(config: &AppConfig, client: Client) -> Self {
+        let s3 = config.storage_s3_bucket.clone().and_then(|bucket| {
+            match (
+                config.aws_access_key_id.clone(),
+                config.aws_secret_access_key.clone(),
+            ) {
+                (Some(access_key), Some(secret_key)) => Some(Backend::S3 {
+                    target: S3Target {
+                        bucket,
+                        region: config.storage_s3_region.clone(),
+                        endpoint: config.storage_s3_endpoint.clone(),
+                        access_key,
+                        secret_key,
+                        session_token: config.aws_session_token.clone(),
+                    },
+                    prefix: config.storage_s3_prefix.clone(),
+                }),
+                _ => {
+                    warn!(
+                        "[Storage] STORAGE_S3_BUCKET needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+                    );
+                    None
+                }
+            }
+        });
+        let backend = s3.or_else(|| {
+            config
+                .storage_dir
+                .clone()
+                .map(|dir| Backend::Dir(PathBuf::from(dir)))
+        });
+
+        Self { client, backend }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    pub async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), String> {
+        match &self.backend {
+            Some(Backend::Dir(dir)) => {
+                let path = dir.join(relative(key)?);
+                path.parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::write(&path, body))
+                    .map_err(|err| format!("{}: {err}", path.display()))
+            }
+            Some(Backend::S3 { target, prefix }) => {
+                target
+                    .put(&self.client, &format!("{prefix}{key}"), body)
+                    .await
+            }
+            None => Err("storage is not configured".to_owned()),
+        }
+    }
+
+    /// The object stored as `key`, or `None` if there is none. A key that
+    /// could not have been stored has none.
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match &self.backend {
+            Some(Backend::Dir(dir)) => {
+                let Ok(key) = relative(key) else {
+                    return Ok(None);
+                };
+                let path = dir.join(key);
+                match fs::read(&path) {
+                    Ok(body) => Ok(Some(body)),
+                    Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(format!("{}: {err}", path.display())),
+                }
+            }
+            Some(Backend::S3 { target, prefix }) => {
+                target.get(&self.client, &format!("{prefix}{key}")).await
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// `key` as a path that cannot leave the storage directory.
+fn relative(key: &str) -> Result<&Path, String> {
+    let path = Path::new(key);
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Ok(path)
+    } else {
+        Err(format!("invalid storage key {key}"))
+    }
+}
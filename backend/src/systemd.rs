@@ -0,0 +1,86 @@
+use std::env;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::process;
+
+use tracing::{debug, warn};
+
+use crate::server::Listener;
+
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes over a socket passed by systemd socket activation. Sockets are
+/// picked by `FileDescriptorName=` (`public`, `admin`) when the unit names
+/// them, otherwise the first is `public` and the second `admin`.
+pub fn activated_listener(name: &str) -> Option<Listener> {
+    let pid = env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+    if pid != process::id() {
+        return None;
+    }
+    let count = env::var("LISTEN_FDS").ok()?.parse::<RawFd>().ok()?;
+
+    let index = match env::var("LISTEN_FDNAMES") {
+        Ok(names) => names.split(':').position(|candidate| candidate == name)? as RawFd,
+        Err(_) => match name {
+            "public" => 0,
+            "admin" => 1,
+            _ => return None,
+        },
+    };
+    if index >= count {
+        return None;
+    }
+
+    let fd = LISTEN_FDS_START + index;
+    debug!(fd, name, "Using socket-activated listener");
+    match listener_from_fd(fd) {
+        Ok(listener) => Some(listener),
+        Err(err) => {
+            warn!(fd, name, ?err, "Unable to use socket-activated listener");
+            None
+        }
+    }
+}
+
+fn listener_from_fd(fd: RawFd) -> std::io::Result<Listener> {
+    // SAFETY: systemd hands these descriptors to this process exclusively and
+    // `activated_listener` only adopts each index once per name.
+    let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+    if tcp.local_addr().is_ok() {
+        tcp.set_nonblocking(true)?;
+        return Ok(Listener::Tcp(tokio::net::TcpListener::from_std(tcp)?));
+    }
+
+    // SAFETY: the descriptor was released from the TCP wrapper just above.
+    let unix = unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    unix.set_nonblocking(true)?;
+    Ok(Listener::Unix(tokio::net::UnixListener::from_std(unix)?))
+}
+
+/// Sends a state update such as `READY=1` to the service manager. Does
+/// nothing when not started by systemd with `Type=notify`.
+pub fn notify(state: &str) {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let address = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        // Abstract socket names only exist on Linux.
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => Err(std::io::Error::from(std::io::ErrorKind::Unsupported)),
+        None => SocketAddr::from_pathname(&path),
+    };
+
+    let result = address.and_then(|address| {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &address)
+    });
+    if let Err(err) = result {
+        warn!(?err, "Unable to notify systemd");
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::ORIGIN, request::Parts},
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::AppState;
+use crate::session_limit::SessionLimitPolicy;
+
+pub const TENANT_HEADER: &str = "x-argus-tenant";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantConfig {
+    pub id: String,
+    #[serde(default)]
+    pub origins: Vec<String>,
+    #[serde(default)]
+    pub turnstile_site_key: Option<String>,
+    #[serde(default)]
+    pub turnstile_secret_key: Option<String>,
+    /// Keycloak client the tenant's users log in under, so each app can
+    /// have its own session policies. Defaults to the public client.
+    #[serde(default)]
+    pub keycloak_client_id: Option<String>,
+    #[serde(default)]
+    pub keycloak_client_secret: Option<String>,
+    /// Overrides `MAX_SESSIONS_PER_USER` for the tenant; zero lifts the cap.
+    #[serde(default)]
+    pub max_sessions: Option<usize>,
+    #[serde(default)]
+    pub session_limit_policy: Option<SessionLimitPolicy>,
+    /// Overrides `AUTH_SCOPES` for the tenant's logins.
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+}
+
+impl TenantConfig {
+    fn matches_origin(&self, origin: &str) -> bool {
+        self.origins
+            .iter()
+            .any(|candidate| candidate.trim_end_matches('/') == origin)
+    }
+}
+
+/// Tenant selected for the current request, by explicit header first and
+/// `Origin` second. The header can only pick a tenant that lists no origins
+/// (native apps) or one whose origins include the request's `Origin`, so a
+/// browser page cannot borrow another tenant's client or captcha keys.
+/// `None` means the deployment-wide defaults apply.
+pub struct ResolvedTenant(pub Option<TenantConfig>);
+
+impl ResolvedTenant {
+    pub fn as_ref(&self) -> Option<&TenantConfig> {
+        self.0.as_ref()
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ResolvedTenant {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let tenants = &state.config.tenants;
+        if tenants.is_empty() {
+            return Ok(Self(None));
+        }
+
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().trim_end_matches('/').to_owned())
+        };
+
+        let origin = header(ORIGIN.as_str());
+        let by_id = header(TENANT_HEADER)
+            .and_then(|id| tenants.iter().find(|tenant| tenant.id == id))
+            .filter(|tenant| {
+                tenant.origins.is_empty()
+                    || origin
+                        .as_deref()
+                        .is_some_and(|origin| tenant.matches_origin(origin))
+            });
+        let by_origin = || {
+            origin
+                .as_deref()
+                .and_then(|origin| tenants.iter().find(|tenant| tenant.matches_origin(origin)))
+        };
+
+        Ok(Self(by_id.or_else(by_origin).cloned()))
+    }
+}
+
+pub fn load_tenants(path: Option<&str>) -> Vec<TenantConfig> {
+    let Some(path) = path.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Vec::new();
+    };
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!(%path, ?err, "Unable to read tenants file; multi-tenant settings disabled");
+            return Vec::new();
+        }
+    };
+
+    match serde_json::from_str::<Vec<TenantConfig>>(&contents) {
+        Ok(tenants) => tenants,
+        Err(err) => {
+            warn!(%path, ?err, "Unable to parse tenants file; multi-tenant settings disabled");
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::keycloak::{KeycloakError, KeycloakService};
+use crate::util::unix_now;
+
+pub const TERMS_VERSION_ATTRIBUTE: &str = "termsVersion";
+pub const TERMS_ACCEPTED_AT_ATTRIBUTE: &str = "termsAcceptedAt";
+
+/// Enforces `TERMS_VERSION`: a user whose accepted version, kept in the
+/// `termsVersion` attribute, is missing or older gets a
+/// `terms_update_required` challenge on login and has to accept the current
+/// version before the tokens are released.
+pub struct Terms {
+    keycloak: Arc<KeycloakService>,
+    current_version: Option<String>,
+    url: Option<String>,
+}
+
+impl Terms {
+    pub fn from_config(config: &AppConfig, keycloak: Arc<KeycloakService>) -> Self {
+        Self {
+            keycloak,
+            current_version: config.terms_version.clone(),
+            url: config.terms_url.clone(),
+        }
+    }
+
+    pub fn current_version(&self) -> Option<&str> {
+        self.current_version.as_deref()
+    }
+
+    /// Where the current terms can be read.
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    /// Whether the user still has to accept the current version. Lookups
+    /// that fail are logged and do not block the login.
+    pub async fn needs_acceptance(&self, email: &str) -> bool {
+        let Some(current) = self.current_version() else {
+            return false;
+        };
+
+        let users = match self.keycloak.find_users_by_email(email).await {
+            Ok(users) => users,
+            Err(err) => {
+                warn!("[Terms] user lookup failed for {}: {}", email, err);
+                return false;
+            }
+        };
+        let [user] = users.as_slice() else {
+            return false;
+        };
+        let accepted = user
+            .attributes
+            .get(TERMS_VERSION_ATTRIBUTE)
+            .and_then(|values| values.first());
+        accepted.is_none_or(|accepted| compare_versions(accepted, current) == Ordering::Less)
+    }
+
+    /// Records that the user accepted the current version.
+    pub async fn accept(&self, email: &str) -> Result<(), KeycloakError> {
+        let Some(current) = self.current_version() else {
+            return Ok(());
+        };
+        let users = self.keycloak.find_users_by_email(email).await?;
+        let [user] = users.as_slice() else {
+            return Ok(());
+        };
+
+        let mut user = self.keycloak.get_user(&user.id).await?;
+        user.attributes
+            .insert(TERMS_VERSION_ATTRIBUTE.to_owned(), vec![current.to_owned()]);
+        user.attributes.insert(
+            TERMS_ACCEPTED_AT_ATTRIBUTE.to_owned(),
+            vec![unix_now().to_string()],
+        );
+        self.keycloak.update_user(&user, user.enabled).await
+    }
+}
+
+/// Compares dotted or dashed versions such as `2` or `2024-06.1` segment by
+/// segment, numerically where both segments are numbers.
+pub fn compare_versions(left: &str, right: &str) -> Ordering {
+    let segments = |value: &str| {
+        value
+            .trim()
+            .split(['.', '-'])
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    let (left, right) = (segments(left), segments(right));
+
+    for (a, b) in left.iter().zip(&right) {
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    left.len().cmp(&right.len())
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+use tracing::debug;
+
+const REUSE_LEEWAY_MAX: Duration = Duration::from_secs(60);
+const MAX_ENTRIES: usize = 1_000;
+
+#[derive(Debug, Clone)]
+pub struct CachedToken {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_at: Instant,
+}
+
+impl CachedToken {
+    pub fn new(access_token: String, token_type: String, expires_in: u64) -> Self {
+        Self {
+            access_token,
+            token_type,
+            expires_at: Instant::now() + Duration::from_secs(expires_in),
+        }
+    }
+
+    pub fn expires_in(&self) -> u64 {
+        self.expires_at
+            .saturating_duration_since(Instant::now())
+            .as_secs()
+    }
+
+    /// Reusable while more than a minute, or half its remaining life for
+    /// short-lived tokens, is left, mirroring the admin token refresh leeway.
+    fn is_fresh(&self, issued_at: Instant) -> bool {
+        let lifetime = self.expires_at.saturating_duration_since(issued_at);
+        let leeway = (lifetime / 2).min(REUSE_LEEWAY_MAX);
+        self.expires_at > Instant::now() + leeway
+    }
+}
+
+struct Slot {
+    issued_at: Instant,
+    token: CachedToken,
+}
+
+/// Tokens keyed by whatever identifies a grant (e.g. caller, audience and
+/// scope). Concurrent misses for the same key share one fetch: the first
+/// caller holds the key's lock while fetching and the rest reuse its result.
+/// At most `MAX_ENTRIES` keys are kept; once full, stale ones make room and
+/// while none are stale, new keys are fetched without being kept.
+pub struct TokenCache<K> {
+    slots: std::sync::Mutex<HashMap<K, Arc<Mutex<Option<Slot>>>>>,
+    capacity: usize,
+}
+
+impl<K> TokenCache<K>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        Self::with_capacity(MAX_ENTRIES)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: std::sync::Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    pub async fn get_or_fetch<F, Fut, E>(&self, key: &K, fetch: F) -> Result<CachedToken, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<CachedToken, E>>,
+    {
+        let slot = {
+            let mut slots = self.slots.lock().unwrap_or_else(|err| err.into_inner());
+            if slots.len() >= self.capacity && !slots.contains_key(key) {
+                slots.retain(|_, slot| Arc::strong_count(slot) > 1 || !is_idle(slot));
+            }
+            (slots.len() < self.capacity || slots.contains_key(key))
+                .then(|| Arc::clone(slots.entry(key.clone()).or_default()))
+        };
+        let Some(slot) = slot else {
+            debug!("[TokenCache] cache full, token not kept");
+            return fetch().await;
+        };
+
+        let mut slot = slot.lock().await;
+        if let Some(cached) = slot.as_ref()
+            && cached.token.is_fresh(cached.issued_at)
+        {
+            return Ok(cached.token.clone());
+        }
+
+        let issued_at = Instant::now();
+        let token = fetch().await?;
+        *slot = Some(Slot {
+            issued_at,
+            token: token.clone(),
+        });
+        Ok(token)
+    }
+}
+
+/// Empty, or holding a token no longer worth reusing. Slots being fetched
+/// into are locked and count as busy.
+fn is_idle(slot: &Mutex<Option<Slot>>) -> bool {
+    slot.try_lock().is_ok_and(|slot| {
+        slot.as_ref()
+            .is_none_or(|cached| !cached.token.is_fresh(cached.issued_at))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn token(name: &str, expires_in: u64) -> CachedToken {
+        CachedToken::new(name.to_owned(), "Bearer".to_owned(), expires_in)
+    }
+
+    async fn fetch(
+        cache: &TokenCache<&'static str>,
+        key: &'static str,
+        expires_in: u64,
+        fetches: &AtomicUsize,
+    ) -> CachedToken {
+        cache
+            .get_or_fetch(&key, || async {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, Infallible>(token(key, expires_in))
+            })
+            .await
+            .unwrap()
+    }
+
+    fn len<K>(cache: &TokenCache<K>) -> usize {
+        cache.slots.lock().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn reuses_fresh_tokens() {
+        let cache = TokenCache::new();
+        let fetches = AtomicUsize::new(0);
+        fetch(&cache, "a", 300, &fetches).await;
+        let again = fetch(&cache, "a", 300, &fetches).await;
+        assert_eq!(again.access_token, "a");
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn refetches_tokens_close_to_expiry() {
+        let cache = TokenCache::new();
+        let fetches = AtomicUsize::new(0);
+        fetch(&cache, "a", 0, &fetches).await;
+        fetch(&cache, "a", 0, &fetches).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_errors() {
+        let cache: TokenCache<&str> = TokenCache::new();
+        let failed = cache
+            .get_or_fetch(&"a", || async { Err::<CachedToken, _>("down") })
+            .await;
+        assert_eq!(failed.unwrap_err(), "down");
+        let fetches = AtomicUsize::new(0);
+        fetch(&cache, "a", 300, &fetches).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn stops_growing_at_capacity() {
+        let cache = TokenCache::with_capacity(2);
+        let fetches = AtomicUsize::new(0);
+        fetch(&cache, "a", 300, &fetches).await;
+        fetch(&cache, "b", 300, &fetches).await;
+        fetch(&cache, "c", 300, &fetches).await;
+        fetch(&cache, "c", 300, &fetches).await;
+        assert_eq!(len(&cache), 2);
+        assert_eq!(fetches.load(Ordering::SeqCst), 4);
+
+        fetch(&cache, "a", 300, &fetches).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn evicts_stale_tokens_when_full() {
+        let cache = TokenCache::with_capacity(2);
+        let fetches = AtomicUsize::new(0);
+        fetch(&cache, "a", 0, &fetches).await;
+        fetch(&cache, "b", 300, &fetches).await;
+        fetch(&cache, "c", 300, &fetches).await;
+        fetch(&cache, "c", 300, &fetches).await;
+        assert_eq!(len(&cache), 2);
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{Json, http::StatusCode};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::AppConfig;
+use crate::error_codes::ErrorCode;
+use crate::extract::Rejection;
+use crate::keycloak::KeycloakService;
+use crate::models::user::{ErrorResponse, FieldError, KeycloakUser};
+
+/// `GET /admin/realms/{realm}/users/profile`, trimmed to what is enforced.
+#[derive(Debug, Deserialize)]
+pub struct UserProfileConfig {
+    #[serde(default)]
+    attributes: Vec<ProfileAttribute>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileAttribute {
+    name: String,
+    #[serde(default)]
+    validations: HashMap<String, Value>,
+    #[serde(default)]
+    required: Option<RequiredRule>,
+}
+
+/// Required only applies unconditionally when it is not limited to scopes.
+#[derive(Debug, Deserialize)]
+struct RequiredRule {
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+/// Keycloak's validators that can be checked up front. Others are left to
+/// Keycloak, which still enforces the full profile when the user is created.
+enum Rule {
+    Length {
+        min: Option<u64>,
+        max: Option<u64>,
+    },
+    Email,
+    Pattern {
+        /// Anchored, since Keycloak requires the whole value to match.
+        pattern: Regex,
+        source: String,
+        message: Option<String>,
+    },
+    Options(Vec<String>),
+    Integer {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    Double {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+}
+
+struct AttributeRules {
+    name: String,
+    required: bool,
+    rules: Vec<Rule>,
+}
+
+/// Enforces the realm's declarative user profile on registrations so the
+/// portal can answer with field errors instead of Keycloak's generic 400.
+/// The profile is fetched through the admin API and cached for
+/// `USER_PROFILE_CACHE_TTL_SECS`; enabled by `KEYCLOAK_USER_PROFILE`.
+pub struct UserProfileService {
+    keycloak: Arc<KeycloakService>,
+    enabled: bool,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, Arc<Vec<AttributeRules>>)>>,
+}
+
+impl UserProfileService {
+    pub fn from_config(config: &AppConfig, keycloak: Arc<KeycloakService>) -> Self {
+        Self {
+            keycloak,
+            enabled: config.keycloak_user_profile,
+            ttl: config.user_profile_cache_ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub async fn check(&self, user: &KeycloakUser) -> Result<(), Rejection> {
+        let Some(profile) = self.profile().await else {
+            return Ok(());
+        };
+
+        let mut fields = Vec::new();
+        for attribute in profile.iter() {
+            let values = attribute_values(user, &attribute.name);
+            if values.is_empty() {
+                if attribute.required {
+                    fields.push(FieldError {
+                        field: attribute.name.clone(),
+                        expected: None,
+                        message: "missing field".to_owned(),
+                    });
+                }
+                continue;
+            }
+
+            for value in &values {
+                if let Some(error) = attribute
+                    .rules
+                    .iter()
+                    .find_map(|rule| rule.violation(&attribute.name, value))
+                {
+                    fields.push(error);
+                    break;
+                }
+            }
+        }
+
+        if fields.is_empty() {
+            Ok(())
+        } else {
+            Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(
+                    ErrorResponse::new(
+                        ErrorCode::ValidationFailed,
+                        "Invalid request body".to_owned(),
+                    )
+                    .with_fields(fields),
+                ),
+            ))
+        }
+    }
+
+    async fn profile(&self) -> Option<Arc<Vec<AttributeRules>>> {
+        if !self.enabled {
+            return None;
+        }
+
+        let mut cached = self.cached.lock().await;
+        if let Some((fetched_at, profile)) = cached.as_ref()
+            && fetched_at.elapsed() < self.ttl
+        {
+            return Some(Arc::clone(profile));
+        }
+
+        match self.keycloak.user_profile().await {
+            Ok(config) => {
+                let profile = Arc::new(compile(config));
+                *cached = Some((Instant::now(), Arc::clone(&profile)));
+                Some(profile)
+            }
+            Err(err) => {
+                warn!("[UserProfile] unable to fetch the user profile: {}", err);
+                // Keep enforcing the last known profile rather than none.
+                cached.as_ref().map(|(_, profile)| Arc::clone(profile))
+            }
+        }
+    }
+}
+
+fn compile(config: UserProfileConfig) -> Vec<AttributeRules> {
+    config
+        .attributes
+        .into_iter()
+        // Registrations always use the email address as the username.
+        .filter(|attribute| attribute.name != "username")
+        .map(|attribute| AttributeRules {
+            required: attribute
+                .required
+                .is_some_and(|required| required.scopes.is_empty()),
+            rules: attribute
+                .validations
+                .iter()
+                .filter_map(|(validator, options)| Rule::parse(&attribute.name, validator, options))
+                .collect(),
+            name: attribute.name,
+        })
+        .collect()
+}
+
+impl Rule {
+    fn parse(attribute: &str, validator: &str, options: &Value) -> Option<Self> {
+        let number = |key: &str| options.get(key).and_then(lenient_f64);
+        match validator {
+            "length" => Some(Self::Length {
+                min: number("min").map(|value| value as u64),
+                max: number("max").map(|value| value as u64),
+            }),
+            "email" => Some(Self::Email),
+            "pattern" => {
+                let pattern = options.get("pattern")?.as_str()?;
+                match Regex::new(&format!("^(?:{pattern})$")) {
+                    Ok(anchored) => Some(Self::Pattern {
+                        pattern: anchored,
+                        source: pattern.to_owned(),
+                        message: options
+                            .get("error-message")
+                            .and_then(Value::as_str)
+                            .map(str::to_owned),
+                    }),
+                    Err(err) => {
+                        warn!("[UserProfile] ignoring pattern for {}: {}", attribute, err);
+                        None
+                    }
+                }
+            }
+            "options" => Some(Self::Options(
+                options
+                    .get("options")?
+                    .as_array()?
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect(),
+            )),
+            "integer" => Some(Self::Integer {
+                min: number("min").map(|value| value as i64),
+                max: number("max").map(|value| value as i64),
+            }),
+            "double" => Some(Self::Double {
+                min: number("min"),
+                max: number("max"),
+            }),
+            _ => None,
+        }
+    }
+
+    fn violation(&self, field: &str, value: &str) -> Option<FieldError> {
+        let error = |expected: String, message: &str| FieldError {
+            field: field.to_owned(),
+            expected: Some(expected),
+            message: message.to_owned(),
+        };
+
+        match self {
+            Self::Length { min, max } => {
+                let length = value.trim().chars().count() as u64;
+                let expected = match (min, max) {
+                    (Some(min), Some(max)) => format!("between {min} and {max} characters"),
+                    (Some(min), None) => format!("at least {min} characters"),
+                    (None, Some(max)) => format!("at most {max} characters"),
+                    (None, None) => return None,
+                };
+                (min.is_some_and(|min| length < min) || max.is_some_and(|max| length > max))
+                    .then(|| error(expected, "invalid length"))
+            }
+            Self::Email => {
+                let valid = value.split_once('@').is_some_and(|(local, domain)| {
+                    !local.is_empty()
+                        && domain.contains('.')
+                        && !value.contains(char::is_whitespace)
+                });
+                (!valid).then(|| error("email address".to_owned(), "not an email address"))
+            }
+            Self::Pattern {
+                pattern,
+                source,
+                message,
+            } => (!pattern.is_match(value)).then(|| {
+                error(
+                    format!("value matching {source}"),
+                    message
+                        .as_deref()
+                        .unwrap_or("does not match the required format"),
+                )
+            }),
+            Self::Options(options) => (!options.iter().any(|option| option == value)).then(|| {
+                error(
+                    format!("one of {}", options.join(", ")),
+                    "not one of the accepted values",
+                )
+            }),
+            Self::Integer { min, max } => match value.trim().parse::<i64>() {
+                Ok(number)
+                    if min.is_some_and(|min| number < min)
+                        || max.is_some_and(|max| number > max) =>
+                {
+                    Some(error(range("integer", *min, *max), "out of range"))
+                }
+                Ok(_) => None,
+                Err(_) => Some(error(range("integer", *min, *max), "not an integer")),
+            },
+            Self::Double { min, max } => match value.trim().parse::<f64>() {
+                Ok(number)
+                    if min.is_some_and(|min| number < min)
+                        || max.is_some_and(|max| number > max) =>
+                {
+                    Some(error(range("number", *min, *max), "out of range"))
+                }
+                Ok(_) => None,
+                Err(_) => Some(error(range("number", *min, *max), "not a number")),
+            },
+        }
+    }
+}
+
+fn range<T: std::fmt::Display>(kind: &str, min: Option<T>, max: Option<T>) -> String {
+    match (min, max) {
+        (Some(min), Some(max)) => format!("{kind} between {min} and {max}"),
+        (Some(min), None) => format!("{kind} of at least {min}"),
+        (None, Some(max)) => format!("{kind} of at most {max}"),
+        (None, None) => kind.to_owned(),
+    }
+}
+
+/// Keycloak stores validator options as strings as often as numbers.
+fn lenient_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn attribute_values(user: &KeycloakUser, name: &str) -> Vec<String> {
+    let root = match name {
+        "email" => Some(user.email.as_str()),
+        "firstName" => user.first_name.as_deref(),
+        "lastName" => user.last_name.as_deref(),
+        _ => None,
+    };
+    match root {
+        Some(value) => vec![value.to_owned()],
+        None => user.attributes.get(name).cloned().unwrap_or_default(),
+    }
+    .into_iter()
+    .filter(|value| !value.trim().is_empty())
+    .collect()
+}
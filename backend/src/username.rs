@@ -0,0 +1,79 @@
+use serde::Serialize;
+
+use crate::models::user::FieldError;
+
+pub const MIN_LENGTH: usize = 3;
+pub const MAX_LENGTH: usize = 32;
+
+/// `USERNAME_MODE`: whether accounts get a username of their own. Under
+/// `email` (the default) the email is the username, as it always was;
+/// `optional` lets registration pick one and falls back to the email, and
+/// `required` insists on one. Either way users may sign in with their email
+/// or their username, so realms can move over without breaking anyone.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsernameMode {
+    Email,
+    Optional,
+    Required,
+}
+
+impl UsernameMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "email" => Some(Self::Email),
+            "optional" => Some(Self::Optional),
+            "required" => Some(Self::Required),
+            _ => None,
+        }
+    }
+
+    pub fn distinct(self) -> bool {
+        self != Self::Email
+    }
+}
+
+/// Keycloak stores usernames lowercased.
+pub fn normalize(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+/// A login identifier without `@` is a username; an email always has one,
+/// which is why usernames may not.
+pub fn is_username(identifier: &str) -> bool {
+    !identifier.contains('@')
+}
+
+/// 3–32 lowercase letters, digits, `.`, `_` or `-`, starting with a letter
+/// or digit. `username` is expected normalized.
+pub fn check(username: &str) -> Result<(), FieldError> {
+    let invalid = |message: &str| FieldError {
+        field: "username".to_owned(),
+        expected: Some(format!(
+            "{MIN_LENGTH}-{MAX_LENGTH} letters, digits, '.', '_' or '-', starting with a letter or digit"
+        )),
+        message: message.to_owned(),
+    };
+
+    let length = username.chars().count();
+    if length < MIN_LENGTH {
+        return Err(invalid("too short"));
+    }
+    if length > MAX_LENGTH {
+        return Err(invalid("too long"));
+    }
+    if !username
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphanumeric())
+    {
+        return Err(invalid("must start with a letter or digit"));
+    }
+    if !username
+        .chars()
+        .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || matches!(ch, '.' | '_' | '-'))
+    {
+        return Err(invalid("contains characters that are not allowed"));
+    }
+    Ok(())
+}
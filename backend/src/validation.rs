@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use axum::{Json, http::StatusCode};
+use serde_json::Value;
+
+use crate::AppConfig;
+use crate::error_codes::ErrorCode;
+use crate::models::user::{ErrorResponse, FieldError};
+use crate::registration_schema::{AttributeKind, AttributeSpec};
+use crate::util::unix_now;
+
+type Rejection = (StatusCode, Json<ErrorResponse>);
+
+/// In strict mode, any key that a request model captured through its
+/// flattened `extra` map is treated as a client bug.
+pub fn reject_unknown_fields(
+    config: &AppConfig,
+    extra: &HashMap<String, Value>,
+) -> Result<(), Rejection> {
+    if !config.strict_request_bodies || extra.is_empty() {
+        return Ok(());
+    }
+
+    let mut keys: Vec<&String> = extra.keys().collect();
+    keys.sort();
+    let fields = keys
+        .into_iter()
+        .map(|key| FieldError {
+            field: key.clone(),
+            expected: None,
+            message: "unknown field".to_owned(),
+        })
+        .collect();
+
+    Err(unprocessable("Unexpected fields in request body", fields))
+}
+
+/// Bounds the free-form registration attributes so a client cannot push
+/// arbitrarily large or numerous values into Keycloak.
+pub fn check_register_extra(
+    config: &AppConfig,
+    extra: &HashMap<String, Value>,
+) -> Result<(), Rejection> {
+    if !config.strict_request_bodies {
+        return Ok(());
+    }
+
+    let mut fields = Vec::new();
+
+    if extra.len() > config.register_extra_max_keys {
+        fields.push(FieldError {
+            field: "extra".to_owned(),
+            expected: Some(format!("at most {} fields", config.register_extra_max_keys)),
+            message: format!("received {} extra fields", extra.len()),
+        });
+    }
+
+    let mut keys: Vec<&String> = extra.keys().collect();
+    keys.sort();
+    for key in keys {
+        if key.len() > config.register_extra_max_key_len {
+            fields.push(FieldError {
+                field: key.clone(),
+                expected: Some(format!(
+                    "key of at most {} characters",
+                    config.register_extra_max_key_len
+                )),
+                message: "field name too long".to_owned(),
+            });
+            continue;
+        }
+
+        let size = extra[key].to_string().len();
+        if size > config.register_extra_max_value_bytes {
+            fields.push(FieldError {
+                field: key.clone(),
+                expected: Some(format!(
+                    "value of at most {} bytes",
+                    config.register_extra_max_value_bytes
+                )),
+                message: format!("value is {size} bytes"),
+            });
+        }
+    }
+
+    if fields.is_empty() {
+        Ok(())
+    } else {
+        Err(unprocessable(
+            "Registration attributes exceed limits",
+            fields,
+        ))
+    }
+}
+
+/// With `REGISTRATION_ATTRIBUTES_FILE` set, extra registration attributes
+/// must be listed there, present when required and of the declared type.
+pub fn check_register_attributes(
+    config: &AppConfig,
+    extra: &HashMap<String, Value>,
+) -> Result<(), Rejection> {
+    let specs = &config.registration_attributes;
+    if specs.is_empty() {
+        return Ok(());
+    }
+
+    let mut fields = Vec::new();
+
+    let honeypot = config.register_honeypot_field.as_deref();
+    let mut keys: Vec<&String> = extra.keys().collect();
+    keys.sort();
+    for key in keys {
+        if Some(key.as_str()) != honeypot && !specs.iter().any(|spec| spec.name == *key) {
+            fields.push(FieldError {
+                field: key.clone(),
+                expected: None,
+                message: "not an accepted attribute".to_owned(),
+            });
+        }
+    }
+
+    for spec in specs {
+        match extra.get(&spec.name) {
+            None | Some(Value::Null) if spec.required => fields.push(FieldError {
+                field: spec.name.clone(),
+                expected: Some(expected_type(spec)),
+                message: "missing field".to_owned(),
+            }),
+            None | Some(Value::Null) => {}
+            Some(value) => {
+                if let Err(message) = check_attribute_value(spec, value) {
+                    fields.push(FieldError {
+                        field: spec.name.clone(),
+                        expected: Some(expected_type(spec)),
+                        message,
+                    });
+                }
+            }
+        }
+    }
+
+    if fields.is_empty() {
+        Ok(())
+    } else {
+        Err(unprocessable("Invalid request body", fields))
+    }
+}
+
+fn check_attribute_value(spec: &AttributeSpec, value: &Value) -> Result<(), String> {
+    let text = match (spec.kind, value) {
+        (AttributeKind::Number, Value::Number(_)) | (AttributeKind::Boolean, Value::Bool(_)) => {
+            return Ok(());
+        }
+        (AttributeKind::Number | AttributeKind::Boolean, _) => {
+            return Err("wrong type".to_owned());
+        }
+        (_, Value::String(text)) => text.trim(),
+        _ => return Err("wrong type".to_owned()),
+    };
+
+    if spec.kind == AttributeKind::Date && parse_date(text).is_none() {
+        return Err("not a valid calendar date".to_owned());
+    }
+    if spec.kind == AttributeKind::Email && !text.contains('@') {
+        return Err("not an email address".to_owned());
+    }
+    if let Some(max) = spec.max_length
+        && text.chars().count() > max
+    {
+        return Err(format!("longer than {max} characters"));
+    }
+    if !spec.options.is_empty() && !spec.options.iter().any(|option| option == text) {
+        return Err("not one of the accepted values".to_owned());
+    }
+
+    Ok(())
+}
+
+fn expected_type(spec: &AttributeSpec) -> String {
+    if !spec.options.is_empty() {
+        return format!("one of {}", spec.options.join(", "));
+    }
+    match spec.kind {
+        AttributeKind::String | AttributeKind::Password => "string".to_owned(),
+        AttributeKind::Email => "email address".to_owned(),
+        AttributeKind::Number => "number".to_owned(),
+        AttributeKind::Boolean => "boolean".to_owned(),
+        AttributeKind::Date => "date formatted as YYYY-MM-DD".to_owned(),
+    }
+}
+
+pub const DATE_OF_BIRTH_ATTRIBUTE: &str = "dateOfBirth";
+
+/// Accepts an ISO `YYYY-MM-DD` date of birth and, when `REGISTER_MIN_AGE` is
+/// set, rejects applicants younger than that with the `underage` code.
+pub fn check_date_of_birth(config: &AppConfig, value: &str) -> Result<(), Rejection> {
+    let invalid = |message: &str| {
+        unprocessable(
+            "Invalid request body",
+            vec![FieldError {
+                field: "dateOfBirth".to_owned(),
+                expected: Some("date formatted as YYYY-MM-DD".to_owned()),
+                message: message.to_owned(),
+            }],
+        )
+    };
+
+    let Some(birth) = parse_date(value.trim()) else {
+        return Err(invalid("not a valid calendar date"));
+    };
+
+    let today = today();
+    if birth > today {
+        return Err(invalid("date is in the future"));
+    }
+    if birth.0 < 1900 {
+        return Err(invalid("date is too far in the past"));
+    }
+
+    let min_age = config.register_min_age;
+    if min_age == 0 {
+        return Ok(());
+    }
+
+    let mut age = today.0 - birth.0;
+    if (today.1, today.2) < (birth.1, birth.2) {
+        age -= 1;
+    }
+
+    if age < i64::from(min_age) {
+        let (status, Json(mut body)) = unprocessable(
+            "Applicant does not meet the minimum age",
+            vec![FieldError {
+                field: "dateOfBirth".to_owned(),
+                expected: Some(format!("at least {min_age} years old")),
+                message: "below minimum age".to_owned(),
+            }],
+        );
+        body.code = ErrorCode::Underage;
+        return Err((status, Json(body)));
+    }
+
+    Ok(())
+}
+
+fn parse_date(value: &str) -> Option<(i64, u32, u32)> {
+    let mut parts = value.splitn(3, '-');
+    let year_part = parts.next()?;
+    let month_part = parts.next()?;
+    let day_part = parts.next()?;
+    if year_part.len() != 4 || month_part.len() != 2 || day_part.len() != 2 {
+        return None;
+    }
+
+    let year: i64 = year_part.parse().ok()?;
+    let month: u32 = month_part.parse().ok()?;
+    let day: u32 = day_part.parse().ok()?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    Some((year, month, day))
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Current UTC date, converted from the Unix day count.
+fn today() -> (i64, u32, u32) {
+    let days = (unix_now() / 86_400) as i64;
+    civil_date(days)
+}
+
+/// Unix seconds as an RFC 3339 UTC timestamp, e.g. `2024-05-01T09:30:00Z`.
+pub fn rfc3339(secs: u64) -> String {
+    let (year, month, day) = civil_date((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+/// Calendar date of a Unix day count.
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+fn unprocessable(message: &str, fields: Vec<FieldError>) -> Rejection {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(
+            ErrorResponse::new(ErrorCode::ValidationFailed, message.to_owned()).with_fields(fields),
+        ),
+    )
+}
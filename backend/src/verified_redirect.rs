@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::Mac;
+use reqwest::Url;
+
+use crate::AppConfig;
+use crate::util::{HmacSha256, hmac_sha256};
+
+/// Set the first time the verified callback sees a user's email verified,
+/// so the verification is recorded and published only once.
+pub const EMAIL_VERIFIED_AT_ATTRIBUTE: &str = "emailVerifiedAt";
+
+/// Sent to the frontend as `?status=`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VerificationStatus {
+    Verified,
+    /// Keycloak does not consider the address verified yet.
+    Pending,
+    /// The user is unknown or could not be looked up.
+    Error,
+}
+
+impl VerificationStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Verified => "verified",
+            Self::Pending => "pending",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Where users land after following a verification email. Keycloak is sent
+/// `VERIFIED_CALLBACK_URL` as the email's `redirect_uri`; the callback then
+/// forwards to the frontend page for the user's locale from
+/// `VERIFIED_REDIRECT_URLS` (`de=https://…/de/verified,…`), or to
+/// `VERIFIED_REDIRECT_URL` when no locale matches. The user id in the
+/// callback is signed with `VERIFIED_CALLBACK_SECRET`, so the callback only
+/// looks up users it sent an email to.
+pub struct VerifiedRedirects {
+    callback_url: Option<String>,
+    secret: Option<Vec<u8>>,
+    default_url: Option<String>,
+    by_locale: HashMap<String, String>,
+}
+
+impl VerifiedRedirects {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            callback_url: config.verified_callback_url.clone(),
+            secret: config
+                .verified_callback_secret
+                .as_deref()
+                .map(|secret| secret.as_bytes().to_vec()),
+            default_url: config.verified_redirect_url.clone(),
+            by_locale: config
+                .verified_redirect_urls
+                .iter()
+                .map(|(locale, url)| (locale.to_ascii_lowercase(), url.clone()))
+                .collect(),
+        }
+    }
+
+    /// The `redirect_uri` for a verification email to `user_id`, or `None`
+    /// when `VERIFIED_CALLBACK_URL` is not set and Keycloak's own page is
+    /// shown instead.
+    pub fn callback_for(&self, user_id: &str, locale: Option<&str>) -> Option<String> {
+        let mut url = Url::parse(self.callback_url.as_deref()?).ok()?;
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(user_id)?.finalize().into_bytes());
+        url.query_pairs_mut()
+            .append_pair("user", user_id)
+            .append_pair("sig", &signature);
+        if let Some(locale) = locale {
+            url.query_pairs_mut().append_pair("locale", locale);
+        }
+        Some(url.into())
+    }
+
+    /// `user` when `signature` is the one [`Self::callback_for`] put next
+    /// to it.
+    pub fn verified_user<'a>(&self, user: &'a str, signature: &str) -> Option<&'a str> {
+        let signature = URL_SAFE_NO_PAD.decode(signature.trim()).ok()?;
+        self.mac(user)?.verify_slice(&signature).ok()?;
+        Some(user)
+    }
+
+    fn mac(&self, user_id: &str) -> Option<HmacSha256> {
+        let mut mac = hmac_sha256(self.secret.as_deref()?);
+        mac.update(user_id.as_bytes());
+        Some(mac)
+    }
+
+    /// The frontend URL for the first of `locales` that has one, trying
+    /// `de-AT` before `de`, with `status` appended.
+    pub fn target<'a>(
+        &self,
+        locales: impl IntoIterator<Item = &'a str>,
+        status: VerificationStatus,
+    ) -> Option<String> {
+        let base = locales
+            .into_iter()
+            .map(|locale| locale.trim().to_ascii_lowercase().replace('_', "-"))
+            .find_map(|locale| {
+                self.by_locale.get(&locale).or_else(|| {
+                    let (language, _) = locale.split_once('-')?;
+                    self.by_locale.get(language)
+                })
+            })
+            .or(self.default_url.as_ref())?;
+
+        let mut url = Url::parse(base).ok()?;
+        url.query_pairs_mut().append_pair("status", status.as_str());
+        Some(url.into())
+    }
+}
+
+/// Language tags from an `Accept-Language` value, in the order given.
+pub fn accept_language(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .filter_map(|entry| entry.split(';').next())
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty() && *tag != "*")
+}
@@ -0,0 +1,816 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hmac::Mac;
+use rand::RngCore;
+use reqwest::Client;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::Notify;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::AppConfig;
+use crate::outbox::{DeadLetter, Outbox, OutboxEntry};
+use crate::util::{hmac_sha256, unix_now};
+
+pub const SIGNATURE_HEADER: &str = "x-argus-signature";
+pub const EVENT_HEADER: &str = "x-argus-event";
+pub const DELIVERY_HEADER: &str = "x-argus-delivery";
+pub const TIMESTAMP_HEADER: &str = "x-argus-timestamp";
+pub const TEST_EVENT: &str = "webhook.test";
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Outbox destination of mail deliveries, which no subscription id matches.
+const MAIL_DESTINATION: &str = "mail";
+/// Marks a secret in `WEBHOOKS_FILE` as sealed with `WEBHOOK_SECRETS_KEY`.
+const SEALED_PREFIX: &str = "sealed:";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub url: String,
+    pub secret: String,
+    /// Event types to deliver; `*` matches all and `user.*` a whole family.
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub created_at: u64,
+}
+
+impl WebhookSubscription {
+    fn wants(&self, event: &str) -> bool {
+        self.enabled
+            && self
+                .events
+                .iter()
+                .any(|filter| match filter.strip_suffix('*') {
+                    Some(prefix) => event.starts_with(prefix),
+                    None => filter == event,
+                })
+    }
+}
+
+/// Fields an admin may set; `None` leaves the current value on update.
+#[derive(Debug, Default)]
+pub struct SubscriptionChanges {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Retrying,
+    Delivered,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Delivery {
+    pub id: String,
+    pub subscription_id: String,
+    pub event: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<u64>,
+}
+
+/// Admin-managed webhook subscriptions. Subscriptions are kept in
+/// `WEBHOOKS_FILE` when set, with their secrets encrypted under
+/// `WEBHOOK_SECRETS_KEY`. Events are queued in the [`Outbox`], signed with
+/// the subscription's secret (`X-Argus-Timestamp: <unix seconds>` and
+/// `X-Argus-Signature: sha256=<hex HMAC of "<timestamp>.<body>">`, so
+/// receivers can turn away replays), retried with exponential backoff up to
+/// `WEBHOOK_MAX_ATTEMPTS` and then dead-lettered for replay. The last
+/// `WEBHOOK_DELIVERY_HISTORY` deliveries per subscription are kept for
+/// inspection.
+///
+/// Subscriptions are only delivered to public addresses: hosts that resolve
+/// to loopback, private, link-local or other internal ranges are refused at
+/// connection time, and redirects are not followed.
+///
+/// Events that carry a link which acts on the account, such as reactivation
+/// or "this wasn't me" links, never go to subscriptions. They are sent to
+/// the mail integration at `MAIL_DELIVERY_URL` alone, signed with
+/// `MAIL_DELIVERY_SECRET`, through the same outbox.
+pub struct WebhookService {
+    client: Client,
+    /// Client for subscriptions, which only connects to public addresses.
+    guarded: Client,
+    file: Option<String>,
+    cipher: Option<Aes256Gcm>,
+    mail: Option<WebhookSubscription>,
+    max_attempts: u32,
+    history: usize,
+    poll_interval: Duration,
+    outbox: Arc<Outbox>,
+    wake: Notify,
+    subscriptions: Mutex<Vec<WebhookSubscription>>,
+    deliveries: Mutex<VecDeque<Delivery>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("webhook not found")]
+    NotFound,
+    #[error("dead letter not found")]
+    DeadLetterNotFound,
+    #[error("{0}")]
+    Invalid(&'static str),
+}
+
+impl WebhookService {
+    pub fn from_config(config: &AppConfig, client: Client, outbox: Arc<Outbox>) -> Self {
+        let file = config.webhooks_file.clone();
+        let cipher = config.webhook_secrets_key.as_deref().and_then(cipher);
+        let subscriptions = file
+            .as_deref()
+            .map(|path| load_subscriptions(path, cipher.as_ref()))
+            .unwrap_or_default();
+
+        // Events still in the outbox after a restart keep showing up in the
+        // delivery history until they settle.
+        let deliveries = outbox
+            .entries()
+            .into_iter()
+            .map(|entry| Delivery {
+                status: if entry.attempts == 0 {
+                    DeliveryStatus::Pending
+                } else {
+                    DeliveryStatus::Retrying
+                },
+                id: entry.id,
+                subscription_id: entry.destination,
+                event: entry.event,
+                attempts: entry.attempts,
+                response_status: None,
+                error: None,
+                created_at: entry.created_at,
+                next_attempt_at: Some(entry.next_attempt_at),
+            })
+            .collect();
+
+        let mail = config
+            .mail_delivery_url
+            .clone()
+            .map(|url| WebhookSubscription {
+                id: MAIL_DESTINATION.to_owned(),
+                url,
+                secret: config.mail_delivery_secret.clone().unwrap_or_default(),
+                events: vec!["*".to_owned()],
+                enabled: true,
+                created_at: 0,
+            });
+
+        let guarded = Client::builder()
+            .dns_resolver(Arc::new(PublicOnly))
+            .redirect(Policy::none())
+            .no_proxy()
+            .build()
+            .expect("failed to build webhook HTTP client");
+
+        Self {
+            client,
+            guarded,
+            file,
+            cipher,
+            mail,
+            max_attempts: config.webhook_max_attempts.max(1),
+            history: config.webhook_delivery_history,
+            poll_interval: config.outbox_poll_interval,
+            outbox,
+            wake: Notify::new(),
+            subscriptions: Mutex::new(subscriptions),
+            deliveries: Mutex::new(deliveries),
+        }
+    }
+
+    pub fn list(&self) -> Vec<WebhookSubscription> {
+        self.lock_subscriptions().clone()
+    }
+
+    pub fn get(&self, id: &str) -> Option<WebhookSubscription> {
+        self.lock_subscriptions()
+            .iter()
+            .find(|subscription| subscription.id == id)
+            .cloned()
+    }
+
+    pub fn create(
+        &self,
+        changes: SubscriptionChanges,
+    ) -> Result<WebhookSubscription, WebhookError> {
+        let url = changes
+            .url
+            .ok_or(WebhookError::Invalid("url is required"))?;
+        validate_url(&url)?;
+        let events = changes.events.unwrap_or_else(|| vec!["*".to_owned()]);
+        validate_events(&events)?;
+
+        let subscription = WebhookSubscription {
+            id: random_hex(8),
+            url,
+            secret: changes
+                .secret
+                .filter(|secret| !secret.is_empty())
+                .unwrap_or_else(|| random_hex(32)),
+            events,
+            enabled: changes.enabled.unwrap_or(true),
+            created_at: unix_now(),
+        };
+
+        let mut subscriptions = self.lock_subscriptions();
+        subscriptions.push(subscription.clone());
+        self.persist(&subscriptions);
+        Ok(subscription)
+    }
+
+    pub fn update(
+        &self,
+        id: &str,
+        changes: SubscriptionChanges,
+    ) -> Result<WebhookSubscription, WebhookError> {
+        if let Some(url) = &changes.url {
+            validate_url(url)?;
+        }
+        if let Some(events) = &changes.events {
+            validate_events(events)?;
+        }
+
+        let mut subscriptions = self.lock_subscriptions();
+        let subscription = subscriptions
+            .iter_mut()
+            .find(|subscription| subscription.id == id)
+            .ok_or(WebhookError::NotFound)?;
+        if let Some(url) = changes.url {
+            subscription.url = url;
+        }
+        if let Some(secret) = changes.secret.filter(|secret| !secret.is_empty()) {
+            subscription.secret = secret;
+        }
+        if let Some(events) = changes.events {
+            subscription.events = events;
+        }
+        if let Some(enabled) = changes.enabled {
+            subscription.enabled = enabled;
+        }
+
+        let updated = subscription.clone();
+        self.persist(&subscriptions);
+        Ok(updated)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), WebhookError> {
+        let mut subscriptions = self.lock_subscriptions();
+        let before = subscriptions.len();
+        subscriptions.retain(|subscription| subscription.id != id);
+        if subscriptions.len() == before {
+            return Err(WebhookError::NotFound);
+        }
+        self.persist(&subscriptions);
+        drop(subscriptions);
+
+        self.lock_deliveries()
+            .retain(|delivery| delivery.subscription_id != id);
+        Ok(())
+    }
+
+    /// Deliveries for one subscription, newest first.
+    pub fn deliveries(&self, id: &str) -> Vec<Delivery> {
+        self.lock_deliveries()
+            .iter()
+            .rev()
+            .filter(|delivery| delivery.subscription_id == id)
+            .cloned()
+            .collect()
+    }
+
+    /// Waits until the outbox file holds every queued event, for shutdown.
+    pub async fn flush(&self) {
+        self.outbox.flush().await;
+    }
+
+    /// Queues `event` for every enabled subscription whose filter matches.
+    pub fn publish(&self, event: &str, data: Value) {
+        let targets: Vec<WebhookSubscription> = self
+            .lock_subscriptions()
+            .iter()
+            .filter(|subscription| subscription.wants(event))
+            .cloned()
+            .collect();
+        for subscription in targets {
+            self.dispatch(&subscription, event, data.clone());
+        }
+    }
+
+    /// Queues `event` for the mail integration only. Without
+    /// `MAIL_DELIVERY_URL` the event is dropped.
+    pub fn send_mail(&self, event: &str, data: Value) {
+        match &self.mail {
+            Some(mail) => {
+                self.dispatch(mail, event, data);
+            }
+            None => warn!("[Webhook] MAIL_DELIVERY_URL is not set; {} not sent", event),
+        }
+    }
+
+    /// Sends a `webhook.test` event to one subscription, enabled or not.
+    pub fn send_test(&self, id: &str) -> Result<Delivery, WebhookError> {
+        let subscription = self.get(id).ok_or(WebhookError::NotFound)?;
+        let data = json!({ "message": "Test event from the Argus portal" });
+        Ok(self.dispatch(&subscription, TEST_EVENT, data))
+    }
+
+    /// Works through the outbox: due entries are attempted as soon as they
+    /// are queued, failed ones again once their backoff has passed.
+    pub fn spawn_dispatcher(self: &Arc<Self>) {
+        let service = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                for entry in service.outbox.claim_due() {
+                    let service = Arc::clone(&service);
+                    tokio::spawn(async move { service.attempt(entry).await });
+                }
+                tokio::select! {
+                    () = sleep(service.poll_interval) => {}
+                    () = service.wake.notified() => {}
+                }
+            }
+        });
+    }
+
+    /// Deliveries that exhausted their retries, optionally for one
+    /// subscription, newest first.
+    pub fn dead_letters(&self, subscription_id: Option<&str>) -> Vec<DeadLetter> {
+        self.outbox
+            .dead_letters()
+            .into_iter()
+            .rev()
+            .filter(|dead| subscription_id.is_none_or(|id| dead.entry.destination == id))
+            .collect()
+    }
+
+    /// Re-queues a dead letter under its original delivery id, so receivers
+    /// that deduplicate on `X-Argus-Delivery` recognise a replay.
+    pub fn replay(&self, id: &str) -> Result<Delivery, WebhookError> {
+        let dead = self
+            .outbox
+            .dead_letters()
+            .into_iter()
+            .find(|dead| dead.entry.id == id)
+            .ok_or(WebhookError::DeadLetterNotFound)?;
+        if self.destination(&dead.entry.destination).is_none() {
+            return Err(WebhookError::Invalid("subscription no longer exists"));
+        }
+        let entry = self
+            .outbox
+            .replay(id)
+            .ok_or(WebhookError::DeadLetterNotFound)?;
+
+        let delivery = Delivery {
+            id: entry.id.clone(),
+            subscription_id: entry.destination.clone(),
+            event: entry.event.clone(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            response_status: None,
+            error: None,
+            created_at: entry.created_at,
+            next_attempt_at: Some(entry.next_attempt_at),
+        };
+        self.lock_deliveries()
+            .retain(|existing| existing.id != delivery.id);
+        self.record(delivery.clone());
+        self.wake.notify_one();
+
+        info!(
+            "[Webhook] replaying event={} subscription={} delivery={}",
+            entry.event, entry.destination, entry.id
+        );
+        Ok(delivery)
+    }
+
+    /// Replays every dead letter of one subscription, oldest first.
+    pub fn replay_all(&self, subscription_id: &str) -> Result<Vec<Delivery>, WebhookError> {
+        if self.destination(subscription_id).is_none() {
+            return Err(WebhookError::NotFound);
+        }
+        let ids: Vec<String> = self
+            .outbox
+            .dead_letters()
+            .into_iter()
+            .filter(|dead| dead.entry.destination == subscription_id)
+            .map(|dead| dead.entry.id)
+            .collect();
+        ids.iter().map(|id| self.replay(id)).collect()
+    }
+
+    pub fn discard(&self, id: &str) -> Result<(), WebhookError> {
+        if self.outbox.discard(id) {
+            Ok(())
+        } else {
+            Err(WebhookError::DeadLetterNotFound)
+        }
+    }
+
+    fn dispatch(&self, subscription: &WebhookSubscription, event: &str, data: Value) -> Delivery {
+        let delivery = Delivery {
+            id: random_hex(8),
+            subscription_id: subscription.id.clone(),
+            event: event.to_owned(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            response_status: None,
+            error: None,
+            created_at: unix_now(),
+            next_attempt_at: None,
+        };
+        self.record(delivery.clone());
+
+        let payload = json!({
+            "id": delivery.id,
+            "event": event,
+            "createdAt": delivery.created_at,
+            "data": data,
+        })
+        .to_string();
+        self.outbox.enqueue(OutboxEntry {
+            id: delivery.id.clone(),
+            destination: subscription.id.clone(),
+            event: event.to_owned(),
+            payload,
+            attempts: 0,
+            next_attempt_at: delivery.created_at,
+            created_at: delivery.created_at,
+        });
+        self.wake.notify_one();
+
+        delivery
+    }
+
+    async fn attempt(&self, entry: OutboxEntry) {
+        let Some(subscription) = self.destination(&entry.destination) else {
+            warn!(
+                "[Webhook] dropping event={} for deleted subscription={}",
+                entry.event, entry.destination
+            );
+            self.outbox.complete(&entry.id);
+            return;
+        };
+
+        let attempt = entry.attempts + 1;
+        // The mail integration is operator configuration and may well live
+        // on the internal network; subscriptions are not.
+        let client = if subscription.id == MAIL_DESTINATION {
+            &self.client
+        } else {
+            &self.guarded
+        };
+        let timestamp = unix_now().to_string();
+        let result = match check_literal_host(&subscription.url) {
+            Ok(()) => client
+                .post(&subscription.url)
+                .header("content-type", "application/json")
+                .header(
+                    SIGNATURE_HEADER,
+                    sign(&subscription.secret, &timestamp, &entry.payload),
+                )
+                .header(TIMESTAMP_HEADER, &timestamp)
+                .header(EVENT_HEADER, &entry.event)
+                .header(DELIVERY_HEADER, &entry.id)
+                .timeout(DELIVERY_TIMEOUT)
+                .body(entry.payload.clone())
+                .send()
+                .await
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        let (response_status, error) = match result {
+            Ok(response) if response.status().is_success() => {
+                self.outbox.complete(&entry.id);
+                self.update_delivery(&entry.id, |delivery| {
+                    delivery.status = DeliveryStatus::Delivered;
+                    delivery.attempts = attempt;
+                    delivery.response_status = Some(response.status().as_u16());
+                    delivery.error = None;
+                    delivery.next_attempt_at = None;
+                });
+                info!(
+                    "[Webhook] delivered event={} subscription={} attempt={}",
+                    entry.event, subscription.id, attempt
+                );
+                return;
+            }
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                format!("endpoint returned {}", response.status()),
+            ),
+            Err(err) => (None, err),
+        };
+
+        let last = attempt >= self.max_attempts;
+        let next_attempt_at = unix_now() + 2u64.saturating_pow(attempt);
+        if last {
+            self.outbox.dead_letter(&entry.id, attempt, error.clone());
+        } else {
+            self.outbox.reschedule(&entry.id, attempt, next_attempt_at);
+        }
+        self.update_delivery(&entry.id, |delivery| {
+            delivery.status = if last {
+                DeliveryStatus::Failed
+            } else {
+                DeliveryStatus::Retrying
+            };
+            delivery.attempts = attempt;
+            delivery.response_status = response_status;
+            delivery.error = Some(error.clone());
+            delivery.next_attempt_at = (!last).then_some(next_attempt_at);
+        });
+        warn!(
+            "[Webhook] delivery failed event={} subscription={} attempt={}: {}",
+            entry.event, subscription.id, attempt, error
+        );
+    }
+
+    /// The subscription, or the mail integration, an outbox entry is for.
+    fn destination(&self, id: &str) -> Option<WebhookSubscription> {
+        match &self.mail {
+            Some(mail) if id == MAIL_DESTINATION => Some(mail.clone()),
+            _ => self.get(id),
+        }
+    }
+
+    fn record(&self, delivery: Delivery) {
+        let mut deliveries = self.lock_deliveries();
+        let subscription_id = delivery.subscription_id.clone();
+        deliveries.push_back(delivery);
+
+        let kept = deliveries
+            .iter()
+            .filter(|delivery| delivery.subscription_id == subscription_id)
+            .count();
+        if kept > self.history
+            && let Some(oldest) = deliveries
+                .iter()
+                .position(|delivery| delivery.subscription_id == subscription_id)
+        {
+            deliveries.remove(oldest);
+        }
+    }
+
+    fn update_delivery(&self, id: &str, update: impl FnOnce(&mut Delivery)) {
+        if let Some(delivery) = self
+            .lock_deliveries()
+            .iter_mut()
+            .find(|delivery| delivery.id == id)
+        {
+            update(delivery);
+        }
+    }
+
+    fn persist(&self, subscriptions: &[WebhookSubscription]) {
+        let Some(path) = self.file.as_deref() else {
+            return;
+        };
+        let Some(cipher) = &self.cipher else {
+            warn!(%path, "[Webhook] WEBHOOK_SECRETS_KEY is not set; subscriptions not saved");
+            return;
+        };
+        let sealed: Option<Vec<WebhookSubscription>> = subscriptions
+            .iter()
+            .map(|subscription| {
+                Some(WebhookSubscription {
+                    secret: seal_secret(cipher, &subscription.secret)?,
+                    ..subscription.clone()
+                })
+            })
+            .collect();
+        let result = sealed
+            .ok_or_else(|| "unable to seal secrets".to_owned())
+            .and_then(|sealed| serde_json::to_string_pretty(&sealed).map_err(|err| err.to_string()))
+            .map_err(|err| err.to_string())
+            .and_then(|json| fs::write(path, json).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            warn!(%path, "[Webhook] unable to save subscriptions: {}", err);
+        }
+    }
+
+    fn lock_subscriptions(&self) -> std::sync::MutexGuard<'_, Vec<WebhookSubscription>> {
+        self.subscriptions
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn lock_deliveries(&self) -> std::sync::MutexGuard<'_, VecDeque<Delivery>> {
+        self.deliveries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+fn load_subscriptions(path: &str, cipher: Option<&Aes256Gcm>) -> Vec<WebhookSubscription> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let subscriptions: Vec<WebhookSubscription> = match serde_json::from_str(&contents) {
+        Ok(subscriptions) => subscriptions,
+        Err(err) => {
+            warn!(%path, ?err, "Unable to parse webhooks file; starting without subscriptions");
+            return Vec::new();
+        }
+    };
+
+    // Secrets saved before they were sealed are read as they are and sealed
+    // the next time the file is written.
+    subscriptions
+        .into_iter()
+        .filter_map(|mut subscription| {
+            if let Some(sealed) = subscription.secret.strip_prefix(SEALED_PREFIX) {
+                let Some(secret) = cipher.and_then(|cipher| open_secret(cipher, sealed)) else {
+                    warn!(
+                        %path,
+                        "[Webhook] unable to open the secret of subscription {}; skipping it",
+                        subscription.id
+                    );
+                    return None;
+                };
+                subscription.secret = secret;
+            }
+            Some(subscription)
+        })
+        .collect()
+}
+
+/// Checks `WEBHOOK_SECRETS_KEY` for startup validation; a webhooks file
+/// needs it so secrets are not written in the clear.
+pub fn check(config: &AppConfig) -> Result<(), String> {
+    match (&config.webhooks_file, config.webhook_secrets_key.as_deref()) {
+        (_, Some(key)) if cipher(key).is_none() => {
+            Err("WEBHOOK_SECRETS_KEY must be 32 base64 bytes".to_owned())
+        }
+        (Some(_), None) => Err("WEBHOOKS_FILE requires WEBHOOK_SECRETS_KEY".to_owned()),
+        _ => Ok(()),
+    }
+}
+
+fn cipher(key: &str) -> Option<Aes256Gcm> {
+    let bytes = STANDARD.decode(key.trim()).ok()?;
+    (bytes.len() == KEY_LEN).then(|| Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)))
+}
+
+fn seal_secret(cipher: &Aes256Gcm, secret: &str) -> Option<String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: secret.as_bytes(),
+                aad: SEALED_PREFIX.as_bytes(),
+            },
+        )
+        .ok()?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Some(format!("{SEALED_PREFIX}{}", STANDARD.encode(sealed)))
+}
+
+fn open_secret(cipher: &Aes256Gcm, sealed: &str) -> Option<String> {
+    let bytes = STANDARD.decode(sealed).ok()?;
+    if bytes.len() <= NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let secret = cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: SEALED_PREFIX.as_bytes(),
+            },
+        )
+        .ok()?;
+    String::from_utf8(secret).ok()
+}
+
+/// Resolves subscription hosts, refusing any name with an address that is
+/// not public so a subscription cannot reach the internal network, nor be
+/// pointed there later through DNS.
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_owned();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if addrs.is_empty() || addrs.iter().any(|addr| !is_public(addr.ip())) {
+                return Err(format!("{host} does not resolve to a public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Hosts given as an IP address never reach the resolver, so they are
+/// checked on their own.
+fn check_literal_host(url: &str) -> Result<(), WebhookError> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|_| WebhookError::Invalid("url must be an absolute http(s) URL"))?;
+    let host = parsed.host_str().unwrap_or_default();
+    let Ok(ip) = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    else {
+        return Ok(());
+    };
+    if is_public(ip) {
+        Ok(())
+    } else {
+        Err(WebhookError::Invalid("url must point at a public address"))
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                // 100.64.0.0/10, carrier-grade NAT.
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public(IpAddr::V4(mapped)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // fc00::/7 unique local and fe80::/10 link-local.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+fn validate_url(url: &str) -> Result<(), WebhookError> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.host().is_some() => {
+            check_literal_host(url)
+        }
+        _ => Err(WebhookError::Invalid("url must be an absolute http(s) URL")),
+    }
+}
+
+fn validate_events(events: &[String]) -> Result<(), WebhookError> {
+    if events.is_empty() || events.iter().any(|event| event.trim().is_empty()) {
+        return Err(WebhookError::Invalid(
+            "events must list at least one event type",
+        ));
+    }
+    Ok(())
+}
+
+pub fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac = hmac_sha256(secret.as_bytes());
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buffer);
+    hex::encode(buffer)
+}
@@ -58,6 +58,16 @@ interface AuthApiResponse {
   refreshExpiresIn?: number;
 }
 
+interface LoginChallenge {
+  type: string;
+  continuationToken: string;
+  expiresIn: number;
+  message: string;
+  actionUrl?: string;
+}
+
+type LoginApiResponse = { tokens: AuthApiResponse } | { challenge: LoginChallenge };
+
 interface AuthBroadcastTokensMessage {
   type: "tokens";
   sourceId: string;
@@ -562,8 +572,11 @@ export function AuthProvider({ children }: { children: ReactNode }): JSX.Element
           throw new Error(message || "Unable to sign in");
         }
 
-        const data = (await response.json()) as AuthApiResponse;
-        applyResponse(data);
+        const data = (await response.json()) as LoginApiResponse;
+        if ("challenge" in data) {
+          throw new Error(data.challenge.message);
+        }
+        applyResponse(data.tokens);
       } finally {
         setState((prev) => ({ ...prev, isLoading: false }));
       }